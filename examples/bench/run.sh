@@ -0,0 +1,12 @@
+#!/bin/bash
+# Compiles and times each benchmark: `examples/bench/run.sh [jazzlightc] [jazzlight]`.
+# Build both with `cargo build --release` first.
+jazzlightc=$(realpath "${1:-target/release/jazzlightc}")
+jazzlight=$(realpath "${2:-target/release/jazzlight}")
+cd "$(dirname "$0")" || exit 1
+TIMEFORMAT=%R
+for src in *.jzl; do
+    "$jazzlightc" "$src" || exit 1
+    printf '%-10s ' "${src%.jzl}"
+    { time "$jazzlight" "${src%.jzl}.j" >/dev/null; } 2>&1
+done
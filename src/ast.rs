@@ -6,7 +6,9 @@ pub enum Constant {
     Null,
     This,
     Int(i64),
+    BigInt(String, u32),
     Float(f64),
+    Char(char),
     Str(String),
     Builtin(String),
     Ident(String),
@@ -20,6 +22,52 @@ pub struct Expr {
     pub decl: ExprDecl,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Const(Constant),
+    Bind(String),
+    /// Equal to the variable of that name, what a bare identifier tests in a `switch` arm.
+    Equal(String),
+    Variant(String, Vec<Pattern>),
+    Array(Vec<Pattern>),
+    Object(Vec<(String, Pattern)>),
+    Or(Vec<Pattern>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<P<Expr>>,
+    pub body: P<Expr>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatchClause {
+    pub name: String,
+    /// Only exceptions that are instances of this prototype are caught.
+    pub ty: Option<P<Expr>>,
+    pub guard: Option<P<Expr>>,
+    pub body: P<Expr>,
+}
+
+/// Optional type annotations of a function, only used by the type checker, and its doc
+/// comment.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Signature {
+    pub params: Vec<Option<String>>,
+    pub ret: Option<String>,
+    pub doc: Option<String>,
+}
+
+/// `var` is function-scoped and hoisted, `let` and `const` are block-scoped.
+#[derive(Clone, Debug, PartialEq, Copy)]
+pub enum VarKind {
+    Var,
+    Let,
+    Const,
+}
+
 #[derive(Clone, Debug, PartialEq, Copy)]
 pub enum WhileFlag {
     NormalWhile,
@@ -33,6 +81,8 @@ pub enum ExprDecl {
     Paren(P<Expr>),
     Field(P<Expr>, String),
     Call(P<Expr>, Vec<P<Expr>>),
+    /// `name: value` argument of a call, passed to the parameter called `name`.
+    NamedArg(String, P<Expr>),
     Array(P<Expr>, P<Expr>),
 
     Vars(Vec<(String, Option<P<Expr>>)>),
@@ -40,23 +90,44 @@ pub enum ExprDecl {
     ForIn(String, P<Expr>, P<Expr>),
     While(P<Expr>, P<Expr>),
     If(P<Expr>, P<Expr>, Option<P<Expr>>),
-    Try(P<Expr>, String, P<Expr>),
-    Function(Vec<String>, P<Expr>),
+    Try(P<Expr>, Vec<CatchClause>),
+    /// Parameters, body, type annotations and source text.
+    Function(Vec<String>, P<Expr>, Signature, Option<String>),
     Binop(String, P<Expr>, P<Expr>),
     Return(Option<P<Expr>>),
     Break(Option<P<Expr>>),
-    Var(bool, String, Option<P<Expr>>),
+    /// Declaration with an optional type annotation and initializer.
+    Var(VarKind, String, Option<String>, Option<P<Expr>>),
     Continue,
     Next(P<Expr>, P<Expr>),
-    Object(Vec<(String, P<Expr>)>),
+    /// Object with an optional prototype and initial fields.
+    Object(Option<P<Expr>>, Vec<(String, P<Expr>)>),
     Label(String),
-    Switch(P<Expr>, Vec<(P<Expr>, P<Expr>)>, Option<P<Expr>>),
+    Match(P<Expr>, Vec<MatchArm>),
+    Enum(String, Vec<(String, Vec<String>)>),
+    /// Postfix `?`: unwraps Ok/Some, returns Err/None from the current function.
+    Propagate(P<Expr>),
     Unop(String, P<Expr>),
+    /// `++` or `--` on a variable, field or element. Prefix yields the new value, postfix
+    /// the old one.
+    Update(String, P<Expr>, bool),
     Throw(P<Expr>),
+    /// Bare `throw;` inside a catch clause, rethrows the caught exception.
+    Rethrow,
     Include(String),
     Yield(P<Expr>),
+    /// `defer expr`: runs `expr` when the enclosing function returns or throws.
+    Defer(P<Expr>),
+    /// `using (let name = value) body`: disposes of `value` with `$dispose` when leaving
+    /// `body`, however that happens.
+    Using(String, P<Expr>, P<Expr>),
+    /// `delete object.field` or `delete object[key]`: removes the field `key` of `object`,
+    /// yields whether it had one.
+    Delete(P<Expr>, P<Expr>),
     Jazz(String),
     Goto(String),
+    /// `macro name(params) body`, expanded at every call before codegen.
+    Macro(String, Vec<String>, P<Expr>),
 }
 
 pub fn make_call(v: P<Expr>, args: Vec<P<Expr>>, pos: Position) -> Expr {
@@ -97,6 +168,7 @@ pub fn make_bin(op: String, e1: P<Expr>, e2: P<Expr>, pos: Position) -> Expr {
 }
 
 impl Expr {
+    /// Calls `f` on every direct subexpression, in source order.
     pub fn iter(&self, mut f: impl FnMut(&P<Expr>)) {
         match &self.decl {
             ExprDecl::Block(el) => {
@@ -105,7 +177,9 @@ impl Expr {
                 }
             }
             ExprDecl::Paren(e) => f(e),
+            ExprDecl::Propagate(e) => f(e),
             ExprDecl::Field(e, _) => f(e),
+            ExprDecl::NamedArg(_, e) => f(e),
             ExprDecl::Call(e, el) => {
                 f(e);
                 for x in el.iter() {
@@ -116,11 +190,28 @@ impl Expr {
                 f(e1);
                 f(e2);
             }
-            ExprDecl::Var(_, _, e) => match e {
+            ExprDecl::Var(_, _, _, e) => match e {
                 Some(e) => f(e),
                 _ => (),
             },
-            ExprDecl::While(e1, e2) => {
+            ExprDecl::Vars(vars) => {
+                for (_, e) in vars.iter() {
+                    if let Some(e) = e {
+                        f(e);
+                    }
+                }
+            }
+            ExprDecl::For(e1, e2, e3, e4) => {
+                f(e1);
+                f(e2);
+                f(e3);
+                f(e4);
+            }
+            ExprDecl::While(e1, e2) | ExprDecl::Delete(e1, e2) => {
+                f(e1);
+                f(e2);
+            }
+            ExprDecl::ForIn(_, e1, e2) | ExprDecl::Using(_, e1, e2) => {
                 f(e1);
                 f(e2);
             }
@@ -132,18 +223,190 @@ impl Expr {
                     _ => (),
                 }
             }
-            ExprDecl::Function(_, e) => f(e),
+            ExprDecl::Function(_, e, _, _) => f(e),
             ExprDecl::Binop(_, e1, e2) => {
                 f(e1);
                 f(e2)
             }
             ExprDecl::Return(Some(e)) => f(e),
+            ExprDecl::Assign(e1, e2) => {
+                f(e1);
+                f(e2);
+            }
+            ExprDecl::Unop(_, e) => f(e),
+            ExprDecl::Update(_, e, _) => f(e),
+            ExprDecl::Throw(e) => f(e),
+            ExprDecl::Try(e, clauses) => {
+                f(e);
+                for clause in clauses.iter() {
+                    if let Some(ty) = &clause.ty {
+                        f(ty);
+                    }
+                    if let Some(guard) = &clause.guard {
+                        f(guard);
+                    }
+                    f(&clause.body);
+                }
+            }
+            ExprDecl::Object(proto, fields) => {
+                if let Some(proto) = proto {
+                    f(proto);
+                }
+                for (_, e) in fields.iter() {
+                    f(e);
+                }
+            }
             ExprDecl::Break(Some(e)) => f(e),
             ExprDecl::Next(e1, e2) => {
                 f(e1);
                 f(e2);
             }
+            ExprDecl::Match(e, arms) => {
+                f(e);
+                for arm in arms.iter() {
+                    if let Some(guard) = &arm.guard {
+                        f(guard);
+                    }
+                    f(&arm.body);
+                }
+            }
+            ExprDecl::Yield(e) | ExprDecl::Defer(e) => f(e),
+            ExprDecl::Macro(_, _, e) => f(e),
             _ => (),
         }
     }
+
+    /// Rebuilds the expression with every direct subexpression replaced by `f`.
+    pub fn try_map<E>(&self, mut f: impl FnMut(&P<Expr>) -> Result<P<Expr>, E>) -> Result<Expr, E> {
+        let decl = match &self.decl {
+            ExprDecl::Assign(e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::Assign(e1, f(e2)?)
+            }
+            ExprDecl::Block(el) => {
+                ExprDecl::Block(el.iter().map(&mut f).collect::<Result<_, _>>()?)
+            }
+            ExprDecl::Paren(e) => ExprDecl::Paren(f(e)?),
+            ExprDecl::Propagate(e) => ExprDecl::Propagate(f(e)?),
+            ExprDecl::Field(e, name) => ExprDecl::Field(f(e)?, name.clone()),
+            ExprDecl::NamedArg(name, e) => ExprDecl::NamedArg(name.clone(), f(e)?),
+            ExprDecl::Call(e, args) => {
+                let e = f(e)?;
+                let args = args.iter().map(&mut f).collect::<Result<_, _>>()?;
+                ExprDecl::Call(e, args)
+            }
+            ExprDecl::Array(e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::Array(e1, f(e2)?)
+            }
+            ExprDecl::Vars(vars) => ExprDecl::Vars(
+                vars.iter()
+                    .map(|(name, e)| map_opt(&mut f, e).map(|e| (name.clone(), e)))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ExprDecl::For(e1, e2, e3, e4) => {
+                let e1 = f(e1)?;
+                let e2 = f(e2)?;
+                let e3 = f(e3)?;
+                ExprDecl::For(e1, e2, e3, f(e4)?)
+            }
+            ExprDecl::ForIn(name, e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::ForIn(name.clone(), e1, f(e2)?)
+            }
+            ExprDecl::Using(name, e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::Using(name.clone(), e1, f(e2)?)
+            }
+            ExprDecl::While(e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::While(e1, f(e2)?)
+            }
+            ExprDecl::Delete(e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::Delete(e1, f(e2)?)
+            }
+            ExprDecl::If(e1, e2, e3) => {
+                let e1 = f(e1)?;
+                let e2 = f(e2)?;
+                ExprDecl::If(e1, e2, map_opt(&mut f, e3)?)
+            }
+            ExprDecl::Try(e, clauses) => {
+                let e = f(e)?;
+                let clauses = clauses
+                    .iter()
+                    .map(|clause| {
+                        Ok(CatchClause {
+                            name: clause.name.clone(),
+                            ty: map_opt(&mut f, &clause.ty)?,
+                            guard: map_opt(&mut f, &clause.guard)?,
+                            body: f(&clause.body)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                ExprDecl::Try(e, clauses)
+            }
+            ExprDecl::Function(params, e, sig, source) => {
+                ExprDecl::Function(params.clone(), f(e)?, sig.clone(), source.clone())
+            }
+            ExprDecl::Binop(op, e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::Binop(op.clone(), e1, f(e2)?)
+            }
+            ExprDecl::Return(e) => ExprDecl::Return(map_opt(&mut f, e)?),
+            ExprDecl::Break(e) => ExprDecl::Break(map_opt(&mut f, e)?),
+            ExprDecl::Var(kind, name, ty, e) => {
+                ExprDecl::Var(*kind, name.clone(), ty.clone(), map_opt(&mut f, e)?)
+            }
+            ExprDecl::Next(e1, e2) => {
+                let e1 = f(e1)?;
+                ExprDecl::Next(e1, f(e2)?)
+            }
+            ExprDecl::Object(proto, fields) => {
+                let proto = map_opt(&mut f, proto)?;
+                let fields = fields
+                    .iter()
+                    .map(|(name, e)| f(e).map(|e| (name.clone(), e)))
+                    .collect::<Result<_, _>>()?;
+                ExprDecl::Object(proto, fields)
+            }
+            ExprDecl::Match(e, arms) => {
+                let e = f(e)?;
+                let arms = arms
+                    .iter()
+                    .map(|arm| {
+                        Ok(MatchArm {
+                            pattern: arm.pattern.clone(),
+                            guard: map_opt(&mut f, &arm.guard)?,
+                            body: f(&arm.body)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                ExprDecl::Match(e, arms)
+            }
+            ExprDecl::Unop(op, e) => ExprDecl::Unop(op.clone(), f(e)?),
+            ExprDecl::Update(op, e, prefix) => ExprDecl::Update(op.clone(), f(e)?, *prefix),
+            ExprDecl::Throw(e) => ExprDecl::Throw(f(e)?),
+            ExprDecl::Yield(e) => ExprDecl::Yield(f(e)?),
+            ExprDecl::Defer(e) => ExprDecl::Defer(f(e)?),
+            ExprDecl::Macro(name, params, e) => {
+                ExprDecl::Macro(name.clone(), params.clone(), f(e)?)
+            }
+            decl => decl.clone(),
+        };
+        Ok(Expr {
+            pos: self.pos.clone(),
+            decl,
+        })
+    }
+}
+
+fn map_opt<E>(
+    f: &mut impl FnMut(&P<Expr>) -> Result<P<Expr>, E>,
+    e: &Option<P<Expr>>,
+) -> Result<Option<P<Expr>>, E> {
+    match e {
+        Some(e) => Ok(Some(f(e)?)),
+        None => Ok(None),
+    }
 }
@@ -0,0 +1,134 @@
+use jazzlightc::package::{
+    build, check, default_jobs, doc, install, new_project, Manifest, Source, Target,
+};
+use std::path::Path;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "jazz", version = "0.0.1")]
+/// Manages JazzLight projects
+enum Command {
+    /// Adds a dependency to jazz.toml and installs it
+    Add {
+        name: String,
+        #[structopt(long = "path")]
+        /// Directory of the dependency, relative to the project
+        path: Option<String>,
+        #[structopt(long = "git")]
+        /// Git repository of the dependency
+        git: Option<String>,
+        #[structopt(long = "rev", requires = "git")]
+        /// Branch, tag or commit to check out
+        rev: Option<String>,
+    },
+    /// Installs the dependencies in jazz.toml into jazz_modules/
+    Install,
+    /// Creates a project with jazz.toml, src/main.jzl and tests/
+    New { name: String },
+    /// Checks and compiles the modules in src/ to build/
+    Build {
+        #[structopt(long = "target", default_value = "bytecode")]
+        /// bytecode, or wasm for the experimental WebAssembly backend
+        target: Target,
+        #[structopt(short = "j", long = "jobs")]
+        /// Number of modules compiled at the same time, the number of CPUs by default
+        jobs: Option<usize>,
+    },
+    /// Checks the type annotations of the modules in src/ without compiling them
+    Check {
+        #[structopt(short = "j", long = "jobs")]
+        /// Number of modules checked at the same time, the number of CPUs by default
+        jobs: Option<usize>,
+    },
+    /// Writes Markdown documentation of the modules in src/ to docs/
+    Doc,
+}
+
+fn fail(errors: &[String]) -> ! {
+    for error in errors.iter() {
+        eprintln!("{}", error);
+    }
+    std::process::exit(1);
+}
+
+fn install_all(dir: &Path, dependencies: &[(String, Source)]) {
+    for (name, source) in dependencies.iter() {
+        match install(dir, name, source) {
+            Ok(warnings) => {
+                for warning in warnings.iter() {
+                    eprintln!("{}", warning);
+                }
+                println!("installed {}", name);
+            }
+            Err(errors) => fail(&errors),
+        }
+    }
+}
+
+fn build_project(dir: &Path, target: Target, jobs: usize) {
+    let report = build(dir, target, jobs).unwrap_or_else(|e| fail(&[e]));
+    for diagnostic in report.warnings.iter().chain(report.errors.iter()) {
+        eprintln!("{}", diagnostic);
+    }
+    println!(
+        "built {} modules: {} errors, {} warnings",
+        report.modules,
+        report.errors.len(),
+        report.warnings.len()
+    );
+    if !report.errors.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let dir = Path::new(".");
+    let command = Command::from_args();
+    if let Command::New { name } = &command {
+        new_project(name).unwrap_or_else(|e| fail(&[e]));
+        println!("created {}", name);
+        return;
+    }
+    let mut manifest = Manifest::read(dir).unwrap_or_else(|e| fail(&[e]));
+    match command {
+        Command::Add {
+            name,
+            path,
+            git,
+            rev,
+        } => {
+            let source = match (path, git) {
+                (Some(path), None) => Source::Path(path),
+                (None, Some(git)) => Source::Git(git, rev),
+                _ => fail(&["add: either --path or --git expected".to_owned()]),
+            };
+            manifest.add(&name, source.clone());
+            install_all(dir, &[(name, source)]);
+            manifest.write(dir).unwrap_or_else(|e| fail(&[e]));
+        }
+        Command::Install => install_all(dir, &manifest.dependencies),
+        Command::New { .. } => unreachable!(),
+        Command::Build { target, jobs } => {
+            build_project(dir, target, jobs.unwrap_or_else(default_jobs))
+        }
+        Command::Check { jobs } => {
+            let report =
+                check(dir, jobs.unwrap_or_else(default_jobs)).unwrap_or_else(|e| fail(&[e]));
+            for error in report.errors.iter() {
+                eprintln!("{}", error);
+            }
+            println!(
+                "checked {} modules: {} errors",
+                report.modules,
+                report.errors.len()
+            );
+            if !report.errors.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Command::Doc => {
+            let modules = doc(dir).unwrap_or_else(|errors| fail(&errors));
+            println!("documented {} modules", modules);
+        }
+    }
+}
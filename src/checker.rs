@@ -0,0 +1,476 @@
+//! Best-effort static type checker. Types come from annotations (`fun f(a: number) -> string`,
+//! `let x: bool = ...`) and from literals, operators and calls of annotated functions.
+//! Everything else is `any`, which is never reported. Variables without an annotation are
+//! `any` too, whatever they start with, since they can be assigned anything.
+
+use crate::ast::*;
+use crate::msg::*;
+use crate::P;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ty {
+    Any,
+    Null,
+    Bool,
+    Number,
+    Str,
+    Array,
+    Object,
+    /// Parameter and return types, if known.
+    Function(Option<P<(Vec<Ty>, Ty)>>),
+}
+
+impl Ty {
+    /// Whether a value of type `other` can be used where `self` is expected.
+    pub fn accepts(&self, other: &Ty) -> bool {
+        match (self, other) {
+            (Ty::Any, _) | (_, Ty::Any) => true,
+            (Ty::Function(_), Ty::Function(_)) => true,
+            (a, b) => a == b,
+        }
+    }
+
+    fn is_known(&self) -> bool {
+        *self != Ty::Any
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Ty::Any => "any",
+            Ty::Null => "null",
+            Ty::Bool => "bool",
+            Ty::Number => "number",
+            Ty::Str => "string",
+            Ty::Array => "array",
+            Ty::Object => "object",
+            Ty::Function(_) => "function",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Type of an annotation. Capitalized names are prototypes and check as objects.
+pub fn resolve_type(name: &str) -> Option<Ty> {
+    Some(match name {
+        "any" => Ty::Any,
+        "null" => Ty::Null,
+        "bool" => Ty::Bool,
+        "number" | "int" | "float" => Ty::Number,
+        "string" => Ty::Str,
+        "array" => Ty::Array,
+        "object" => Ty::Object,
+        "function" => Ty::Function(None),
+        _ if name.starts_with(char::is_uppercase) => Ty::Object,
+        _ => return None,
+    })
+}
+
+fn builtin_type(name: &str) -> Ty {
+    match name {
+        "array" | "amake" | "awith_capacity" | "acopy" | "schars" | "map_keys" => Ty::Array,
+        "asize" | "nargs" | "builder_len" | "map_size" | "cmp" | "ord" => Ty::Number,
+        "typeof" | "string" | "scopy" | "str_from_chars" | "sconcat" | "builder_to_string" => {
+            Ty::Str
+        }
+        "new" | "new_error" => Ty::Object,
+        "instanceof" => Ty::Bool,
+        _ => Ty::Any,
+    }
+}
+
+struct Binding {
+    ty: Ty,
+    /// Only annotated variables are checked on assignment, the others become `any`.
+    declared: bool,
+}
+
+pub struct Checker {
+    scopes: Vec<HashMap<String, Binding>>,
+    /// Declared return types of the enclosing functions.
+    returns: Vec<Ty>,
+    pub errors: Vec<MsgWithPos>,
+}
+
+impl Default for Checker {
+    fn default() -> Checker {
+        Checker::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        Checker {
+            scopes: vec![HashMap::new()],
+            returns: vec![],
+            errors: vec![],
+        }
+    }
+
+    fn error(&mut self, e: &Expr, msg: Msg) {
+        let msg = MsgWithPos::new(e.pos.file.to_string(), e.pos.clone(), msg);
+        self.errors.push(msg);
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Binding> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    fn bind(&mut self, name: &str, ty: Ty, declared: bool) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_owned(), Binding { ty, declared });
+    }
+
+    fn annotation(&mut self, e: &Expr, name: &Option<String>) -> Option<Ty> {
+        let name = name.as_ref()?;
+        match resolve_type(name) {
+            Some(ty) => Some(ty),
+            None => {
+                self.error(e, Msg::UnknownType(name.to_owned()));
+                Some(Ty::Any)
+            }
+        }
+    }
+
+    fn signature(&mut self, e: &Expr, sig: &Signature, argc: usize) -> Ty {
+        let params = (0..argc)
+            .map(|i| {
+                let ann = sig.params.get(i).cloned().unwrap_or(None);
+                self.annotation(e, &ann).unwrap_or(Ty::Any)
+            })
+            .collect();
+        let ret = self.annotation(e, &sig.ret).unwrap_or(Ty::Any);
+        Ty::Function(Some(P((params, ret))))
+    }
+
+    pub fn check_all(&mut self, ast: &[P<Expr>]) {
+        for e in ast.iter() {
+            self.check(e);
+        }
+    }
+
+    pub fn check(&mut self, e: &P<Expr>) -> Ty {
+        match &e.decl {
+            ExprDecl::Const(c) => match c {
+                Constant::True | Constant::False => Ty::Bool,
+                Constant::Null => Ty::Null,
+                Constant::Int(_) | Constant::Float(_) => Ty::Number,
+                Constant::Str(_) => Ty::Str,
+                Constant::Ident(name) => self.lookup(name).map(|b| b.ty.clone()).unwrap_or(Ty::Any),
+                _ => Ty::Any,
+            },
+            ExprDecl::Block(el) => {
+                self.scopes.push(HashMap::new());
+                let mut ty = Ty::Null;
+                for e in el.iter() {
+                    ty = self.check(e);
+                }
+                self.scopes.pop();
+                ty
+            }
+            ExprDecl::Paren(e) => self.check(e),
+            ExprDecl::Var(_, name, ann, init) => {
+                let declared = self.annotation(e, ann);
+                let ty = match init {
+                    Some(init) => {
+                        if let ExprDecl::Function(params, _, sig, _) = &init.decl {
+                            // Visible in its own body for recursion.
+                            let ty = self.signature(init, sig, params.len());
+                            self.bind(name, ty, false);
+                        }
+                        self.check(init)
+                    }
+                    None => declared.clone().unwrap_or(Ty::Any),
+                };
+                if let Some(declared) = &declared {
+                    if !declared.accepts(&ty) {
+                        let msg =
+                            Msg::AssignType(name.to_owned(), declared.to_string(), ty.to_string());
+                        self.error(e, msg);
+                    }
+                }
+                let is_declared = declared.is_some();
+                let ty = match declared {
+                    Some(declared) => declared,
+                    // Functions keep their signature so calls are checked, until assigned.
+                    None if matches!(ty, Ty::Function(_)) => ty,
+                    None => Ty::Any,
+                };
+                self.bind(name, ty, is_declared);
+                Ty::Null
+            }
+            ExprDecl::Assign(lhs, rhs) => {
+                let ty = self.check(rhs);
+                match &lhs.decl {
+                    ExprDecl::Const(Constant::Ident(name)) => {
+                        let expected = match self.lookup_mut(name) {
+                            Some(b) if b.declared => Some(b.ty.clone()),
+                            Some(b) => {
+                                b.ty = Ty::Any;
+                                None
+                            }
+                            None => None,
+                        };
+                        if let Some(expected) = expected {
+                            if !expected.accepts(&ty) {
+                                let msg = Msg::AssignType(
+                                    name.to_owned(),
+                                    expected.to_string(),
+                                    ty.to_string(),
+                                );
+                                self.error(e, msg);
+                            }
+                        }
+                    }
+                    _ => {
+                        self.check(lhs);
+                    }
+                }
+                ty
+            }
+            ExprDecl::Binop(op, e1, e2) => {
+                let lhs = self.check(e1);
+                let rhs = self.check(e2);
+                self.check_binop(e, op, lhs, rhs)
+            }
+            ExprDecl::Unop(op, operand) => {
+                let ty = self.check(operand);
+                match op.as_str() {
+                    "-" | "~" => {
+                        if !Ty::Number.accepts(&ty) {
+                            self.error(e, Msg::UnOpType(op.to_owned(), ty.to_string()));
+                        }
+                        Ty::Number
+                    }
+                    "!" => Ty::Bool,
+                    _ => Ty::Any,
+                }
+            }
+            ExprDecl::Update(op, operand, _) => {
+                let ty = self.check(operand);
+                if !Ty::Number.accepts(&ty) {
+                    self.error(e, Msg::UnOpType(op.to_owned(), ty.to_string()));
+                }
+                Ty::Number
+            }
+            ExprDecl::Call(callee, args) => {
+                let callee_ty = self.check(callee);
+                let arg_tys = args.iter().map(|arg| self.check(arg)).collect::<Vec<_>>();
+                let named = args
+                    .iter()
+                    .any(|arg| matches!(arg.decl, ExprDecl::NamedArg(_, _)));
+                match (&callee.decl, callee_ty) {
+                    (ExprDecl::Const(Constant::Builtin(name)), _) => builtin_type(name),
+                    // Signatures only have parameter types, named arguments are bound at runtime.
+                    (_, Ty::Function(Some(sig))) if named => sig.1.clone(),
+                    (_, Ty::Function(Some(sig))) => {
+                        let (params, ret) = &*sig;
+                        let compatible = params.len() == arg_tys.len()
+                            && params.iter().zip(arg_tys.iter()).all(|(p, a)| p.accepts(a));
+                        if !compatible {
+                            let name = match &callee.decl {
+                                ExprDecl::Const(Constant::Ident(name)) => name.to_owned(),
+                                _ => "<function>".to_owned(),
+                            };
+                            let msg = Msg::ParamTypesIncompatible(
+                                name,
+                                params.iter().map(|t| t.to_string()).collect(),
+                                arg_tys.iter().map(|t| t.to_string()).collect(),
+                            );
+                            self.error(e, msg);
+                        }
+                        ret.clone()
+                    }
+                    _ => Ty::Any,
+                }
+            }
+            ExprDecl::NamedArg(_, value) => self.check(value),
+            ExprDecl::Function(params, body, sig, _) => {
+                let ty = self.signature(e, sig, params.len());
+                let (param_tys, ret) = match &ty {
+                    Ty::Function(Some(sig)) => (**sig).clone(),
+                    _ => unreachable!(),
+                };
+                self.scopes.push(HashMap::new());
+                for (name, ty) in params.iter().zip(param_tys) {
+                    self.bind(name, ty, true);
+                }
+                self.returns.push(ret);
+                self.check(body);
+                self.returns.pop();
+                self.scopes.pop();
+                ty
+            }
+            ExprDecl::Return(value) => {
+                let ty = match value {
+                    Some(value) => self.check(value),
+                    None => Ty::Null,
+                };
+                if let Some(expected) = self.returns.last().cloned() {
+                    if !expected.accepts(&ty) {
+                        self.error(e, Msg::ReturnType(expected.to_string(), ty.to_string()));
+                    }
+                }
+                Ty::Any
+            }
+            ExprDecl::If(cond, then, otherwise) => {
+                self.check(cond);
+                let then = self.check(then);
+                match otherwise {
+                    Some(otherwise) if self.check(otherwise) == then => then,
+                    _ => Ty::Any,
+                }
+            }
+            ExprDecl::Match(subject, arms) => {
+                self.check(subject);
+                for arm in arms.iter() {
+                    let mut binds = vec![];
+                    pattern_binds(&arm.pattern, &mut binds);
+                    self.scopes.push(HashMap::new());
+                    for name in binds.iter() {
+                        self.bind(name, Ty::Any, false);
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.check(guard);
+                    }
+                    self.check(&arm.body);
+                    self.scopes.pop();
+                }
+                Ty::Any
+            }
+            ExprDecl::Try(body, clauses) => {
+                self.check(body);
+                for clause in clauses.iter() {
+                    if let Some(ty) = &clause.ty {
+                        self.check(ty);
+                    }
+                    self.scopes.push(HashMap::new());
+                    self.bind(&clause.name, Ty::Any, false);
+                    if let Some(guard) = &clause.guard {
+                        self.check(guard);
+                    }
+                    self.check(&clause.body);
+                    self.scopes.pop();
+                }
+                Ty::Any
+            }
+            ExprDecl::Object(_, _) => {
+                e.iter(|e| {
+                    self.check(e);
+                });
+                Ty::Object
+            }
+            _ => {
+                e.iter(|e| {
+                    self.check(e);
+                });
+                Ty::Any
+            }
+        }
+    }
+
+    fn check_binop(&mut self, e: &Expr, op: &str, lhs: Ty, rhs: Ty) -> Ty {
+        let mismatch = |this: &mut Self| {
+            let msg = Msg::BinOpType(op.to_owned(), lhs.to_string(), rhs.to_string());
+            this.error(e, msg);
+        };
+        match op {
+            "+" => match (&lhs, &rhs) {
+                (Ty::Number, Ty::Number) => Ty::Number,
+                (Ty::Str, _) | (_, Ty::Str) => Ty::Str,
+                (Ty::Null, _) | (Ty::Bool, _) | (_, Ty::Null) | (_, Ty::Bool)
+                    if lhs.is_known() && rhs.is_known() =>
+                {
+                    mismatch(self);
+                    Ty::Any
+                }
+                _ => Ty::Any,
+            },
+            "-" | "*" | "/" | "%" | "**" | "<<" | ">>" | ">>>" | "&" | "|" | "^" => {
+                if !Ty::Number.accepts(&lhs) || !Ty::Number.accepts(&rhs) {
+                    mismatch(self);
+                }
+                Ty::Number
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let comparable = match (&lhs, &rhs) {
+                    (Ty::Number, Ty::Number) | (Ty::Str, Ty::Str) => true,
+                    _ => !lhs.is_known() || !rhs.is_known(),
+                };
+                if !comparable {
+                    mismatch(self);
+                }
+                Ty::Bool
+            }
+            "==" | "!=" | "===" | "!==" | "instanceof" => Ty::Bool,
+            "&&" | "||" if lhs == Ty::Bool && rhs == Ty::Bool => Ty::Bool,
+            _ => Ty::Any,
+        }
+    }
+}
+
+fn pattern_binds(pattern: &Pattern, binds: &mut Vec<String>) {
+    match pattern {
+        Pattern::Bind(name) => binds.push(name.to_owned()),
+        Pattern::Variant(_, pats) | Pattern::Array(pats) | Pattern::Or(pats) => {
+            for pat in pats.iter() {
+                pattern_binds(pat, binds);
+            }
+        }
+        Pattern::Object(fields) => {
+            for (_, pat) in fields.iter() {
+                pattern_binds(pat, binds);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Type checks a parsed module, returning the type errors found.
+pub fn check(ast: &[P<Expr>]) -> Vec<MsgWithPos> {
+    let mut checker = Checker::new();
+    checker.check_all(ast);
+    checker.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::reader::Reader;
+
+    fn errors(src: &str) -> Vec<String> {
+        let mut ast = vec![];
+        Parser::new(Reader::from_string(src), &mut ast)
+            .parse()
+            .unwrap();
+        super::check(&ast).iter().map(|e| e.msg.message()).collect()
+    }
+
+    #[test]
+    fn unannotated_variables_take_anything() {
+        assert!(errors("var x = null\nx = 5\nx + 1").is_empty());
+        assert!(errors("var s = \"a\"\ns = 1\ns - 1").is_empty());
+        let f = "var f = function(a: number) { return a }\n";
+        assert_eq!(errors(&format!("{}f(\"a\")", f)).len(), 1);
+        assert!(errors(&format!("{}f = function(a) {{ return a }}\nf(\"a\")", f)).is_empty());
+    }
+
+    #[test]
+    fn annotations_are_checked() {
+        assert_eq!(errors("var x: number = null").len(), 1);
+        assert_eq!(errors("var x: number = 1\nx = \"a\"").len(), 1);
+        assert_eq!(errors("var x: string = \"a\"\nx - 1").len(), 1);
+    }
+}
@@ -5,6 +5,9 @@ use jazzlight::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// `match`es testing at least this many constants are compiled to a `Switch`.
+const MIN_SWITCH_KEYS: usize = 4;
+
 #[derive(Clone)]
 pub enum UOP {
     Goto(String),
@@ -15,12 +18,29 @@ pub enum UOP {
     Op(Op),
 }
 
+/// Code and positions of a compiled function, with the global holding it and its number of
+/// parameters.
+pub type FunctionCode = (Vec<UOP>, Vec<(i32, i32)>, i32, i32);
+
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
 pub enum Global {
     Var(String),
-    Func(i32, i32),
+    /// Address, argument count, parameter names, source text, doc comment and the largest
+    /// stack height the function reaches.
+    Func(i32, i32, Vec<String>, Option<String>, Option<String>, u32),
     Str(String),
     Float(u64),
+    Char(char),
+    BigInt(String, u32),
+    /// Constant keys mapped to Ints, for `Switch` and named arguments.
+    Table(Vec<(TableKey, u32)>),
+}
+
+#[derive(Clone, Debug, PartialEq, Hash, Eq)]
+pub enum TableKey {
+    Int(i64),
+    Char(char),
+    Str(String),
 }
 #[derive(Clone, Debug, PartialEq)]
 pub enum Access {
@@ -37,14 +57,31 @@ pub enum Access {
 pub struct Globals {
     pub globals: LinkedHashMap<Global, i32>,
     pub objects: LinkedHashMap<String, Vec<i32>>,
-    pub functions: Vec<(Vec<UOP>, Vec<(i32, i32)>, i32, i32)>,
+    pub functions: Vec<FunctionCode>,
     pub table: Vec<Global>,
+    /// Enum name -> variants with their field names.
+    pub enums: LinkedHashMap<String, Vec<(String, Vec<String>)>>,
+    pub errors: Vec<MsgWithPos>,
+    pub warnings: Vec<MsgWithPos>,
+    /// Set by `"use strict"` or `--strict`: assigning undeclared variables, reading missing
+    /// fields with `.` and reading array elements out of bounds throw.
+    pub strict: bool,
+    /// Set by `--contracts`: annotated parameters and return values are checked at runtime.
+    pub contracts: bool,
+    /// Set by `--opt=2`: the code of every function goes through `ssa::optimize`, and calls
+    /// to small functions are inlined.
+    pub ssa: bool,
+    /// Module-level variables, which live in the module's globals instead of locals.
+    pub module_vars: HashSet<String>,
+    /// Module-level constants with their value if it is a literal.
+    pub module_consts: HashMap<String, Option<Constant>>,
 }
 
 use crate::ast::*;
+use crate::msg::*;
 use crate::token::Position;
 use hashlink::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct Context {
     pub g: Rc<RefCell<Globals>>,
@@ -62,6 +99,48 @@ pub struct Context {
     pub used_upvars: LinkedHashMap<String, i32>,
     pub trace_info: HashMap<u32, (usize, String)>,
     pub ret_lbl: String,
+    /// Locals holding the exceptions of the enclosing catch clauses, for `throw;`.
+    pub catch_exc: Vec<i32>,
+    /// `const` locals with their value if it is a literal, a name only refers to the
+    /// constant while it maps to the same slot.
+    pub consts: HashMap<String, (i32, Option<Constant>)>,
+    /// Constants of the enclosing functions, those of outer ones included unless a function
+    /// in between declares the same name.
+    pub env_consts: HashMap<String, Option<Constant>>,
+    /// Slots of the function-scoped `var`s, allocated when the function starts.
+    pub hoisted: HashMap<String, i32>,
+    /// Description and annotated type of the return value, checked in contracts mode.
+    pub ret_contract: Option<(String, String)>,
+    /// Declared variables and parameters by name and slot, with where they were declared,
+    /// whether they are parameters and whether they were read, for unused warnings.
+    pub bindings: LinkedHashMap<(String, i32), (Position, bool, bool)>,
+    /// Constants of the enclosing function that were substituted here.
+    pub env_const_reads: HashSet<String>,
+    /// Labels of the function by name, with their internal label and, once defined, the
+    /// scopes they are in and their position.
+    pub user_labels: HashMap<String, (String, Option<(Vec<usize>, Position)>)>,
+    /// `goto`s of the function with the scopes they are in, validated at the end. `goto`s
+    /// inside a try body jump through a stub that first drops the catch blocks they leave.
+    pub gotos: Vec<(String, Vec<usize>, Position, Option<String>)>,
+    /// Loop bodies and try bodies enclosing the current expression, which can't be entered
+    /// with `goto`.
+    pub scopes: Vec<usize>,
+    pub scope_count: usize,
+    /// Scopes in `scopes` that are try bodies.
+    pub try_scopes: HashSet<usize>,
+    /// Length of `scopes` outside each enclosing loop, parallel to `breaks`.
+    pub loop_scopes: Vec<usize>,
+    /// Whether this is the module's top-level code, where `var`s and declarations outside
+    /// of blocks are module-level variables.
+    pub module: bool,
+    pub block_depth: usize,
+    /// Whether this is the body of a generator, where `yield` suspends it.
+    pub generator: bool,
+    /// In a function using `defer`, the locals holding the array of deferred closures and
+    /// the return value, and the label where `return` jumps to run them.
+    pub defers: Option<(i32, i32, String)>,
+    /// Scopes that are `using` bodies, with the local holding the value to dispose of.
+    pub usings: HashMap<usize, i32>,
 }
 impl Context {
     pub fn new_named_label(&mut self) {}
@@ -75,38 +154,38 @@ impl Context {
                 _ => (),
             }
         }
-        self.ops
-            .iter()
-            .map(|i| match *i {
-                UOP::Op(ref op) => op.clone(),
-                UOP::PAddr(ref lbl) => Op::CatchPush(self.labels.get(lbl).unwrap().unwrap() as _),
-                UOP::Goto(ref lbl) => Op::Jump(self.labels.get(lbl).unwrap().unwrap() as u32),
-                UOP::GotoF(ref lbl) => Op::JumpIfNot(self.labels.get(lbl).unwrap().unwrap() as u32),
-                UOP::GotoT(ref lbl) => Op::JumpIf(self.labels.get(lbl).unwrap().unwrap() as u32),
-                _ => Op::Nop,
-            })
-            .collect::<Vec<Op>>()
+        resolve_labels(&self.ops, &self.labels)
     }
 
     pub fn pos(&self) -> usize {
         self.ops.len()
     }
 
+    /// Appends an instruction, recording the line of the expression being compiled.
+    fn push(&mut self, op: UOP) {
+        let pos = match &self.cur_pos {
+            Some(pos) => (pos.line as i32, pos.column as i32),
+            None => (0, 0),
+        };
+        self.ops.push(op);
+        self.pos.push(pos);
+    }
+
     pub fn write(&mut self, op: Op) {
-        self.ops.push(UOP::Op(op));
+        self.push(UOP::Op(op));
     }
     pub fn emit_paddr(&mut self, t: &str) {
-        self.ops.push(UOP::PAddr(t.to_owned()));
+        self.push(UOP::PAddr(t.to_owned()));
     }
     pub fn emit_goto(&mut self, to: &str) {
-        self.ops.push(UOP::Goto(to.to_owned()));
+        self.push(UOP::Goto(to.to_owned()));
     }
     pub fn emit_gotof(&mut self, to: &str) {
-        self.ops.push(UOP::GotoF(to.to_owned()));
+        self.push(UOP::GotoF(to.to_owned()));
     }
 
     pub fn emit_gotot(&mut self, to: &str) {
-        self.ops.push(UOP::GotoT(to.to_owned()));
+        self.push(UOP::GotoT(to.to_owned()));
     }
 
     pub fn new_empty_label(&mut self) -> String {
@@ -116,7 +195,7 @@ impl Context {
     }
 
     pub fn label_here(&mut self, label: &str) {
-        self.ops.push(UOP::Label(label.to_owned()));
+        self.push(UOP::Label(label.to_owned()));
         //*self.labels.get_mut(label).unwrap() = Some(self.ops.len());
     }
 
@@ -148,22 +227,34 @@ impl Context {
                 let pos = self.global(&Global::Float(f.to_bits()));
                 self.write(Op::LoadGlobal(pos as _));
             }
+            Constant::Char(c) => {
+                let pos = self.global(&Global::Char(*c));
+                self.write(Op::LoadGlobal(pos as _));
+            }
             Constant::Str(s) => {
                 let pos = self.global(&Global::Str(s.to_owned()));
                 self.write(Op::LoadGlobal(pos as _));
             }
+            Constant::BigInt(digits, radix) => {
+                let pos = self.global(&Global::BigInt(digits.to_owned(), *radix));
+                self.write(Op::LoadGlobal(pos as _));
+            }
             Constant::Ident(s) => {
                 let s: &str = s;
-                if self.locals.contains_key(s) {
+                if let Some(value) = self.const_value(s) {
+                    self.mark_read(s);
+                    self.compile_const(&value);
+                } else if self.locals.contains_key(s) {
                     let i = *self.locals.get(s).unwrap();
+                    self.mark_read(s);
                     self.write(Op::LoadLocal(i as u16));
                 } else if self.env.contains_key(s) {
                     self.nenv += 1;
                     let pos = if !self.used_upvars.contains_key(s) {
                         let pos = self.used_upvars.len();
-
+                        let pos = self.operand(pos, "captured variables in function");
                         self.used_upvars.insert(s.to_owned(), pos as _);
-                        pos as u16
+                        pos
                     } else {
                         *self.used_upvars.get(s).unwrap() as u16
                     };
@@ -188,6 +279,7 @@ impl Context {
             "-" => self.write(Sub),
             "/" => self.write(Div),
             "*" => self.write(Mul),
+            "**" => self.write(Pow),
             "%" => self.write(Mod),
             "<<" => self.write(Shl),
             ">>" => self.write(Shr),
@@ -196,6 +288,11 @@ impl Context {
             "^" => self.write(Xor),
             "==" => self.write(Eq),
             "!=" => self.write(Neq),
+            "===" => self.write(StrictEq),
+            "!==" => {
+                self.write(StrictEq);
+                self.write(Not);
+            }
             ">" => self.write(Gt),
             ">=" => self.write(Gte),
             "<" => self.write(Lt),
@@ -237,6 +334,24 @@ impl Context {
         }
     }
 
+    /// Loads a field read with `.`, which must exist in strict mode.
+    fn write_field_load(&mut self) {
+        if self.g.borrow().strict {
+            self.write(Op::LoadStrict);
+        } else {
+            self.write(Op::Load);
+        }
+    }
+
+    /// Loads an element read with `[]`, whose array index must be in bounds in strict mode.
+    fn write_element_load(&mut self) {
+        if self.g.borrow().strict {
+            self.write(Op::LoadElementStrict);
+        } else {
+            self.write(Op::Load);
+        }
+    }
+
     pub fn access_get(&mut self, acc: Access) {
         match acc {
             Access::Env(i) => self.write(Op::LoadEnv(i as _)),
@@ -246,14 +361,14 @@ impl Context {
                 let gid = self.global(&Global::Str(f));
                 self.write(Op::LoadGlobal(gid as _));
                 self.compile(&e, false);
-                self.write(Op::Load)
+                self.write_field_load()
             }
             Access::Index(_) => unimplemented!(),
             Access::This => self.write(Op::LoadThis),
             Access::Array(ea, ei) => {
                 self.compile(&ei, false);
                 self.compile(&ea, false);
-                self.write(Op::Load);
+                self.write_element_load();
             }
         }
     }
@@ -262,11 +377,7 @@ impl Context {
         match acc {
             Access::Env(n) => self.write(Op::StoreEnv(n as _)),
             Access::Stack(l) => self.write(Op::StoreLocal(l as _)),
-            Access::Global(_) =>
-            /*self.write(Op::StoreGlobal(g as u32)),*/
-            {
-                unimplemented!()
-            }
+            Access::Global(g) => self.write(Op::StoreGlobal(g as u32)),
             Access::Field(e, f) => {
                 let gid = self.global(&Global::Str(f.to_owned()));
                 self.write(Op::LoadGlobal(gid as _));
@@ -283,6 +394,29 @@ impl Context {
         }
     }
     pub fn compile(&mut self, e: &P<Expr>, tail: bool) {
+        let pos = self.cur_pos.replace(e.pos.clone());
+        self.compile_expr(e, tail);
+        self.cur_pos = pos;
+    }
+
+    /// Compiles `e` for its effects, without leaving its value on the stack.
+    fn compile_discard(&mut self, e: &P<Expr>) {
+        self.compile(e, false);
+        if diverges(e) {
+            return;
+        }
+        match self.ops.last() {
+            Some(UOP::Op(Op::LoadNull)) => {
+                self.ops.pop();
+                self.pos.pop();
+            }
+            _ => self.write(Op::Pop(1)),
+        }
+    }
+
+    /// Compiles `e`, leaving its value on the stack unless control doesn't continue after
+    /// it. With `tail`, a call that is the last thing `e` does can be a tail call.
+    fn compile_expr(&mut self, e: &P<Expr>, tail: bool) {
         match &e.decl {
             ExprDecl::Break(e) => {
                 if e.is_some() {
@@ -292,6 +426,7 @@ impl Context {
                     self.write(Op::LoadNull);
                 }
                 let br = self.breaks.last().expect("break in wrong context").clone();
+                self.leave_tries(*self.loop_scopes.last().unwrap());
                 self.emit_goto(&br);
             }
             ExprDecl::Continue => {
@@ -300,6 +435,7 @@ impl Context {
                     .last()
                     .expect("continue in wrong context")
                     .clone();
+                self.leave_tries(*self.loop_scopes.last().unwrap());
                 self.emit_goto(&c);
             }
             ExprDecl::Const(c) => self.compile_const(c),
@@ -308,15 +444,34 @@ impl Context {
                     self.write(Op::LoadNull);
                 } else {
                     let locals = self.locals.clone();
+                    let consts = self.consts.clone();
                     //let stack = self.stack;
-                    for el in v.iter() {
-                        self.compile(el, tail);
+                    self.block_depth += 1;
+                    let mut diverged = false;
+                    for (i, el) in v.iter().enumerate() {
+                        if diverged && !is_label(el) {
+                            let pos = self.cur_pos.replace(el.pos.clone());
+                            self.report(Msg::UnreachableCode, true);
+                            self.cur_pos = pos;
+                        }
+                        // The block's value is the one of its last expression.
+                        if i + 1 == v.len() {
+                            self.compile(el, tail);
+                        } else {
+                            self.compile_discard(el);
+                        }
+                        diverged = matches!(
+                            el.decl,
+                            ExprDecl::Return(_)
+                                | ExprDecl::Throw(_)
+                                | ExprDecl::Rethrow
+                                | ExprDecl::Break(_)
+                                | ExprDecl::Continue
+                        );
                     }
-
-                    /*if stack < self.stack {
-                        self.write(Op::Pop((self.stack - stack) as u32)); // clear stack from values and locals
-                    }*/
+                    self.block_depth -= 1;
                     self.locals = locals;
+                    self.consts = consts;
                 }
             }
             ExprDecl::Paren(e) => self.compile(e, tail),
@@ -327,39 +482,83 @@ impl Context {
                 let gid = self.global(&Global::Str(f.to_owned()));
                 self.write(Op::LoadGlobal(gid as _));
                 self.compile(e, false);
-                self.write(Op::Load);
+                self.write_field_load();
             }
             ExprDecl::Array(ea, ei) => {
                 self.compile(ei, false);
                 self.compile(ea, false);
-                self.write(Op::Load);
+                self.write_element_load();
             }
-            ExprDecl::Var(_, name, init) => {
+            ExprDecl::Var(kind, name, _, init) => {
                 match init {
                     Some(e) => match &e.decl {
-                        ExprDecl::Function(args, body) => {
-                            self.compile_function(args, body, Some(name))
+                        ExprDecl::Function(args, body, sig, source) => {
+                            self.compile_function(args, body, sig, Some(name), source.as_deref())
                         }
                         _ => self.compile(e, false),
                     },
                     None => self.write(Op::LoadNull),
                 }
-                let id = self.locals.len() as u16;
-                self.locals.insert(name.to_owned(), id as i32);
-
-                self.write(Op::StoreLocal(id));
+                if self.module && (*kind == VarKind::Var || self.block_depth == 1) {
+                    let gid = self.global(&Global::Var(name.to_owned()));
+                    self.write(Op::StoreGlobal(gid as _));
+                    self.write(Op::LoadNull);
+                    return;
+                }
+                let id = match (kind, self.hoisted.get(name)) {
+                    (VarKind::Var, Some(&id)) => {
+                        self.locals.insert(name.to_owned(), id);
+                        self.consts.remove(name);
+                        id
+                    }
+                    _ => self.declare_local(name, *kind == VarKind::Const),
+                };
+                if *kind == VarKind::Const {
+                    if let Some(value) = init.as_ref().and_then(literal) {
+                        self.consts.insert(name.to_owned(), (id, Some(value)));
+                    }
+                }
+                self.track_binding(name, id, false);
+                self.write(Op::StoreLocal(id as _));
+                self.write(Op::LoadNull);
             }
 
             ExprDecl::Assign(e1, e2) => {
+                if let ExprDecl::Const(Constant::Ident(name)) = &e1.decl {
+                    if self.is_const(name) {
+                        self.report(Msg::ConstReassigned(name.to_owned()), false);
+                    }
+                }
                 let a = self.compile_access(e1);
                 self.compile(e2, false);
-                self.access_set(a);
+                match (a, &e1.decl) {
+                    (Access::Global(_), ExprDecl::Const(Constant::Ident(name)))
+                        if self.g.borrow().strict
+                            && !self.g.borrow().module_vars.contains(name) =>
+                    {
+                        let msg = format!("assignment to undeclared variable '{}'", name);
+                        self.compile_const(&Constant::Str(msg));
+                        self.write(Op::Throw);
+                    }
+                    (a, _) => {
+                        self.access_set(a);
+                        self.write(Op::LoadNull);
+                    }
+                }
             }
             ExprDecl::Binop(op, e1, e2) => {
                 self.compile_binop(op, e1, e2, tail);
             }
-            ExprDecl::Function(params, e) => {
-                self.compile_function(params, e, None);
+            ExprDecl::Function(params, e, sig, source) => {
+                self.compile_function(params, e, sig, None, source.as_deref());
+            }
+            ExprDecl::Yield(e) => {
+                if !self.generator {
+                    self.report(Msg::YieldOutsideFunction, false);
+                }
+                self.compile(e, false);
+                self.write(Op::Yield);
+                self.write(Op::LoadNull);
             }
             ExprDecl::Return(e) => {
                 match e {
@@ -368,64 +567,217 @@ impl Context {
                 }
 
                 //let _ = self.ret_lbl.clone();
-                self.write(Op::Ret);
+                self.compile_return();
                 //self.stack = stack;
             }
+            ExprDecl::Using(name, value, body) => {
+                let locals = self.locals.clone();
+                let consts = self.consts.clone();
+                let handler = self.new_empty_label();
+                let end = self.new_empty_label();
+                self.compile(value, false);
+                let id = self.declare_local(name, false);
+                self.track_binding(name, id, false);
+                self.mark_read(name);
+                self.write(Op::StoreLocal(id as _));
+                self.emit_paddr(&handler);
+                self.enter_scope();
+                self.try_scopes.insert(self.scope_count);
+                self.usings.insert(self.scope_count, id);
+                self.compile(body, false);
+                self.leave_tries(self.scopes.len() - 1);
+                self.scopes.pop();
+                self.emit_goto(&end);
+                self.label_here(&handler);
+                let exc = self.new_hidden_local();
+                self.write(Op::StoreLocal(exc as _));
+                self.dispose(id);
+                self.write(Op::LoadLocal(exc as _));
+                self.write(Op::Throw);
+                self.label_here(&end);
+                self.locals = locals;
+                self.consts = consts;
+            }
+            ExprDecl::Defer(e) => match self.defers.clone() {
+                Some((defers, _, _)) => {
+                    self.compile_function(&[], e, &Signature::default(), None, None);
+                    self.write(Op::LoadLocal(defers as _));
+                    self.compile_const(&Constant::Builtin("apush".to_owned()));
+                    self.write(Op::Call(2));
+                }
+                None => self.report(Msg::DeferOutsideFunction, false),
+            },
+            ExprDecl::Delete(object, key) => {
+                self.compile(key, false);
+                self.compile(object, false);
+                self.write(Op::Delete);
+            }
             ExprDecl::While(cond, body) => {
                 let start = self.new_empty_label();
+                let exit = self.new_empty_label();
                 let end = self.new_empty_label();
                 self.breaks.push(end.clone());
                 self.continues.push(start.clone());
+                self.loop_scopes.push(self.scopes.len());
                 self.label_here(&start);
                 self.compile(cond, false);
-                self.emit_gotof(&end);
-                self.compile(body, false);
+                self.emit_gotof(&exit);
+                self.enter_scope();
+                self.compile_discard(body);
+                self.scopes.pop();
                 self.emit_goto(&start);
+                // A loop that isn't left with `break value` is null.
+                self.label_here(&exit);
+                self.write(Op::LoadNull);
                 self.label_here(&end);
                 self.breaks.pop();
                 self.continues.pop();
+                self.loop_scopes.pop();
             }
-            ExprDecl::Switch(value, with, default_) => {
-                let orl = self.new_empty_label();
+            ExprDecl::ForIn(name, collection, body) => {
+                let start = self.new_empty_label();
+                let exit = self.new_empty_label();
                 let end = self.new_empty_label();
-
-                for (cond, expr) in with.iter() {
-                    let l1 = self.new_empty_label();
-                    self.compile(value, false);
-                    self.compile(cond, false);
-                    self.write(Op::Eq);
-                    self.emit_gotof(&l1);
-                    self.compile(&expr, tail);
-                    self.emit_goto(&end);
-                    self.label_here(&l1);
+                let locals = self.locals.clone();
+                let consts = self.consts.clone();
+                self.compile(collection, false);
+                self.compile_const(&Constant::Builtin("iter".to_owned()));
+                self.write(Op::Call(1));
+                let it = self.new_hidden_local();
+                self.write(Op::StoreLocal(it as _));
+                let step = self.new_hidden_local();
+                let id = self.declare_local(name, false);
+                self.track_binding(name, id, false);
+                self.breaks.push(end.clone());
+                self.continues.push(start.clone());
+                self.loop_scopes.push(self.scopes.len());
+                self.label_here(&start);
+                self.write(Op::LoadLocal(it as _));
+                self.compile_const(&Constant::Builtin("iter_next".to_owned()));
+                self.write(Op::Call(1));
+                self.write(Op::StoreLocal(step as _));
+                self.write(Op::LoadLocal(step as _));
+                self.write(Op::IsNotNull);
+                self.emit_gotof(&exit);
+                self.write(Op::LoadInt(0));
+                self.write(Op::LoadLocal(step as _));
+                self.write(Op::Load);
+                self.write(Op::StoreLocal(id as _));
+                self.enter_scope();
+                self.compile_discard(body);
+                self.scopes.pop();
+                self.emit_goto(&start);
+                self.label_here(&exit);
+                self.write(Op::LoadNull);
+                self.label_here(&end);
+                self.breaks.pop();
+                self.continues.pop();
+                self.loop_scopes.pop();
+                self.locals = locals;
+                self.consts = consts;
+            }
+            ExprDecl::Match(value, arms) => {
+                let end = self.new_empty_label();
+                let locals = self.locals.clone();
+                // The subject is evaluated once and every pattern test reads it from a local.
+                self.compile(value, false);
+                let subject = self.new_hidden_local();
+                self.write(Op::StoreLocal(subject as _));
+                self.check_exhaustive(arms);
+                if let Some(keys) = self.switch_keys(arms) {
+                    self.compile_switch(arms, keys, subject, &end, tail);
+                } else {
+                    for arm in arms.iter() {
+                        let arm_locals = self.locals.clone();
+                        let next = self.new_empty_label();
+                        let mut binds = HashMap::new();
+                        self.compile_pattern(&arm.pattern, subject, &next, &mut binds);
+                        if let Some(guard) = &arm.guard {
+                            self.compile(guard, false);
+                            self.emit_gotof(&next);
+                        }
+                        self.compile(&arm.body, tail);
+                        self.emit_goto(&end);
+                        self.label_here(&next);
+                        self.locals = arm_locals;
+                    }
+                    self.write(Op::LoadNull);
                 }
-                if default_.is_some() {
-                    self.emit_goto(&orl);
+                self.label_here(&end);
+                self.locals = locals;
+            }
+            ExprDecl::Propagate(e) => {
+                let done = self.new_empty_label();
+                let ret = self.new_empty_label();
+                let unwrap = self.new_empty_label();
+                let locals = self.locals.clone();
+                self.compile(e, false);
+                let value = self.new_hidden_local();
+                self.write(Op::StoreLocal(value as _));
+                let gid = self.global(&Global::Str("__tag".to_owned()));
+                self.write(Op::LoadGlobal(gid as _));
+                self.write(Op::LoadLocal(value as _));
+                self.write(Op::Load);
+                let tag = self.new_hidden_local();
+                self.write(Op::StoreLocal(tag as _));
+                for (variant, target) in [
+                    ("Err", &ret),
+                    ("None", &ret),
+                    ("Ok", &unwrap),
+                    ("Some", &unwrap),
+                ]
+                .iter()
+                {
+                    self.compile_const(&Constant::Str(variant.to_string()));
+                    self.write(Op::LoadLocal(tag as _));
+                    self.write(Op::Eq);
+                    self.emit_gotot(target);
                 }
-                self.label_here(&orl);
-                if default_.is_some() {
-                    self.compile(&default_.clone().unwrap(), false);
-                    self.emit_goto(&end);
+                // Anything that is not a Result or Option passes through unchanged.
+                self.write(Op::LoadLocal(value as _));
+                self.emit_goto(&done);
+                self.label_here(&ret);
+                self.write(Op::LoadLocal(value as _));
+                self.compile_return();
+                self.label_here(&unwrap);
+                let gid = self.global(&Global::Str("value".to_owned()));
+                self.write(Op::LoadGlobal(gid as _));
+                self.write(Op::LoadLocal(value as _));
+                self.write(Op::Load);
+                self.label_here(&done);
+                self.locals = locals;
+            }
+            ExprDecl::Enum(name, variants) => {
+                self.g
+                    .borrow_mut()
+                    .enums
+                    .insert(name.to_owned(), variants.clone());
+                for decl in enum_decls(name, variants, &e.pos).iter() {
+                    self.compile_discard(decl);
                 }
-                self.label_here(&end);
+                self.write(Op::LoadNull);
             }
 
             ExprDecl::If(e, e1, e2) => {
                 //let stack = self.stack;
 
                 let lbl_false = self.new_empty_label();
+                let end = self.new_empty_label();
                 self.compile(&e, false);
                 self.emit_gotof(&lbl_false);
                 self.compile(e1, tail);
+                self.emit_goto(&end);
                 self.label_here(&lbl_false);
-                if e2.is_some() {
-                    let e2 = e2.clone().unwrap();
-                    self.compile(&e2, tail);
+                match e2 {
+                    Some(e2) => self.compile(e2, tail),
+                    None => self.write(Op::LoadNull),
                 }
+                self.label_here(&end);
             }
             ExprDecl::Call(e, el) => {
+                let names = self.named_args(el);
                 match &e.decl {
-                    ExprDecl::Const(Constant::Builtin(name)) => {
+                    ExprDecl::Const(Constant::Builtin(name)) if names.is_none() => {
                         let builtin: &str = name;
                         match builtin {
                             "new" => {
@@ -438,6 +790,17 @@ impl Context {
                                 self.write(Op::Hash);
                                 return;
                             }
+                            "array" if el.len() <= u16::MAX as usize => {
+                                for e in el.iter() {
+                                    self.compile(e, false);
+                                }
+                                self.write(Op::MakeArray(el.len() as _));
+                                return;
+                            }
+                            "debug_locals" if el.is_empty() => {
+                                self.compile_debug_locals();
+                                return;
+                            }
                             /*"typeof" => {
                                 self.compile(&el[0]);
                                 self.write(Op::TypeOf);
@@ -447,41 +810,56 @@ impl Context {
                         }
                     }
                     ExprDecl::Field(e, f) => {
-                        for e in el.iter().rev() {
-                            self.compile(e, false);
-                        }
+                        self.compile_args(el);
                         self.compile(e, false);
                         let gid = self.global(&Global::Str(f.to_owned()));
-                        self.write(Op::LoadGlobal(gid as _));
-                        self.compile(e, false);
-                        self.write(Op::Load);
-                        self.write(Op::ObjCall(el.len() as u16));
+                        let argc = self.operand(el.len(), "arguments in call");
+                        match names {
+                            Some(names) => self.write(Op::InvokeNamed(gid as _, argc, names as _)),
+                            None => self.write(Op::InvokeMethod(gid as _, argc)),
+                        }
                         return;
                     }
                     _ => (),
                 }
-                for x in el.iter().rev() {
-                    self.compile(x, false);
-                }
+                self.compile_args(el);
                 self.compile(e, false);
-                if !tail {
-                    self.write(Op::Call(el.len() as _));
-                } else {
-                    self.write(Op::TailCall(el.len() as _));
+                let argc = self.operand(el.len(), "arguments in call");
+                match names {
+                    Some(names) => self.write(Op::CallNamed(argc, names as _)),
+                    None if !tail => self.write(Op::Call(argc)),
+                    None => self.write(Op::TailCall(argc)),
                 }
             }
-            ExprDecl::Label(label) => {
-                self.labels.insert(label.to_owned(), Some(self.pos()));
+            ExprDecl::Label(name) => {
+                let label = self.user_label(name);
+                let pos = e.pos.clone();
+                match self.user_labels.get_mut(name) {
+                    Some((_, Some(_))) => self.report(Msg::DuplicateLabel(name.to_owned()), false),
+                    Some((_, def)) => *def = Some((self.scopes.clone(), pos)),
+                    None => unreachable!(),
+                }
+                self.label_here(&label);
+                self.write(Op::LoadNull);
             }
-            ExprDecl::Goto(label) => {
-                self.emit_goto(label);
+            ExprDecl::Goto(name) => {
+                let label = self.user_label(name);
+                let stub = match self.tries_since(0) {
+                    0 => None,
+                    _ => Some(self.new_empty_label()),
+                };
+                self.emit_goto(stub.as_ref().unwrap_or(&label));
+                self.gotos
+                    .push((name.to_owned(), self.scopes.clone(), e.pos.clone(), stub));
             }
+            ExprDecl::Update(op, target, prefix) => self.compile_update(op, target, *prefix),
             ExprDecl::Unop(op, e) => {
-                self.compile(e, tail);
+                self.compile(e, false);
                 let op: &str = op;
                 match op {
                     "-" => self.write(Op::Neg),
                     "!" => self.write(Op::Not),
+                    "~" => self.write(Op::BitNot),
                     _ => (),
                 }
             }
@@ -489,27 +867,935 @@ impl Context {
                 self.compile(expr, false);
                 self.write(Op::Throw);
             }
-            ExprDecl::Try(expr, name, catch) => {
+            ExprDecl::Rethrow => match self.catch_exc.last() {
+                Some(&exc) => {
+                    self.write(Op::LoadLocal(exc as _));
+                    self.write(Op::Throw);
+                }
+                None => self.report(Msg::RethrowOutsideCatch, false),
+            },
+            ExprDecl::Try(expr, clauses) => {
                 let catch_lbl = self.new_empty_label();
                 let end_lbl = self.new_empty_label();
                 self.emit_paddr(&catch_lbl);
+                self.enter_scope();
+                self.try_scopes.insert(self.scope_count);
                 self.compile(expr, false);
+                self.scopes.pop();
+                self.write(Op::CatchPop);
                 self.emit_goto(&end_lbl);
                 self.label_here(&catch_lbl);
                 let locals = self.locals.clone();
-                let id = self.locals.len() as _;
-                self.locals.insert(name.to_owned(), id);
-                self.write(Op::StoreLocal(id as _));
-                self.compile(catch, tail);
+                let exc = self.new_hidden_local();
+                self.write(Op::StoreLocal(exc as _));
+                self.catch_exc.push(exc);
+                for clause in clauses.iter() {
+                    let next = self.new_empty_label();
+                    let clause_locals = self.locals.clone();
+                    if let Some(ty) = &clause.ty {
+                        self.compile(ty, false);
+                        self.write(Op::LoadLocal(exc as _));
+                        self.compile_const(&Constant::Builtin("instanceof".to_owned()));
+                        self.write(Op::Call(2));
+                        self.emit_gotof(&next);
+                    }
+                    let id = self.declare_local(&clause.name, false);
+                    self.write(Op::LoadLocal(exc as _));
+                    self.write(Op::StoreLocal(id as _));
+                    if let Some(guard) = &clause.guard {
+                        self.compile(guard, false);
+                        self.emit_gotof(&next);
+                    }
+                    self.enter_scope();
+                    self.compile(&clause.body, tail);
+                    self.scopes.pop();
+                    self.emit_goto(&end_lbl);
+                    self.label_here(&next);
+                    self.locals = clause_locals;
+                }
+                self.catch_exc.pop();
+                // No handler matched: rethrow to the enclosing try.
+                self.write(Op::LoadLocal(exc as _));
+                self.write(Op::Throw);
                 self.locals = locals;
                 self.label_here(&end_lbl);
             }
+            ExprDecl::Object(proto, fields) => {
+                let locals = self.locals.clone();
+                match proto {
+                    Some(proto) => self.compile(proto, false),
+                    None => self.write(Op::LoadNull),
+                }
+                self.write(Op::New);
+                let obj = self.new_hidden_local();
+                self.write(Op::StoreLocal(obj as _));
+                for (key, value) in fields.iter() {
+                    self.compile(value, false);
+                    let gid = self.global(&Global::Str(key.to_owned()));
+                    self.write(Op::LoadGlobal(gid as _));
+                    self.write(Op::LoadLocal(obj as _));
+                    self.write(Op::Store);
+                }
+                self.write(Op::LoadLocal(obj as _));
+                self.locals = locals;
+            }
             v => panic!("{:?}", v),
         }
     }
 
+    /// Records a diagnostic at the position of the expression being compiled.
+    fn report(&mut self, msg: Msg, warning: bool) {
+        let pos = self.cur_pos.clone().expect("no position for diagnostic");
+        let msg = MsgWithPos::new(pos.file.to_string(), pos, msg);
+        let mut g = self.g.borrow_mut();
+        if warning {
+            g.warnings.push(msg);
+        } else {
+            g.errors.push(msg);
+        }
+    }
+
+    /// Returns the enum name and field names of `variant`.
+    fn find_variant(&self, variant: &str) -> Option<(String, Vec<String>)> {
+        for (name, variants) in self.g.borrow().enums.iter() {
+            for (v, fields) in variants.iter() {
+                if v == variant {
+                    return Some((name.clone(), fields.clone()));
+                }
+            }
+        }
+        None
+    }
+
+    /// Keys tested by each arm if the match can be compiled to a `Switch`: all arms test Int,
+    /// char or string constants and have no guard, except for a catch-all last arm.
+    fn switch_keys(&self, arms: &[MatchArm]) -> Option<Vec<Vec<TableKey>>> {
+        fn pattern_keys(pat: &Pattern, keys: &mut Vec<TableKey>) -> Option<()> {
+            match pat {
+                Pattern::Const(Constant::Int(x)) => keys.push(TableKey::Int(*x)),
+                Pattern::Const(Constant::Char(ch)) => keys.push(TableKey::Char(*ch)),
+                Pattern::Const(Constant::Str(s)) => keys.push(TableKey::Str(s.to_owned())),
+                Pattern::Or(pats) => {
+                    for pat in pats.iter() {
+                        pattern_keys(pat, keys)?;
+                    }
+                }
+                _ => return None,
+            }
+            Some(())
+        }
+
+        let mut cases = vec![];
+        for (i, arm) in arms.iter().enumerate() {
+            if arm.guard.is_some() {
+                return None;
+            }
+            match &arm.pattern {
+                Pattern::Wildcard if i + 1 == arms.len() => (),
+                Pattern::Bind(name) if i + 1 == arms.len() && self.find_variant(name).is_none() => {
+                }
+                pat => {
+                    let mut keys = vec![];
+                    pattern_keys(pat, &mut keys)?;
+                    cases.push(keys);
+                }
+            }
+        }
+        if cases.iter().map(|keys| keys.len()).sum::<usize>() < MIN_SWITCH_KEYS {
+            return None;
+        }
+        Some(cases)
+    }
+
+    /// Compiles a match whose arms test the constants `cases`, see `switch_keys`. A key
+    /// repeated in a later arm belongs to the first one, like in a chain of tests.
+    fn compile_switch(
+        &mut self,
+        arms: &[MatchArm],
+        cases: Vec<Vec<TableKey>>,
+        subject: i32,
+        end: &str,
+        tail: bool,
+    ) {
+        let mut table = vec![];
+        let mut seen = HashSet::new();
+        for (case, keys) in cases.iter().enumerate() {
+            for key in keys.iter() {
+                if seen.insert(key.clone()) {
+                    table.push((key.clone(), case as u32));
+                }
+            }
+        }
+        let table = self.global(&Global::Table(table));
+        let labels = arms
+            .iter()
+            .map(|_| self.new_empty_label())
+            .collect::<Vec<_>>();
+        let default = if cases.len() < arms.len() {
+            labels[cases.len()].clone()
+        } else {
+            self.new_empty_label()
+        };
+        self.write(Op::LoadLocal(subject as _));
+        self.write(Op::Switch(table as _, cases.len() as _));
+        for label in labels[..cases.len()].iter() {
+            self.emit_goto(label);
+        }
+        self.emit_goto(&default);
+        for (i, (arm, label)) in arms.iter().zip(labels.iter()).enumerate() {
+            let arm_locals = self.locals.clone();
+            self.label_here(label);
+            // The table already tested the constant arms. Only the catch-all arm binds
+            // anything, and its pattern can't fail.
+            if i >= cases.len() {
+                self.compile_pattern(&arm.pattern, subject, end, &mut HashMap::new());
+            }
+            self.compile(&arm.body, tail);
+            self.emit_goto(end);
+            self.locals = arm_locals;
+        }
+        if cases.len() == arms.len() {
+            self.label_here(&default);
+            self.write(Op::LoadNull);
+        }
+    }
+
+    /// Warns when a match over enum variants without a catch-all arm misses some variants.
+    fn check_exhaustive(&mut self, arms: &[MatchArm]) {
+        let mut enum_name = None;
+        let mut covered = vec![];
+        for arm in arms.iter().filter(|arm| arm.guard.is_none()) {
+            let alts = match &arm.pattern {
+                Pattern::Or(alts) => alts.clone(),
+                pat => vec![pat.clone()],
+            };
+            for alt in alts.iter() {
+                let (variant, pats) = match alt {
+                    Pattern::Wildcard => return,
+                    Pattern::Bind(name) if self.find_variant(name).is_none() => return,
+                    Pattern::Equal(name) if self.find_variant(name).is_none() => continue,
+                    Pattern::Bind(name) | Pattern::Equal(name) => (name, vec![]),
+                    Pattern::Variant(name, pats) => (name, pats.clone()),
+                    _ => continue,
+                };
+                let irrefutable = pats.iter().all(|pat| match pat {
+                    Pattern::Wildcard => true,
+                    Pattern::Bind(name) => self.find_variant(name).is_none(),
+                    _ => false,
+                });
+                if let Some((name, _)) = self.find_variant(variant) {
+                    enum_name = Some(name);
+                    if irrefutable {
+                        covered.push(variant.to_owned());
+                    }
+                }
+            }
+        }
+        let enum_name = match enum_name {
+            Some(name) => name,
+            None => return,
+        };
+        let missing = self.g.borrow().enums[&enum_name]
+            .iter()
+            .map(|(v, _)| v.clone())
+            .filter(|v| !covered.contains(v))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.report(Msg::NonExhaustiveMatch(missing), true);
+        }
+    }
+
+    /// Call arguments are evaluated right to left, named ones like positional ones.
+    fn compile_args(&mut self, args: &[P<Expr>]) {
+        for arg in args.iter().rev() {
+            match &arg.decl {
+                ExprDecl::NamedArg(_, value) => self.compile(value, false),
+                _ => self.compile(arg, false),
+            }
+        }
+    }
+
+    /// Global of the table mapping the names of a call's named arguments to their
+    /// positions, if it has any.
+    fn named_args(&mut self, args: &[P<Expr>]) -> Option<i32> {
+        let names = args
+            .iter()
+            .enumerate()
+            .filter_map(|(i, arg)| match &arg.decl {
+                ExprDecl::NamedArg(name, _) => Some((TableKey::Str(name.to_owned()), i as u32)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return None;
+        }
+        Some(self.global(&Global::Table(names)))
+    }
+
+    /// Converts a local, environment slot or count to a 16-bit instruction operand.
+    fn operand(&mut self, n: usize, what: &str) -> u16 {
+        match n {
+            n if n <= u16::MAX as usize => n as u16,
+            _ => {
+                self.report(Msg::OperandLimit(what.to_owned(), u16::MAX as usize), false);
+                u16::MAX
+            }
+        }
+    }
+
+    /// Declares a local in the current scope, `constant` locals can't be assigned.
+    fn declare_local(&mut self, name: &str, constant: bool) -> i32 {
+        let id = self.new_local_id();
+        self.locals.insert(name.to_owned(), id);
+        if constant {
+            self.consts.insert(name.to_owned(), (id, None));
+        } else {
+            self.consts.remove(name);
+        }
+        id
+    }
+
+    /// Starts tracking reads of a variable or parameter. A `var` declared again keeps its
+    /// binding.
+    fn track_binding(&mut self, name: &str, id: i32, param: bool) {
+        let pos = self.cur_pos.clone().expect("no position for binding");
+        self.bindings
+            .entry((name.to_owned(), id))
+            .or_insert((pos, param, false));
+    }
+
+    fn mark_read(&mut self, name: &str) {
+        match self.locals.get(name) {
+            Some(&id) => {
+                if let Some(binding) = self.bindings.get_mut(&(name.to_owned(), id)) {
+                    binding.2 = true;
+                }
+            }
+            None => {
+                self.env_const_reads.insert(name.to_owned());
+            }
+        }
+    }
+
+    fn enter_scope(&mut self) {
+        self.scope_count += 1;
+        self.scopes.push(self.scope_count);
+    }
+
+    /// The internal label of the user label `name` in this function.
+    fn user_label(&mut self, name: &str) -> String {
+        if let Some((label, _)) = self.user_labels.get(name) {
+            return label.clone();
+        }
+        let label = self.new_empty_label();
+        self.user_labels
+            .insert(name.to_owned(), (label.clone(), None));
+        label
+    }
+
+    /// Optimizes the code of the function with `--opt=2`.
+    fn optimize_code(&mut self) {
+        if self.g.borrow().ssa && self.g.borrow().errors.is_empty() {
+            let (ops, pos) = ssa::optimize(&self.ops, &self.pos);
+            self.ops = ops;
+            self.pos = pos;
+        }
+    }
+
+    /// Checks that the code of the function pops only what it pushed and that the paths
+    /// meeting at an instruction agree on the stack height there, which codegen should
+    /// guarantee. Returns the largest stack height the code reaches.
+    fn check_stack(&mut self) -> u32 {
+        if !self.g.borrow().errors.is_empty() {
+            return 0;
+        }
+        match max_stack(&self.ops) {
+            Ok(depth) => depth as u32,
+            Err(e) => {
+                self.report(Msg::StackEffect(e.to_string()), false);
+                0
+            }
+        }
+    }
+
+    /// Inlines calls to small functions in every function and the module's code with
+    /// `--opt=2`, once they are all compiled.
+    fn inline_calls(&mut self) {
+        if !self.g.borrow().ssa || !self.g.borrow().errors.is_empty() {
+            return;
+        }
+        let inliner = inline::Inliner::new(&self.g.borrow().functions, &self.ops);
+        let mut functions = std::mem::take(&mut self.g.borrow_mut().functions);
+        for (ops, pos, gid, params) in functions.iter_mut() {
+            if inliner.inline(ops, pos, *params as usize) {
+                let (new_ops, new_pos) = ssa::optimize(ops, pos);
+                *ops = new_ops;
+                *pos = new_pos;
+                match max_stack(ops) {
+                    Ok(depth) => {
+                        if let Global::Func(.., max_stack) =
+                            &mut self.g.borrow_mut().table[*gid as usize]
+                        {
+                            *max_stack = depth as u32;
+                        }
+                    }
+                    Err(e) => self.report(Msg::StackEffect(e.to_string()), false),
+                }
+            }
+        }
+        self.g.borrow_mut().functions = functions;
+        if inliner.inline(&mut self.ops, &mut self.pos, 0) {
+            let (ops, pos) = ssa::optimize(&self.ops, &self.pos);
+            self.ops = ops;
+            self.pos = pos;
+            self.check_stack();
+        }
+    }
+
+    /// Every `goto` must target a label of the same function that is not inside a loop or
+    /// try body the `goto` is outside of.
+    fn check_gotos(&mut self) {
+        let cur_pos = self.cur_pos.clone();
+        for (name, scopes, pos, stub) in std::mem::take(&mut self.gotos) {
+            self.cur_pos = Some(pos);
+            let (label, def) = self.user_labels[&name].clone();
+            let depth = match def {
+                None => {
+                    self.report(Msg::UnknownLabel(name), false);
+                    0
+                }
+                Some((label_scopes, _)) if !scopes.starts_with(&label_scopes) => {
+                    self.report(Msg::GotoIntoScope(name), false);
+                    0
+                }
+                Some((label_scopes, _)) => label_scopes.len(),
+            };
+            if let Some(stub) = stub {
+                self.label_here(&stub);
+                self.leave_scopes(&scopes[depth..]);
+                self.emit_goto(&label);
+            }
+        }
+        self.cur_pos = cur_pos;
+    }
+
+    fn tries_in(&self, scopes: &[usize]) -> usize {
+        scopes
+            .iter()
+            .filter(|scope| self.try_scopes.contains(scope))
+            .count()
+    }
+
+    /// Number of try bodies entered after the first `depth` scopes.
+    fn tries_since(&self, depth: usize) -> usize {
+        self.tries_in(&self.scopes[depth..])
+    }
+
+    /// Drops the catch blocks of the try bodies a jump out of the first `depth` scopes
+    /// leaves, and disposes of the values of the `using` bodies it leaves.
+    fn leave_tries(&mut self, depth: usize) {
+        let scopes = self.scopes.split_off(depth);
+        self.leave_scopes(&scopes);
+        self.scopes.extend(scopes);
+    }
+
+    /// `leave_tries` for leaving `scopes`, innermost last.
+    fn leave_scopes(&mut self, scopes: &[usize]) {
+        for scope in scopes.iter().rev() {
+            if self.try_scopes.contains(scope) {
+                self.write(Op::CatchPop);
+            }
+            if let Some(&id) = self.usings.get(scope) {
+                self.dispose(id);
+            }
+        }
+    }
+
+    /// Calls `$dispose` on the value in local `id`.
+    fn dispose(&mut self, id: i32) {
+        self.write(Op::LoadLocal(id as _));
+        self.compile_const(&Constant::Builtin("dispose".to_owned()));
+        self.write(Op::Call(1));
+        self.write(Op::Pop(1));
+    }
+
+    /// Compiles the body `e` of a function using `defer`. Every `return` and the end of the
+    /// body store the value and jump to code calling the deferred closures, last deferred
+    /// first, and exceptions are caught to call them before being rethrown.
+    fn compile_deferring(&mut self, e: &P<Expr>) {
+        let defers = self.new_hidden_local();
+        let value = self.new_hidden_local();
+        let exit = self.new_empty_label();
+        let handler = self.new_empty_label();
+        self.write(Op::MakeArray(0));
+        self.write(Op::StoreLocal(defers as _));
+        self.defers = Some((defers, value, exit.clone()));
+        self.emit_paddr(&handler);
+        self.enter_scope();
+        self.try_scopes.insert(self.scope_count);
+        self.compile(e, false);
+        self.write(Op::StoreLocal(value as _));
+        self.leave_tries(0);
+        self.scopes.pop();
+        self.label_here(&exit);
+        self.run_defers(defers);
+        self.write(Op::LoadLocal(value as _));
+        self.emit_goto(&self.ret_lbl.clone());
+        self.label_here(&handler);
+        self.write(Op::StoreLocal(value as _));
+        self.run_defers(defers);
+        self.write(Op::LoadLocal(value as _));
+        self.write(Op::Throw);
+        self.defers = None;
+    }
+
+    /// Calls the closures in the array in local `defers`, last first.
+    fn run_defers(&mut self, defers: i32) {
+        let left = self.new_hidden_local();
+        let start = self.new_empty_label();
+        let end = self.new_empty_label();
+        self.write(Op::LoadLocal(defers as _));
+        self.compile_const(&Constant::Builtin("asize".to_owned()));
+        self.write(Op::Call(1));
+        self.write(Op::StoreLocal(left as _));
+        self.label_here(&start);
+        self.write(Op::LoadInt(0));
+        self.write(Op::LoadLocal(left as _));
+        self.write(Op::Gt);
+        self.emit_gotof(&end);
+        self.write(Op::LoadInt(1));
+        self.write(Op::LoadLocal(left as _));
+        self.write(Op::Sub);
+        self.write(Op::StoreLocal(left as _));
+        self.write(Op::LoadLocal(left as _));
+        self.write(Op::LoadLocal(defers as _));
+        self.write(Op::Load);
+        self.write(Op::Call(0));
+        self.write(Op::Pop(1));
+        self.emit_goto(&start);
+        self.label_here(&end);
+    }
+
+    /// Warns about variables and parameters that are never read. Names starting with `_`
+    /// are exempt.
+    fn report_unused(&mut self) {
+        let unused: Vec<_> = self
+            .bindings
+            .iter()
+            .filter(|((name, _), (_, _, read))| !read && !name.starts_with('_'))
+            .map(|((name, _), (pos, param, _))| (name.clone(), pos.clone(), *param))
+            .collect();
+        let cur_pos = self.cur_pos.clone();
+        for (name, pos, param) in unused {
+            self.cur_pos = Some(pos);
+            if param {
+                self.report(Msg::UnusedParameter(name), true);
+            } else {
+                self.report(Msg::UnusedVariable(name), true);
+            }
+        }
+        self.cur_pos = cur_pos;
+    }
+
+    /// Allocates the `var`s declared anywhere in `body` up front, so they are visible in the
+    /// whole function. Parameters keep their slot.
+    fn hoist_vars(&mut self, body: &P<Expr>) {
+        let mut names = vec![];
+        hoisted_vars(body, &mut names);
+        for name in names {
+            let id = match self.locals.get(&name) {
+                Some(&id) => id,
+                None => {
+                    let id = self.declare_local(&name, false);
+                    self.write(Op::LoadNull);
+                    self.write(Op::StoreLocal(id as _));
+                    id
+                }
+            };
+            self.hoisted.insert(name, id);
+        }
+    }
+
+    /// Checks the annotated parameters on entry and remembers the return type, which is
+    /// checked before the `Ret` at `ret_lbl` every return reaches, so annotations are
+    /// enforced at runtime by `$check_type`.
+    fn compile_contracts(&mut self, params: &[String], sig: &Signature, name: &str) {
+        for (idx, (param, ty)) in params.iter().zip(sig.params.iter()).enumerate() {
+            if let Some(ty) = ty {
+                let what = format!("parameter '{}' of {}", param, name);
+                self.compile_type_check(&what, ty, Op::LoadLocal(idx as _));
+                self.write(Op::StoreLocal(idx as _));
+            }
+        }
+        self.ret_contract = sig
+            .ret
+            .as_ref()
+            .map(|ty| (format!("return value of {}", name), ty.to_owned()));
+    }
+
+    /// Returns the value on top of the stack like `return` does: through the deferred
+    /// closures, the disposal of `using` values and the contract check.
+    fn compile_return(&mut self) {
+        match self.defers.clone() {
+            Some((_, value, exit)) => {
+                self.write(Op::StoreLocal(value as _));
+                self.leave_tries(0);
+                self.drop_stack(false);
+                self.emit_goto(&exit);
+            }
+            None if self
+                .scopes
+                .iter()
+                .any(|scope| self.usings.contains_key(scope)) =>
+            {
+                let value = self.new_hidden_local();
+                self.write(Op::StoreLocal(value as _));
+                self.leave_tries(0);
+                self.write(Op::LoadLocal(value as _));
+                self.emit_ret();
+            }
+            None => self.emit_ret(),
+        }
+    }
+
+    /// Returns the value on top of the stack, through the contract check at `ret_lbl` if
+    /// there is one.
+    fn emit_ret(&mut self) {
+        if self.ret_contract.is_some() {
+            self.drop_stack(true);
+            self.emit_goto(&self.ret_lbl.clone());
+        } else {
+            self.write(Op::Ret);
+        }
+    }
+
+    /// Pops the values an enclosing expression left on the stack, all but the top one if
+    /// `keep_top`, before jumping to code every return shares. `?` returns from the middle
+    /// of an expression, `Ret` alone would drop them.
+    fn drop_stack(&mut self, keep_top: bool) {
+        let keep = keep_top as usize;
+        let height = match stack_height(&self.ops) {
+            Some(height) if height > keep => height,
+            _ => return,
+        };
+        let count = self.operand(height - keep, "stack values");
+        if keep_top {
+            let top = self.new_hidden_local();
+            self.write(Op::StoreLocal(top as _));
+            self.write(Op::Pop(count));
+            self.write(Op::LoadLocal(top as _));
+        } else {
+            self.write(Op::Pop(count));
+        }
+    }
+
+    /// Checks the return value on top of the stack, leaving it there.
+    fn check_return_contract(&mut self) {
+        if let Some((what, ty)) = self.ret_contract.clone() {
+            let value = self.new_hidden_local();
+            self.write(Op::StoreLocal(value as _));
+            self.compile_type_check(&what, &ty, Op::LoadLocal(value as _));
+        }
+    }
+
+    /// Pushes `$check_type(value, type, what, proto)`, which returns the value if it has the
+    /// type. Capitalized types name the prototype the value must be an instance of.
+    fn compile_type_check(&mut self, what: &str, ty: &str, load: Op) {
+        if ty.starts_with(char::is_uppercase) {
+            self.compile_const(&Constant::Ident(ty.to_owned()));
+        } else {
+            self.write(Op::LoadNull);
+        }
+        self.compile_const(&Constant::Str(what.to_owned()));
+        self.compile_const(&Constant::Str(ty.to_owned()));
+        self.write(load);
+        self.compile_const(&Constant::Builtin("check_type".to_owned()));
+        self.write(Op::Call(4));
+    }
+
+    fn is_const(&self, name: &str) -> bool {
+        match self.locals.get(name) {
+            Some(id) => self.consts.get(name).map(|c| c.0) == Some(*id),
+            None if self.env.contains_key(name) => self.env_consts.contains_key(name),
+            None if self.env_consts.contains_key(name) => true,
+            None => self.g.borrow().module_consts.contains_key(name),
+        }
+    }
+
+    /// The literal value of the constant `name` refers to, substituted for every read.
+    fn const_value(&self, name: &str) -> Option<Constant> {
+        if !self.is_const(name) {
+            return None;
+        }
+        match self.locals.get(name) {
+            Some(_) => self.consts[name].1.clone(),
+            None if self.env_consts.contains_key(name) => self.env_consts[name].clone(),
+            None => self.g.borrow().module_consts[name].clone(),
+        }
+    }
+
+    /// Next free local slot.
+    fn new_local_id(&mut self) -> i32 {
+        let id = self.locals.len();
+        self.operand(id, "locals in function") as i32
+    }
+
+    /// `$debug_locals()`: array of `{name, value, depth}` objects for the variables in scope,
+    /// `depth` is 0 for locals of the function and 1 for the ones of the enclosing function.
+    fn compile_debug_locals(&mut self) {
+        let mut names: Vec<(String, i32)> = self
+            .locals
+            .keys()
+            .filter(|name| !name.starts_with('#'))
+            .map(|name| (name.clone(), 0))
+            .collect();
+        for name in self.env.keys() {
+            if !name.starts_with('#') && !self.locals.contains_key(name) {
+                names.push((name.clone(), 1));
+            }
+        }
+        let locals = self.locals.clone();
+        let obj = self.new_hidden_local();
+        for (name, depth) in names.iter() {
+            self.write(Op::LoadNull);
+            self.write(Op::New);
+            self.write(Op::StoreLocal(obj as _));
+            let fields = [
+                ("name", Constant::Str(name.clone())),
+                ("value", Constant::Ident(name.clone())),
+                ("depth", Constant::Int(*depth as _)),
+            ];
+            for (key, value) in fields.iter() {
+                self.compile_const(value);
+                let gid = self.global(&Global::Str((*key).to_owned()));
+                self.write(Op::LoadGlobal(gid as _));
+                self.write(Op::LoadLocal(obj as _));
+                self.write(Op::Store);
+            }
+            self.write(Op::LoadLocal(obj as _));
+        }
+        let count = self.operand(names.len(), "elements in array");
+        self.write(Op::MakeArray(count));
+        self.locals = locals;
+    }
+
+    fn new_hidden_local(&mut self) -> i32 {
+        let id = self.new_local_id();
+        self.locals.insert(format!("#{}", id), id);
+        id
+    }
+
+    /// Local holding the left operand of `&&` and `||`. It is only read right after it is
+    /// stored, so all of them in scope share one.
+    fn logic_temp(&mut self) -> i32 {
+        match self.locals.get("#logic") {
+            Some(&slot) => slot,
+            None => {
+                let id = self.new_local_id();
+                self.locals.insert("#logic".to_owned(), id);
+                id
+            }
+        }
+    }
+
+    fn compile_type_test(&mut self, slot: i32, ty: &str, fail: &str) {
+        let gid = self.global(&Global::Str(ty.to_owned()));
+        self.write(Op::LoadGlobal(gid as _));
+        self.write(Op::LoadLocal(slot as _));
+        self.compile_const(&Constant::Builtin("typeof".to_owned()));
+        self.write(Op::Call(1));
+        self.write(Op::Eq);
+        self.emit_gotof(fail);
+    }
+
+    /// Emits the tests for `pat` against the value in local `slot`, jumping to `fail` on mismatch.
+    /// Bindings of the same name share one local so `|` alternatives can bind the same variable.
+    fn compile_pattern(
+        &mut self,
+        pat: &Pattern,
+        slot: i32,
+        fail: &str,
+        binds: &mut HashMap<String, i32>,
+    ) {
+        match pat {
+            Pattern::Wildcard => (),
+            Pattern::Bind(name) | Pattern::Equal(name) if self.find_variant(name).is_some() => {
+                let variant = Pattern::Variant(name.to_owned(), vec![]);
+                self.compile_pattern(&variant, slot, fail, binds)
+            }
+            Pattern::Variant(name, pats) => {
+                let fields = match self.find_variant(name) {
+                    Some((_, fields)) => fields,
+                    None => return self.report(Msg::UnknownVariant(name.to_owned()), false),
+                };
+                if fields.len() != pats.len() {
+                    let msg = Msg::VariantArity(name.to_owned(), fields.len(), pats.len());
+                    return self.report(msg, false);
+                }
+                self.compile_type_test(slot, "object", fail);
+                self.compile_const(&Constant::Str(name.to_owned()));
+                let gid = self.global(&Global::Str("__tag".to_owned()));
+                self.write(Op::LoadGlobal(gid as _));
+                self.write(Op::LoadLocal(slot as _));
+                self.write(Op::Load);
+                self.write(Op::Eq);
+                self.emit_gotof(fail);
+                let fields = fields
+                    .into_iter()
+                    .zip(pats.iter().cloned())
+                    .collect::<Vec<_>>();
+                for (field, pat) in fields.iter() {
+                    if let Pattern::Wildcard = pat {
+                        continue;
+                    }
+                    let gid = self.global(&Global::Str(field.to_owned()));
+                    self.write(Op::LoadGlobal(gid as _));
+                    self.write(Op::LoadLocal(slot as _));
+                    self.write(Op::Load);
+                    let value = self.new_hidden_local();
+                    self.write(Op::StoreLocal(value as _));
+                    self.compile_pattern(pat, value, fail, binds);
+                }
+            }
+            Pattern::Bind(name) => {
+                let id = match binds.get(name) {
+                    Some(id) => *id,
+                    None => {
+                        let id = self.new_local_id();
+                        binds.insert(name.to_owned(), id);
+                        id
+                    }
+                };
+                self.locals.insert(name.to_owned(), id);
+                self.consts.remove(name);
+                self.write(Op::LoadLocal(slot as _));
+                self.write(Op::StoreLocal(id as _));
+            }
+            Pattern::Equal(name) => {
+                self.compile_const(&Constant::Ident(name.to_owned()));
+                self.write(Op::LoadLocal(slot as _));
+                self.write(Op::Eq);
+                self.emit_gotof(fail);
+            }
+            Pattern::Const(Constant::Null) => {
+                self.write(Op::LoadLocal(slot as _));
+                self.write(Op::IsNull);
+                self.emit_gotof(fail);
+            }
+            Pattern::Const(c) => {
+                self.compile_const(c);
+                self.write(Op::LoadLocal(slot as _));
+                self.write(Op::Eq);
+                self.emit_gotof(fail);
+            }
+            Pattern::Array(pats) => {
+                self.compile_type_test(slot, "array", fail);
+                self.write(Op::LoadInt(pats.len() as _));
+                self.write(Op::LoadLocal(slot as _));
+                self.compile_const(&Constant::Builtin("asize".to_owned()));
+                self.write(Op::Call(1));
+                self.write(Op::Eq);
+                self.emit_gotof(fail);
+                for (i, pat) in pats.iter().enumerate() {
+                    if let Pattern::Wildcard = pat {
+                        continue;
+                    }
+                    self.write(Op::LoadInt(i as _));
+                    self.write(Op::LoadLocal(slot as _));
+                    self.write(Op::Load);
+                    let elem = self.new_hidden_local();
+                    self.write(Op::StoreLocal(elem as _));
+                    self.compile_pattern(pat, elem, fail, binds);
+                }
+            }
+            Pattern::Object(fields) => {
+                self.compile_type_test(slot, "object", fail);
+                for (key, pat) in fields.iter() {
+                    if let Pattern::Wildcard = pat {
+                        continue;
+                    }
+                    let gid = self.global(&Global::Str(key.to_owned()));
+                    self.write(Op::LoadGlobal(gid as _));
+                    self.write(Op::LoadLocal(slot as _));
+                    self.write(Op::Load);
+                    let field = self.new_hidden_local();
+                    self.write(Op::StoreLocal(field as _));
+                    self.compile_pattern(pat, field, fail, binds);
+                }
+            }
+            Pattern::Or(alts) => {
+                let ok = self.new_empty_label();
+                for alt in alts[..alts.len() - 1].iter() {
+                    let next = self.new_empty_label();
+                    self.compile_pattern(alt, slot, &next, binds);
+                    self.emit_goto(&ok);
+                    self.label_here(&next);
+                }
+                self.compile_pattern(alts.last().unwrap(), slot, fail, binds);
+                self.label_here(&ok);
+            }
+        }
+    }
+
+    /// `++`/`--`. The object and index of a field or element are evaluated once into hidden
+    /// locals.
+    fn compile_update(&mut self, op: &str, target: &P<Expr>, prefix: bool) {
+        let hidden = |ctx: &mut Context, e: &P<Expr>| {
+            ctx.compile(e, false);
+            let id = ctx.new_hidden_local();
+            ctx.write(Op::StoreLocal(id as _));
+            P(Expr {
+                pos: e.pos.clone(),
+                decl: ExprDecl::Const(Constant::Ident(format!("#{}", id))),
+            })
+        };
+        let target = match &target.decl {
+            ExprDecl::Const(Constant::Ident(name)) => {
+                if self.is_const(name) {
+                    self.report(Msg::ConstReassigned(name.to_owned()), false);
+                }
+                self.mark_read(name);
+                target.clone()
+            }
+            ExprDecl::Field(object, field) => P(Expr {
+                pos: target.pos.clone(),
+                decl: ExprDecl::Field(hidden(self, object), field.clone()),
+            }),
+            ExprDecl::Array(array, index) => {
+                let array = hidden(self, array);
+                let index = hidden(self, index);
+                P(Expr {
+                    pos: target.pos.clone(),
+                    decl: ExprDecl::Array(array, index),
+                })
+            }
+            _ => unreachable!(),
+        };
+        let access = self.compile_access(&target);
+        let result = self.new_hidden_local();
+        self.write(Op::LoadInt(1));
+        self.access_get(access.clone());
+        if !prefix {
+            self.write(Op::StoreLocal(result as _));
+            self.write(Op::LoadLocal(result as _));
+        }
+        self.write(if op == "++" { Op::Add } else { Op::Sub });
+        if prefix {
+            self.write(Op::StoreLocal(result as _));
+            self.write(Op::LoadLocal(result as _));
+        }
+        self.access_set(access);
+        self.write(Op::LoadLocal(result as _));
+    }
+
     pub fn compile_binop(&mut self, op: &str, e1: &P<Expr>, e2: &P<Expr>, tail: bool) {
         match op {
+            "instanceof" => {
+                self.compile(e2, false);
+                self.compile(e1, false);
+                self.compile_const(&Constant::Builtin("instanceof".to_owned()));
+                self.write(Op::Call(2));
+            }
             "==" => match &e2.decl {
                 ExprDecl::Const(Constant::Null) => {
                     self.compile(e1, false);
@@ -532,20 +1818,24 @@ impl Context {
                     self.write(Op::Neq);
                 }
             },
-            "&&" => {
-                let if_false = self.new_empty_label();
-                self.compile(e1, false);
-                self.emit_gotof(&if_false);
-                self.compile(e2, tail);
-                self.label_here(&if_false);
-            }
-            "||" => {
-                let if_true = self.new_empty_label();
+            // `a && b` is `a` if it is falsy and `b` otherwise, `a || b` is `a` if it is truthy.
+            "&&" | "||" => {
+                let short = self.new_empty_label();
+                let end = self.new_empty_label();
                 self.compile(e1, false);
-                self.emit_gotot(&if_true);
+                let lhs = self.logic_temp();
+                self.write(Op::StoreLocal(lhs as _));
+                self.write(Op::LoadLocal(lhs as _));
+                if op == "&&" {
+                    self.emit_gotof(&short);
+                } else {
+                    self.emit_gotot(&short);
+                }
                 self.compile(e2, tail);
-
-                self.label_here(&if_true);
+                self.emit_goto(&end);
+                self.label_here(&short);
+                self.write(Op::LoadLocal(lhs as _));
+                self.label_here(&end);
             }
             _ => {
                 self.compile(e2, false);
@@ -556,7 +1846,27 @@ impl Context {
         }
     }
 
-    pub fn compile_function(&mut self, params: &[String], e: &P<Expr>, vname: Option<&str>) {
+    pub fn compile_function(
+        &mut self,
+        params: &[String],
+        e: &P<Expr>,
+        sig: &Signature,
+        vname: Option<&str>,
+        source: Option<&str>,
+    ) {
+        self.compile_closure(params, e, sig, vname, source, false)
+    }
+
+    /// Compiles a function, or with `generator` the body of a generator function.
+    fn compile_closure(
+        &mut self,
+        params: &[String],
+        e: &P<Expr>,
+        sig: &Signature,
+        vname: Option<&str>,
+        source: Option<&str>,
+        generator: bool,
+    ) {
         let mut ctx = Context {
             g: self.g.clone(),
             ops: Vec::new(),
@@ -566,17 +1876,48 @@ impl Context {
             locals: LinkedHashMap::new(),
             nenv: 0,
             env: self.locals.clone(),
-            cur_pos: None,
+            cur_pos: self.cur_pos.clone(),
             continues: vec![],
             breaks: vec![],
             labels: self.labels.clone(),
             used_upvars: LinkedHashMap::new(),
             trace_info: HashMap::new(),
             ret_lbl: String::new(),
+            catch_exc: vec![],
+            consts: HashMap::new(),
+            hoisted: HashMap::new(),
+            ret_contract: None,
+            bindings: LinkedHashMap::new(),
+            env_const_reads: HashSet::new(),
+            user_labels: HashMap::new(),
+            gotos: vec![],
+            scopes: vec![],
+            scope_count: 0,
+            try_scopes: HashSet::new(),
+            loop_scopes: vec![],
+            module: false,
+            block_depth: 0,
+            generator: false,
+            defers: None,
+            usings: HashMap::new(),
+            env_consts: self
+                .env_consts
+                .iter()
+                .filter(|(name, _)| !self.locals.contains_key(*name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .chain(
+                    self.locals
+                        .keys()
+                        .filter(|name| self.is_const(name))
+                        .map(|name| (name.clone(), self.const_value(name))),
+                )
+                .collect(),
         };
+        ctx.operand(params.len(), "parameters in function");
         for (idx, p) in params.iter().enumerate() {
             ctx.stack += 1;
             ctx.locals.insert(p.to_owned(), idx as i32);
+            ctx.track_binding(p, idx as i32, true);
         }
 
         let gid = ctx.g.borrow().table.len();
@@ -586,13 +1927,48 @@ impl Context {
                 .globals
                 .insert(Global::Var(vname.unwrap().to_owned()), gid as i32);
         }
-        ctx.g.borrow_mut().table.push(Global::Func(gid as i32, -1));
+        ctx.g.borrow_mut().table.push(Global::Func(
+            gid as i32,
+            -1,
+            params.to_vec(),
+            source.map(str::to_owned),
+            sig.doc.clone(),
+            0,
+        ));
         ctx.ret_lbl = ctx.new_empty_label();
-        ctx.compile(e, true);
+        if ctx.g.borrow().contracts {
+            ctx.compile_contracts(params, sig, vname.unwrap_or("<function>"));
+        }
+        ctx.generator = generator;
+        if !generator && contains_yield(e) {
+            // Calls only create the generator, its body is a closure over the arguments.
+            ctx.compile_const(&Constant::This);
+            ctx.compile_closure(&[], e, &Signature::default(), None, None, true);
+            ctx.compile_const(&Constant::Builtin("generator".to_owned()));
+            ctx.write(Op::Call(2));
+        } else if !generator && contains_defer(e) {
+            ctx.hoist_vars(e);
+            ctx.compile_deferring(e);
+        } else {
+            ctx.hoist_vars(e);
+            // A tail call would return without the contract check.
+            let tail = ctx.ret_contract.is_none();
+            ctx.compile(e, tail);
+        }
         let ret_lbl = ctx.ret_lbl.clone();
         ctx.label_here(&ret_lbl);
+        ctx.check_return_contract();
         ctx.write(Op::Ret);
-        //ctx.check_stack(s, "");
+        ctx.check_gotos();
+        ctx.report_unused();
+        for name in ctx.env_const_reads.iter() {
+            self.mark_read(name);
+        }
+        ctx.optimize_code();
+        let depth = ctx.check_stack();
+        if let Global::Func(.., max_stack) = &mut ctx.g.borrow_mut().table[gid] {
+            *max_stack = depth;
+        }
 
         ctx.g.borrow_mut().functions.push((
             ctx.ops.clone(),
@@ -610,7 +1986,8 @@ impl Context {
             }
             self.write(Op::LoadGlobal(gid as _));
 
-            self.write(Op::MakeEnv((ctx.used_upvars.len()) as u16));
+            let count = self.operand(ctx.used_upvars.len(), "captured variables in function");
+            self.write(Op::MakeEnv(count));
         } else {
             self.write(Op::LoadGlobal(gid as _));
         }
@@ -622,6 +1999,14 @@ impl Context {
             objects: LinkedHashMap::new(),
             functions: vec![],
             table: vec![],
+            enums: prelude_enums(),
+            errors: vec![],
+            warnings: vec![],
+            strict: false,
+            contracts: false,
+            ssa: false,
+            module_vars: HashSet::new(),
+            module_consts: HashMap::new(),
         };
         Context {
             g: Rc::new(RefCell::new(g)),
@@ -639,32 +2024,354 @@ impl Context {
             used_upvars: Default::default(),
             trace_info: HashMap::new(),
             ret_lbl: String::new(),
+            catch_exc: vec![],
+            consts: HashMap::new(),
+            hoisted: HashMap::new(),
+            ret_contract: None,
+            bindings: LinkedHashMap::new(),
+            env_const_reads: HashSet::new(),
+            user_labels: HashMap::new(),
+            gotos: vec![],
+            scopes: vec![],
+            scope_count: 0,
+            try_scopes: HashSet::new(),
+            loop_scopes: vec![],
+            module: false,
+            block_depth: 0,
+            generator: false,
+            defers: None,
+            usings: HashMap::new(),
+            env_consts: HashMap::new(),
         }
     }
 }
 
-pub fn compile(ast: Vec<P<Expr>>) -> Context {
+/// Largest stack height the code of a function reaches, see `stack::max_depth`.
+fn max_stack(ops: &[UOP]) -> Result<usize, stack::StackError> {
+    let mut labels = LinkedHashMap::new();
+    for (idx, op) in ops.iter().enumerate() {
+        if let UOP::Label(l) = op {
+            labels.insert(l.to_owned(), Some(idx));
+        }
+    }
+    stack::max_depth(&resolve_labels(ops, &labels), 0)
+}
+
+/// Stack height at the end of `ops`, the code of a function compiled so far, or `None` if
+/// nothing reaches it yet. Jumps to labels placed later pass their height on to them.
+fn stack_height(ops: &[UOP]) -> Option<usize> {
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut height = Some(0);
+    // Gotos after a `Switch` are its cases, each reached with the same height.
+    let mut cases = 0;
+    for op in ops {
+        let (op, label) = match op {
+            UOP::Label(label) => {
+                match labels.get(label.as_str()) {
+                    Some(seen) => height = Some(*seen),
+                    None => {
+                        if let Some(h) = height {
+                            labels.insert(label, h);
+                        }
+                    }
+                }
+                continue;
+            }
+            UOP::Op(op) => (op.clone(), None),
+            UOP::Goto(label) => (Op::Jump(0), Some(label)),
+            UOP::GotoF(label) => (Op::JumpIfNot(0), Some(label)),
+            UOP::GotoT(label) => (Op::JumpIf(0), Some(label)),
+            UOP::PAddr(label) => (Op::CatchPush(0), Some(label)),
+        };
+        let h = match height {
+            Some(h) => h,
+            None => continue,
+        };
+        let (pops, pushes) = stack::stack_effect(&op);
+        let after = h.saturating_sub(pops) + pushes;
+        if let Some(label) = label {
+            let handler = matches!(op, Op::CatchPush(_)) as usize;
+            labels.entry(label).or_insert(after + handler);
+        }
+        height = match op {
+            Op::Switch(_, n) => {
+                cases = n as usize + 1;
+                Some(after)
+            }
+            Op::Jump(_) if cases > 0 => {
+                cases -= 1;
+                if cases == 0 {
+                    None
+                } else {
+                    Some(after)
+                }
+            }
+            Op::Jump(_) | Op::Throw | Op::Ret | Op::Last => None,
+            _ => Some(after),
+        };
+    }
+    height
+}
+
+/// Instructions of `ops` with the jumps to `labels` resolved, labels become `Nop`s.
+fn resolve_labels(ops: &[UOP], labels: &LinkedHashMap<String, Option<usize>>) -> Vec<Op> {
+    let at = |lbl: &str| labels.get(lbl).unwrap().unwrap() as u32;
+    ops.iter()
+        .map(|i| match *i {
+            UOP::Op(ref op) => op.clone(),
+            UOP::PAddr(ref lbl) => Op::CatchPush(at(lbl)),
+            UOP::Goto(ref lbl) => Op::Jump(at(lbl)),
+            UOP::GotoF(ref lbl) => Op::JumpIfNot(at(lbl)),
+            UOP::GotoT(ref lbl) => Op::JumpIf(at(lbl)),
+            _ => Op::Nop,
+        })
+        .collect::<Vec<Op>>()
+}
+
+/// Whether control never continues after `e`.
+fn diverges(e: &P<Expr>) -> bool {
+    matches!(
+        &e.decl,
+        ExprDecl::Return(_)
+            | ExprDecl::Throw(_)
+            | ExprDecl::Rethrow
+            | ExprDecl::Break(_)
+            | ExprDecl::Continue
+            | ExprDecl::Goto(_)
+    )
+}
+
+/// Whether `e` yields, `yield` in nested functions makes them generators instead.
+fn contains_yield(e: &P<Expr>) -> bool {
+    match &e.decl {
+        ExprDecl::Function(..) => false,
+        ExprDecl::Yield(_) => true,
+        _ => {
+            let mut found = false;
+            e.iter(|e| found = found || contains_yield(e));
+            found
+        }
+    }
+}
+
+/// Whether `e` has a `defer`, not counting nested functions.
+fn contains_defer(e: &P<Expr>) -> bool {
+    match &e.decl {
+        ExprDecl::Function(..) => false,
+        ExprDecl::Defer(_) => true,
+        _ => {
+            let mut found = false;
+            e.iter(|e| found = found || contains_defer(e));
+            found
+        }
+    }
+}
+
+/// Names declared with `var` in a function body, not counting nested functions.
+fn hoisted_vars(e: &P<Expr>, names: &mut Vec<String>) {
+    match &e.decl {
+        ExprDecl::Function(..) => return,
+        ExprDecl::Var(VarKind::Var, name, _, _) if !names.contains(name) => {
+            names.push(name.clone())
+        }
+        _ => (),
+    }
+    e.iter(|e| hoisted_vars(e, names));
+}
+
+/// Layout of the enums declared by std/Result, known to every module so `Ok(v)`/`None`
+/// patterns work on values it produces.
+fn prelude_enums() -> LinkedHashMap<String, Vec<(String, Vec<String>)>> {
+    let mut enums = LinkedHashMap::new();
+    enums.insert(
+        "Result".to_owned(),
+        vec![
+            ("Ok".to_owned(), vec!["value".to_owned()]),
+            ("Err".to_owned(), vec!["error".to_owned()]),
+        ],
+    );
+    enums.insert(
+        "Option".to_owned(),
+        vec![
+            ("Some".to_owned(), vec!["value".to_owned()]),
+            ("None".to_owned(), vec![]),
+        ],
+    );
+    enums
+}
+
+/// Desugars an enum declaration: the enum itself is an object that serves as the prototype of
+/// its values, every variant with fields becomes a constructor function and every variant
+/// without fields a single shared value. Values carry their variant name in `__tag`.
+fn enum_decls(name: &str, variants: &[(String, Vec<String>)], pos: &Position) -> Vec<P<Expr>> {
+    let expr = |decl| {
+        P(Expr {
+            pos: pos.clone(),
+            decl,
+        })
+    };
+    let ident = |name: &str| P(make_ident(name.to_owned(), pos.clone()));
+    let str_ = |s: &str| P(make_str(s.to_owned(), pos.clone()));
+    let new_object = |proto| {
+        P(make_call(
+            P(make_builtin("new".to_owned(), pos.clone())),
+            vec![proto],
+            pos.clone(),
+        ))
+    };
+    let set_field = |obj: &str, field: &str, value| {
+        expr(ExprDecl::Assign(
+            expr(ExprDecl::Field(ident(obj), field.to_owned())),
+            value,
+        ))
+    };
+
+    let mut decls = vec![];
+    decls.push(expr(ExprDecl::Var(
+        VarKind::Let,
+        name.to_owned(),
+        None,
+        Some(new_object(expr(ExprDecl::Const(Constant::Null)))),
+    )));
+    let tags = variants.iter().map(|(v, _)| str_(v)).collect();
+    let false_ = || expr(ExprDecl::Const(Constant::False));
+    // Hidden from `foreach` and printing, and read-only.
+    decls.push(P(make_call(
+        expr(ExprDecl::Field(ident(name), "define".to_owned())),
+        vec![
+            str_("__variants"),
+            P(make_call(
+                P(make_builtin("array".to_owned(), pos.clone())),
+                tags,
+                pos.clone(),
+            )),
+            false_(),
+            false_(),
+        ],
+        pos.clone(),
+    )));
+    for (variant, fields) in variants.iter() {
+        if fields.is_empty() {
+            decls.push(expr(ExprDecl::Var(
+                VarKind::Let,
+                variant.to_owned(),
+                None,
+                Some(new_object(ident(name))),
+            )));
+            decls.push(set_field(variant, "__tag", str_(variant)));
+        } else {
+            let mut body = vec![expr(ExprDecl::Var(
+                VarKind::Let,
+                "__value".to_owned(),
+                None,
+                Some(new_object(ident(name))),
+            ))];
+            body.push(set_field("__value", "__tag", str_(variant)));
+            for field in fields.iter() {
+                body.push(set_field("__value", field, ident(field)));
+            }
+            body.push(expr(ExprDecl::Return(Some(ident("__value")))));
+            decls.push(expr(ExprDecl::Var(
+                VarKind::Let,
+                variant.to_owned(),
+                None,
+                Some(expr(ExprDecl::Function(
+                    fields.clone(),
+                    expr(ExprDecl::Block(body)),
+                    Signature::default(),
+                    None,
+                ))),
+            )));
+        }
+        decls.push(set_field(name, variant, ident(variant)));
+    }
+    decls
+}
+
+/// Collects the module-level variables up front, so functions refer to them even when
+/// they are declared after the function.
+fn declare_module_vars(g: &mut Globals, ast: &P<Expr>) {
+    let mut names = vec![];
+    hoisted_vars(ast, &mut names);
+    g.module_vars.extend(names);
+    if let ExprDecl::Block(decls) = &ast.decl {
+        for decl in decls.iter() {
+            if let ExprDecl::Var(kind, name, _, init) = &decl.decl {
+                g.module_vars.insert(name.to_owned());
+                if *kind == VarKind::Const {
+                    g.module_consts
+                        .insert(name.to_owned(), init.as_ref().and_then(literal));
+                }
+            }
+        }
+    }
+}
+
+fn is_label(e: &P<Expr>) -> bool {
+    matches!(e.decl, ExprDecl::Label(_))
+}
+
+/// The value of a `const` initializer that is known at compile time.
+fn literal(e: &P<Expr>) -> Option<Constant> {
+    match &e.decl {
+        ExprDecl::Const(c) => match c {
+            Constant::This | Constant::Builtin(_) | Constant::Ident(_) => None,
+            c => Some(c.clone()),
+        },
+        ExprDecl::Paren(e) => literal(e),
+        ExprDecl::Unop(op, e) if op == "-" => match literal(e)? {
+            Constant::Int(i) => Some(Constant::Int(-i)),
+            Constant::Float(f) => Some(Constant::Float(-f)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Compiles a module, in strict mode if `strict` is set or the module starts with "use strict".
+/// With `contracts`, type annotations of functions become runtime checks.
+pub fn compile(ast: Vec<P<Expr>>, strict: bool, contracts: bool, ssa: bool) -> Context {
     let mut ctx = Context::new();
+    ctx.g.borrow_mut().contracts = contracts;
+    ctx.g.borrow_mut().ssa = ssa;
+    ctx.g.borrow_mut().strict = strict
+        || match ast.first().map(|e| &e.decl) {
+            Some(ExprDecl::Const(Constant::Str(s))) => s == "use strict",
+            _ => false,
+        };
+    let file = ast
+        .first()
+        .map(|x| x.pos.file.clone())
+        .unwrap_or(Arc::from("<>".to_owned()));
     let ast = P(Expr {
-        pos: Position::new(
-            ast.get(0)
-                .map(|x| x.pos.file.clone())
-                .unwrap_or(Arc::from("<>".to_owned())),
-            0,
-            0,
-        ),
+        pos: Position::new(file.clone(), 0, 0),
         decl: ExprDecl::Block(ast.clone()),
     });
 
+    ctx.module = true;
+    declare_module_vars(&mut ctx.g.borrow_mut(), &ast);
     ctx.ret_lbl = ctx.new_empty_label();
     ctx.compile(&ast, false);
     let ret_lbl = ctx.ret_lbl.clone();
     ctx.label_here(&ret_lbl);
     ctx.write(Op::Ret);
+    ctx.check_gotos();
+    ctx.report_unused();
+    ctx.optimize_code();
+    ctx.cur_pos = Some(ast.pos.clone());
+    ctx.check_stack();
+    ctx.cur_pos = None;
+    ctx.inline_calls();
+    if ctx.ops.len() > opcode::MAX_CODE_SIZE {
+        ctx.report(
+            Msg::OperandLimit("instructions in module".to_owned(), opcode::MAX_CODE_SIZE),
+            false,
+        );
+    }
 
     if ctx.g.borrow().functions.len() != 0 || ctx.g.borrow().objects.len() != 0 {
         let ctxops = ctx.ops.clone();
-        let _ctxpos = ctx.pos.clone();
+        let ctxpos = ctx.pos.clone();
         let ops = vec![];
         let pos = vec![];
         ctx.ops = ops;
@@ -674,7 +2381,14 @@ pub fn compile(ast: Vec<P<Expr>>) -> Context {
         for (fops, fpos, gid, nargs) in functions.iter().rev() {
             let mut g = ctx.g.borrow_mut();
 
-            g.table[*gid as usize] = Global::Func(ctx.ops.len() as i32, *nargs);
+            let address = ctx.ops.len() as i32;
+            match &mut g.table[*gid as usize] {
+                Global::Func(at, argc, ..) => {
+                    *at = address;
+                    *argc = *nargs;
+                }
+                _ => unreachable!(),
+            }
 
             for op in fops.iter() {
                 ctx.ops.push(op.clone());
@@ -687,6 +2401,14 @@ pub fn compile(ast: Vec<P<Expr>>) -> Context {
         for op in ctxops.iter() {
             ctx.ops.push(op.clone());
         }
+        ctx.pos.extend(ctxpos);
+    }
+
+    for (idx, (line, _)) in ctx.pos.iter().enumerate() {
+        if *line > 0 {
+            ctx.trace_info
+                .insert(idx as u32, (*line as usize, file.to_string()));
+        }
     }
 
     ctx
@@ -698,22 +2420,31 @@ pub fn module_from_context(ctx: &mut Context) -> Ref<Module> {
         exports: Value::Object(Ref(Object {
             prototype: None,
             table: Default::default(),
+            modcount: 0,
+            attributes: Default::default(),
+            sealed: false,
         })),
-        code: vec![],
+        code: Rc::from(vec![]),
+        feedback: vec![],
 
         globals: vec![Value::Null; ctx.g.borrow().table.len()],
-        trace_info: Default::default(),
+        trace_info: ctx.trace_info.clone(),
     });
 
     for (i, g) in ctx.g.borrow().table.iter().enumerate() {
         match g {
-            Global::Func(off, nargs) => {
+            Global::Func(off, nargs, params, source, doc, max_stack) => {
                 let func = Ref(Function {
                     native: false,
                     address: *off as _,
                     argc: *nargs,
-                    env: Value::Array(Ref(vec![])),
+                    env: Value::Array(Ref(Elements::new())),
                     module: Some(m.clone()),
+                    params: params.to_vec().into(),
+                    source: source.as_deref().map(Rc::from),
+                    doc: doc.as_deref().map(Rc::from),
+                    max_stack: *max_stack,
+                    hotness: 0,
                 });
 
                 m.borrow_mut().globals[i] = Value::Function(func);
@@ -724,10 +2455,35 @@ pub fn module_from_context(ctx: &mut Context) -> Ref<Module> {
             Global::Float(x) => {
                 m.borrow_mut().globals[i] = Value::Float(f64::from_bits(*x));
             }
+            Global::Char(c) => {
+                m.borrow_mut().globals[i] = Value::Char(*c);
+            }
+            Global::Table(cases) => {
+                let mut table = Object {
+                    prototype: None,
+                    table: Default::default(),
+                    modcount: 0,
+                    attributes: Default::default(),
+                    sealed: false,
+                };
+                for (key, case) in cases.iter() {
+                    let key = match key {
+                        TableKey::Int(x) => Value::Int(*x),
+                        TableKey::Char(ch) => Value::Char(*ch),
+                        TableKey::Str(s) => Value::String(Ref(s.to_owned())),
+                    };
+                    table.set(key, Value::Int(*case as i64));
+                }
+                m.borrow_mut().globals[i] = Value::Object(Ref(table));
+            }
+            Global::BigInt(digits, radix) => {
+                let x = num_bigint::BigInt::parse_bytes(digits.as_bytes(), *radix).unwrap();
+                m.borrow_mut().globals[i] = Value::BigInt(Ref(x));
+            }
             _ => (),
         };
     }
-    m.borrow_mut().code = ctx.finish();
+    m.borrow_mut().set_code(ctx.finish());
 
     m
 }
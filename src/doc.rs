@@ -0,0 +1,67 @@
+//! Markdown API documentation of a module: its top-level functions and constants with their
+//! `///` doc comments.
+
+use crate::ast::*;
+use crate::parser::Parser;
+use crate::reader::Reader;
+
+use std::fmt::Write;
+
+/// `name(a: int, b) -> int`, with the annotations `sig` has.
+fn signature(name: &str, params: &[String], sig: &Signature) -> String {
+    let params = params
+        .iter()
+        .zip(sig.params.iter())
+        .map(|(param, ty)| match ty {
+            Some(ty) => format!("{}: {}", param, ty),
+            None => param.clone(),
+        })
+        .collect::<Vec<_>>();
+    match &sig.ret {
+        Some(ret) => format!("{}({}) -> {}", name, params.join(", "), ret),
+        None => format!("{}({})", name, params.join(", ")),
+    }
+}
+
+/// Documentation of the module `title` with the source `reader` reads. Functions and
+/// constants are listed whether they are documented or not, variables only when they are.
+pub fn markdown(title: &str, reader: Reader) -> Result<String, String> {
+    let mut ast = vec![];
+    let mut parser = Parser::new(reader, &mut ast);
+    parser.parse().map_err(|e| e.to_string())?;
+    let docs = parser.docs().to_vec();
+    let doc_at = |pos| docs.iter().find(|(at, _)| at == pos).map(|(_, doc)| doc);
+
+    let mut out = format!("# {}\n", title);
+    for expr in ast.iter() {
+        let (kind, name, ty, init) = match &expr.decl {
+            ExprDecl::Var(kind, name, ty, init) => (kind, name, ty, init),
+            _ => continue,
+        };
+        let (heading, doc) = match init.as_ref().map(|init| &init.decl) {
+            Some(ExprDecl::Function(params, _, sig, _)) => {
+                (signature(name, params, sig), sig.doc.as_ref())
+            }
+            _ => {
+                let doc = doc_at(&expr.pos);
+                if *kind != VarKind::Const && doc.is_none() {
+                    continue;
+                }
+                let keyword = match kind {
+                    VarKind::Var => "var",
+                    VarKind::Let => "let",
+                    VarKind::Const => "const",
+                };
+                match ty {
+                    Some(ty) => (format!("{} {}: {}", keyword, name, ty), doc),
+                    None => (format!("{} {}", keyword, name), doc),
+                }
+            }
+        };
+        write!(out, "\n## `{}`\n", heading).unwrap();
+        if let Some(doc) = doc {
+            write!(out, "\n{}\n", doc).unwrap();
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,183 @@
+//! Running scripts from a host that hands over source text, like a web page driving the
+//! interpreter compiled to wasm32. `eval` compiles and runs a script, `call` calls what it
+//! put in `$exports`, and the `_json` variants exchange values as JSON:
+//! `{"ok": value}` or `{"error": message}`.
+//!
+//! On wasm32 these are exported to JavaScript with strings passed as pointer and length
+//! into the module's memory, allocated with `jazz_alloc`:
+//!
+//! ```js
+//! const { jazz_alloc, jazz_free, jazz_eval, memory } = instance.exports;
+//! const src = new TextEncoder().encode('$exports.twice = fun(x) x * 2');
+//! const ptr = jazz_alloc(src.length);
+//! new Uint8Array(memory.buffer, ptr, src.length).set(src);
+//! const result = jazz_eval(ptr, src.length); // u32 length, then the JSON text
+//! jazz_free(ptr, src.length);
+//! ```
+//!
+//! Build with `--no-default-features` there, see the features of `jazzlight`.
+
+use crate::codegen::{compile, module_from_context};
+use crate::fuzz::catch_panic;
+use crate::macros::expand;
+use crate::optimizer::optimize;
+use crate::parser::Parser;
+use crate::reader::Reader;
+use jazzlight::get_vm;
+use jazzlight::interp::{error_message, val_call, Stop, Vm, VM};
+use jazzlight::json::{from_json, to_json};
+use jazzlight::reader::BytecodeReader;
+use jazzlight::value::Value;
+use jazzlight::writer::BytecodeWriter;
+use jazzlight::{Module, Ref, RefCell};
+
+use std::panic;
+
+thread_local! {
+    /// Module of the last script `eval` ran.
+    static CURRENT: RefCell<Option<Ref<Module>>> = const { RefCell::new(None) };
+}
+
+fn load(src: &str) -> Result<Ref<Module>, String> {
+    let mut ast = vec![];
+    Parser::new(Reader::from_string(src), &mut ast)
+        .parse()
+        .map_err(|e| e.to_string())?;
+    let ast = expand(ast).map_err(|e| e.to_string())?;
+    let mut ctx = compile(optimize(ast), false, false, false);
+    if let Some(e) = ctx.g.borrow().errors.first() {
+        return Err(e.to_string());
+    }
+    let mut w = BytecodeWriter { bytecode: vec![] };
+    w.write_module(module_from_context(&mut ctx));
+    Ok(BytecodeReader::new(&w.bytecode).read_module())
+}
+
+/// Runs `f` on a fresh VM, with uncaught exceptions and `$exit` as errors. Modules keep
+/// their globals.
+fn run(f: impl FnOnce() -> Result<Value, Value>) -> Result<Value, String> {
+    *get_vm!() = Vm::new();
+    let result = catch_panic(|| {
+        let value = get_vm!().protect(|vm| {
+            vm.save_state_exit();
+            let value = f();
+            match vm.pending.take() {
+                Some(e) => Err(e),
+                None => value,
+            }
+        });
+        jazzlight::builtins::promise::run_event_loop();
+        value
+    });
+    match result {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(e)) => Err(error_message(&e)),
+        Err(payload) => match payload.downcast_ref::<Stop>() {
+            Some(&Stop::Exit(code)) => Err(format!("exited with code {}", code)),
+            _ => panic::resume_unwind(payload),
+        },
+    }
+}
+
+/// Compiles and runs the script `src`, returning its value. Its exports are what `call`
+/// calls afterwards.
+pub fn eval(src: &str) -> Result<Value, String> {
+    let m = load(src)?;
+    CURRENT.with(|current| *current.borrow_mut() = Some(m.clone()));
+    run(|| Ok(get_vm!().interp(m)))
+}
+
+/// Calls the function the last script `eval` ran exports as `name`.
+pub fn call(name: &str, args: &[Value]) -> Result<Value, String> {
+    let m = match CURRENT.with(|current| current.borrow().clone()) {
+        Some(m) => m,
+        None => return Err("call: no script was evaluated".to_owned()),
+    };
+    run(|| {
+        let f = match &m.borrow().exports {
+            Value::Object(exports) => exports.borrow().get(Value::String(Ref(name.to_owned()))),
+            _ => None,
+        };
+        match f {
+            Some(f) => val_call(f, args),
+            None => Err(Value::String(Ref(format!(
+                "Module has no export '{}'",
+                name
+            )))),
+        }
+    })
+}
+
+fn outcome_json(outcome: Result<Value, String>) -> String {
+    match outcome.and_then(|value| to_json(&value)) {
+        Ok(json) => format!("{{\"ok\":{}}}", json),
+        // Strings always convert.
+        Err(e) => format!("{{\"error\":{}}}", to_json(&Value::String(Ref(e))).unwrap()),
+    }
+}
+
+/// `eval` with the outcome as JSON.
+pub fn eval_json(src: &str) -> String {
+    outcome_json(eval(src))
+}
+
+/// `call` with the arguments as a JSON array and the outcome as JSON.
+pub fn call_json(name: &str, args: &str) -> String {
+    let args = match from_json(args) {
+        Ok(Value::Array(args)) => args.borrow().to_vec(),
+        Ok(_) => return outcome_json(Err("call: array of arguments expected".to_owned())),
+        Err(e) => return outcome_json(Err(format!("call: {}", e))),
+    };
+    outcome_json(call(name, &args))
+}
+
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    use std::alloc::{alloc, dealloc, Layout};
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len.max(1), 4).unwrap()
+    }
+
+    unsafe fn text<'a>(ptr: *const u8, len: usize) -> &'a str {
+        std::str::from_utf8(std::slice::from_raw_parts(ptr, len)).unwrap_or("")
+    }
+
+    /// `json` preceded by its length as a little-endian u32, freed with `jazz_free` with
+    /// the length plus 4.
+    fn result(json: String) -> *mut u8 {
+        let bytes = json.into_bytes();
+        let ptr = jazz_alloc(bytes.len() + 4);
+        unsafe {
+            let out = std::slice::from_raw_parts_mut(ptr, bytes.len() + 4);
+            out[..4].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+            out[4..].copy_from_slice(&bytes);
+        }
+        ptr
+    }
+
+    #[no_mangle]
+    pub extern "C" fn jazz_alloc(len: usize) -> *mut u8 {
+        unsafe { alloc(layout(len)) }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn jazz_free(ptr: *mut u8, len: usize) {
+        dealloc(ptr, layout(len))
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn jazz_eval(src: *const u8, len: usize) -> *mut u8 {
+        result(super::eval_json(text(src, len)))
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn jazz_call(
+        name: *const u8,
+        name_len: usize,
+        args: *const u8,
+        args_len: usize,
+    ) -> *mut u8 {
+        result(super::call_json(text(name, name_len), text(args, args_len)))
+    }
+}
@@ -0,0 +1,357 @@
+//! Entry points for fuzz targets: compile and run arbitrary input, reporting every failure,
+//! panics included, as a `FuzzError`.
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     if let Err(e) = jazzlightc::fuzz::run_differential(data, 100_000) {
+//!         assert!(!e.is_bug(), "{:?}", e);
+//!     }
+//! });
+//! ```
+
+use crate::codegen::{compile, module_from_context};
+use crate::macros::expand;
+use crate::msg::Msg;
+use crate::optimizer::optimize;
+use crate::parser::Parser;
+use crate::reader::Reader;
+use jazzlight::get_vm;
+use jazzlight::interp::{Stop, Vm, VM};
+use jazzlight::reader::BytecodeReader;
+use jazzlight::value::Value;
+use jazzlight::writer::BytecodeWriter;
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Nesting of arrays and objects shown by `describe`, deeper values may be cyclic.
+const MAX_DEPTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Parse,
+    Expand,
+    Compile,
+    Bytecode,
+    Run,
+}
+
+/// How a program that compiled ended, with values as `describe` shows them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Returned(String),
+    Threw(String),
+    Exited(i32),
+}
+
+#[derive(Debug)]
+pub enum FuzzError {
+    NotUtf8,
+    /// Diagnostics of input the compiler rejects.
+    Rejected(Stage, Vec<String>),
+    Panic(Stage, String),
+    /// Diagnostics of the compiler's own checks of the code it generated, like its stack
+    /// effects.
+    Internal(Vec<String>),
+    /// The program ran more instructions than allowed.
+    OutOfFuel,
+    /// The optimized and unoptimized code ended differently.
+    Mismatch {
+        optimized: Outcome,
+        unoptimized: Outcome,
+    },
+}
+
+impl FuzzError {
+    /// Whether the error is a bug in the compiler or the VM rather than in the input.
+    pub fn is_bug(&self) -> bool {
+        matches!(
+            self,
+            FuzzError::Panic(..) | FuzzError::Internal(_) | FuzzError::Mismatch { .. }
+        )
+    }
+}
+
+/// `value` shown the same way whatever code produced it, without function addresses.
+pub fn describe(value: &Value) -> String {
+    fn show(value: &Value, depth: usize) -> String {
+        if depth == MAX_DEPTH {
+            return "...".to_owned();
+        }
+        match value {
+            Value::Array(array) => {
+                let items = array
+                    .borrow()
+                    .iter()
+                    .map(|x| show(&x, depth + 1))
+                    .collect::<Vec<_>>();
+                format!("[{}]", items.join(", "))
+            }
+            Value::Object(object) => {
+                let fields = object
+                    .borrow()
+                    .table
+                    .iter()
+                    .map(|(k, v)| format!("{} => {}", show(k, depth + 1), show(v, depth + 1)))
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", fields.join(", "))
+            }
+            Value::String(s) => format!("{:?}", s.borrow()),
+            Value::Function(_) => "function".to_owned(),
+            value => format!("{}", value),
+        }
+    }
+    show(value, 0)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "panic".to_owned(),
+        },
+    }
+}
+
+/// Runs `f` without printing the message of a panic, which is returned instead.
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, Box<dyn Any + Send>> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    panic::set_hook(hook);
+    result
+}
+
+fn stage<T>(stage: Stage, f: impl FnOnce() -> Result<T, Vec<String>>) -> Result<T, FuzzError> {
+    match catch_panic(f) {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(diagnostics)) => Err(FuzzError::Rejected(stage, diagnostics)),
+        Err(payload) => Err(FuzzError::Panic(stage, panic_message(&*payload))),
+    }
+}
+
+/// Compiles `src` to bytecode and loads it back, as `jazzlightc` and `jazzlight` would.
+fn build(src: &str, optimized: bool) -> Result<Vec<u8>, FuzzError> {
+    let ast = stage(Stage::Parse, || {
+        let mut ast = vec![];
+        match Parser::new(Reader::from_string(src), &mut ast).parse() {
+            Ok(()) => Ok(ast),
+            Err(e) => Err(vec![e.to_string()]),
+        }
+    })?;
+    let ast = stage(Stage::Expand, || {
+        expand(ast).map_err(|e| vec![e.to_string()])
+    })?;
+    let compiled = stage(Stage::Compile, || {
+        let ast = if optimized { optimize(ast) } else { ast };
+        let mut ctx = compile(ast, false, false, optimized);
+        let g = ctx.g.borrow();
+        let errors = g.errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        if g.errors
+            .iter()
+            .any(|e| matches!(e.msg, Msg::StackEffect(_)))
+        {
+            return Ok(Err(errors));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        drop(g);
+        let mut w = BytecodeWriter { bytecode: vec![] };
+        w.write_module(module_from_context(&mut ctx));
+        Ok(Ok(w.bytecode))
+    })?;
+    compiled.map_err(FuzzError::Internal)
+}
+
+/// Runs the program in `bytecode` on a fresh VM for at most `fuel` instructions. Promises
+/// left pending aren't run.
+fn run(bytecode: &[u8], fuel: u64) -> Result<Outcome, FuzzError> {
+    let m = stage(Stage::Bytecode, || {
+        Ok(BytecodeReader::new(bytecode).read_module())
+    })?;
+    let vm = get_vm!();
+    *vm = Vm::new();
+    vm.fuel = Some(fuel);
+    let result = catch_panic(|| {
+        get_vm!().protect(|vm| {
+            vm.save_state_exit();
+            let value = vm.interp(m);
+            match vm.pending.take() {
+                Some(e) => Err(e),
+                None => Ok(value),
+            }
+        })
+    });
+    get_vm!().fuel = None;
+    match result {
+        Ok(Ok(value)) => Ok(Outcome::Returned(describe(&value))),
+        Ok(Err(e)) => Ok(Outcome::Threw(describe(&e))),
+        Err(payload) => match payload.downcast_ref::<Stop>() {
+            Some(&Stop::OutOfFuel) => Err(FuzzError::OutOfFuel),
+            Some(&Stop::Exit(code)) => Ok(Outcome::Exited(code)),
+            None => Err(FuzzError::Panic(Stage::Run, panic_message(&*payload))),
+        },
+    }
+}
+
+/// Compiles `data` as a program with all optimizations and runs it for at most `fuel`
+/// instructions.
+pub fn run_bytes(data: &[u8], fuel: u64) -> Result<Outcome, FuzzError> {
+    let src = std::str::from_utf8(data).map_err(|_| FuzzError::NotUtf8)?;
+    run(&build(src, true)?, fuel)
+}
+
+/// `run_bytes` with and without the optimizers, `FuzzError::Mismatch` if they end
+/// differently.
+pub fn run_differential(data: &[u8], fuel: u64) -> Result<Outcome, FuzzError> {
+    let src = std::str::from_utf8(data).map_err(|_| FuzzError::NotUtf8)?;
+    let optimized = run(&build(src, true)?, fuel)?;
+    let unoptimized = run(&build(src, false)?, fuel)?;
+    if optimized != unoptimized {
+        return Err(FuzzError::Mismatch {
+            optimized,
+            unoptimized,
+        });
+    }
+    Ok(optimized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn returned(src: &str) -> String {
+        match run_bytes(src.as_bytes(), 1_000_000) {
+            Ok(Outcome::Returned(value)) => value,
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn globals_keep_their_slots_through_bytecode() {
+        let src = "
+            var a = 1
+            var b = \"two\"
+            var c = 3.5
+            return $array(a, b, c)
+        ";
+        assert_eq!(returned(src), "[1, \"two\", 3.5]");
+    }
+
+    #[test]
+    fn closures_of_one_literal_keep_their_own_env() {
+        let src = "
+            var make = function(x) { return function() { return x } }
+            var a = make(1)
+            var b = make(2)
+            return $array(a(), b(), a())
+        ";
+        assert_eq!(returned(src), "[1, 2, 1]");
+    }
+
+    #[test]
+    fn calls_leave_the_stack_as_they_found_it() {
+        let src = "
+            var first = function(a, o) {
+                o.field
+                foreach x in a {
+                    return x
+                }
+                return null
+            }
+            var o = $new(null)
+            var total = 0
+            var i = 0
+            while i < 100 {
+                total = total + first($array(i, 0), o)
+                i = i + 1
+            }
+            return $array(total, first($array(7), o) + 1)
+        ";
+        assert_eq!(returned(src), "[4950, 8]");
+    }
+
+    #[test]
+    fn matches_on_constants_jump_through_a_table() {
+        let src = "
+            var name = function(n) {
+                return match n {
+                    1 -> \"one\",
+                    2 | 3 -> \"few\",
+                    4 -> \"four\",
+                    5 -> \"five\",
+                    other -> other * 10
+                }
+            }
+            var kind = function(s) {
+                return match s {
+                    \"a\" -> 1,
+                    \"b\" -> 2,
+                    \"c\" -> 3,
+                    \"d\" -> 4
+                }
+            }
+            return $array(name(1), name(3), name(5), name(6), kind(\"d\"), kind(\"e\"))
+        ";
+        assert_eq!(returned(src), "[\"one\", \"few\", \"five\", 60, 4, null]");
+    }
+
+    #[test]
+    fn question_mark_runs_deferred_code() {
+        let src = "
+            enum Result { Ok(value), Err(error) }
+            var cleaned = 0
+            var half = function(n) {
+                if n % 2 == 0 {
+                    return Ok(n / 2)
+                }
+                return Err(\"odd\")
+            }
+            var quarter = function(n) {
+                defer cleaned = cleaned + 1
+                var h = half(n)?
+                return Ok($array(half(h)?, h))
+            }
+            var show = function(r) {
+                return match r { Ok(v) -> v, Err(e) -> e }
+            }
+            var a = show(quarter(8))
+            var b = show(quarter(6))
+            var c = show(quarter(3))
+            return $array(a, b, c, cleaned)
+        ";
+        assert_eq!(returned(src), "[[2, 4], \"odd\", \"odd\", 3]");
+        match run_differential(src.as_bytes(), 1_000_000) {
+            Ok(_) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn internal_errors_are_bugs() {
+        assert!(FuzzError::Internal(vec![]).is_bug());
+        // A seed that the jump table for constant arms once failed the stack check with.
+        let src = "
+            var total = 0
+            var i = 0
+            while i < 8 {
+                var add = match i % 6 {
+                    0 -> 1,
+                    1 | 2 -> 10,
+                    3 -> 100,
+                    4 -> 1000,
+                    n -> n * 10000
+                }
+                total = total + add
+                i = i + 1
+            }
+            return total
+        ";
+        match run_differential(src.as_bytes(), 100_000) {
+            Ok(Outcome::Returned(total)) => assert_eq!(total, "51132"),
+            result => panic!("{:?}", result),
+        }
+    }
+}
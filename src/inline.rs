@@ -0,0 +1,175 @@
+//! Inlining of calls to small functions, run with `--opt=2` once the whole module is
+//! compiled. A function is inlined when
+//!
+//! - the global holding it is never assigned anything else,
+//! - its body is straight-line code of at most `MAX_SIZE` instructions ending in `Ret`,
+//! - it doesn't read its environment or `this`, yield, throw or refer to itself, and only
+//!   reads locals it stored or its parameters.
+//!
+//! `LoadGlobal(f); Call(n)` with `n` the number of parameters of `f` then becomes stores of
+//! the arguments into unused locals of the caller followed by the body of `f` using those
+//! locals. Calls inside the inlined body stay calls, so inlining goes one level deep.
+
+use crate::codegen::{FunctionCode, UOP};
+use crate::stack::stack_effect;
+use jazzlight::opcode::Op;
+
+use std::collections::{HashMap, HashSet};
+
+/// Largest number of instructions of an inlined body, not counting its `Ret`.
+pub const MAX_SIZE: usize = 16;
+
+/// Body of a function that can be inlined.
+struct Body {
+    argc: u16,
+    /// Number of locals the body uses, parameters included.
+    slots: u16,
+    ops: Vec<(Op, (i32, i32))>,
+}
+
+pub struct Inliner {
+    /// Inlinable functions by the global holding them.
+    bodies: HashMap<u32, Body>,
+}
+
+impl Inliner {
+    /// Finds the inlinable ones among the `functions` of a module, `module` being its
+    /// top-level code.
+    pub fn new(functions: &[FunctionCode], module: &[UOP]) -> Inliner {
+        let mut assigned = HashSet::new();
+        for code in functions.iter().map(|f| &f.0[..]).chain(Some(module)) {
+            let mut previous: Option<&UOP> = None;
+            for op in code.iter() {
+                if let UOP::Op(Op::StoreGlobal(g)) = op {
+                    // Declarations store the function into its own global.
+                    if !matches!(previous, Some(UOP::Op(Op::LoadGlobal(f))) if f == g) {
+                        assigned.insert(*g);
+                    }
+                }
+                previous = Some(op);
+            }
+        }
+        let bodies = functions
+            .iter()
+            .filter(|(_, _, gid, _)| !assigned.contains(&(*gid as u32)))
+            .filter_map(|(ops, pos, gid, argc)| {
+                let body = body(ops, pos, *gid as u32, *argc as u16)?;
+                Some((*gid as u32, body))
+            })
+            .collect();
+        Inliner { bodies }
+    }
+
+    /// Inlines the calls to inlinable functions in `code`, returns whether it had any.
+    /// `params` is the number of parameters of the function `code` belongs to.
+    pub fn inline(&self, code: &mut Vec<UOP>, pos: &mut Vec<(i32, i32)>, params: usize) -> bool {
+        // Inlined bodies run one after the other, they can all use the same locals.
+        let base = code
+            .iter()
+            .filter_map(|op| match op {
+                UOP::Op(Op::LoadLocal(slot)) | UOP::Op(Op::StoreLocal(slot)) => {
+                    Some(*slot as usize + 1)
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+            .max(params);
+        let mut out = Vec::with_capacity(code.len());
+        let mut out_pos = Vec::with_capacity(code.len());
+        let mut changed = false;
+        let mut pc = 0;
+        while pc < code.len() {
+            let body = match (&code[pc], code.get(pc + 1)) {
+                (UOP::Op(Op::LoadGlobal(g)), Some(UOP::Op(Op::Call(argc))))
+                | (UOP::Op(Op::LoadGlobal(g)), Some(UOP::Op(Op::TailCall(argc)))) => self
+                    .bodies
+                    .get(g)
+                    .filter(|body| body.argc == *argc)
+                    .filter(|body| base + body.slots as usize <= u16::MAX as usize),
+                _ => None,
+            };
+            let body = match body {
+                Some(body) => body,
+                None => {
+                    out.push(code[pc].clone());
+                    out_pos.push(pos[pc]);
+                    pc += 1;
+                    continue;
+                }
+            };
+            let local = |slot: u16| (base + slot as usize) as u16;
+            // The first argument is on top.
+            for arg in 0..body.argc {
+                out.push(UOP::Op(Op::StoreLocal(local(arg))));
+                out_pos.push(pos[pc + 1]);
+            }
+            for (op, at) in body.ops.iter() {
+                let op = match op {
+                    Op::LoadLocal(slot) => Op::LoadLocal(local(*slot)),
+                    Op::StoreLocal(slot) => Op::StoreLocal(local(*slot)),
+                    // A tail call would leave the caller.
+                    Op::TailCall(argc) => Op::Call(*argc),
+                    op => op.clone(),
+                };
+                out.push(UOP::Op(op));
+                out_pos.push(*at);
+            }
+            changed = true;
+            pc += 2;
+        }
+        if changed {
+            *code = out;
+            *pos = out_pos;
+        }
+        changed
+    }
+}
+
+/// The body of the function in global `gid` if it can be inlined.
+fn body(code: &[UOP], pos: &[(i32, i32)], gid: u32, argc: u16) -> Option<Body> {
+    let mut ops = vec![];
+    let mut stored: HashSet<u16> = (0..argc).collect();
+    let mut height = 0;
+    for (op, at) in code.iter().zip(pos.iter()) {
+        let op = match op {
+            // Nothing jumps to labels in straight-line code.
+            UOP::Label(_) => continue,
+            UOP::Op(op) => op,
+            _ => return None,
+        };
+        match op {
+            Op::Ret if height == 1 => {
+                let slots = stored.iter().max().map_or(0, |slot| slot + 1);
+                return Some(Body { argc, slots, ops });
+            }
+            Op::LoadLocal(slot) if !stored.contains(slot) => return None,
+            Op::StoreLocal(slot) => {
+                stored.insert(*slot);
+            }
+            Op::LoadGlobal(g) if *g == gid => return None,
+            Op::Ret
+            | Op::LoadEnv(_)
+            | Op::StoreEnv(_)
+            | Op::LoadThis
+            | Op::StoreThis
+            | Op::Jump(_)
+            | Op::JumpIf(_)
+            | Op::JumpIfNot(_)
+            | Op::CatchPush(_)
+            | Op::CatchPop
+            | Op::Switch(..)
+            | Op::Throw
+            | Op::Yield
+            | Op::Last => return None,
+            _ => (),
+        }
+        let (pops, pushes) = stack_effect(op);
+        if pops > height || ops.len() == MAX_SIZE {
+            return None;
+        }
+        height = height - pops + pushes;
+        ops.push((op.clone(), *at));
+    }
+    None
+}
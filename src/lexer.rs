@@ -1,16 +1,26 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use hmap::hmap;
 
 use crate::{
     msg::{Msg, MsgWithPos},
     reader::Reader,
+    session::CompileSession,
     token::*,
 };
 
 pub struct Lexer {
     reader: Reader,
     keywords: HashMap<&'static str, TokenKind>,
+    /// Whether the last token was `.`, the word after it is a field name even if it is a
+    /// keyword, as in `p.catch(f)`.
+    after_dot: bool,
+    /// Byte offset where the last token starts.
+    start: usize,
+    /// Lines of the `///` comments before the last token.
+    doc: Vec<String>,
+    session: Rc<CompileSession>,
 }
 
 impl Lexer {
@@ -20,10 +30,19 @@ impl Lexer {
     }
 
     pub fn new(reader: Reader) -> Lexer {
+        Lexer::with_session(reader, Rc::new(CompileSession::default()))
+    }
+
+    /// Lexer storing the text of its tokens in `session`, shared with other lexers.
+    pub fn with_session(reader: Reader, session: Rc<CompileSession>) -> Lexer {
         let keywords = hmap!(
             "yield" => TokenKind::Yield,
+            "defer" => TokenKind::Defer,
+            "using" => TokenKind::Using,
+            "delete" => TokenKind::Delete,
             "this" => TokenKind::This,
             "function" => TokenKind::Fun,
+            "fun" => TokenKind::Fun,
             "func" => TokenKind::Fun,
             "let" => TokenKind::Let,
             "var" => TokenKind::Var,
@@ -35,9 +54,12 @@ impl Lexer {
             "in" => TokenKind::In,
             "loop" => TokenKind::Loop,
             "break" => TokenKind::Break,
-            "switch" => TokenKind::Match,
+            "switch" => TokenKind::Switch,
+            "match" => TokenKind::Match,
             "continue" => TokenKind::Continue,
             "const" => TokenKind::Const,
+            "enum" => TokenKind::Enum,
+            "instanceof" => TokenKind::InstanceOf,
             "return" => TokenKind::Return,
             "true" => TokenKind::True,
             "false" => TokenKind::False,
@@ -51,12 +73,17 @@ impl Lexer {
             "catch" => TokenKind::Catch,
             "include" => TokenKind::Include,
             "for" => TokenKind::For,
-            "goto" => TokenKind::Goto
+            "goto" => TokenKind::Goto,
+            "macro" => TokenKind::Macro
         );
 
         Lexer {
             reader: reader,
             keywords: keywords,
+            after_dot: false,
+            start: 0,
+            doc: vec![],
+            session,
         }
     }
     pub fn path(&self) -> String {
@@ -66,6 +93,25 @@ impl Lexer {
         self.reader.filename().to_owned()
     }
 
+    /// Byte offsets of the last token read.
+    pub fn span(&self) -> (usize, usize) {
+        (self.start, self.reader.offset())
+    }
+
+    /// The source read so far, which covers every token read.
+    pub fn src(&self) -> &str {
+        &self.reader.src
+    }
+
+    /// Text of the `///` comments right before the last token, if there were any.
+    pub fn take_doc(&mut self) -> Option<String> {
+        if self.doc.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.doc).join("\n"))
+        }
+    }
+
     fn read_multi_comment(&mut self) -> Result<(), MsgWithPos> {
         let pos = self.reader.pos();
 
@@ -87,13 +133,19 @@ impl Lexer {
     }
 
     pub fn read_token(&mut self) -> Result<Token, MsgWithPos> {
+        let after_dot = std::mem::replace(&mut self.after_dot, false);
+        self.doc.clear();
         loop {
             self.skip_white();
 
+            self.start = self.reader.offset();
             let pos = self.reader.pos();
             let ch = self.cur();
 
             if let None = ch {
+                if self.reader.error().is_some() {
+                    return Err(MsgWithPos::new(self.path(), pos, Msg::IoError));
+                }
                 return Ok(Token::new(TokenKind::End, pos));
             }
 
@@ -103,19 +155,21 @@ impl Lexer {
                 self.read_comment()?;
             } else if self.is_multi_comment_start() {
                 self.read_multi_comment()?;
+            } else if ch == Some('r') && is_quote(self.next()) {
+                return self.read_string(true);
             } else if is_identifier_start(ch) {
-                return self.read_identifier();
+                return self.read_identifier(!after_dot);
             } else if is_quote(ch) {
-                return self.read_string();
+                return self.read_string(false);
             } else if is_char_quote(ch) {
                 return self.read_char_literal();
             } else if is_operator(ch) {
                 return self.read_operator();
             } else if ch == Some('$') {
                 self.read_char();
-                let tok = self.read_identifier()?;
+                let tok = self.read_identifier(true)?;
                 if let TokenKind::Identifier(ident) = tok.kind {
-                    return Ok(Token::new(TokenKind::Builtin(ident.clone()), pos));
+                    return Ok(Token::new(TokenKind::Builtin(ident), pos));
                 } else {
                     return Err(MsgWithPos::new(
                         self.path(),
@@ -123,6 +177,20 @@ impl Lexer {
                         Msg::ExpectedIdentifier("builtin".into()),
                     ));
                 }
+            } else if ch == Some('@') && self.next() == Some('@') {
+                self.read_char();
+                self.read_char();
+                let tok = self.read_identifier(false)?;
+                if let TokenKind::Identifier(ident) = tok.kind {
+                    let name = self.session.intern(&format!("@@{}", ident));
+                    return Ok(Token::new(TokenKind::Builtin(name), pos));
+                } else {
+                    return Err(MsgWithPos::new(
+                        self.path(),
+                        pos,
+                        Msg::ExpectedIdentifier("symbol".into()),
+                    ));
+                }
             } else {
                 let ch = ch.unwrap();
 
@@ -137,25 +205,27 @@ impl Lexer {
         }
     }
 
-    fn read_identifier(&mut self) -> Result<Token, MsgWithPos> {
+    fn read_identifier(&mut self, keywords: bool) -> Result<Token, MsgWithPos> {
         let pos = self.reader.pos();
-        let mut value = String::new();
+        let start = self.reader.offset();
 
         while is_identifier(self.cur()) {
-            let ch = self.cur().unwrap();
             self.read_char();
-            value.push(ch);
         }
+        let value = &self.reader.src[start..self.reader.offset()];
 
-        let lookup = self.keywords.get(&value[..]).cloned();
+        let lookup = match keywords {
+            true => self.keywords.get(value).cloned(),
+            false => None,
+        };
         let ttype;
 
         if let Some(tok_type) = lookup {
             ttype = tok_type;
-        } else if value == "_".to_string() {
+        } else if value == "_" {
             ttype = TokenKind::Underscore;
         } else {
-            ttype = TokenKind::Identifier(value);
+            ttype = TokenKind::Identifier(self.session.intern(value));
         }
 
         Ok(Token::new(ttype, pos))
@@ -178,6 +248,8 @@ impl Lexer {
     }
 
     fn read_escaped_char(&mut self, pos: Position, unclosed: Msg) -> Result<char, MsgWithPos> {
+        let escape_pos = self.reader.pos();
+
         if let Some(ch) = self.cur() {
             self.read_char();
 
@@ -198,9 +270,11 @@ impl Lexer {
                     '\"' => Ok('\"'),
                     '\'' => Ok('\''),
                     '0' => Ok('\0'),
+                    'x' => self.read_hex_escape(escape_pos),
+                    'u' => self.read_unicode_escape(escape_pos),
                     _ => {
                         let msg = Msg::InvalidEscapeSequence(ch);
-                        Err(MsgWithPos::new(self.filename(), pos, msg))
+                        Err(MsgWithPos::new(self.filename(), escape_pos, msg))
                     }
                 }
             } else {
@@ -211,21 +285,89 @@ impl Lexer {
         }
     }
 
-    fn read_string(&mut self) -> Result<Token, MsgWithPos> {
+    /// `\xNN`: exactly two hex digits, i.e. U+0000 to U+00FF.
+    fn read_hex_escape(&mut self, pos: Position) -> Result<char, MsgWithPos> {
+        let mut value = 0;
+
+        for _ in 0..2 {
+            match self.cur().and_then(|ch| ch.to_digit(16)) {
+                Some(digit) => {
+                    self.read_char();
+                    value = value * 16 + digit;
+                }
+                None => return Err(MsgWithPos::new(self.filename(), pos, Msg::InvalidHexEscape)),
+            }
+        }
+
+        Ok(std::char::from_u32(value).unwrap())
+    }
+
+    /// `\u{N}`: one to six hex digits naming a unicode scalar value.
+    fn read_unicode_escape(&mut self, pos: Position) -> Result<char, MsgWithPos> {
+        let mut digits = String::new();
+
+        if self.cur() == Some('{') {
+            self.read_char();
+
+            while let Some(ch) = self.cur() {
+                if ch == '}' || !ch.is_ascii_hexdigit() {
+                    break;
+                }
+                self.read_char();
+                digits.push(ch);
+            }
+
+            if self.cur() == Some('}') && !digits.is_empty() && digits.len() <= 6 {
+                self.read_char();
+
+                let ch = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(std::char::from_u32);
+                if let Some(ch) = ch {
+                    return Ok(ch);
+                }
+            }
+        }
+
+        let msg = Msg::InvalidUnicodeEscape(digits);
+        Err(MsgWithPos::new(self.filename(), pos, msg))
+    }
+
+    /// Reads `"..."`, or `"""..."""` which may contain unescaped quotes. Raw strings (`r"..."`)
+    /// keep backslashes as written.
+    fn read_string(&mut self, raw: bool) -> Result<Token, MsgWithPos> {
         let pos = self.reader.pos();
         let mut value = String::new();
 
-        self.read_char();
-
-        while !self.cur().is_none() && !is_quote(self.cur()) {
-            let ch = self.read_escaped_char(pos.clone(), Msg::UnclosedString)?;
-            value.push(ch);
+        if raw {
+            self.read_char();
         }
 
-        if is_quote(self.cur()) {
+        let quote = if self.reader.starts_with("\"\"\"") {
+            "\"\"\""
+        } else {
+            "\""
+        };
+        for _ in 0..quote.len() {
             self.read_char();
+        }
+
+        while !self.cur().is_none() && !self.reader.starts_with(quote) {
+            if raw {
+                value.push(self.cur().unwrap());
+                self.read_char();
+            } else {
+                let ch = self.read_escaped_char(pos.clone(), Msg::UnclosedString)?;
+                value.push(ch);
+            }
+        }
 
-            let ttype = TokenKind::String(value);
+        if self.reader.starts_with(quote) {
+            for _ in 0..quote.len() {
+                self.read_char();
+            }
+
+            let ttype = TokenKind::String(self.session.intern(&value));
             Ok(Token::new(ttype, pos))
         } else {
             Err(MsgWithPos::new(self.filename(), pos, Msg::UnclosedString))
@@ -240,17 +382,34 @@ impl Lexer {
         let nch = self.cur().unwrap_or('x');
 
         tok.kind = match ch {
-            '+' => TokenKind::Add,
+            '+' => {
+                if nch == '+' {
+                    self.read_char();
+                    TokenKind::Inc
+                } else {
+                    TokenKind::Add
+                }
+            }
             '-' => {
                 if nch == '>' {
                     self.read_char();
                     TokenKind::Arrow
+                } else if nch == '-' {
+                    self.read_char();
+                    TokenKind::Dec
                 } else {
                     TokenKind::Sub
                 }
             }
 
-            '*' => TokenKind::Mul,
+            '*' => {
+                if nch == '*' {
+                    self.read_char();
+                    TokenKind::Pow
+                } else {
+                    TokenKind::Mul
+                }
+            }
             '/' => TokenKind::Div,
             '%' => TokenKind::Mod,
 
@@ -281,6 +440,7 @@ impl Lexer {
 
             '^' => TokenKind::Caret,
             '~' => TokenKind::Tilde,
+            '?' => TokenKind::Question,
             ',' => TokenKind::Comma,
             ';' => TokenKind::Semicolon,
             ':' => {
@@ -291,11 +451,19 @@ impl Lexer {
                     TokenKind::Colon
                 }
             }
-            '.' => TokenKind::Dot,
+            '.' => {
+                self.after_dot = true;
+                TokenKind::Dot
+            }
             '=' => {
                 if nch == '=' {
                     self.read_char();
-                    TokenKind::EqEq
+                    if self.cur() == Some('=') {
+                        self.read_char();
+                        TokenKind::EqEqEq
+                    } else {
+                        TokenKind::EqEq
+                    }
                 } else {
                     TokenKind::Eq
                 }
@@ -333,7 +501,12 @@ impl Lexer {
             '!' => {
                 if nch == '=' {
                     self.read_char();
-                    TokenKind::Ne
+                    if self.cur() == Some('=') {
+                        self.read_char();
+                        TokenKind::NeEq
+                    } else {
+                        TokenKind::Ne
+                    }
                 } else {
                     TokenKind::Not
                 }
@@ -352,9 +525,17 @@ impl Lexer {
     }
 
     fn read_comment(&mut self) -> Result<(), MsgWithPos> {
+        let start = self.reader.offset();
         while !self.cur().is_none() && !is_newline(self.cur()) {
             self.read_char();
         }
+        let text = &self.reader.src[start..self.reader.offset()];
+        // `////...` lines are ordinary comments.
+        if text.starts_with("///") && !text.starts_with("////") {
+            let line = &text[3..];
+            let line = line.strip_prefix(' ').unwrap_or(line);
+            self.doc.push(line.trim_end().to_owned());
+        }
 
         Ok(())
     }
@@ -367,6 +548,29 @@ impl Lexer {
         }
     }
 
+    /// Reads an exponent like `e9` or `E-3` if one follows.
+    fn read_exponent(&mut self, buffer: &mut String) -> bool {
+        if self.cur() != Some('e') && self.cur() != Some('E') {
+            return false;
+        }
+
+        let sign = self.next() == Some('+') || self.next() == Some('-');
+        if !is_digit(self.reader.peek(if sign { 2 } else { 1 })) {
+            return false;
+        }
+
+        buffer.push(self.cur().unwrap());
+        self.read_char();
+
+        if sign {
+            buffer.push(self.cur().unwrap());
+            self.read_char();
+        }
+
+        self.read_digits(buffer, IntBase::Dec);
+        true
+    }
+
     fn read_char(&mut self) {
         self.reader.advance();
     }
@@ -417,6 +621,13 @@ impl Lexer {
                     IntBase::Bin
                 }
 
+                Some('o') => {
+                    self.read_char();
+                    self.read_char();
+
+                    IntBase::Oct
+                }
+
                 _ => IntBase::Dec,
             }
         } else {
@@ -430,24 +641,35 @@ impl Lexer {
             value.push('.');
 
             self.read_digits(&mut value, IntBase::Dec);
+            self.read_exponent(&mut value);
 
-            if self.cur() == Some('e') || self.cur() == Some('E') {
-                value.push(self.cur().unwrap());
-                self.read_char();
-
-                if self.cur() == Some('+') || self.cur() == Some('-') {
-                    value.push(self.cur().unwrap());
-                    self.read_char();
-                }
-
-                self.read_digits(&mut value, IntBase::Dec);
-            }
+            let ttype = TokenKind::LitFloat(value);
+            return Ok(Token::new(ttype, pos));
+        }
 
+        if base == IntBase::Dec && self.read_exponent(&mut value) {
             let ttype = TokenKind::LitFloat(value);
             return Ok(Token::new(ttype, pos));
         }
 
-        let ttype = TokenKind::LitInt(value, base, IntSuffix::Int);
+        let suffix = match self.cur() {
+            Some(ch) if !is_identifier(self.next()) => match ch {
+                'n' => Some(IntSuffix::BigInt),
+                'L' => Some(IntSuffix::Long),
+                'B' if base != IntBase::Hex => Some(IntSuffix::Byte),
+                _ => None,
+            },
+            _ => None,
+        };
+        let suffix = match suffix {
+            Some(suffix) => {
+                self.read_char();
+                suffix
+            }
+            None => IntSuffix::Int,
+        };
+
+        let ttype = TokenKind::LitInt(value, base, suffix);
         Ok(Token::new(ttype, pos))
     }
 }
@@ -461,6 +683,67 @@ fn is_digit_or_underscore(ch: Option<char>, base: IntBase) -> bool {
         .unwrap_or(false)
 }
 
+/// Every token of `source` together with the whitespace and comments between them, in
+/// order, so that the spans cover the whole source.
+pub fn tokenize_with_trivia(source: &str) -> Result<Vec<SpannedToken>, MsgWithPos> {
+    let mut lexer = Lexer::new(Reader::from_string(source));
+    // Walks the source to find the position of each piece.
+    let mut cursor = Reader::from_string(source);
+    let mut position = |offset: usize| {
+        while cursor.offset() < offset {
+            cursor.advance();
+        }
+        cursor.pos()
+    };
+    let mut tokens = vec![];
+    let mut end = 0;
+    loop {
+        let token = lexer.read_token()?;
+        let (start, token_end) = match token.kind {
+            TokenKind::End => (source.len(), source.len()),
+            _ => lexer.span(),
+        };
+        // Only whitespace and comments are skipped between two tokens.
+        while end < start {
+            let rest = &source[end..start];
+            let (kind, len) = if rest.starts_with("/*") {
+                (
+                    SpannedKind::Comment,
+                    rest.find("*/").map_or(rest.len(), |i| i + 2),
+                )
+            } else if rest.starts_with("//") {
+                let kind = if rest.starts_with("///") && !rest.starts_with("////") {
+                    SpannedKind::DocComment
+                } else {
+                    SpannedKind::Comment
+                };
+                (kind, rest.find('\n').unwrap_or(rest.len()))
+            } else {
+                let len = rest
+                    .find(|ch: char| !ch.is_whitespace())
+                    .filter(|&len| len > 0)
+                    .unwrap_or(rest.len());
+                (SpannedKind::Whitespace, len)
+            };
+            tokens.push(SpannedToken {
+                kind,
+                position: position(end),
+                span: (end, end + len),
+            });
+            end += len;
+        }
+        if token.kind == TokenKind::End {
+            return Ok(tokens);
+        }
+        tokens.push(SpannedToken {
+            kind: SpannedKind::Token(token.kind),
+            position: position(start),
+            span: (start, token_end),
+        });
+        end = token_end;
+    }
+}
+
 fn is_whitespace(ch: Option<char>) -> bool {
     ch.map(|ch| ch.is_whitespace()).unwrap_or(false)
 }
@@ -478,7 +761,7 @@ fn is_char_quote(ch: Option<char>) -> bool {
 }
 
 fn is_operator(ch: Option<char>) -> bool {
-    ch.map(|ch| "^+-*/%&|,=!~;:.()[]{}<>".contains(ch))
+    ch.map(|ch| "^+-*/%&|,=!~;:.()[]{}<>?".contains(ch))
         .unwrap_or(false)
 }
 
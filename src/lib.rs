@@ -1,11 +1,24 @@
 pub mod ast;
+pub mod checker;
 pub mod codegen;
+pub mod doc;
+pub mod embed;
+pub mod fuzz;
+pub mod inline;
 pub mod lexer;
+pub mod macros;
 pub mod msg;
 pub mod optimizer;
+pub mod package;
 pub mod parser;
 pub mod reader;
+pub mod session;
+pub mod ssa;
+pub mod stack;
+pub mod template;
 pub mod token;
+pub mod visit;
+pub mod wasm;
 use std::sync::Arc;
 
 pub type P<T> = Arc<T>;
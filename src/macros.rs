@@ -0,0 +1,138 @@
+//! AST macros. `macro name(params) { ... }` at the top level defines a macro, and every call
+//! `name(args)` is replaced by its body with the parameters substituted by the argument
+//! expressions. Expansion is not hygienic: names declared in the body are visible to the
+//! arguments.
+//!
+//! Calls to `$template.render` with a literal template are expanded here too, see `template`.
+
+use crate::ast::*;
+use crate::msg::*;
+use crate::visit::{fold_subexprs, Folder};
+use crate::P;
+use std::collections::HashMap;
+
+/// Nesting limit of expansions, reached by macros that expand to themselves.
+const MAX_DEPTH: usize = 64;
+
+struct Macro {
+    params: Vec<String>,
+    body: P<Expr>,
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+}
+
+/// Removes the macro definitions from `ast` and expands their uses.
+pub fn expand(ast: Vec<P<Expr>>) -> Result<Vec<P<Expr>>, MsgWithPos> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+    };
+    let mut rest = vec![];
+    for e in ast {
+        match &e.decl {
+            ExprDecl::Macro(name, params, body) => {
+                expander.macros.insert(
+                    name.to_owned(),
+                    Macro {
+                        params: params.clone(),
+                        body: body.clone(),
+                    },
+                );
+            }
+            _ => rest.push(e),
+        }
+    }
+    rest.iter().map(|e| expander.expand(e, 0)).collect()
+}
+
+impl Expander {
+    fn expand(&self, e: &P<Expr>, depth: usize) -> Result<P<Expr>, MsgWithPos> {
+        match &e.decl {
+            ExprDecl::Macro(name, _, _) => Err(MsgWithPos::new(
+                e.pos.file.to_string(),
+                e.pos.clone(),
+                Msg::MacroNotTopLevel(name.to_owned()),
+            )),
+            ExprDecl::Call(callee, args) => match &callee.decl {
+                ExprDecl::Const(Constant::Ident(name)) if self.macros.contains_key(name) => {
+                    let mac = &self.macros[name];
+                    let error =
+                        |msg| Err(MsgWithPos::new(e.pos.file.to_string(), e.pos.clone(), msg));
+                    if args.len() != mac.params.len() {
+                        return error(Msg::MacroArity(
+                            name.to_owned(),
+                            mac.params.len(),
+                            args.len(),
+                        ));
+                    }
+                    if depth == MAX_DEPTH {
+                        return error(Msg::MacroRecursion(name.to_owned()));
+                    }
+                    let mut bound: HashMap<&str, &P<Expr>> = HashMap::new();
+                    for (param, arg) in mac.params.iter().zip(args.iter()) {
+                        if let ExprDecl::NamedArg(_, _) = arg.decl {
+                            break;
+                        }
+                        bound.insert(param, arg);
+                    }
+                    for arg in args.iter() {
+                        if let ExprDecl::NamedArg(arg_name, value) = &arg.decl {
+                            let param = match mac.params.iter().find(|p| *p == arg_name) {
+                                Some(param) => param,
+                                None => {
+                                    let msg = Msg::UnknownNamedArgument(
+                                        name.to_owned(),
+                                        arg_name.to_owned(),
+                                    );
+                                    return error(msg);
+                                }
+                            };
+                            if bound.insert(param, value).is_some() {
+                                return error(Msg::DuplicateNamedArgument(arg_name.to_owned()));
+                            }
+                        }
+                    }
+                    let args = bound;
+                    let body = Substitute(&args).fold_expr(&mac.body);
+                    self.expand(&body, depth + 1)
+                }
+                ExprDecl::Field(object, name)
+                    if name == "render"
+                        && matches!(&object.decl, ExprDecl::Const(Constant::Builtin(b)) if b == "template") =>
+                {
+                    let text = match args.first().map(|text| &text.decl) {
+                        Some(ExprDecl::Const(Constant::Str(text))) if args.len() == 2 => text,
+                        _ => {
+                            return Err(MsgWithPos::new(
+                                e.pos.file.to_string(),
+                                e.pos.clone(),
+                                Msg::TemplateSyntax(
+                                    "render expects a string literal and the data".to_owned(),
+                                ),
+                            ))
+                        }
+                    };
+                    let code = crate::template::compile(text, &args[0].pos, args[1].clone())?;
+                    self.expand(&code, depth)
+                }
+                _ => Ok(P(e.try_map(|e| self.expand(e, depth))?)),
+            },
+            _ => Ok(P(e.try_map(|e| self.expand(e, depth))?)),
+        }
+    }
+}
+
+/// Replaces the parameters in a macro body by the argument expressions.
+struct Substitute<'a>(&'a HashMap<&'a str, &'a P<Expr>>);
+
+impl Folder for Substitute<'_> {
+    fn fold_expr(&mut self, e: &P<Expr>) -> P<Expr> {
+        match &e.decl {
+            ExprDecl::Const(Constant::Ident(name)) if self.0.contains_key(name.as_str()) => {
+                self.0[name.as_str()].clone()
+            }
+            _ => fold_subexprs(self, e),
+        }
+    }
+}
@@ -1,8 +1,13 @@
 use jazzlightc::reader::Reader;
 
 use jazzlight::writer::BytecodeWriter;
+use jazzlightc::checker::check;
 use jazzlightc::codegen::{compile, module_from_context};
+use jazzlightc::lexer::tokenize_with_trivia;
+use jazzlightc::macros::expand;
+use jazzlightc::optimizer::optimize;
 use jazzlightc::parser::Parser;
+use jazzlightc::token::SpannedKind;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -19,18 +24,66 @@ pub struct Options {
     verbose: bool,
     #[structopt(long = "run")]
     run: bool,
+    #[structopt(long = "strict")]
+    /// Compile as if the file started with "use strict"
+    strict: bool,
+    #[structopt(short = "c", long = "check")]
+    /// Only run the static type checker on type annotations
+    check: bool,
+    #[structopt(long = "contracts")]
+    /// Check annotated parameter and return types at runtime
+    contracts: bool,
+    #[structopt(long = "deny-warnings")]
+    /// Treat warnings as errors
+    deny_warnings: bool,
+    #[structopt(long = "opt", default_value = "1", possible_values = &["0", "1", "2"])]
+    /// Optimization level: 0 for none, 1 for rewriting the syntax tree, 2 to also optimize
+    /// the bytecode of every function
+    opt: u8,
+    #[structopt(long = "emit", possible_values = &["tokens"])]
+    /// Print the tokens, whitespace and comments of the file instead of compiling it
+    emit: Option<String>,
+}
+
+fn emit_tokens(src: &str) {
+    let tokens = match tokenize_with_trivia(src) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    for token in tokens.iter() {
+        let kind = match &token.kind {
+            SpannedKind::Token(kind) => format!("{:?}", kind),
+            kind => format!("{:?}", kind),
+        };
+        println!(
+            "{}:{} {}..{} {} {:?}",
+            token.position.line,
+            token.position.column,
+            token.span.0,
+            token.span.1,
+            kind,
+            &src[token.span.0..token.span.1]
+        );
+    }
 }
 
 fn main() {
     let ops = Options::from_args();
     let string = ops.file.unwrap().to_str().unwrap().to_owned();
-    let r = match Reader::from_file(&string) {
+    let mut r = match Reader::from_file(&string) {
         Ok(r) => r,
         Err(e) => {
             eprintln!("Failed to open file '{}': {}", string, e);
             std::process::exit(1);
         }
     };
+    if ops.emit.is_some() {
+        emit_tokens(r.read_to_end());
+        return;
+    }
     let mut ast = vec![];
     let mut parser = Parser::new(r, &mut ast);
     match parser.parse() {
@@ -40,15 +93,45 @@ fn main() {
             std::process::exit(1);
         }
     }
-    let mut ctx = compile(ast);
+    let ast = match expand(ast) {
+        Ok(ast) => ast,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if ops.check {
+        let errors = check(&ast);
+        for error in errors.iter() {
+            eprintln!("{}", error);
+        }
+        std::process::exit(if errors.is_empty() { 0 } else { 1 });
+    }
+    let ast = if ops.opt > 0 { optimize(ast) } else { ast };
+    let mut ctx = compile(ast, ops.strict, ops.contracts, ops.opt >= 2);
+    for warning in ctx.g.borrow().warnings.iter() {
+        eprintln!("{}", warning.warning());
+    }
+    if ops.deny_warnings && !ctx.g.borrow().warnings.is_empty() {
+        eprintln!("aborting due to warnings");
+        std::process::exit(1);
+    }
+    if !ctx.g.borrow().errors.is_empty() {
+        for error in ctx.g.borrow().errors.iter() {
+            eprintln!("{}", error);
+        }
+        std::process::exit(1);
+    }
     let m = module_from_context(&mut ctx);
 
     if ops.dump_op || ops.verbose {
         println!("Byteocde:");
         for (i, op) in m.borrow().code.iter().enumerate() {
-            println!("{:04}: {:?}", i, op)
+            println!("{:04}: {:?}", i, op.op)
         }
         println!();
+        println!("Constant pool: {}", m.borrow().pool_stats());
+        println!();
     }
     let mut w = BytecodeWriter { bytecode: vec![] };
     w.write_module(m);
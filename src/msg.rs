@@ -65,8 +65,32 @@ pub enum Msg {
     UnclosedChar,
     UnclosedString,
     NumberOverflow(String),
+    UnsupportedIntType(String),
     ExpectedClass(String),
     ExpectedFactor(String),
+    ExpectedPattern(String),
+    UnknownVariant(String),
+    VariantArity(String, usize, usize),
+    NonExhaustiveMatch(Vec<String>),
+    RethrowOutsideCatch,
+    YieldOutsideFunction,
+    DeferOutsideFunction,
+    DeleteTarget,
+    ConstReassigned(String),
+    UnusedVariable(String),
+    UnusedParameter(String),
+    UnreachableCode,
+    MacroArity(String, usize, usize),
+    MacroRecursion(String),
+    MacroNotTopLevel(String),
+    PositionalAfterNamed,
+    DuplicateNamedArgument(String),
+    UnknownNamedArgument(String, String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+    GotoIntoScope(String),
+    OperandLimit(String, usize),
+    StackEffect(String),
     ExpectedToken(String, String),
     ExpectedTopLevelElement(String),
     ExpectedTrait(String),
@@ -78,6 +102,8 @@ pub enum Msg {
     RedundantModifier(String),
     MisplacedModifier(String),
     InvalidEscapeSequence(char),
+    InvalidHexEscape,
+    InvalidUnicodeEscape(String),
     MissingFctBody,
     FctCallExpected,
     ThisOrSuperExpected(String),
@@ -113,6 +139,7 @@ pub enum Msg {
     MakeIteratorReturnType(String),
     UnknownStructField(String, String),
     StructFieldNotInitialized(String, String),
+    TemplateSyntax(String),
 }
 
 impl Msg {
@@ -237,8 +264,54 @@ impl Msg {
             MisplacedElse => "misplace else.".into(),
             ExpectedToken(ref exp, ref got) => format!("expected {} but got {}.", exp, got),
             NumberOverflow(ref ty) => format!("number does not fit into type {}.", ty),
+            UnsupportedIntType(ref ty) => format!(
+                "there is no {} type yet, write the number without a suffix.",
+                ty
+            ),
             ExpectedClass(ref cls) => format!("expected class name but got {}.", cls),
             ExpectedFactor(ref got) => format!("factor expected but got {}.", got),
+            ExpectedPattern(ref got) => format!("pattern expected but got {}.", got),
+            UnknownVariant(ref name) => format!("unknown enum variant `{}`.", name),
+            VariantArity(ref name, expected, got) => format!(
+                "variant `{}` has {} fields but the pattern has {}.",
+                name, expected, got
+            ),
+            NonExhaustiveMatch(ref missing) => format!(
+                "non-exhaustive match, missing variants: {}.",
+                missing.join(", ")
+            ),
+            ConstReassigned(ref name) => format!("cannot assign to constant `{}`.", name),
+            RethrowOutsideCatch => "`throw;` is only allowed inside a catch clause.".into(),
+            YieldOutsideFunction => "`yield` is only allowed inside a function.".into(),
+            DeferOutsideFunction => {
+                "`defer` is only allowed inside a function that doesn't yield.".into()
+            }
+            DeleteTarget => "`delete` expects a field or an element.".into(),
+            UnusedVariable(ref name) => format!("variable `{}` is never read.", name),
+            UnusedParameter(ref name) => format!("parameter `{}` is never used.", name),
+            UnreachableCode => "unreachable code.".into(),
+            MacroArity(ref name, expected, got) => format!(
+                "macro `{}` expects {} arguments but got {}.",
+                name, expected, got
+            ),
+            PositionalAfterNamed => "positional argument after named arguments.".into(),
+            DuplicateNamedArgument(ref name) => format!("argument `{}` is given twice.", name),
+            UnknownNamedArgument(ref callee, ref name) => {
+                format!("`{}` has no parameter named `{}`.", callee, name)
+            }
+            MacroRecursion(ref name) => {
+                format!("recursion limit reached expanding macro `{}`.", name)
+            }
+            DuplicateLabel(ref name) => format!("label `{}` is already defined.", name),
+            UnknownLabel(ref name) => format!("no label `{}` in this function.", name),
+            GotoIntoScope(ref name) => {
+                format!("goto `{}` jumps into a loop or try body.", name)
+            }
+            OperandLimit(ref what, limit) => format!("too many {}, the limit is {}.", what, limit),
+            StackEffect(ref e) => format!("internal compiler error, {}.", e),
+            MacroNotTopLevel(ref name) => {
+                format!("macro `{}` must be defined at the top level.", name)
+            }
             ExpectedTrait(ref trt) => format!("expected trait name but got {}.", trt),
             ExpectedType(ref got) => format!("type expected but got {}.", got),
             ExpectedIdentifier(ref tok) => format!("identifier expected but got {}.", tok),
@@ -253,6 +326,10 @@ impl Msg {
             UnknownChar(ch) => format!("unknown character {} (codepoint {}).", ch, ch as usize),
             UnclosedComment => "unclosed comment.".into(),
             InvalidEscapeSequence(ch) => format!("unknown escape sequence `\\{}`.", ch),
+            InvalidHexEscape => "`\\x` escape expects two hex digits.".into(),
+            InvalidUnicodeEscape(ref digits) => {
+                format!("invalid unicode escape `\\u{{{}}}`.", digits)
+            }
             UnclosedString => "unclosed string.".into(),
             UnclosedChar => "unclosed char.".into(),
             IoError => "error reading from file.".into(),
@@ -347,6 +424,7 @@ impl Msg {
             StructFieldNotInitialized(ref struc, ref field) => {
                 format!("field `{}` in struct `{}` not initialized.", field, struc)
             }
+            TemplateSyntax(ref msg) => format!("template: {}.", msg),
         }
     }
 }
@@ -371,6 +449,10 @@ impl MsgWithPos {
         }
     }
 
+    pub fn warning(&self) -> String {
+        format!("warning in {}: {}", self.pos, self.msg.message())
+    }
+
     pub fn without_path(pos: Position, msg: Msg) -> MsgWithPos {
         MsgWithPos {
             path: "".to_string(),
@@ -1 +1,74 @@
+//! AST rewrites applied before codegen.
 
+use crate::ast::*;
+use crate::visit::{fold_subexprs, Folder};
+use crate::P;
+
+/// Chains of at least this many `+` operands starting with a string literal are
+/// concatenated by a single `$sconcat` call.
+const MIN_CONCAT_CHAIN: usize = 3;
+
+pub fn optimize(ast: Vec<P<Expr>>) -> Vec<P<Expr>> {
+    Optimizer.fold_program(&ast)
+}
+
+struct Optimizer;
+
+impl Folder for Optimizer {
+    fn fold_expr(&mut self, e: &P<Expr>) -> P<Expr> {
+        let mut operands = vec![];
+        concat_operands(e, &mut operands);
+        if operands.len() >= MIN_CONCAT_CHAIN {
+            if let ExprDecl::Const(Constant::Str(_)) = &operands[0].decl {
+                let args = operands.iter().map(|e| self.fold_expr(e)).collect();
+                let sconcat = P(make_builtin("sconcat".to_owned(), e.pos.clone()));
+                return P(make_call(sconcat, args, e.pos.clone()));
+            }
+        }
+        fold_subexprs(self, e)
+    }
+}
+
+/// Operands of a left-nested chain of `+`, `a + b + c` is `(a + b) + c`.
+fn concat_operands(e: &P<Expr>, operands: &mut Vec<P<Expr>>) {
+    match &e.decl {
+        ExprDecl::Binop(op, lhs, rhs) if op == "+" => {
+            concat_operands(lhs, operands);
+            operands.push(rhs.clone());
+        }
+        _ => operands.push(e.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::{run_differential, Outcome};
+
+    fn returned(src: &str) -> String {
+        match run_differential(src.as_bytes(), 100_000) {
+            Ok(Outcome::Returned(value)) => value,
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn concat_chain_converts_like_add() {
+        let src = r#"
+            var o = $new(null)
+            o.__to_string__ = function() { return "obj" }
+            return "<" + o + "|" + $array(o, 1) + "|" + 1.5 + ">"
+        "#;
+        assert_eq!(returned(src), r#""<obj|[obj,1]|1.5>""#);
+    }
+
+    #[test]
+    fn concat_chain_keeps_evaluation_order() {
+        let src = r#"
+            var log = ""
+            function f(x) { log = log + x; return x }
+            var s = "s" + f(1) + f(2) + f(3)
+            return s + log
+        "#;
+        assert_eq!(returned(src), r#""s123321""#);
+    }
+}
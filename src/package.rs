@@ -0,0 +1,496 @@
+//! `jazz.toml` manifests, installing the dependencies they list into `jazz_modules/` where
+//! `$load("name/Module")` finds them, and creating, building and documenting projects.
+//!
+//! Manifests use the subset of TOML needed for them:
+//!
+//! ```toml
+//! [package]
+//! name = "app"
+//! version = "0.1.0"
+//!
+//! [dependencies]
+//! util = { path = "../util" }
+//! json = { git = "https://example.com/json.git", rev = "v1.0" }
+//! ```
+
+use crate::ast::Expr;
+use crate::codegen::{compile, module_from_context};
+use crate::macros::expand;
+use crate::optimizer::optimize;
+use crate::parser::Parser;
+use crate::reader::Reader;
+use crate::P;
+use jazzlight::writer::BytecodeWriter;
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub const MANIFEST: &str = "jazz.toml";
+pub const MODULES_DIR: &str = "jazz_modules";
+pub const SRC_DIR: &str = "src";
+/// Where `jazz build` puts the bytecode of `src/`.
+pub const BUILD_DIR: &str = "build";
+/// Where `jazz doc` puts the documentation of `src/`.
+pub const DOC_DIR: &str = "docs";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Path(String),
+    /// Repository and the revision to check out, the default branch if `None`.
+    Git(String, Option<String>),
+    /// Version requirement for a registry, which doesn't exist yet.
+    Registry(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub dependencies: Vec<(String, Source)>,
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        Some(
+            value[1..value.len() - 1]
+                .replace("\\\"", "\"")
+                .replace("\\\\", "\\"),
+        )
+    } else {
+        None
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            '#' if !in_string => return &line[..i],
+            '"' if !escaped => in_string = !in_string,
+            _ => (),
+        }
+        escaped = ch == '\\' && !escaped;
+    }
+    line
+}
+
+/// Splits `key = value`, `None` if there is no `=`.
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    let eq = line.find('=')?;
+    Some((line[..eq].trim(), line[eq + 1..].trim()))
+}
+
+fn parse_source(name: &str, value: &str) -> Result<Source, String> {
+    if let Some(version) = parse_string(value) {
+        return Ok(Source::Registry(version));
+    }
+    if !value.starts_with('{') || !value.ends_with('}') {
+        return Err(format!(
+            "dependency '{}': string or inline table expected",
+            name
+        ));
+    }
+    let mut fields = vec![];
+    for field in value[1..value.len() - 1].split(',') {
+        if field.trim().is_empty() {
+            continue;
+        }
+        let (key, value) = split_key(field)
+            .ok_or_else(|| format!("dependency '{}': key = value expected", name))?;
+        let value = parse_string(value)
+            .ok_or_else(|| format!("dependency '{}': string expected for '{}'", name, key))?;
+        fields.push((key, value));
+    }
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.clone())
+    };
+    match (field("path"), field("git"), field("version")) {
+        (Some(path), None, None) => Ok(Source::Path(path)),
+        (None, Some(git), None) => Ok(Source::Git(git, field("rev"))),
+        (None, None, Some(version)) => Ok(Source::Registry(version)),
+        _ => Err(format!(
+            "dependency '{}': exactly one of path, git or version expected",
+            name
+        )),
+    }
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let mut manifest = Manifest::default();
+        let mut section = String::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_owned();
+                continue;
+            }
+            let error = |msg: &str| format!("{}:{}: {}", MANIFEST, i + 1, msg);
+            let (key, value) = split_key(line).ok_or_else(|| error("key = value expected"))?;
+            match &section[..] {
+                "package" => {
+                    let value = parse_string(value).ok_or_else(|| error("string expected"))?;
+                    match key {
+                        "name" => manifest.name = value,
+                        "version" => manifest.version = value,
+                        _ => return Err(error(&format!("unknown key '{}'", key))),
+                    }
+                }
+                "dependencies" => {
+                    let source = parse_source(key, value).map_err(|e| error(&e))?;
+                    manifest.dependencies.push((key.to_owned(), source));
+                }
+                _ => return Err(error(&format!("unknown section '{}'", section))),
+            }
+        }
+        if manifest.name.is_empty() {
+            return Err(format!("{}: package name missing", MANIFEST));
+        }
+        Ok(manifest)
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "[package]").unwrap();
+        writeln!(s, "name = {}", quote(&self.name)).unwrap();
+        writeln!(s, "version = {}", quote(&self.version)).unwrap();
+        writeln!(s).unwrap();
+        writeln!(s, "[dependencies]").unwrap();
+        for (name, source) in self.dependencies.iter() {
+            let value = match source {
+                Source::Path(path) => format!("{{ path = {} }}", quote(path)),
+                Source::Git(url, None) => format!("{{ git = {} }}", quote(url)),
+                Source::Git(url, Some(rev)) => {
+                    format!("{{ git = {}, rev = {} }}", quote(url), quote(rev))
+                }
+                Source::Registry(version) => quote(version),
+            };
+            writeln!(s, "{} = {}", name, value).unwrap();
+        }
+        s
+    }
+
+    /// The manifest of the project in `dir`.
+    pub fn read(dir: &Path) -> Result<Manifest, String> {
+        let path = dir.join(MANIFEST);
+        let text =
+            std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Manifest::parse(&text)
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<(), String> {
+        let path = dir.join(MANIFEST);
+        std::fs::write(&path, self.to_toml()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Adds the dependency `name`, replacing the one of the same name.
+    pub fn add(&mut self, name: &str, source: Source) {
+        match self.dependencies.iter_mut().find(|(n, _)| n == name) {
+            Some(dependency) => dependency.1 = source,
+            None => self.dependencies.push((name.to_owned(), source)),
+        }
+    }
+}
+
+/// The AST of the source file `src` with its macros expanded.
+fn parse_file(src: &Path) -> Result<Vec<P<Expr>>, Vec<String>> {
+    let reader = Reader::from_file(&src.to_string_lossy())
+        .map_err(|e| vec![format!("{}: {}", src.display(), e)])?;
+    let mut ast = vec![];
+    Parser::new(reader, &mut ast)
+        .parse()
+        .map_err(|e| vec![e.to_string()])?;
+    expand(ast).map_err(|e| vec![e.to_string()])
+}
+
+fn write_file(out: &Path, contents: &[u8]) -> Result<(), Vec<String>> {
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| vec![format!("{}: {}", dir.display(), e)])?;
+    }
+    std::fs::write(out, contents).map_err(|e| vec![format!("{}: {}", out.display(), e)])
+}
+
+/// Compiles the source file `src` to the bytecode file `out`, returning the warnings or
+/// the errors. With `check`, type annotations are checked first as with `jazzlightc -c`.
+pub fn compile_file(src: &Path, out: &Path, check: bool) -> Result<Vec<String>, Vec<String>> {
+    let ast = parse_file(src)?;
+    if check {
+        let errors = crate::checker::check(&ast);
+        if !errors.is_empty() {
+            return Err(errors.iter().map(|e| e.to_string()).collect());
+        }
+    }
+    let mut ctx = compile(optimize(ast), false, false, false);
+    let g = ctx.g.borrow();
+    if !g.errors.is_empty() {
+        return Err(g.errors.iter().map(|e| e.to_string()).collect());
+    }
+    let warnings = g.warnings.iter().map(|w| w.warning()).collect();
+    drop(g);
+    let mut w = BytecodeWriter { bytecode: vec![] };
+    w.write_module(module_from_context(&mut ctx));
+    write_file(out, &w.bytecode)?;
+    Ok(warnings)
+}
+
+/// Compiles the functions of the source file `src` that `wasm` supports to the WebAssembly
+/// module `out`, returning a warning for each of the others.
+pub fn compile_file_wasm(src: &Path, out: &Path) -> Result<Vec<String>, Vec<String>> {
+    let ast = parse_file(src)?;
+    let (module, warnings) = crate::wasm::compile(&ast);
+    write_file(out, &module)?;
+    Ok(warnings)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path
+            .file_name()
+            .is_some_and(|name| name == MODULES_DIR || name == ".git")
+        {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            copy_dir(&path, &to.join(entry.file_name()))?;
+        } else {
+            std::fs::copy(&path, to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+fn git(args: &[&str]) -> Result<(), String> {
+    let status = Command::new("git")
+        .args(args)
+        .status()
+        .map_err(|e| format!("git: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("git {} failed", args[0]))
+    }
+}
+
+/// Number of threads `build` and `install` compile on unless told otherwise.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Calls `f` on each of `files` from up to `jobs` threads, which is how modules are compiled:
+/// each is lexed, parsed and compiled on its own. The results are in the order of `files`.
+fn for_each_file<T: Send>(files: &[PathBuf], jobs: usize, f: impl Fn(&Path) -> T + Sync) -> Vec<T> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(files.iter().map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(files.len()) {
+            scope.spawn(|| {
+                let mut i = next.fetch_add(1, Ordering::Relaxed);
+                while i < files.len() {
+                    let result = f(&files[i]);
+                    results.lock().unwrap()[i] = Some(result);
+                    i = next.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(Option::unwrap)
+        .collect()
+}
+
+/// Source files of `dir` and its subdirectories.
+fn source_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            source_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "jzl") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Fetches `source` into `jazz_modules/name` of the project in `dir` and compiles its
+/// source files next to them. Returns the compiler's warnings.
+pub fn install(dir: &Path, name: &str, source: &Source) -> Result<Vec<String>, Vec<String>> {
+    let target = dir.join(MODULES_DIR).join(name);
+    if target.exists() {
+        std::fs::remove_dir_all(&target).map_err(|e| vec![format!("{}: {}", name, e)])?;
+    }
+    let fetched = match source {
+        Source::Path(path) => {
+            copy_dir(&dir.join(path), &target).map_err(|e| format!("{}: {}", path, e))
+        }
+        Source::Git(url, rev) => {
+            let target = target.to_string_lossy();
+            git(&["clone", "--quiet", url, &target]).and_then(|()| match rev {
+                Some(rev) => git(&["-C", &target, "checkout", "--quiet", rev]),
+                None => Ok(()),
+            })
+        }
+        Source::Registry(_) => Err("registry dependencies aren't supported yet".to_owned()),
+    };
+    fetched.map_err(|e| vec![format!("{}: {}", name, e)])?;
+    let mut files = vec![];
+    source_files(&target, &mut files).map_err(|e| vec![format!("{}: {}", name, e)])?;
+    let mut warnings = vec![];
+    for compiled in for_each_file(&files, default_jobs(), |file| {
+        compile_file(file, &file.with_extension("j"), false)
+    }) {
+        warnings.extend(compiled?);
+    }
+    Ok(warnings)
+}
+
+/// Creates the project `name` in a new directory of that name: `jazz.toml`,
+/// `src/main.jzl` and an empty `tests/`.
+pub fn new_project(name: &str) -> Result<(), String> {
+    let dir = Path::new(name);
+    if dir.exists() {
+        return Err(format!("{}: already exists", name));
+    }
+    let io = |e: std::io::Error| format!("{}: {}", name, e);
+    std::fs::create_dir_all(dir.join(SRC_DIR)).map_err(io)?;
+    std::fs::create_dir_all(dir.join("tests")).map_err(io)?;
+    let main = format!("$print(\"Hello from {}!\\n\")\n", name);
+    std::fs::write(dir.join(SRC_DIR).join("main.jzl"), main).map_err(io)?;
+    let manifest = Manifest {
+        name: name.to_owned(),
+        version: "0.1.0".to_owned(),
+        dependencies: vec![],
+    };
+    manifest.write(dir)
+}
+
+/// Diagnostics of all the modules `build` compiled.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    pub modules: usize,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// What `build` compiles modules to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Bytecode,
+    /// WebAssembly modules, see `wasm`.
+    Wasm,
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, String> {
+        match s {
+            "bytecode" => Ok(Target::Bytecode),
+            "wasm" => Ok(Target::Wasm),
+            _ => Err(format!("unknown target '{}'", s)),
+        }
+    }
+}
+
+/// Checks and compiles every module in `src/` of the project in `dir` to `build/`, with
+/// the same path relative to it, on `jobs` threads. Modules with errors are left out, the
+/// diagnostics are reported in the order of the module paths.
+pub fn build(dir: &Path, target: Target, jobs: usize) -> Result<BuildReport, String> {
+    let src = dir.join(SRC_DIR);
+    let mut files = vec![];
+    source_files(&src, &mut files).map_err(|e| format!("{}: {}", src.display(), e))?;
+    files.sort();
+    let mut report = BuildReport::default();
+    for compiled in for_each_file(&files, jobs, |file| {
+        let out = dir
+            .join(BUILD_DIR)
+            .join(file.strip_prefix(&src).unwrap())
+            .with_extension(match target {
+                Target::Bytecode => "j",
+                Target::Wasm => "wasm",
+            });
+        match target {
+            Target::Bytecode => compile_file(file, &out, true),
+            Target::Wasm => compile_file_wasm(file, &out),
+        }
+    }) {
+        report.modules += 1;
+        match compiled {
+            Ok(warnings) => report.warnings.extend(warnings),
+            Err(errors) => report.errors.extend(errors),
+        }
+    }
+    Ok(report)
+}
+
+/// Checks the type annotations of every module in `src/` of the project in `dir` on `jobs`
+/// threads, like `build` without writing anything. The errors are reported in the order of
+/// the module paths.
+pub fn check(dir: &Path, jobs: usize) -> Result<BuildReport, String> {
+    let src = dir.join(SRC_DIR);
+    let mut files = vec![];
+    source_files(&src, &mut files).map_err(|e| format!("{}: {}", src.display(), e))?;
+    files.sort();
+    let mut report = BuildReport::default();
+    for errors in for_each_file(&files, jobs, |file| match parse_file(file) {
+        Ok(ast) => crate::checker::check(&ast)
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+        Err(errors) => errors,
+    }) {
+        report.modules += 1;
+        report.errors.extend(errors);
+    }
+    Ok(report)
+}
+
+/// Writes the Markdown documentation of every module in `src/` of the project in `dir` to
+/// `docs/`, with the same path relative to it. Returns the number of modules.
+pub fn doc(dir: &Path) -> Result<usize, Vec<String>> {
+    let src = dir.join(SRC_DIR);
+    let mut files = vec![];
+    source_files(&src, &mut files).map_err(|e| vec![format!("{}: {}", src.display(), e)])?;
+    files.sort();
+    let mut errors = vec![];
+    for file in files.iter() {
+        let module = file.strip_prefix(&src).unwrap().with_extension("");
+        let out = dir.join(DOC_DIR).join(&module).with_extension("md");
+        let written = Reader::from_file(&file.to_string_lossy())
+            .map_err(|e| format!("{}: {}", file.display(), e))
+            .and_then(|reader| crate::doc::markdown(&module.to_string_lossy(), reader))
+            .and_then(|markdown| {
+                let io = |e: std::io::Error| format!("{}: {}", out.display(), e);
+                std::fs::create_dir_all(out.parent().unwrap()).map_err(io)?;
+                std::fs::write(&out, markdown).map_err(io)
+            });
+        if let Err(e) = written {
+            errors.push(e);
+        }
+    }
+    if errors.is_empty() {
+        Ok(files.len())
+    } else {
+        Err(errors)
+    }
+}
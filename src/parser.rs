@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::mem;
 
 use crate::{ast::*, lexer::*, msg::*, reader::Reader, token::*};
@@ -5,6 +6,16 @@ use crate::{ast::*, lexer::*, msg::*, reader::Reader, token::*};
 pub struct Parser<'a> {
     lexer: Lexer,
     token: Token,
+    /// Byte offsets of `token`.
+    span: (usize, usize),
+    /// Byte offset where the token before `token` ends.
+    prev_end: usize,
+    /// Doc comment of `token`.
+    doc: Option<String>,
+    /// Doc comments of declarations, by position.
+    docs: Vec<(Position, String)>,
+    /// Tokens to read before the lexer's, put back when a `++` or `--` is split in two.
+    pending: Vec<Token>,
     ast: &'a mut Vec<P<Expr>>,
 }
 use crate::P;
@@ -28,10 +39,21 @@ impl<'a> Parser<'a> {
                 TokenKind::End,
                 Position::new(crate::P("<>".to_owned()), 1, 1),
             ),
+            span: (0, 0),
+            prev_end: 0,
+            doc: None,
+            docs: vec![],
+            pending: vec![],
             ast,
         }
     }
 
+    /// Doc comments of the declarations parsed so far, with the position of the
+    /// declaration.
+    pub fn docs(&self) -> &[(Position, String)] {
+        &self.docs
+    }
+
     fn init(&mut self) -> Result<(), MsgWithPos> {
         self.advance_token()?;
 
@@ -77,25 +99,78 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_function(&mut self) -> EResult {
+        let start = self.span.0;
+        let doc = self.doc.take();
         let pos = self.expect_token(TokenKind::Fun)?.position;
 
-        //self.expect_identifier()?;
+        // `fun name(...) ...` declares a variable holding the function.
+        let name = match self.token.kind {
+            TokenKind::Identifier(_) => Some(self.expect_identifier()?),
+            _ => None,
+        };
         self.expect_token(TokenKind::LParen)?;
-        let params = if self.token.kind == TokenKind::RParen {
-            vec![]
-        } else {
-            let mut tmp = vec![];
-            while !self.token.is(TokenKind::RParen) {
-                tmp.push(self.expect_identifier()?);
-                if !self.token.is(TokenKind::RParen) {
-                    self.expect_token(TokenKind::Comma)?;
-                }
-            }
-            tmp
+        let mut params = vec![];
+        let mut signature = Signature {
+            doc: doc.clone(),
+            ..Signature::default()
         };
+        while !self.token.is(TokenKind::RParen) {
+            params.push(self.expect_identifier()?);
+            signature.params.push(self.parse_type_annotation()?);
+            if !self.token.is(TokenKind::RParen) {
+                self.expect_token(TokenKind::Comma)?;
+            }
+        }
         self.expect_token(TokenKind::RParen)?;
+        if self.token.is(TokenKind::Arrow) {
+            self.advance_token()?;
+            signature.ret = Some(self.parse_type()?);
+        }
         let body = self.parse_expression()?;
-        Ok(expr!(ExprDecl::Function(params, body), pos))
+        let source = self.lexer.src()[start..self.prev_end].to_owned();
+        let function = expr!(
+            ExprDecl::Function(params, body, signature, Some(source)),
+            pos.clone()
+        );
+        match name {
+            Some(name) => {
+                if let Some(doc) = doc {
+                    self.docs.push((pos.clone(), doc));
+                }
+                Ok(expr!(
+                    ExprDecl::Var(VarKind::Var, name, None, Some(function)),
+                    pos
+                ))
+            }
+            None => Ok(function),
+        }
+    }
+
+    fn parse_macro(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Macro)?.position;
+        let name = self.expect_identifier()?;
+        self.expect_token(TokenKind::LParen)?;
+        let params = self.parse_comma_list(TokenKind::RParen, |p| p.expect_identifier())?;
+        let body = self.parse_block()?;
+        Ok(expr!(ExprDecl::Macro(name, params, body), pos))
+    }
+
+    fn parse_type(&mut self) -> Result<String, MsgWithPos> {
+        if self.token.is(TokenKind::Nil) {
+            self.advance_token()?;
+            return Ok("null".to_owned());
+        }
+        self.expect_identifier()
+    }
+
+    /// Optional `: type` after a parameter or variable name.
+    fn parse_type_annotation(&mut self) -> Result<Option<String>, MsgWithPos> {
+        if self.token.is(TokenKind::Colon) {
+            self.advance_token()?;
+            Ok(Some(self.parse_type()?))
+        } else {
+            Ok(None)
+        }
     }
 
     fn parse_yield(&mut self) -> EResult {
@@ -104,19 +179,76 @@ impl<'a> Parser<'a> {
         Ok(expr!(ExprDecl::Yield(expr), pos))
     }
 
+    fn parse_defer(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Defer)?.position;
+        let expr = self.parse_expression()?;
+        Ok(expr!(ExprDecl::Defer(expr), pos))
+    }
+
+    /// `delete object.field` or `delete object[key]`.
+    fn parse_delete(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Delete)?.position;
+        let target = self.parse_expression()?;
+        match &target.decl {
+            ExprDecl::Field(object, field) => {
+                let key = expr!(
+                    ExprDecl::Const(Constant::Str(field.clone())),
+                    target.pos.clone()
+                );
+                Ok(expr!(ExprDecl::Delete(object.clone(), key), pos))
+            }
+            ExprDecl::Array(object, key) => {
+                Ok(expr!(ExprDecl::Delete(object.clone(), key.clone()), pos))
+            }
+            _ => Err(MsgWithPos::new(
+                self.lexer.path(),
+                target.pos.clone(),
+                Msg::DeleteTarget,
+            )),
+        }
+    }
+
+    /// `using (let name = value) body`, `let` being optional.
+    fn parse_using(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Using)?.position;
+        self.expect_token(TokenKind::LParen)?;
+        if let TokenKind::Let | TokenKind::Var | TokenKind::Const = self.token.kind {
+            self.advance_token()?;
+        }
+        let name = self.expect_identifier()?;
+        self.expect_token(TokenKind::Eq)?;
+        let value = self.parse_expression()?;
+        self.expect_token(TokenKind::RParen)?;
+        let body = self.parse_block()?;
+        Ok(expr!(ExprDecl::Using(name, value, body), pos))
+    }
+
     fn parse_let(&mut self) -> EResult {
-        let reassignable = self.token.is(TokenKind::Var);
+        let kind = match self.token.kind {
+            TokenKind::Var => VarKind::Var,
+            TokenKind::Const => VarKind::Const,
+            _ => VarKind::Let,
+        };
 
+        let doc = self.doc.take();
         let pos = self.advance_token()?.position;
         let ident = self.expect_identifier()?;
+        let ty = self.parse_type_annotation()?;
         let expr = if self.token.is(TokenKind::Eq) {
             self.expect_token(TokenKind::Eq)?;
+            // `let f = fun ...` documents the function.
+            if self.token.is(TokenKind::Fun) && self.doc.is_none() {
+                self.doc = doc.clone();
+            }
             let expr = self.parse_expression()?;
             Some(expr)
         } else {
             None
         };
-        Ok(expr!(ExprDecl::Var(reassignable, ident, expr), pos))
+        if let Some(doc) = doc {
+            self.docs.push((pos.clone(), doc));
+        }
+        Ok(expr!(ExprDecl::Var(kind, ident, ty, expr), pos))
     }
 
     fn parse_return(&mut self) -> EResult {
@@ -129,12 +261,18 @@ impl<'a> Parser<'a> {
         let expr = match self.token.kind {
             TokenKind::Fun => self.parse_function(),
 
-            TokenKind::Match => self.parse_match(),
-            TokenKind::Let | TokenKind::Var => self.parse_let(),
+            TokenKind::Match | TokenKind::Switch => self.parse_match(),
+            TokenKind::Enum => self.parse_enum(),
+            TokenKind::Macro => self.parse_macro(),
+            TokenKind::Let | TokenKind::Var | TokenKind::Const => self.parse_let(),
             TokenKind::Yield => self.parse_yield(),
+            TokenKind::Defer => self.parse_defer(),
+            TokenKind::Using => self.parse_using(),
+            TokenKind::Delete => self.parse_delete(),
             TokenKind::LBrace => self.parse_block(),
             TokenKind::If => self.parse_if(),
             TokenKind::For => self.parse_for(),
+            TokenKind::ForEach => self.parse_foreach(),
             TokenKind::Goto => self.parse_goto(),
             TokenKind::While => self.parse_while(),
             TokenKind::Break => self.parse_break(),
@@ -155,10 +293,43 @@ impl<'a> Parser<'a> {
     fn parse_try(&mut self) -> EResult {
         let pos = self.advance_token()?.position;
         let expr = self.parse_expression()?;
+        let mut clauses = vec![self.parse_catch()?];
+        while self.token.is(TokenKind::Catch) {
+            clauses.push(self.parse_catch()?);
+        }
+        Ok(expr!(ExprDecl::Try(expr, clauses), pos))
+    }
+
+    /// `catch e body`, `catch (e: Type) body` or `catch e if cond body`.
+    fn parse_catch(&mut self) -> Result<CatchClause, MsgWithPos> {
         self.expect_token(TokenKind::Catch)?;
-        let name = self.expect_identifier()?;
-        let catch = self.parse_expression()?;
-        Ok(expr!(ExprDecl::Try(expr, name, catch), pos))
+        let (name, ty) = if self.token.is(TokenKind::LParen) {
+            self.advance_token()?;
+            let name = self.expect_identifier()?;
+            let ty = if self.token.is(TokenKind::Colon) {
+                self.advance_token()?;
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
+            self.expect_token(TokenKind::RParen)?;
+            (name, ty)
+        } else {
+            (self.expect_identifier()?, None)
+        };
+        let guard = if self.token.is(TokenKind::If) {
+            self.advance_token()?;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        let body = self.parse_expression()?;
+        Ok(CatchClause {
+            name,
+            ty,
+            guard,
+            body,
+        })
     }
     fn parse_self(&mut self) -> EResult {
         let pos = self.expect_token(TokenKind::This)?.position;
@@ -184,7 +355,31 @@ impl<'a> Parser<'a> {
 
     fn parse_throw(&mut self) -> EResult {
         let pos = self.advance_token()?.position;
-        let expr = self.parse_expression()?;
+        if self.token.is(TokenKind::Semicolon)
+            || self.token.is(TokenKind::RBrace)
+            || self.token.is_eof()
+        {
+            return Ok(expr!(ExprDecl::Rethrow, pos));
+        }
+        let mut expr = self.parse_expression()?;
+        // `throw Type { field: value, ... }` builds an object whose prototype is `Type`.
+        if self.token.is(TokenKind::LBrace) {
+            match expr.decl {
+                ExprDecl::Const(Constant::Ident(_)) | ExprDecl::Field(_, _) => {
+                    self.advance_token()?;
+                    let fields = self.parse_comma_list(TokenKind::RBrace, |p| {
+                        let key = p.expect_identifier()?;
+                        p.expect_token(TokenKind::Colon)?;
+                        Ok((key, p.parse_expression()?))
+                    })?;
+                    expr = expr!(
+                        ExprDecl::Object(Some(expr.clone()), fields),
+                        expr.pos.clone()
+                    );
+                }
+                _ => (),
+            }
+        }
         return Ok(expr!(ExprDecl::Throw(expr), pos));
     }
 
@@ -193,10 +388,19 @@ impl<'a> Parser<'a> {
 
         let decl = self.parse_expression()?;
         if self.token.is(TokenKind::In) {
+            let name = match &decl.decl {
+                ExprDecl::Const(Constant::Ident(name)) => name.to_owned(),
+                _ => {
+                    return Err(MsgWithPos::new(
+                        self.lexer.path(),
+                        decl.pos.clone(),
+                        Msg::ExpectedIdentifier("expression".to_owned()),
+                    ))
+                }
+            };
             self.advance_token()?;
             let in_ = self.parse_expression()?;
             let block = self.parse_expression()?;
-            let name = self.expect_identifier()?;
             Ok(expr!(ExprDecl::ForIn(name, in_, block), pos))
         } else {
             self.expect_token(TokenKind::Semicolon)?;
@@ -210,6 +414,23 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// `foreach (x in e) body` or `foreach x in e body`.
+    fn parse_foreach(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::ForEach)?.position;
+        let paren = self.token.is(TokenKind::LParen);
+        if paren {
+            self.advance_token()?;
+        }
+        let name = self.expect_identifier()?;
+        self.expect_token(TokenKind::In)?;
+        let in_ = self.parse_expression()?;
+        if paren {
+            self.expect_token(TokenKind::RParen)?;
+        }
+        let block = self.parse_expression()?;
+        Ok(expr!(ExprDecl::ForIn(name, in_, block), pos))
+    }
+
     fn parse_while(&mut self) -> EResult {
         let pos = self.expect_token(TokenKind::While)?.position;
         let cond = self.parse_expression()?;
@@ -217,29 +438,133 @@ impl<'a> Parser<'a> {
         Ok(expr!(ExprDecl::While(cond, block), pos))
     }
 
+    /// `match` or `switch`. Bare identifiers of `switch` arms compare with the variable of
+    /// that name, like `switch` did before it got patterns, instead of binding it.
     fn parse_match(&mut self) -> EResult {
-        let pos = self.expect_token(TokenKind::Match)?.position;
+        let switch = self.token.is(TokenKind::Switch);
+        let pos = self.advance_token()?.position;
         let value = self.parse_expression()?;
         self.expect_token(TokenKind::LBrace)?;
-        let mut data = vec![];
-        let mut or = None;
+        let mut arms = vec![];
         while !self.token.is(TokenKind::RBrace) && !self.token.is_eof() {
-            if self.token.is(TokenKind::Underscore) {
-                self.expect_token(TokenKind::Underscore)?;
-                self.expect_token(TokenKind::Arrow)?;
-                let expr = self.parse_expression()?;
-                or = Some(expr);
-                continue;
+            let mut pattern = self.parse_pattern()?;
+            if switch {
+                pattern = compare_identifiers(pattern);
             }
-            let cond = self.parse_expression()?;
+            let guard = if self.token.is(TokenKind::If) {
+                self.advance_token()?;
+                Some(self.parse_expression()?)
+            } else {
+                None
+            };
             self.expect_token(TokenKind::Arrow)?;
-            let expr = self.parse_expression()?;
-            data.push((cond, expr));
+            let body = self.parse_expression()?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+            if self.token.is(TokenKind::Comma) {
+                self.advance_token()?;
+            }
         }
 
         self.expect_token(TokenKind::RBrace)?;
 
-        Ok(expr!(ExprDecl::Switch(value, data, or), pos))
+        Ok(expr!(ExprDecl::Match(value, arms), pos))
+    }
+
+    fn parse_enum(&mut self) -> EResult {
+        let pos = self.expect_token(TokenKind::Enum)?.position;
+        let name = self.expect_identifier()?;
+        self.expect_token(TokenKind::LBrace)?;
+        let variants = self.parse_comma_list(TokenKind::RBrace, |p| {
+            let variant = p.expect_identifier()?;
+            let fields = if p.token.is(TokenKind::LParen) {
+                p.advance_token()?;
+                p.parse_comma_list(TokenKind::RParen, |p| p.expect_identifier())?
+            } else {
+                vec![]
+            };
+            Ok((variant, fields))
+        })?;
+        Ok(expr!(ExprDecl::Enum(name, variants), pos))
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, MsgWithPos> {
+        let first = self.parse_single_pattern()?;
+        if !self.token.is(TokenKind::BitOr) {
+            return Ok(first);
+        }
+        let mut alts = vec![first];
+        while self.token.is(TokenKind::BitOr) {
+            self.advance_token()?;
+            alts.push(self.parse_single_pattern()?);
+        }
+        Ok(Pattern::Or(alts))
+    }
+
+    fn parse_single_pattern(&mut self) -> Result<Pattern, MsgWithPos> {
+        match self.token.kind.clone() {
+            TokenKind::Underscore => {
+                self.advance_token()?;
+                Ok(Pattern::Wildcard)
+            }
+            TokenKind::Identifier(name) => {
+                self.advance_token()?;
+                if self.token.is(TokenKind::LParen) {
+                    self.advance_token()?;
+                    let pats = self.parse_comma_list(TokenKind::RParen, |p| p.parse_pattern())?;
+                    return Ok(Pattern::Variant(name.to_string(), pats));
+                }
+                Ok(Pattern::Bind(name.to_string()))
+            }
+            TokenKind::LBracket => {
+                self.advance_token()?;
+                let pats = self.parse_comma_list(TokenKind::RBracket, |p| p.parse_pattern())?;
+                Ok(Pattern::Array(pats))
+            }
+            TokenKind::LBrace => {
+                self.advance_token()?;
+                let fields = self.parse_comma_list(TokenKind::RBrace, |p| {
+                    let key = p.expect_identifier()?;
+                    if p.token.is(TokenKind::Colon) {
+                        p.advance_token()?;
+                        Ok((key, p.parse_pattern()?))
+                    } else {
+                        Ok((key.clone(), Pattern::Bind(key)))
+                    }
+                })?;
+                Ok(Pattern::Object(fields))
+            }
+            TokenKind::Sub => {
+                let tok = self.advance_token()?;
+                match self.parse_single_pattern()? {
+                    Pattern::Const(Constant::Int(i)) => Ok(Pattern::Const(Constant::Int(-i))),
+                    Pattern::Const(Constant::Float(f)) => Ok(Pattern::Const(Constant::Float(-f))),
+                    _ => Err(MsgWithPos::new(
+                        self.lexer.path(),
+                        tok.position.clone(),
+                        Msg::ExpectedPattern(tok.name()),
+                    )),
+                }
+            }
+            TokenKind::LitInt(_, _, _)
+            | TokenKind::LitFloat(_)
+            | TokenKind::LitChar(_)
+            | TokenKind::String(_)
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Nil => match &self.parse_factor()?.decl {
+                ExprDecl::Const(c) => Ok(Pattern::Const(c.clone())),
+                _ => unreachable!(),
+            },
+            _ => Err(MsgWithPos::new(
+                self.lexer.path(),
+                self.token.position.clone(),
+                Msg::ExpectedPattern(self.token.name()),
+            )),
+        }
     }
 
     fn parse_if(&mut self) -> EResult {
@@ -284,6 +609,8 @@ impl<'a> Parser<'a> {
             TokenKind::BitAnd => "&",
             TokenKind::EqEq => "==",
             TokenKind::Ne => "!=",
+            TokenKind::EqEqEq => "===",
+            TokenKind::NeEq => "!==",
             TokenKind::Lt => "<",
             TokenKind::Gt => ">",
             TokenKind::Le => "<=",
@@ -297,6 +624,8 @@ impl<'a> Parser<'a> {
             TokenKind::GtGtGt => ">>>",
             TokenKind::GtGt => ">>",
             TokenKind::Mod => "%",
+            TokenKind::Pow => "**",
+            TokenKind::InstanceOf => "instanceof",
             _ => unimplemented!(),
         };
 
@@ -312,10 +641,13 @@ impl<'a> Parser<'a> {
                 TokenKind::Eq => 3,
                 TokenKind::EqEq
                 | TokenKind::Ne
+                | TokenKind::EqEqEq
+                | TokenKind::NeEq
                 | TokenKind::Lt
                 | TokenKind::Le
                 | TokenKind::Gt
-                | TokenKind::Ge => 4,
+                | TokenKind::Ge
+                | TokenKind::InstanceOf => 4,
                 TokenKind::BitOr | TokenKind::BitAnd | TokenKind::Caret => 6,
                 TokenKind::LtLt
                 | TokenKind::GtGt
@@ -323,6 +655,7 @@ impl<'a> Parser<'a> {
                 | TokenKind::Add
                 | TokenKind::Sub => 8,
                 TokenKind::Mul | TokenKind::Div | TokenKind::Mod => 9,
+                TokenKind::Pow => 10,
                 _ => {
                     return Ok(left);
                 }
@@ -333,7 +666,11 @@ impl<'a> Parser<'a> {
 
             let tok = self.advance_token()?;
             left = {
-                let right = self.parse_binary(right_precedence)?;
+                // `**` is right associative: `2 ** 3 ** 2` is `2 ** 9`.
+                let right = match tok.kind {
+                    TokenKind::Pow => self.parse_binary(right_precedence - 1)?,
+                    _ => self.parse_binary(right_precedence)?,
+                };
                 self.create_binary(tok, left, right)
             };
         }
@@ -346,18 +683,65 @@ impl<'a> Parser<'a> {
     }
     pub fn parse_unary(&mut self) -> EResult {
         match self.token.kind {
-            TokenKind::Add | TokenKind::Sub | TokenKind::Not => {
+            TokenKind::Add | TokenKind::Sub | TokenKind::Not | TokenKind::Tilde => {
                 let tok = self.advance_token()?;
                 let op = match tok.kind {
                     TokenKind::Add => String::from("+"),
                     TokenKind::Sub => String::from("-"),
                     TokenKind::Not => String::from("!"),
+                    TokenKind::Tilde => String::from("~"),
                     _ => unreachable!(),
                 };
                 let expr = self.parse_primary()?;
                 Ok(expr!(ExprDecl::Unop(op, expr), tok.position))
             }
-            _ => self.parse_primary(),
+            TokenKind::Inc | TokenKind::Dec => {
+                let tok = self.advance_token()?;
+                let expr = self.parse_primary()?;
+                if is_lvalue(&expr) {
+                    return self.update(tok, expr, true);
+                }
+                // `--5` is `-(-5)`.
+                let op = match tok.kind {
+                    TokenKind::Inc => String::from("+"),
+                    _ => String::from("-"),
+                };
+                let inner = expr!(ExprDecl::Unop(op.clone(), expr), tok.position.clone());
+                Ok(expr!(ExprDecl::Unop(op, inner), tok.position))
+            }
+            _ => {
+                let expr = self.parse_primary()?;
+                // A `++` on the next line starts a new expression.
+                match self.token.kind {
+                    TokenKind::Inc | TokenKind::Dec
+                        if self.token.position.line == expr.pos.line =>
+                    {
+                        let tok = self.advance_token()?;
+                        // `a--b` is `a - -b`.
+                        if starts_operand(&self.token.kind)
+                            && self.token.position.line == tok.position.line
+                        {
+                            self.split_update(tok);
+                            return Ok(expr);
+                        }
+                        self.update(tok, expr, false)
+                    }
+                    _ => Ok(expr),
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, tok: Token, expr: P<Expr>, prefix: bool) -> EResult {
+        if is_lvalue(&expr) {
+            let op = tok.name().to_owned();
+            Ok(expr!(ExprDecl::Update(op, expr, prefix), tok.position))
+        } else {
+            Err(MsgWithPos::new(
+                self.lexer.path(),
+                tok.position,
+                Msg::LvalueExpected,
+            ))
         }
     }
 
@@ -382,6 +766,10 @@ impl<'a> Parser<'a> {
                     let ident = self.expect_identifier()?;
                     expr!(ExprDecl::Field(left, ident), tok.position)
                 }
+                TokenKind::Question => {
+                    let tok = self.advance_token()?;
+                    expr!(ExprDecl::Propagate(left), tok.position)
+                }
 
                 TokenKind::LBracket => {
                     let tok = self.advance_token()?;
@@ -407,7 +795,8 @@ impl<'a> Parser<'a> {
                         self.expect_token(TokenKind::LParen)?;
 
                         let args =
-                            self.parse_comma_list(TokenKind::RParen, |p| p.parse_expression())?;
+                            self.parse_comma_list(TokenKind::RParen, |p| p.parse_argument())?;
+                        self.check_arguments(&args)?;
 
                         expr!(ExprDecl::Call(expr, args), expr.pos.clone())
                     } else {
@@ -418,11 +807,41 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Call argument, `name: value` is parsed like a label followed by the value.
+    fn parse_argument(&mut self) -> EResult {
+        let arg = self.parse_expression()?;
+        if let ExprDecl::Label(name) = &arg.decl {
+            let value = self.parse_expression()?;
+            return Ok(expr!(
+                ExprDecl::NamedArg(name.to_owned(), value),
+                arg.pos.clone()
+            ));
+        }
+        Ok(arg)
+    }
+
+    /// Named arguments must come last and name each parameter once.
+    fn check_arguments(&self, args: &[P<Expr>]) -> Result<(), MsgWithPos> {
+        let mut names = HashSet::new();
+        for arg in args.iter() {
+            let msg = match &arg.decl {
+                ExprDecl::NamedArg(name, _) if !names.insert(name) => {
+                    Msg::DuplicateNamedArgument(name.to_owned())
+                }
+                ExprDecl::NamedArg(_, _) => continue,
+                _ if !names.is_empty() => Msg::PositionalAfterNamed,
+                _ => continue,
+            };
+            return Err(MsgWithPos::new(self.lexer.path(), arg.pos.clone(), msg));
+        }
+        Ok(())
+    }
+
     fn expect_identifier(&mut self) -> Result<String, MsgWithPos> {
         let tok = self.advance_token()?;
 
         if let TokenKind::Identifier(ref value) = tok.kind {
-            Ok(value.to_owned())
+            Ok(value.to_string())
         } else {
             Err(MsgWithPos::new(
                 self.lexer.path(),
@@ -467,11 +886,34 @@ impl<'a> Parser<'a> {
     }
 
     fn advance_token(&mut self) -> Result<Token, MsgWithPos> {
-        let tok = self.lexer.read_token()?;
+        let tok = match self.pending.pop() {
+            Some(tok) => tok,
+            None => {
+                let tok = self.lexer.read_token()?;
+                self.prev_end = self.span.1;
+                self.span = self.lexer.span();
+                self.doc = self.lexer.take_doc();
+                tok
+            }
+        };
 
         Ok(mem::replace(&mut self.token, tok))
     }
 
+    /// Reads the `++` or `--` just passed as two `+` or `-` tokens, the current token
+    /// following them.
+    fn split_update(&mut self, tok: Token) {
+        let half = match tok.kind {
+            TokenKind::Inc => TokenKind::Add,
+            _ => TokenKind::Sub,
+        };
+        let mut second = Token::new(half.clone(), tok.position.clone());
+        second.position.column += 1;
+        let next = mem::replace(&mut self.token, Token::new(half, tok.position));
+        self.pending.push(next);
+        self.pending.push(second);
+    }
+
     fn parse_lambda(&mut self) -> EResult {
         unimplemented!()
         /*let tok = self.advance_token()?;
@@ -519,7 +961,10 @@ impl<'a> Parser<'a> {
         };
         let pos = self.advance_token()?.position;
 
-        Ok(expr!(ExprDecl::Const(Constant::Builtin(b.clone())), pos))
+        Ok(expr!(
+            ExprDecl::Const(Constant::Builtin(b.to_string())),
+            pos
+        ))
     }
 
     fn parse_parentheses(&mut self) -> EResult {
@@ -552,11 +997,35 @@ impl<'a> Parser<'a> {
     fn lit_int(&mut self) -> EResult {
         let tok = self.advance_token()?;
         let pos = tok.position.clone();
-        if let TokenKind::LitInt(i, _, _) = tok.kind {
-            Ok(expr!(
-                ExprDecl::Const(Constant::Int(i.parse().unwrap())),
-                pos
-            ))
+        if let TokenKind::LitInt(i, base, suffix) = tok.kind {
+            let digits = i.replace('_', "");
+            if suffix == IntSuffix::BigInt {
+                return Ok(expr!(
+                    ExprDecl::Const(Constant::BigInt(digits, base.num())),
+                    pos
+                ));
+            }
+            // The VM has a single integer type, a literal can't be a Byte or a Long.
+            let ty = match suffix {
+                IntSuffix::Byte => Some("Byte"),
+                IntSuffix::Long => Some("Long"),
+                _ => None,
+            };
+            if let Some(ty) = ty {
+                return Err(MsgWithPos::new(
+                    self.lexer.path(),
+                    pos,
+                    Msg::UnsupportedIntType(ty.into()),
+                ));
+            }
+            match i64::from_str_radix(&digits, base.num()) {
+                Ok(value) => Ok(expr!(ExprDecl::Const(Constant::Int(value)), pos)),
+                Err(_) => Err(MsgWithPos::new(
+                    self.lexer.path(),
+                    pos,
+                    Msg::NumberOverflow("Int".into()),
+                )),
+            }
         } else {
             unreachable!()
         }
@@ -564,9 +1033,9 @@ impl<'a> Parser<'a> {
 
     fn lit_char(&mut self) -> EResult {
         let tok = self.advance_token()?;
-        let _pos = tok.position.clone();
-        if let TokenKind::LitChar(_c) = tok.kind {
-            Ok(expr!(ExprDecl::Const(Constant::Str(_c.to_string())), _pos))
+        let pos = tok.position.clone();
+        if let TokenKind::LitChar(c) = tok.kind {
+            Ok(expr!(ExprDecl::Const(Constant::Char(c)), pos))
         } else {
             unreachable!()
         }
@@ -577,7 +1046,7 @@ impl<'a> Parser<'a> {
         let pos = tok.position.clone();
         if let TokenKind::LitFloat(c) = tok.kind {
             Ok(expr!(
-                ExprDecl::Const(Constant::Float(c.parse().unwrap())),
+                ExprDecl::Const(Constant::Float(c.replace('_', "").parse().unwrap())),
                 pos
             ))
         } else {
@@ -589,7 +1058,7 @@ impl<'a> Parser<'a> {
         let tok = self.advance_token()?;
         let pos = tok.position.clone();
         if let TokenKind::String(s) = tok.kind {
-            Ok(expr!(ExprDecl::Const(Constant::Str(s)), pos))
+            Ok(expr!(ExprDecl::Const(Constant::Str(s.to_string())), pos))
         } else {
             unreachable!()
         }
@@ -606,3 +1075,42 @@ impl<'a> Parser<'a> {
         Ok(expr!(ExprDecl::Const(Constant::Ident(ident)), pos))
     }
 }
+
+fn is_lvalue(expr: &Expr) -> bool {
+    matches!(
+        expr.decl,
+        ExprDecl::Const(Constant::Ident(_)) | ExprDecl::Field(..) | ExprDecl::Array(..)
+    )
+}
+
+/// Whether `kind` can only start an operand, so a `++` or `--` right before it is two
+/// operators rather than a postfix update.
+fn starts_operand(kind: &TokenKind) -> bool {
+    matches!(
+        kind,
+        TokenKind::Identifier(_)
+            | TokenKind::Builtin(_)
+            | TokenKind::LitChar(_)
+            | TokenKind::LitInt(..)
+            | TokenKind::LitFloat(_)
+            | TokenKind::String(_)
+            | TokenKind::LParen
+            | TokenKind::This
+            | TokenKind::True
+            | TokenKind::False
+            | TokenKind::Nil
+            | TokenKind::Fun
+            | TokenKind::Not
+            | TokenKind::Tilde
+    )
+}
+
+/// `pattern` with its top-level bare identifiers, alternatives included, compared instead of
+/// bound.
+fn compare_identifiers(pattern: Pattern) -> Pattern {
+    match pattern {
+        Pattern::Bind(name) => Pattern::Equal(name),
+        Pattern::Or(alts) => Pattern::Or(alts.into_iter().map(compare_identifiers).collect()),
+        pattern => pattern,
+    }
+}
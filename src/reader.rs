@@ -1,13 +1,21 @@
 use std::{
     fs::File,
-    io::{self, Error, Read},
+    io::{self, BufRead, BufReader, Error},
 };
 
 use crate::token::Position;
 
+/// Number of chars after the current one that are always read from the input, the most
+/// `peek`, `next` and `starts_with` look ahead.
+pub const LOOKAHEAD: usize = 4;
+
+/// Source text, read from its input a line at a time as the lexer gets to it.
 pub struct Reader {
     filename: crate::P<String>,
+    /// The source read so far, all of it once the current char is the end.
     pub src: String,
+    input: Option<Box<dyn BufRead>>,
+    error: Option<Error>,
 
     pos: usize,
     next_pos: usize,
@@ -19,24 +27,58 @@ pub struct Reader {
 }
 
 impl Reader {
-    pub fn from_input() -> Result<Reader, Error> {
-        let mut src = String::new();
-        io::stdin().read_to_string(&mut src)?;
-
-        Ok(common_init("<<stdin>>".into(), src))
+    pub fn from_input() -> Reader {
+        Reader::from_reader("<<stdin>>", BufReader::new(io::stdin()))
     }
 
     pub fn from_file(filename: &str) -> Result<Reader, Error> {
-        let mut src = String::new();
+        let file = File::open(filename)?;
 
-        let mut file = File::open(filename)?;
-        file.read_to_string(&mut src)?;
+        Ok(Reader::from_reader(filename, BufReader::new(file)))
+    }
 
-        Ok(common_init(filename.into(), src))
+    /// Reader of the source `input` gives, consumed as the lexer advances. An error reading
+    /// it ends the source, see `error`.
+    pub fn from_reader(filename: &str, input: impl BufRead + 'static) -> Reader {
+        common_init(filename.into(), String::new(), Some(Box::new(input)))
     }
 
     pub fn from_string(src: &str) -> Reader {
-        common_init("<<code>>".into(), src.into())
+        common_init("<<code>>".into(), src.into(), None)
+    }
+
+    /// The error that ended reading the input early, if there was one.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Reads the rest of the input, returns the whole source.
+    pub fn read_to_end(&mut self) -> &str {
+        while self.read_line() {}
+        &self.src
+    }
+
+    /// Reads lines of the input until `chars` chars after the current one are read or the
+    /// input ends.
+    fn fill(&mut self, chars: usize) {
+        while self.src[self.pos..].chars().take(chars).count() < chars && self.read_line() {}
+    }
+
+    /// Reads a line of the input, returns false once it has ended.
+    fn read_line(&mut self) -> bool {
+        let input = match self.input.as_mut() {
+            Some(input) => input,
+            None => return false,
+        };
+        match input.read_line(&mut self.src) {
+            Ok(0) => self.input = None,
+            Ok(_) => (),
+            Err(e) => {
+                self.error = Some(e);
+                self.input = None;
+            }
+        }
+        self.input.is_some()
     }
 
     pub fn set_tabwidth(&mut self, width: usize) {
@@ -70,9 +112,11 @@ impl Reader {
             None => panic!("advancing from eof"),
         }
 
+        self.pos = self.next_pos;
+        self.fill(LOOKAHEAD + 1);
+
         self.cur = if self.next_pos < self.src.len() {
             let ch = self.src[self.next_pos..].chars().next().unwrap();
-            self.pos = self.next_pos;
             self.next_pos += ch.len_utf8();
 
             Some(ch)
@@ -87,6 +131,14 @@ impl Reader {
         self.cur
     }
 
+    /// Byte offset of the current char in the source.
+    pub fn offset(&self) -> usize {
+        match self.cur {
+            Some(_) => self.pos,
+            None => self.src.len(),
+        }
+    }
+
     pub fn pos(&self) -> Position {
         Position {
             file: self.filename.clone(),
@@ -95,6 +147,18 @@ impl Reader {
         }
     }
 
+    /// The char `n` positions after the current one, `n` at most `LOOKAHEAD`.
+    pub fn peek(&self, n: usize) -> Option<char> {
+        self.cur?;
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    /// Whether the input starting at the current char begins with `s`, which has at most
+    /// `LOOKAHEAD + 1` chars.
+    pub fn starts_with(&self, s: &str) -> bool {
+        self.cur.is_some() && self.src[self.pos..].starts_with(s)
+    }
+
     pub fn next(&self) -> Option<char> {
         if self.next_pos < self.src.len() {
             let ch = self.src[self.next_pos..].chars().next().unwrap();
@@ -105,10 +169,12 @@ impl Reader {
     }
 }
 
-fn common_init(name: String, src: String) -> Reader {
+fn common_init(name: String, src: String, input: Option<Box<dyn BufRead>>) -> Reader {
     let mut reader = Reader {
         filename: crate::P(name),
         src: src,
+        input,
+        error: None,
         pos: 0,
         next_pos: 0,
 
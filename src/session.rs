@@ -0,0 +1,54 @@
+//! Data that lives as long as one compilation rather than as long as one token or node.
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Owns the text of identifier, builtin and string tokens. Each distinct text is stored once
+/// and the tokens share it, so lexing a name that was seen before doesn't allocate and
+/// cloning a token is cheap. The text is freed with the last lexer and token using it.
+///
+/// Nodes of the syntax tree still own their strings, moving them here too needs the tree to
+/// borrow from the session.
+#[derive(Default)]
+pub struct CompileSession {
+    strings: RefCell<HashSet<Rc<str>>>,
+}
+
+impl CompileSession {
+    /// The session's copy of `text`.
+    pub fn intern(&self, text: &str) -> Rc<str> {
+        let mut strings = self.strings.borrow_mut();
+        match strings.get(text) {
+            Some(s) => s.clone(),
+            None => {
+                let s: Rc<str> = Rc::from(text);
+                strings.insert(s.clone());
+                s
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::token::TokenKind;
+    use std::rc::Rc;
+
+    #[test]
+    fn equal_names_share_their_text() {
+        let mut lexer = Lexer::from_str("count = count + $len(\"count\")");
+        let mut texts = vec![];
+        loop {
+            match lexer.read_token().unwrap().kind {
+                TokenKind::Identifier(text) | TokenKind::String(text) => texts.push(text),
+                TokenKind::End => break,
+                _ => (),
+            }
+        }
+        assert_eq!(texts.len(), 3);
+        assert!(Rc::ptr_eq(&texts[0], &texts[1]));
+        assert!(Rc::ptr_eq(&texts[0], &texts[2]));
+    }
+}
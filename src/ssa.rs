@@ -0,0 +1,557 @@
+//! Optimization of the code of one function, run with `--opt=2`. Straight-line code, followed
+//! through conditional jumps into code no other jump reaches, is read into SSA values: what
+//! it computes and what it reads from locals, stack slots and memory gets a value number,
+//! and two computations get the same number when they apply the same operation to the same
+//! values with no effect in between. Copying between locals keeps the number, which
+//! propagates copies. The code is then written back with
+//!
+//! - computations whose value a local still holds replaced by a load of that local,
+//! - values computed more than once kept in a new local the first time and loaded from it
+//!   the other times,
+//! - stores of the value the local already holds dropped.
+//!
+//! Other stores and effects stay where they are, so exception handlers see the same locals.
+
+use crate::codegen::UOP;
+use crate::stack::stack_effect;
+use jazzlight::opcode::Op;
+
+use std::collections::{HashMap, HashSet};
+
+/// Number of an SSA value.
+type Vn = usize;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Depends on the operands only.
+    Pure,
+    /// Also depends on fields, globals, the environment or `this`, which effects change.
+    Memory,
+    /// A new array, object or closure every time.
+    Fresh,
+    /// Has side effects or transfers control.
+    Effect,
+}
+
+fn kind(op: &Op) -> Kind {
+    match op {
+        Op::LoadNull
+        | Op::LoadTrue
+        | Op::LoadFalse
+        | Op::LoadInt(_)
+        | Op::LoadBuiltin(_)
+        | Op::IsNull
+        | Op::IsNotNull
+        | Op::Not
+        | Op::StrictEq => Kind::Pure,
+        Op::LoadGlobal(_)
+        | Op::LoadEnv(_)
+        | Op::LoadThis
+        | Op::Load
+        | Op::LoadStrict
+        | Op::LoadElementStrict => Kind::Memory,
+        Op::New | Op::MakeArray(_) | Op::MakeEnv(_) => Kind::Fresh,
+        // Arithmetic and comparisons can call a `__to_string__` hook, `"" + a` shows `a`,
+        // and `Shl` appends to arrays.
+        _ => Kind::Effect,
+    }
+}
+
+/// Whether the code after `op` is only reached by jumping to it.
+fn ends_block(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Jump(_)
+            | Op::JumpIf(_)
+            | Op::JumpIfNot(_)
+            | Op::CatchPush(_)
+            | Op::Switch(..)
+            | Op::Throw
+            | Op::Ret
+            | Op::Yield
+            | Op::Last
+    )
+}
+
+/// A value on the stack.
+struct Entry {
+    vn: Vn,
+    /// The instructions of the output computing the value, when they compute nothing else
+    /// and have no effects.
+    range: Option<(usize, usize)>,
+    /// Values counted as computed in `range`, in the first pass.
+    inner: Vec<Vn>,
+}
+
+#[derive(Default)]
+struct State {
+    stack: Vec<Entry>,
+    locals: HashMap<u16, Vn>,
+    values: HashMap<(Op, Vec<Vn>, usize), Vn>,
+    /// Effects so far, part of the key of values that depend on memory.
+    epoch: usize,
+    /// Temporaries holding values, with where the output stores them.
+    temps: HashMap<Vn, (u16, usize)>,
+}
+
+/// A pass over the code of a function, the first one counts the values computed again.
+struct Lowering {
+    targets: HashSet<String>,
+    /// How many times each value is computed where no local holds it, from the first pass;
+    /// `None` during that pass.
+    counts: Option<HashMap<Vn, usize>>,
+    seen: HashMap<Vn, usize>,
+    next_vn: Vn,
+    next_temp: usize,
+    state: State,
+    ops: Vec<UOP>,
+    pos: Vec<(i32, i32)>,
+}
+
+impl Lowering {
+    fn new(code: &[UOP], counts: Option<HashMap<Vn, usize>>) -> Lowering {
+        let targets = code
+            .iter()
+            .filter_map(|op| match op {
+                UOP::Goto(l) | UOP::GotoF(l) | UOP::GotoT(l) | UOP::PAddr(l) => Some(l.clone()),
+                _ => None,
+            })
+            .collect();
+        let next_temp = code
+            .iter()
+            .filter_map(|op| match op {
+                UOP::Op(Op::LoadLocal(slot)) | UOP::Op(Op::StoreLocal(slot)) => {
+                    Some(*slot as usize + 1)
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        Lowering {
+            targets,
+            counts,
+            seen: HashMap::new(),
+            next_vn: 0,
+            next_temp,
+            state: State::default(),
+            ops: vec![],
+            pos: vec![],
+        }
+    }
+
+    fn fresh(&mut self) -> Vn {
+        self.next_vn += 1;
+        self.next_vn
+    }
+
+    fn emit(&mut self, op: UOP, pos: (i32, i32)) {
+        self.ops.push(op);
+        self.pos.push(pos);
+    }
+
+    /// Drops the output from `start` on.
+    fn truncate(&mut self, start: usize) {
+        self.ops.truncate(start);
+        self.pos.truncate(start);
+        self.state.temps.retain(|_, (_, at)| *at < start);
+    }
+
+    fn pop(&mut self) -> Entry {
+        match self.state.stack.pop() {
+            Some(entry) => entry,
+            // Pushed before the block.
+            None => Entry {
+                vn: self.fresh(),
+                range: None,
+                inner: vec![],
+            },
+        }
+    }
+
+    fn pop_n(&mut self, count: usize) -> Vec<Entry> {
+        let mut entries = (0..count).map(|_| self.pop()).collect::<Vec<_>>();
+        entries.reverse();
+        entries
+    }
+
+    fn local(&mut self, slot: u16) -> Vn {
+        match self.state.locals.get(&slot) {
+            Some(&vn) => vn,
+            None => {
+                let vn = self.fresh();
+                self.state.locals.insert(slot, vn);
+                vn
+            }
+        }
+    }
+
+    /// A local holding `vn`, temporaries only in the second pass.
+    fn holder(&self, vn: Vn) -> Option<u16> {
+        let local = self
+            .state
+            .locals
+            .iter()
+            .filter(|(_, &held)| held == vn)
+            .map(|(&slot, _)| slot)
+            .min();
+        match (local, self.counts.is_some()) {
+            (Some(slot), _) => Some(slot),
+            (None, true) => self.state.temps.get(&vn).map(|&(slot, _)| slot),
+            (None, false) => None,
+        }
+    }
+
+    fn discount(&mut self, vns: &[Vn]) {
+        for vn in vns.iter() {
+            if let Some(count) = self.seen.get_mut(vn) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Pushes `entry`, just computed, loading it from a local instead when one holds it
+    /// and keeping it in a new one when it is computed again later.
+    fn reuse(&mut self, mut entry: Entry, pos: (i32, i32)) {
+        let (start, end) = match entry.range {
+            Some((start, end)) if end - start >= 2 => (start, end),
+            _ => return self.state.stack.push(entry),
+        };
+        if let Some(slot) = self.holder(entry.vn) {
+            self.truncate(start);
+            self.emit(UOP::Op(Op::LoadLocal(slot)), pos);
+            self.discount(&entry.inner);
+            entry.range = Some((start, start + 1));
+            entry.inner = vec![];
+        } else if let Some(counts) = &self.counts {
+            if counts.get(&entry.vn).cloned().unwrap_or(0) >= 2
+                && self.next_temp <= u16::MAX as usize
+            {
+                let slot = self.next_temp as u16;
+                self.next_temp += 1;
+                self.emit(UOP::Op(Op::StoreLocal(slot)), pos);
+                self.emit(UOP::Op(Op::LoadLocal(slot)), pos);
+                self.state.temps.insert(entry.vn, (slot, end));
+                entry.range = Some((start, end + 2));
+            }
+        } else {
+            let count = self.seen.entry(entry.vn).or_insert(0);
+            *count += 1;
+            if *count >= 2 {
+                // The second pass loads it from a temporary here.
+                self.discount(&entry.inner);
+                entry.inner = vec![];
+            }
+            entry.inner.push(entry.vn);
+        }
+        self.state.stack.push(entry);
+    }
+
+    fn compute(&mut self, op: &Op, kind: Kind, pos: (i32, i32)) {
+        let (pops, _) = stack_effect(op);
+        let operands = self.pop_n(pops);
+        let epoch = if kind == Kind::Memory {
+            self.state.epoch
+        } else {
+            0
+        };
+        let key = (
+            op.clone(),
+            operands.iter().map(|e| e.vn).collect::<Vec<_>>(),
+            epoch,
+        );
+        let vn = match self.state.values.get(&key) {
+            Some(&vn) => vn,
+            None => {
+                let vn = self.fresh();
+                self.state.values.insert(key, vn);
+                vn
+            }
+        };
+        // The operands must be computed right before, by nothing but pure code.
+        let mut start = Some(self.ops.len());
+        for e in operands.iter().rev() {
+            start = match (start, e.range) {
+                (Some(at), Some((from, to))) if to == at => Some(from),
+                _ => None,
+            };
+        }
+        self.emit(UOP::Op(op.clone()), pos);
+        let entry = Entry {
+            vn,
+            range: start.map(|start| (start, self.ops.len())),
+            inner: operands.into_iter().flat_map(|e| e.inner).collect(),
+        };
+        self.reuse(entry, pos);
+    }
+
+    fn store_local(&mut self, slot: u16, pos: (i32, i32)) {
+        let entry = self.pop();
+        if self.state.locals.get(&slot) == Some(&entry.vn) {
+            if let Some((start, end)) = entry.range {
+                if end == self.ops.len() {
+                    self.truncate(start);
+                    self.discount(&entry.inner);
+                    return;
+                }
+            }
+        }
+        self.emit(UOP::Op(Op::StoreLocal(slot)), pos);
+        self.state.locals.insert(slot, entry.vn);
+    }
+
+    fn op(&mut self, op: &Op, pos: (i32, i32)) {
+        match op {
+            Op::LoadLocal(slot) => {
+                let vn = self.local(*slot);
+                self.emit(UOP::Op(op.clone()), pos);
+                let end = self.ops.len();
+                self.state.stack.push(Entry {
+                    vn,
+                    range: Some((end - 1, end)),
+                    inner: vec![],
+                });
+            }
+            Op::StoreLocal(slot) => self.store_local(*slot, pos),
+            Op::Pop(count) => {
+                self.pop_n(*count as usize);
+                self.emit(UOP::Op(op.clone()), pos);
+            }
+            _ => match kind(op) {
+                kind @ Kind::Pure | kind @ Kind::Memory => self.compute(op, kind, pos),
+                Kind::Fresh => {
+                    let (pops, _) = stack_effect(op);
+                    self.pop_n(pops);
+                    self.emit(UOP::Op(op.clone()), pos);
+                    let vn = self.fresh();
+                    self.state.stack.push(Entry {
+                        vn,
+                        range: None,
+                        inner: vec![],
+                    });
+                }
+                Kind::Effect => {
+                    let (pops, pushes) = stack_effect(op);
+                    self.pop_n(pops);
+                    self.emit(UOP::Op(op.clone()), pos);
+                    for _ in 0..pushes {
+                        let vn = self.fresh();
+                        self.state.stack.push(Entry {
+                            vn,
+                            range: None,
+                            inner: vec![],
+                        });
+                    }
+                    self.state.epoch += 1;
+                    if ends_block(op) {
+                        self.state = State::default();
+                    }
+                }
+            },
+        }
+    }
+
+    fn run(&mut self, code: &[UOP], pos: &[(i32, i32)]) {
+        for (op, &pos) in code.iter().zip(pos.iter()) {
+            match op {
+                // Labels nothing jumps to are dropped, the code before runs into what follows.
+                UOP::Label(l) if self.targets.contains(l) => {
+                    self.state = State::default();
+                    self.emit(op.clone(), pos);
+                }
+                UOP::Label(_) => (),
+                UOP::Goto(_) => {
+                    self.emit(op.clone(), pos);
+                    self.state = State::default();
+                }
+                UOP::GotoF(_) | UOP::GotoT(_) => {
+                    self.pop();
+                    self.emit(op.clone(), pos);
+                }
+                UOP::PAddr(_) => self.emit(op.clone(), pos),
+                UOP::Op(op) => self.op(op, pos),
+            }
+        }
+    }
+}
+
+/// Optimized `code` of a function or the module's top-level code, with the positions `pos`
+/// of its instructions.
+pub fn optimize(code: &[UOP], pos: &[(i32, i32)]) -> (Vec<UOP>, Vec<(i32, i32)>) {
+    // Jumps to addresses instead of labels don't occur in function code, but the blocks
+    // couldn't be found with them.
+    let addressed = code.iter().any(|op| {
+        matches!(
+            op,
+            UOP::Op(Op::Jump(_))
+                | UOP::Op(Op::JumpIf(_))
+                | UOP::Op(Op::JumpIfNot(_))
+                | UOP::Op(Op::CatchPush(_))
+        )
+    });
+    if addressed {
+        return (code.to_vec(), pos.to_vec());
+    }
+    let mut counting = Lowering::new(code, None);
+    counting.run(code, pos);
+    let mut lowering = Lowering::new(code, Some(counting.seen));
+    lowering.run(code, pos);
+    (lowering.ops, lowering.pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::{run_differential, Outcome};
+
+    /// What `src` returns, which must be the same with and without `--opt=2`.
+    fn returned(src: &str) -> String {
+        match run_differential(src.as_bytes(), 1_000_000) {
+            Ok(Outcome::Returned(value)) => value,
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn repeated_computations_and_copies() {
+        let src = "
+            var f = function(a, b) {
+                var x = a * b + 1
+                var y = a * b + 1
+                var z = x
+                z = x
+                return $array(x, y, z, a * b - y)
+            }
+            return f(6, 7)
+        ";
+        assert_eq!(returned(src), "[43, 43, 43, -1]");
+    }
+
+    #[test]
+    fn fields_are_read_again_after_stores() {
+        let src = "
+            var f = function(o) {
+                var before = o.n
+                o.n = o.n + 1
+                var after = o.n
+                return $array(before, after, o.n + o.n)
+            }
+            var o = $new(null)
+            o.n = 1
+            return f(o)
+        ";
+        assert_eq!(returned(src), "[1, 2, 4]");
+    }
+
+    #[test]
+    fn globals_are_read_again_after_calls() {
+        let src = "
+            var count = 0
+            var bump = function() {
+                count = count + 1
+                return count
+            }
+            var f = function() {
+                var a = count
+                bump()
+                var b = count
+                var c = bump()
+                return $array(a, b, c, count)
+            }
+            return f()
+        ";
+        assert_eq!(returned(src), "[0, 1, 2, 2]");
+    }
+
+    #[test]
+    fn closures_keep_the_values_they_captured() {
+        let src = "
+            var f = function() {
+                var n = 1
+                var get = function() { return n }
+                var a = get()
+                n = n + 1
+                var b = get()
+                n = n + 1
+                return $array(a, b, get(), n)
+            }
+            return f()
+        ";
+        assert_eq!(returned(src), "[1, 1, 1, 3]");
+    }
+
+    #[test]
+    fn branches_and_loops() {
+        let src = "
+            var f = function(n) {
+                var total = 0
+                var i = 0
+                while i < n {
+                    var sq = i * i
+                    if sq % 2 == 0 {
+                        total = total + sq
+                    } else {
+                        total = total - i * i
+                    }
+                    i = i + 1
+                }
+                return total
+            }
+            var a = $array(1, 2, 3)
+            var i = 0
+            while i < 3 {
+                a[i] = a[i] * a[i] + a[i]
+                i = i + 1
+            }
+            return $array(f(10), f(0), a)
+        ";
+        assert_eq!(returned(src), "[-45, 0, [2, 6, 12]]");
+    }
+
+    #[test]
+    fn handlers_see_the_stored_locals() {
+        let src = "
+            var f = function(x) {
+                var y = x + 1
+                try {
+                    y = x + 1
+                    y = y * 2
+                    throw y
+                } catch e {
+                    return $array(e, y, x + 1)
+                }
+            }
+            return f(4)
+        ";
+        assert_eq!(returned(src), "[10, 10, 5]");
+
+        let src = "
+            var f = function(a, b) {
+                var q = a / b
+                return q + a / b
+            }
+            return f(1, 0)
+        ";
+        match run_differential(src.as_bytes(), 1_000_000) {
+            Ok(Outcome::Threw(_)) => (),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn operators_call_their_hooks_every_time() {
+        let src = "
+            var count = 0
+            var o = $new(null)
+            o.__to_string__ = function() {
+                count = count + 1
+                return \"o\"
+            }
+            var f = function() {
+                var a = \"\" + o
+                var b = \"\" + o
+                return $array(a, b, count)
+            }
+            return f()
+        ";
+        assert_eq!(returned(src), "[\"o\", \"o\", 2]");
+    }
+}
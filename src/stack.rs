@@ -0,0 +1,132 @@
+//! Stack effects of the generated code. Every function body, and the module's top-level
+//! code, is followed along all its paths to check that no instruction pops a value the
+//! function didn't push and that paths meeting at an instruction agree on the stack height
+//! there. The largest height found is what the function needs.
+
+use jazzlight::opcode::Op;
+
+use std::fmt;
+
+/// Values `op` pops and pushes.
+pub fn stack_effect(op: &Op) -> (usize, usize) {
+    match op {
+        Op::LoadNull
+        | Op::LoadTrue
+        | Op::LoadFalse
+        | Op::LoadInt(_)
+        | Op::LoadGlobal(_)
+        | Op::LoadEnv(_)
+        | Op::LoadLocal(_)
+        | Op::LoadBuiltin(_)
+        | Op::LoadThis => (0, 1),
+        Op::Load
+        | Op::LoadStrict
+        | Op::LoadElementStrict
+        | Op::LoadField
+        | Op::LoadIndex
+        | Op::Delete => (2, 1),
+        Op::Store => (3, 0),
+        Op::StoreEnv(_) | Op::StoreLocal(_) | Op::StoreThis | Op::StoreGlobal(_) => (1, 0),
+        Op::Pop(count) => (*count as usize, 0),
+        Op::Call(argc) | Op::TailCall(argc) | Op::CallNamed(argc, _) => (*argc as usize + 1, 1),
+        Op::ObjCall(argc) => (*argc as usize + 2, 1),
+        Op::InvokeMethod(_, argc) | Op::InvokeNamed(_, argc, _) => (*argc as usize + 1, 1),
+        Op::Jump(_) | Op::CatchPush(_) | Op::CatchPop | Op::Nop | Op::Last => (0, 0),
+        Op::JumpIf(_) | Op::JumpIfNot(_) | Op::Switch(..) => (1, 0),
+        Op::Throw | Op::Ret | Op::Yield => (1, 0),
+        Op::MakeEnv(count) => (*count as usize + 1, 1),
+        Op::MakeArray(count) => (*count as usize, 1),
+        Op::IsNull | Op::IsNotNull | Op::Not | Op::Neg | Op::BitNot | Op::Hash | Op::New => (1, 1),
+        Op::Add
+        | Op::Sub
+        | Op::Div
+        | Op::Mul
+        | Op::Mod
+        | Op::Pow
+        | Op::Shl
+        | Op::Shr
+        | Op::UShr
+        | Op::Or
+        | Op::And
+        | Op::Xor
+        | Op::Eq
+        | Op::Neq
+        | Op::StrictEq
+        | Op::Gt
+        | Op::Gte
+        | Op::Lt
+        | Op::Lte
+        | Op::AddNum
+        | Op::SubNum
+        | Op::MulNum
+        | Op::GtNum
+        | Op::GteNum
+        | Op::LtNum
+        | Op::LteNum => (2, 1),
+    }
+}
+
+/// Instructions running after the one at `pc`, with the values each finds pushed on top of
+/// what `op` left.
+fn successors(pc: usize, op: &Op) -> Vec<(usize, usize)> {
+    match op {
+        Op::Jump(to) => vec![(*to as usize, 0)],
+        Op::JumpIf(to) | Op::JumpIfNot(to) => vec![(pc + 1, 0), (*to as usize, 0)],
+        // The handler finds the stack as it was here, with the exception on top.
+        Op::CatchPush(to) => vec![(pc + 1, 0), (*to as usize, 1)],
+        Op::Switch(_, cases) => (0..=*cases as usize)
+            .map(|case| (pc + 1 + case, 0))
+            .collect(),
+        Op::Throw | Op::Ret | Op::Last => vec![],
+        _ => vec![(pc + 1, 0)],
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StackError {
+    /// The instruction at the address pops more values than the stack holds.
+    Underflow(usize),
+    /// Paths reach the instruction at the address with the two stack heights.
+    Mismatch(usize, usize, usize),
+    /// Execution runs past the end of the code from the address.
+    FallsOff(usize),
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Underflow(pc) => write!(f, "stack underflow at {}", pc),
+            StackError::Mismatch(pc, x, y) => {
+                write!(f, "stack height {} and {} meet at {}", x, y, pc)
+            }
+            StackError::FallsOff(pc) => write!(f, "code ends after {}", pc),
+        }
+    }
+}
+
+/// Largest stack height the code starting at `entry` reaches, counted from where it starts.
+pub fn max_depth(code: &[Op], entry: usize) -> Result<usize, StackError> {
+    let mut heights: Vec<Option<usize>> = vec![None; code.len()];
+    let mut pending = vec![(entry, 0)];
+    let mut max = 0;
+    while let Some((pc, height)) = pending.pop() {
+        match heights.get(pc) {
+            None => return Err(StackError::FallsOff(pc.saturating_sub(1))),
+            Some(Some(seen)) if *seen == height => continue,
+            Some(Some(seen)) => return Err(StackError::Mismatch(pc, *seen, height)),
+            Some(None) => heights[pc] = Some(height),
+        }
+        let op = &code[pc];
+        let (pops, pushes) = stack_effect(op);
+        if pops > height {
+            return Err(StackError::Underflow(pc));
+        }
+        let after = height - pops + pushes;
+        max = max.max(after);
+        for (next, extra) in successors(pc, op) {
+            max = max.max(after + extra);
+            pending.push((next, after + extra));
+        }
+    }
+    Ok(max)
+}
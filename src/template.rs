@@ -0,0 +1,290 @@
+//! Text templates, compiled with the rest of the script. `$template.render(text, data)`
+//! with a string literal `text` is replaced by code building the string:
+//!
+//! ```text
+//! $template.render("<ul>{% for u in users %}<li>{{ u.name }}</li>{% endfor %}</ul>", data)
+//! ```
+//!
+//! - `{{ expr }}` inserts `$string(expr)`,
+//! - `{% if expr %}`, `{% elif expr %}`, `{% else %}` and `{% endif %}` pick a branch,
+//! - `{% for name in expr %}` ... `{% endfor %}` repeats its body for every value of `expr`.
+//!
+//! Expressions are parsed with the script's parser. Their variables are fields of `data`,
+//! except the variables of enclosing `for` blocks and the parameters of functions in them.
+
+use crate::ast::*;
+use crate::msg::*;
+use crate::parser::Parser;
+use crate::reader::Reader;
+use crate::token::Position;
+use crate::visit::{fold_subexprs, Folder};
+use crate::P;
+
+/// Parameter of the generated function holding `data`.
+const DATA: &str = "__data";
+/// Local of the generated function holding the text so far.
+const OUT: &str = "__out";
+
+enum Piece<'a> {
+    Text(&'a str),
+    /// `{{ expr }}`, with the offset of the expression in the template.
+    Expr(&'a str, usize),
+    /// `{% tag %}`, with the offset of the tag in the template.
+    Tag(&'a str, usize),
+}
+
+/// Closing tag of a block: its first word, the rest and its offset.
+type End<'a> = Option<(&'a str, &'a str, usize)>;
+
+struct Template<'a> {
+    pieces: Vec<Piece<'a>>,
+    next: usize,
+    text: &'a str,
+    /// Position of the string literal.
+    pos: &'a Position,
+    /// Variables of the enclosing `for` blocks.
+    bound: Vec<String>,
+}
+
+/// Code rendering the template `text` with `data`, `pos` being the position of the literal.
+pub fn compile(text: &str, pos: &Position, data: P<Expr>) -> Result<P<Expr>, MsgWithPos> {
+    let mut template = Template {
+        pieces: vec![],
+        next: 0,
+        text,
+        pos,
+        bound: vec![],
+    };
+    template.split()?;
+    let (mut body, end) = template.block()?;
+    if let Some((word, _, offset)) = end {
+        return Err(template.error(offset, &format!("unexpected `{{% {} %}}`", word)));
+    }
+    let at = |decl| {
+        P(Expr {
+            pos: pos.clone(),
+            decl,
+        })
+    };
+    body.insert(
+        0,
+        at(ExprDecl::Var(
+            VarKind::Var,
+            OUT.to_owned(),
+            None,
+            Some(at(ExprDecl::Const(Constant::Str(String::new())))),
+        )),
+    );
+    body.push(at(ExprDecl::Return(Some(at(ExprDecl::Const(
+        Constant::Ident(OUT.to_owned()),
+    ))))));
+    let function = at(ExprDecl::Function(
+        vec![DATA.to_owned()],
+        at(ExprDecl::Block(body)),
+        Signature::default(),
+        None,
+    ));
+    Ok(at(ExprDecl::Call(function, vec![data])))
+}
+
+impl<'a> Template<'a> {
+    /// Splits the text into pieces.
+    fn split(&mut self) -> Result<(), MsgWithPos> {
+        let text = self.text;
+        let mut start = 0;
+        while let Some(found) = text[start..].find('{') {
+            let open = start + found;
+            let (close, expr) = match text[open + 1..].chars().next() {
+                Some('{') => ("}}", true),
+                Some('%') => ("%}", false),
+                _ => {
+                    self.pieces.push(Piece::Text(&text[start..open + 1]));
+                    start = open + 1;
+                    continue;
+                }
+            };
+            let inner = open + 2;
+            let end = match text[inner..].find(close) {
+                Some(len) => inner + len,
+                None => return Err(self.error(open, &format!("unclosed `{}`", &text[open..inner]))),
+            };
+            if open > start {
+                self.pieces.push(Piece::Text(&text[start..open]));
+            }
+            self.pieces.push(if expr {
+                Piece::Expr(&text[inner..end], inner)
+            } else {
+                Piece::Tag(text[inner..end].trim(), open)
+            });
+            start = end + 2;
+        }
+        if start < text.len() {
+            self.pieces.push(Piece::Text(&text[start..]));
+        }
+        Ok(())
+    }
+
+    /// Statements appending the pieces up to the next `elif`, `else`, `endif` or `endfor`
+    /// tag, which is returned, or up to the end.
+    fn block(&mut self) -> Result<(Vec<P<Expr>>, End<'a>), MsgWithPos> {
+        let mut code = vec![];
+        while self.next < self.pieces.len() {
+            self.next += 1;
+            match self.pieces[self.next - 1] {
+                Piece::Text(text) => {
+                    code.push(self.append(self.at(ExprDecl::Const(Constant::Str(text.to_owned())))))
+                }
+                Piece::Expr(expr, offset) => {
+                    let expr = self.expr(expr, offset)?;
+                    let string = self.at(ExprDecl::Const(Constant::Builtin("string".to_owned())));
+                    code.push(self.append(self.at(ExprDecl::Call(string, vec![expr]))));
+                }
+                Piece::Tag(tag, offset) => {
+                    let (word, rest) =
+                        tag.split_at(tag.find(char::is_whitespace).unwrap_or(tag.len()));
+                    match word {
+                        "if" => code.push(self.if_block(rest, offset)?),
+                        "for" => code.push(self.for_block(rest, offset)?),
+                        "elif" | "else" | "endif" | "endfor" => {
+                            return Ok((code, Some((word, rest, offset))))
+                        }
+                        _ => return Err(self.error(offset, &format!("unknown tag `{}`", word))),
+                    }
+                }
+            }
+        }
+        Ok((code, None))
+    }
+
+    /// `{% if cond %}`, `cond` being the rest of the tag at `offset`.
+    fn if_block(&mut self, cond: &str, offset: usize) -> Result<P<Expr>, MsgWithPos> {
+        let cond = self.expr(cond, offset)?;
+        let (then, end) = self.block()?;
+        let otherwise = match end {
+            Some(("elif", cond, offset)) => Some(self.if_block(cond, offset)?),
+            Some(("else", _, offset)) => match self.block()? {
+                (otherwise, Some(("endif", _, _))) => Some(self.at(ExprDecl::Block(otherwise))),
+                _ => return Err(self.error(offset, "`{% else %}` without `{% endif %}`")),
+            },
+            Some(("endif", _, _)) => None,
+            _ => return Err(self.error(offset, "`{% if %}` without `{% endif %}`")),
+        };
+        Ok(self.at(ExprDecl::If(
+            cond,
+            self.at(ExprDecl::Block(then)),
+            otherwise,
+        )))
+    }
+
+    /// `{% for name in collection %}`, `header` being the rest of the tag at `offset`.
+    fn for_block(&mut self, header: &str, offset: usize) -> Result<P<Expr>, MsgWithPos> {
+        let (name, collection) = match header.trim().split_once(char::is_whitespace) {
+            Some((name, rest)) if rest.trim_start().starts_with("in ") => {
+                (name, &rest.trim_start()[3..])
+            }
+            _ => return Err(self.error(offset, "`{% for name in expr %}` expected")),
+        };
+        if !name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+            || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        {
+            return Err(self.error(offset, &format!("`{}` is not a variable name", name)));
+        }
+        let collection = self.expr(collection, offset)?;
+        self.bound.push(name.to_owned());
+        let body = self.block();
+        self.bound.pop();
+        match body? {
+            (body, Some(("endfor", _, _))) => Ok(self.at(ExprDecl::ForIn(
+                name.to_owned(),
+                collection,
+                self.at(ExprDecl::Block(body)),
+            ))),
+            _ => Err(self.error(offset, "`{% for %}` without `{% endfor %}`")),
+        }
+    }
+
+    /// The expression `src` at `offset`, with its variables read from `data`.
+    fn expr(&self, src: &str, offset: usize) -> Result<P<Expr>, MsgWithPos> {
+        let mut ast = vec![];
+        Parser::new(Reader::from_string(src), &mut ast)
+            .parse()
+            .map_err(|e| {
+                MsgWithPos::new(self.pos.file.to_string(), self.position(offset), e.msg)
+            })?;
+        if ast.len() != 1 {
+            return Err(self.error(offset, "one expression expected"));
+        }
+        let mut fields = Fields {
+            bound: self.bound.clone(),
+        };
+        Ok(fields.fold_expr(&ast[0]))
+    }
+
+    /// `OUT = OUT + value`.
+    fn append(&self, value: P<Expr>) -> P<Expr> {
+        let out = || self.at(ExprDecl::Const(Constant::Ident(OUT.to_owned())));
+        let sum = self.at(ExprDecl::Binop("+".to_owned(), out(), value));
+        self.at(ExprDecl::Assign(out(), sum))
+    }
+
+    fn at(&self, decl: ExprDecl) -> P<Expr> {
+        P(Expr {
+            pos: self.pos.clone(),
+            decl,
+        })
+    }
+
+    /// Position of the byte at `offset` in the template, assuming the literal has no
+    /// escapes before it.
+    fn position(&self, offset: usize) -> Position {
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count();
+        let column = match before.rfind('\n') {
+            Some(newline) => before[newline + 1..].chars().count() + 1,
+            None => self.pos.column as usize + 1 + before.chars().count(),
+        };
+        Position {
+            file: self.pos.file.clone(),
+            line: self.pos.line + line as u32,
+            column: column as u32,
+        }
+    }
+
+    fn error(&self, offset: usize, msg: &str) -> MsgWithPos {
+        MsgWithPos::new(
+            self.pos.file.to_string(),
+            self.position(offset),
+            Msg::TemplateSyntax(msg.to_owned()),
+        )
+    }
+}
+
+/// Replaces the free variables of an expression by fields of `data`.
+struct Fields {
+    bound: Vec<String>,
+}
+
+impl Folder for Fields {
+    fn fold_expr(&mut self, e: &P<Expr>) -> P<Expr> {
+        match &e.decl {
+            ExprDecl::Const(Constant::Ident(name)) if !self.bound.contains(name) => {
+                let data = P(Expr {
+                    pos: e.pos.clone(),
+                    decl: ExprDecl::Const(Constant::Ident(DATA.to_owned())),
+                });
+                P(Expr {
+                    pos: e.pos.clone(),
+                    decl: ExprDecl::Field(data, name.to_owned()),
+                })
+            }
+            ExprDecl::Function(params, _, _, _) => {
+                let len = self.bound.len();
+                self.bound.extend(params.iter().cloned());
+                let e = fold_subexprs(self, e);
+                self.bound.truncate(len);
+                e
+            }
+            _ => fold_subexprs(self, e),
+        }
+    }
+}
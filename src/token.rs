@@ -1,4 +1,5 @@
 use std::fmt;
+use std::rc::Rc;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Position {
@@ -23,14 +24,33 @@ impl fmt::Display for Position {
     }
 }
 
+/// What a `SpannedToken` is: a token the parser reads or text the lexer skips.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SpannedKind {
+    Token(TokenKind),
+    Whitespace,
+    /// `// ...` or `/* ... */`.
+    Comment,
+    /// `/// ...`, see `Lexer::take_doc`.
+    DocComment,
+}
+
+/// Piece of the source with its byte offsets, see `lexer::tokenize_with_trivia`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SpannedToken {
+    pub kind: SpannedKind,
+    pub position: Position,
+    pub span: (usize, usize),
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum TokenKind {
-    String(String),
+    String(Rc<str>),
     LitChar(char),
     LitInt(String, IntBase, IntSuffix),
     LitFloat(String),
-    Identifier(String),
-    Builtin(String),
+    Identifier(Rc<str>),
+    Builtin(Rc<str>),
     End,
 
     LQuote,
@@ -40,6 +60,7 @@ pub enum TokenKind {
     Include,
     This,
     Match,
+    Switch,
     Fun,
     Let,
     Var,
@@ -59,12 +80,18 @@ pub enum TokenKind {
     Try,
     Catch,
     Yield,
+    Defer,
+    Using,
+    Delete,
     Do,
     ForEach,
     Import,
     Type,
     Const,
+    Enum,
+    InstanceOf,
     Goto,
+    Macro,
     Underscore,
 
     // Operators
@@ -74,6 +101,9 @@ pub enum TokenKind {
     Div,
     Mod,
     Not,
+    Inc,
+    Dec,
+    Pow,
     LParen,
     RParen,
     LBracket,
@@ -87,6 +117,7 @@ pub enum TokenKind {
     Sep, // ::
     Arrow,
     Tilde,
+    Question,
     BitOr,
     BitAnd,
     Caret,
@@ -97,6 +128,8 @@ pub enum TokenKind {
     Eq,
     EqEq,
     Ne,
+    EqEqEq,
+    NeEq,
     Lt,
     Le,
     Gt,
@@ -111,12 +144,16 @@ impl TokenKind {
     pub fn name(&self) -> &str {
         match *self {
             TokenKind::Yield => "yield",
+            TokenKind::Defer => "defer",
+            TokenKind::Using => "using",
+            TokenKind::Delete => "delete",
             TokenKind::ForEach => "foreach",
             TokenKind::String(_) => "string",
             TokenKind::LitInt(_, _, suffix) => match suffix {
                 IntSuffix::Byte => "byte number",
                 IntSuffix::Int => "int number",
                 IntSuffix::Long => "long number",
+                IntSuffix::BigInt => "bigint number",
             },
 
             TokenKind::LitChar(_) => "char",
@@ -138,6 +175,7 @@ impl TokenKind {
             TokenKind::Let => "let",
             TokenKind::Var => "var",
             TokenKind::Goto => "goto",
+            TokenKind::Macro => "macro",
             TokenKind::While => "while",
             TokenKind::If => "if",
             TokenKind::Else => "else",
@@ -152,9 +190,12 @@ impl TokenKind {
             TokenKind::Nil => "nil",
             TokenKind::Throw => "throw",
             TokenKind::Match => "match",
+            TokenKind::Switch => "switch",
             TokenKind::Do => "do",
             TokenKind::Type => "type",
             TokenKind::Const => "const",
+            TokenKind::Enum => "enum",
+            TokenKind::InstanceOf => "instanceof",
             TokenKind::Underscore => "_",
 
             TokenKind::Import => "import",
@@ -166,6 +207,9 @@ impl TokenKind {
             TokenKind::Div => "/",
             TokenKind::Mod => "%",
             TokenKind::Not => "!",
+            TokenKind::Inc => "++",
+            TokenKind::Dec => "--",
+            TokenKind::Pow => "**",
             TokenKind::LParen => "(",
             TokenKind::RParen => ")",
             TokenKind::LBracket => "[",
@@ -179,6 +223,7 @@ impl TokenKind {
             TokenKind::Sep => "::",
             TokenKind::Arrow => "->",
             TokenKind::Tilde => "~",
+            TokenKind::Question => "?",
             TokenKind::BitOr => "|",
             TokenKind::BitAnd => "&",
             TokenKind::Caret => "^",
@@ -189,6 +234,8 @@ impl TokenKind {
             TokenKind::Eq => "=",
             TokenKind::EqEq => "==",
             TokenKind::Ne => "!=",
+            TokenKind::EqEqEq => "===",
+            TokenKind::NeEq => "!==",
             TokenKind::Lt => "<",
             TokenKind::Le => "<=",
             TokenKind::Gt => ">",
@@ -205,6 +252,7 @@ pub enum IntSuffix {
     Int,
     Long,
     Byte,
+    BigInt,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -236,13 +284,14 @@ impl Token {
                     IntSuffix::Byte => "B",
                     IntSuffix::Int => "",
                     IntSuffix::Long => "L",
+                    IntSuffix::BigInt => "n",
                 };
 
                 format!("{}{}", val, suffix)
             }
 
             TokenKind::String(ref val) => format!("\"{}\"", &val),
-            TokenKind::Identifier(ref val) => val.clone(),
+            TokenKind::Identifier(ref val) => val.to_string(),
 
             _ => self.kind.name().into(),
         }
@@ -258,6 +307,7 @@ impl fmt::Display for Token {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum IntBase {
     Bin,
+    Oct,
     Dec,
     Hex,
 }
@@ -266,6 +316,7 @@ impl IntBase {
     pub fn num(self) -> u32 {
         match self {
             IntBase::Bin => 2,
+            IntBase::Oct => 8,
             IntBase::Dec => 10,
             IntBase::Hex => 16,
         }
@@ -0,0 +1,56 @@
+//! Traversal of the AST: a `Visitor` looks at every expression of a program, a `Folder`
+//! rebuilds it with some of them replaced. Both only need to handle the expressions they
+//! care about and leave the others to the default methods.
+//!
+//! ```ignore
+//! struct CountCalls(usize);
+//!
+//! impl Visitor for CountCalls {
+//!     fn visit_expr(&mut self, e: &P<Expr>) {
+//!         if let ExprDecl::Call(..) = e.decl {
+//!             self.0 += 1;
+//!         }
+//!         walk_expr(self, e);
+//!     }
+//! }
+//! ```
+
+use crate::ast::*;
+use crate::P;
+
+pub trait Visitor {
+    /// Visits `e`. The default visits its subexpressions, overrides call `walk_expr` to do
+    /// the same.
+    fn visit_expr(&mut self, e: &P<Expr>) {
+        walk_expr(self, e);
+    }
+
+    fn visit_program(&mut self, ast: &[P<Expr>]) {
+        for e in ast.iter() {
+            self.visit_expr(e);
+        }
+    }
+}
+
+/// Visits the direct subexpressions of `e`.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, e: &P<Expr>) {
+    e.iter(|e| visitor.visit_expr(e));
+}
+
+pub trait Folder {
+    /// Expression replacing `e`. The default folds its subexpressions, overrides call
+    /// `fold_subexprs` to do the same.
+    fn fold_expr(&mut self, e: &P<Expr>) -> P<Expr> {
+        fold_subexprs(self, e)
+    }
+
+    fn fold_program(&mut self, ast: &[P<Expr>]) -> Vec<P<Expr>> {
+        ast.iter().map(|e| self.fold_expr(e)).collect()
+    }
+}
+
+/// `e` with its direct subexpressions folded.
+pub fn fold_subexprs<F: Folder + ?Sized>(folder: &mut F, e: &P<Expr>) -> P<Expr> {
+    let e: Result<_, ()> = e.try_map(|e| Ok(folder.fold_expr(e)));
+    P(e.unwrap())
+}
@@ -0,0 +1,445 @@
+//! Experimental WebAssembly backend. Top-level functions that only compute with integers
+//! are compiled to a module exporting them under their own names, taking and returning
+//! `i64`. Such functions need no values besides integers, so the module has no runtime:
+//! functions using anything else (floats, strings, objects, globals, builtins, closures,
+//! `null`) are left out with a warning.
+//!
+//! Supported are parameters and local variables, integer literals, `+ - * / %`, the bitwise
+//! and shift operators, comparisons, `&& || !` in conditions, `if`, `while`, `return`, `++`
+//! and `--` on locals and calls to the other compiled functions. Division by zero traps, as
+//! does reaching the end of a function without a value, which returns `null` on the VM.
+
+use crate::ast::*;
+use crate::P;
+
+use std::collections::HashMap;
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: u32 = 1;
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const TYPE_FUNC: u8 = 0x60;
+const TYPE_I32: u8 = 0x7f;
+const TYPE_I64: u8 = 0x7e;
+const BLOCK_EMPTY: u8 = 0x40;
+const EXPORT_FUNC: u8 = 0;
+
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1a;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const I32_EQZ: u8 = 0x45;
+const I64_SUB: u8 = 0x7d;
+const I64_ADD: u8 = 0x7c;
+
+fn write_uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_uleb(out, name.len() as u64);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, count: usize, contents: &[u8]) {
+    let mut section = vec![];
+    write_uleb(&mut section, count as u64);
+    section.extend_from_slice(contents);
+    out.push(id);
+    write_uleb(out, section.len() as u64);
+    out.extend_from_slice(&section);
+}
+
+/// `i64` opcode of an arithmetic or bitwise operator.
+fn arith_op(op: &str) -> Option<u8> {
+    Some(match op {
+        "+" => I64_ADD,
+        "-" => I64_SUB,
+        "*" => 0x7e,
+        "/" => 0x7f,
+        "%" => 0x81,
+        "&" => 0x83,
+        "|" => 0x84,
+        "^" => 0x85,
+        "<<" => 0x86,
+        ">>" => 0x87,
+        ">>>" => 0x88,
+        _ => return None,
+    })
+}
+
+/// `i64` opcode of a comparison, producing an `i32`.
+fn compare_op(op: &str) -> Option<u8> {
+    Some(match op {
+        "==" | "===" => 0x51,
+        "!=" | "!==" => 0x52,
+        "<" => 0x53,
+        ">" => 0x55,
+        "<=" => 0x57,
+        ">=" => 0x59,
+        _ => return None,
+    })
+}
+
+/// Name, parameters and body of the top-level function `e` declares, if it declares one.
+fn function_decl(e: &P<Expr>) -> Option<(&str, &[String], &P<Expr>)> {
+    match &e.decl {
+        ExprDecl::Var(_, name, _, Some(init)) => match &init.decl {
+            ExprDecl::Function(params, body, _, _) => Some((name, params, body)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+struct FunctionCompiler<'a> {
+    /// Index and arity of the functions that can be called.
+    functions: &'a HashMap<String, (u32, usize)>,
+    locals: HashMap<String, u32>,
+    nlocals: u32,
+    code: Vec<u8>,
+    /// Blocks and loops the code is in, `true` for the blocks `break` leaves.
+    labels: Vec<bool>,
+}
+
+type CResult = Result<(), String>;
+
+impl FunctionCompiler<'_> {
+    fn local(&self, name: &str) -> Result<u32, String> {
+        match self.locals.get(name) {
+            Some(&idx) => Ok(idx),
+            None => Err(format!("`{}` is not a local variable", name)),
+        }
+    }
+
+    /// Compiles `e` leaving an `i64` on the stack.
+    fn value(&mut self, e: &P<Expr>) -> CResult {
+        match &e.decl {
+            ExprDecl::Const(Constant::Int(i)) => {
+                self.code.push(I64_CONST);
+                write_sleb(&mut self.code, *i);
+            }
+            ExprDecl::Const(Constant::Ident(name)) => {
+                let idx = self.local(name)?;
+                self.code.push(LOCAL_GET);
+                write_uleb(&mut self.code, idx as u64);
+            }
+            ExprDecl::Paren(e) => self.value(e)?,
+            ExprDecl::Unop(op, e) if op == "-" => {
+                self.code.push(I64_CONST);
+                write_sleb(&mut self.code, 0);
+                self.value(e)?;
+                self.code.push(I64_SUB);
+            }
+            ExprDecl::Unop(op, e) if op == "+" => self.value(e)?,
+            ExprDecl::Binop(op, e1, e2) if arith_op(op).is_some() => {
+                self.value(e1)?;
+                self.value(e2)?;
+                self.code.push(arith_op(op).unwrap());
+            }
+            ExprDecl::Call(f, args) => {
+                let (idx, arity) = match &f.decl {
+                    ExprDecl::Const(Constant::Ident(name)) => match self.functions.get(name) {
+                        Some(&f) => f,
+                        None => return Err(format!("`{}` is not a compiled function", name)),
+                    },
+                    ExprDecl::Const(Constant::Builtin(name)) => {
+                        return Err(format!("builtin `${}`", name))
+                    }
+                    _ => return Err("calls of function values".to_owned()),
+                };
+                if args.len() != arity {
+                    return Err("calls with a different number of arguments".to_owned());
+                }
+                for arg in args.iter() {
+                    self.value(arg)?;
+                }
+                self.code.push(CALL);
+                write_uleb(&mut self.code, idx as u64);
+            }
+            ExprDecl::If(cond, then, Some(otherwise)) => {
+                self.condition(cond)?;
+                self.code.extend_from_slice(&[IF, TYPE_I64]);
+                self.labels.push(false);
+                self.value(then)?;
+                self.code.push(ELSE);
+                self.value(otherwise)?;
+                self.code.push(END);
+                self.labels.pop();
+            }
+            ExprDecl::Block(exprs) if !exprs.is_empty() => {
+                for e in exprs[..exprs.len() - 1].iter() {
+                    self.statement(e)?;
+                }
+                self.value(&exprs[exprs.len() - 1])?;
+            }
+            ExprDecl::Return(Some(e)) => {
+                self.value(e)?;
+                self.code.push(RETURN);
+            }
+            // Ends a function that returns from its last statement.
+            ExprDecl::While(..) | ExprDecl::Var(..) => {
+                self.statement(e)?;
+                self.code.push(UNREACHABLE);
+            }
+            _ => return Err("expressions that may not be integers".to_owned()),
+        }
+        Ok(())
+    }
+
+    /// Compiles the boolean `e` leaving an `i32` on the stack.
+    fn condition(&mut self, e: &P<Expr>) -> CResult {
+        match &e.decl {
+            ExprDecl::Const(Constant::True) => {
+                self.code.push(I32_CONST);
+                write_sleb(&mut self.code, 1);
+            }
+            ExprDecl::Const(Constant::False) => {
+                self.code.push(I32_CONST);
+                write_sleb(&mut self.code, 0);
+            }
+            ExprDecl::Paren(e) => self.condition(e)?,
+            ExprDecl::Unop(op, e) if op == "!" => {
+                self.condition(e)?;
+                self.code.push(I32_EQZ);
+            }
+            ExprDecl::Binop(op, e1, e2) if compare_op(op).is_some() => {
+                self.value(e1)?;
+                self.value(e2)?;
+                self.code.push(compare_op(op).unwrap());
+            }
+            ExprDecl::Binop(op, e1, e2) if op == "&&" || op == "||" => {
+                self.condition(e1)?;
+                self.code.extend_from_slice(&[IF, TYPE_I32]);
+                self.labels.push(false);
+                if op == "&&" {
+                    self.condition(e2)?;
+                    self.code.push(ELSE);
+                    self.code.extend_from_slice(&[I32_CONST, 0]);
+                } else {
+                    self.code.extend_from_slice(&[I32_CONST, 1]);
+                    self.code.push(ELSE);
+                    self.condition(e2)?;
+                }
+                self.code.push(END);
+                self.labels.pop();
+            }
+            _ => return Err("conditions that may not be booleans".to_owned()),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, e: &P<Expr>) -> CResult {
+        match &e.decl {
+            ExprDecl::Var(_, name, _, Some(init)) => {
+                self.value(init)?;
+                let idx = match self.locals.get(name) {
+                    Some(&idx) => idx,
+                    None => {
+                        self.locals.insert(name.clone(), self.nlocals);
+                        self.nlocals += 1;
+                        self.nlocals - 1
+                    }
+                };
+                self.code.push(LOCAL_SET);
+                write_uleb(&mut self.code, idx as u64);
+            }
+            ExprDecl::Var(..) => return Err("variables without initializer".to_owned()),
+            ExprDecl::Assign(target, value) => {
+                let idx = match &target.decl {
+                    ExprDecl::Const(Constant::Ident(name)) => self.local(name)?,
+                    _ => return Err("assignments to fields and elements".to_owned()),
+                };
+                self.value(value)?;
+                self.code.push(LOCAL_SET);
+                write_uleb(&mut self.code, idx as u64);
+            }
+            ExprDecl::Update(op, target, _) => {
+                let idx = match &target.decl {
+                    ExprDecl::Const(Constant::Ident(name)) => self.local(name)?,
+                    _ => return Err("updates of fields and elements".to_owned()),
+                };
+                self.code.push(LOCAL_GET);
+                write_uleb(&mut self.code, idx as u64);
+                self.code.extend_from_slice(&[I64_CONST, 1]);
+                self.code.push(if op == "++" { I64_ADD } else { I64_SUB });
+                self.code.push(LOCAL_SET);
+                write_uleb(&mut self.code, idx as u64);
+            }
+            ExprDecl::Block(exprs) => {
+                for e in exprs.iter() {
+                    self.statement(e)?;
+                }
+            }
+            ExprDecl::If(cond, then, otherwise) => {
+                self.condition(cond)?;
+                self.code.extend_from_slice(&[IF, BLOCK_EMPTY]);
+                self.labels.push(false);
+                self.statement(then)?;
+                if let Some(otherwise) = otherwise {
+                    self.code.push(ELSE);
+                    self.statement(otherwise)?;
+                }
+                self.code.push(END);
+                self.labels.pop();
+            }
+            ExprDecl::While(cond, body) => {
+                // block { loop { br_if !cond 1; body; br 0 } }
+                self.code
+                    .extend_from_slice(&[BLOCK, BLOCK_EMPTY, LOOP, BLOCK_EMPTY]);
+                self.labels.push(true);
+                self.labels.push(false);
+                self.condition(cond)?;
+                self.code.extend_from_slice(&[I32_EQZ, BR_IF, 1]);
+                self.statement(body)?;
+                self.code.extend_from_slice(&[BR, 0, END, END]);
+                self.labels.pop();
+                self.labels.pop();
+            }
+            ExprDecl::Break(None) => {
+                let depth = match self.labels.iter().rev().position(|&exit| exit) {
+                    Some(depth) => depth,
+                    None => return Err("`break` outside of a loop".to_owned()),
+                };
+                self.code.push(BR);
+                write_uleb(&mut self.code, depth as u64);
+            }
+            ExprDecl::Return(None) => return Err("returning null".to_owned()),
+            _ => {
+                self.value(e)?;
+                self.code.push(DROP);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Body of the function `params`/`body` in the code section, with its locals.
+fn compile_function(
+    params: &[String],
+    body: &P<Expr>,
+    functions: &HashMap<String, (u32, usize)>,
+) -> Result<Vec<u8>, String> {
+    let mut compiler = FunctionCompiler {
+        functions,
+        locals: params
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.clone(), i as u32))
+            .collect(),
+        nlocals: params.len() as u32,
+        code: vec![],
+        labels: vec![],
+    };
+    compiler.value(body)?;
+    compiler.code.push(END);
+    let mut out = vec![];
+    let nlocals = compiler.nlocals - params.len() as u32;
+    if nlocals == 0 {
+        write_uleb(&mut out, 0);
+    } else {
+        write_uleb(&mut out, 1);
+        write_uleb(&mut out, nlocals as u64);
+        out.push(TYPE_I64);
+    }
+    out.extend_from_slice(&compiler.code);
+    Ok(out)
+}
+
+/// The WebAssembly module of the functions in `ast` that can be compiled, and a warning for
+/// each that can't.
+pub fn compile(ast: &[P<Expr>]) -> (Vec<u8>, Vec<String>) {
+    let decls = ast.iter().filter_map(function_decl).collect::<Vec<_>>();
+    let mut warnings = vec![];
+    let mut skipped = vec![false; decls.len()];
+    // Leaving out a function leaves out its callers, compile until none is left out.
+    let bodies = loop {
+        let mut functions = HashMap::new();
+        for (i, (name, params, _)) in decls.iter().enumerate() {
+            if !skipped[i] {
+                functions.insert(name.to_string(), (functions.len() as u32, params.len()));
+            }
+        }
+        let mut bodies = vec![];
+        let mut failed = false;
+        for (i, (name, params, body)) in decls.iter().enumerate() {
+            if skipped[i] {
+                continue;
+            }
+            match compile_function(params, body, &functions) {
+                Ok(code) => bodies.push((*name, params.len(), code)),
+                Err(e) => {
+                    warnings.push(format!(
+                        "{}: wasm: `{}` left out, unsupported: {}",
+                        body.pos, name, e
+                    ));
+                    skipped[i] = true;
+                    failed = true;
+                }
+            }
+        }
+        if !failed {
+            break bodies;
+        }
+    };
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    let mut types = vec![];
+    let mut funcs = vec![];
+    let mut exports = vec![];
+    let mut code = vec![];
+    for (idx, (name, nparams, body)) in bodies.iter().enumerate() {
+        types.push(TYPE_FUNC);
+        write_uleb(&mut types, *nparams as u64);
+        types.extend(vec![TYPE_I64; *nparams]);
+        types.extend_from_slice(&[1, TYPE_I64]);
+        write_uleb(&mut funcs, idx as u64);
+        write_name(&mut exports, name);
+        exports.push(EXPORT_FUNC);
+        write_uleb(&mut exports, idx as u64);
+        write_uleb(&mut code, body.len() as u64);
+        code.extend_from_slice(body);
+    }
+    write_section(&mut out, SECTION_TYPE, bodies.len(), &types);
+    write_section(&mut out, SECTION_FUNCTION, bodies.len(), &funcs);
+    write_section(&mut out, SECTION_EXPORT, bodies.len(), &exports);
+    write_section(&mut out, SECTION_CODE, bodies.len(), &code);
+    (out, warnings)
+}
@@ -2,7 +2,32 @@ use crate::interp::*;
 use crate::value::*;
 use crate::*;
 
+#[cfg(feature = "fs")]
+pub mod archive;
+pub mod builder;
+pub mod bytes;
+pub mod channel;
+pub mod cli;
+pub mod debug;
+pub mod encoding;
+pub mod fiber;
+pub mod func;
+#[cfg(feature = "fs")]
 pub mod io;
+pub mod iter;
+pub mod map;
+pub mod num;
+pub mod object;
+pub mod os;
+pub mod plugin;
+pub mod promise;
+pub mod report;
+pub mod shared;
+#[cfg(feature = "process")]
+pub mod shell;
+pub mod symbol;
+pub mod term;
+pub mod uuid;
 use std::collections::HashMap;
 
 thread_local! {
@@ -13,9 +38,35 @@ pub fn get_builtin(field: &str) -> Option<Value> {
     BUILTINS.with(|builtins| builtins.get(field).cloned())
 }
 
+/// The `@@to_string` or `__to_string__` method of `value`.
+pub fn to_string_method(value: &Value) -> Option<Value> {
+    symbol::find_hook(value, symbol::WellKnown::ToString).or_else(|| match value {
+        Value::Object(object) => {
+            match object
+                .borrow()
+                .get(Value::String(Ref("__to_string__".to_owned())))
+            {
+                Some(method @ Value::Function(_)) => Some(method),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Text of `value` for `$string`, `$print` and string concatenation: the result of its
+/// `@@to_string` or `__to_string__` method if it is an object with one. Elements of arrays
+/// and fields of objects are converted the same way.
+pub fn display(value: &Value) -> Result<String, Value> {
+    match to_string_method(value) {
+        Some(method) => Ok(val_callex(method, value.clone(), &[])?.to_string()),
+        None => value.format_with(&mut display),
+    }
+}
+
 pub fn builtin_print(args: &[Value]) -> Result<Value, Value> {
     for val in args.iter() {
-        print!("{}", val);
+        print!("{}", display(val)?);
     }
     Ok(Value::Null)
 }
@@ -31,19 +82,31 @@ pub fn builtin_apply(args: &[Value]) -> Result<Value, Value> {
                     )))
                 }
             };
-            return val_callex(args[0].clone(), args[1].clone(), &*array);
+            let array = array.to_vec();
+            return val_callex(args[0].clone(), args[1].clone(), &array);
         }
         _ => Err(Value::String(Ref("apply: Function expected".to_owned()))),
     }
 }
 
 pub fn builtin_array(args: &[Value]) -> Result<Value, Value> {
-    Ok(Value::Array(Ref(args.to_vec())))
+    Ok(Value::Array(Ref(args.to_vec().into())))
 }
 
 pub fn builtin_amake(args: &[Value]) -> Result<Value, Value> {
     let array = vec![Value::Null; args[0].to_int().unwrap_or(0) as usize];
-    Ok(Value::Array(Ref(array)))
+    Ok(Value::Array(Ref(array.into())))
+}
+
+pub fn builtin_awith_capacity(args: &[Value]) -> Result<Value, Value> {
+    match args[0].to_int() {
+        Some(capacity) if capacity >= 0 => Ok(Value::Array(Ref(Elements::with_capacity(
+            capacity as usize,
+        )))),
+        _ => Err(Value::String(Ref(
+            "awith_capacity: positive Int expected".to_owned()
+        ))),
+    }
 }
 
 pub fn builtin_asize(args: &[Value]) -> Result<Value, Value> {
@@ -69,11 +132,7 @@ pub fn builtin_apop(args: &[Value]) -> Result<Value, Value> {
 
 pub fn builtin_acopy(args: &[Value]) -> Result<Value, Value> {
     match &args[0] {
-        Value::Array(array) => Ok(Value::Array(Ref(array
-            .borrow()
-            .iter()
-            .map(|x| x.clone())
-            .collect::<Vec<_>>()))),
+        Value::Array(array) => Ok(Value::Array(Ref(array.borrow().clone()))),
         _ => return Err(Value::String(Ref("acopy: Array expected".to_owned()))),
     }
 }
@@ -103,7 +162,7 @@ pub fn builtin_str_from_chars(args: &[Value]) -> Result<Value, Value> {
 
             for ch in array.borrow().iter() {
                 match ch {
-                    Value::Char(x) => chars.push(*x),
+                    Value::Char(x) => chars.push(x),
                     _ => return Ok(Value::Null),
                 }
             }
@@ -128,6 +187,35 @@ pub fn builtin_sget(args: &[Value]) -> Result<Value, Value> {
     }
 }
 
+/// `$char(x)`: the char with code point `x`, or the only char of a one-char string.
+pub fn builtin_char(args: &[Value]) -> Result<Value, Value> {
+    let ch = match &args[0] {
+        Value::Char(ch) => Some(*ch),
+        Value::Int(x) if *x >= 0 && *x <= u32::MAX as i64 => std::char::from_u32(*x as u32),
+        Value::String(s) => {
+            let s = s.borrow();
+            let mut chars = s.chars();
+            chars.next().filter(|_| chars.next().is_none())
+        }
+        _ => None,
+    };
+    match ch {
+        Some(ch) => Ok(Value::Char(ch)),
+        None => Err(Value::String(Ref(format!(
+            "char: Cannot convert {} to char",
+            args[0]
+        )))),
+    }
+}
+
+/// `$ord(ch)`: the code point of `ch`.
+pub fn builtin_ord(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Char(ch) => Ok(Value::Int(*ch as i64)),
+        _ => Err(Value::String(Ref("ord: Char expected".to_owned()))),
+    }
+}
+
 pub fn builtin_sfind(args: &[Value]) -> Result<Value, Value> {
     let pat = format!("{}", args[1]);
     match &args[0] {
@@ -142,87 +230,202 @@ pub fn builtin_sfind(args: &[Value]) -> Result<Value, Value> {
 pub fn builtin_instanceof(args: &[Value]) -> Result<Value, Value> {
     match &args[0] {
         Value::Object(obj) => match &args[1] {
-            Value::Object(obj2) => match &obj.borrow().prototype {
-                Some(proto) => return Ok(Value::Bool(Rc::ptr_eq(proto, obj2))),
-                None => return Ok(Value::Bool(Rc::ptr_eq(obj, obj2))),
-            },
+            Value::Object(obj2) => {
+                if obj.borrow().prototype.is_none() {
+                    return Ok(Value::Bool(Rc::ptr_eq(obj, obj2)));
+                }
+                let mut proto = obj.borrow().prototype.clone();
+                while let Some(p) = proto {
+                    if Rc::ptr_eq(&p, obj2) {
+                        return Ok(Value::Bool(true));
+                    }
+                    proto = p.borrow().prototype.clone();
+                }
+                return Ok(Value::Bool(false));
+            }
             _ => return Ok(Value::Bool(false)),
         },
         _ => return Ok(Value::Bool(false)),
     }
 }
 
+fn error_object(fields: Vec<(&str, Value)>) -> Value {
+    let mut error = Object {
+        prototype: None,
+        table: Default::default(),
+        modcount: 0,
+        attributes: Default::default(),
+        sealed: false,
+    };
+    for (name, value) in fields {
+        error.set(Value::String(Ref(name.to_owned())), value);
+    }
+    Value::Object(Ref(error))
+}
+
+/// `$new_error(message, cause?)`: an error object wrapping the error that caused it.
+pub fn builtin_new_error(args: &[Value]) -> Result<Value, Value> {
+    Ok(error_object(vec![
+        ("message", args.get(0).cloned().unwrap_or(Value::Null)),
+        ("cause", args.get(1).cloned().unwrap_or(Value::Null)),
+    ]))
+}
+
+/// Operand as shown in assertion messages, strings are quoted.
+fn assert_repr(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", *s.borrow()),
+        value => value.to_string(),
+    }
+}
+
+/// The thrown object also gets a `stack` field with the position of the failed assertion.
+/// Multi-line strings that differ are shown as a diff.
+fn assertion_failed(name: &str, operands: &[Value], msg: Option<&Value>) -> Value {
+    let mut message = format!("{} failed", name);
+    if let [left, right] = operands {
+        message.push_str(&format!(
+            ": left = {}, right = {}",
+            assert_repr(left),
+            assert_repr(right)
+        ));
+    }
+    if let Some(msg) = msg {
+        message.push_str(&format!(": {}", msg));
+    }
+    if let [Value::String(left), Value::String(right)] = operands {
+        let (left, right) = (left.borrow(), right.borrow());
+        let diff = report::unified_diff(&left, &right, 3, "left", "right");
+        if !diff.is_empty() && (left.contains('\n') || right.contains('\n')) {
+            message.push('\n');
+            message.push_str(diff.trim_end_matches('\n'));
+        }
+    }
+    let mut fields = vec![("message", Value::String(Ref(message)))];
+    if let [left, right] = operands {
+        fields.push(("left", left.clone()));
+        fields.push(("right", right.clone()));
+    }
+    error_object(fields)
+}
+
+/// `$assert(cond, msg?)`
+pub fn builtin_assert(args: &[Value]) -> Result<Value, Value> {
+    match args.get(0) {
+        Some(cond) if cond.to_bool() => Ok(Value::Null),
+        _ => Err(assertion_failed("assertion", &[], args.get(1))),
+    }
+}
+
+/// `$assert_eq(a, b, msg?)`
+pub fn builtin_assert_eq(args: &[Value]) -> Result<Value, Value> {
+    if args.len() < 2 {
+        return Err(Value::String(Ref(
+            "assert_eq: Two values expected".to_owned()
+        )));
+    }
+    if args[0] == args[1] {
+        Ok(Value::Null)
+    } else {
+        Err(assertion_failed("assert_eq", &args[..2], args.get(2)))
+    }
+}
+
+/// `$assert_ne(a, b, msg?)`
+pub fn builtin_assert_ne(args: &[Value]) -> Result<Value, Value> {
+    if args.len() < 2 {
+        return Err(Value::String(Ref(
+            "assert_ne: Two values expected".to_owned()
+        )));
+    }
+    if args[0] != args[1] {
+        Ok(Value::Null)
+    } else {
+        Err(assertion_failed("assert_ne", &args[..2], args.get(2)))
+    }
+}
+
 pub fn builtin_string(args: &[Value]) -> Result<Value, Value> {
-    let value = args[0].to_string();
+    let value = display(&args[0])?;
     return Ok(Value::String(Ref(value)));
 }
 pub fn builtin_typeof(args: &[Value]) -> Result<Value, Value> {
-    let tag = args[0].tag();
-    Ok(Value::String(Ref(match tag {
-        ValTag::Array => "array",
-        ValTag::Null => "null",
-        ValTag::Float => "float",
-        ValTag::Int => "int",
-        ValTag::Str => "string",
-        ValTag::Bool => "bool",
-        ValTag::Object => "object",
-        ValTag::Char => "char",
-        ValTag::Func => "function",
-        ValTag::User(x) => x,
-    }
-    .to_owned())))
+    Ok(Value::String(Ref(args[0].type_name().to_owned())))
 }
 
-pub fn builtin_nargs(args: &[Value]) -> Result<Value, Value> {
-    match &args[0] {
-        Value::Function(fun) => Ok(Value::Int(fun.borrow().argc as _)),
-        _ => Ok(Value::Null),
+/// `$cmp(a, b)`: -1, 0 or 1 as `a` is less than, equal to or greater than `b`, see
+/// `Value::compare`.
+pub fn builtin_cmp(args: &[Value]) -> Result<Value, Value> {
+    match args[0].compare(&args[1])? {
+        Some(ordering) => Ok(Value::Int(ordering as i64)),
+        None => Err(Value::String(Ref("cmp: Cannot compare NaN".to_owned()))),
     }
 }
-pub fn builtin_load(args: &[Value]) -> Result<Value, Value> {
-    let path = args[0].to_string();
 
-    let libs_path: Option<&'static str> = option_env!("JAZZLIGHT_PATH");
-    let path = match libs_path {
-        Some(lpath) if std::path::Path::new(&format!("{}/{}", lpath, path)).exists() => {
-            format!("{}/{}", lpath, path)
-        }
-        Some(lpath) if std::path::Path::new(&format!("{}/{}.j", lpath, path)).exists() => {
-            format!("{}/{}.j", lpath, path)
+/// `$check_type(value, type, what, proto)`: returns `value` if it has the annotated `type`
+/// and throws otherwise. Capitalized types pass `proto`, the prototype `value` must be an
+/// instance of. Used by contracts mode.
+pub fn builtin_check_type(args: &[Value]) -> Result<Value, Value> {
+    let actual = builtin_typeof(&args[..1])?.to_string();
+    let expected = args[1].to_string();
+    let ok = match expected.as_str() {
+        "any" => true,
+        "number" => ["int", "float", "bigint", "decimal"].contains(&actual.as_str()),
+        _ if expected.starts_with(char::is_uppercase) => {
+            builtin_instanceof(&[args[0].clone(), args[3].clone()])? == Value::Bool(true)
         }
-        Some(_) => path,
-        None => path,
+        ty => ty == actual,
     };
-    let path = if std::path::Path::new(&format!("{}.j", path)).exists() {
-        format!("{}.j", path)
+    if ok {
+        Ok(args[0].clone())
     } else {
-        path
-    };
-    let contents = std::fs::read(&path);
-    match contents {
-        Ok(contents) => {
-            use crate::reader::BytecodeReader;
-            let mut r = BytecodeReader {
-                bytes: std::io::Cursor::new(&contents),
-            };
-
-            let m = r.read_module();
+        Err(Value::String(Ref(format!(
+            "{} expects {} but got {}",
+            args[2], expected, actual
+        ))))
+    }
+}
 
+pub fn builtin_nargs(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Function(fun) => Ok(Value::Int(fun.borrow().argc as _)),
+        _ => Ok(Value::Null),
+    }
+}
+/// `$load(name)`: runs the module `name` refers to, see `crate::loader`, and returns its
+/// exports.
+pub fn builtin_load(args: &[Value]) -> Result<Value, Value> {
+    match crate::loader::load_module(&args[0].to_string()) {
+        Ok(m) => {
             let mut vm = Vm::new();
             vm.save_state_exit();
             vm.interp(m.clone());
 
-            return Ok(m.borrow().exports.clone());
-        }
-        Err(e) => {
-            return Err(Value::String(Ref(format!(
-                "load: failed to load module at '{}': {}",
-                path, e
-            ))))
+            let exports = m.borrow().exports.clone();
+            Ok(exports)
         }
+        Err(e) => Err(Value::String(Ref(format!("load: {}", e)))),
     }
 }
 
+/// `$dispose(value)`: calls the `@@dispose` method of `value`, or its `close` method if it
+/// has none, as `using` does when leaving its body. Does nothing for null.
+pub fn builtin_dispose(args: &[Value]) -> Result<Value, Value> {
+    if let Value::Null = args[0] {
+        return Ok(Value::Null);
+    }
+    let method = symbol::find_hook(&args[0], symbol::WellKnown::Dispose)
+        .or_else(|| find_property(&args[0], &Value::String(Ref("close".to_owned()))));
+    if let Some(method) = method {
+        return val_callex(method, args[0].clone(), &[]);
+    }
+    Err(Value::String(Ref(format!(
+        "dispose: {} has no @@dispose or close method",
+        args[0].type_name()
+    ))))
+}
+
+#[cfg(feature = "native")]
 pub fn builtin_load_function(args: &[Value]) -> Result<Value, Value> {
     use libloading::{Library, Symbol};
     let lib = format!("{}", args[0]);
@@ -271,6 +474,11 @@ pub fn new_native_fn(x: fn(&[Value]) -> Result<Value, Value>, argc: i32) -> Valu
         env: Value::Null,
         module: None,
         argc,
+        params: Rc::from(vec![]),
+        source: None,
+        doc: None,
+        max_stack: 0,
+        hotness: 0,
     }))
 }
 
@@ -281,22 +489,35 @@ pub fn builtins_init() -> HashMap<String, Value> {
     map.insert("array".to_owned(), new_native_fn(builtin_array, -1));
     map.insert("amake".to_owned(), new_native_fn(builtin_amake, 1));
     map.insert("asize".to_owned(), new_native_fn(builtin_asize, 1));
+    map.insert(
+        "awith_capacity".to_owned(),
+        new_native_fn(builtin_awith_capacity, 1),
+    );
     map.insert("apush".to_owned(), new_native_fn(builtin_apush, 2));
-    map.insert("apop".to_owned(), new_native_fn(builtin_apop, 0));
+    map.insert("apop".to_owned(), new_native_fn(builtin_apop, 1));
     map.insert("acopy".to_owned(), new_native_fn(builtin_acopy, 1));
     map.insert("nargs".to_owned(), new_native_fn(builtin_nargs, 1));
     map.insert("typeof".to_owned(), new_native_fn(builtin_typeof, 1));
+    map.insert("cmp".to_owned(), new_native_fn(builtin_cmp, 2));
     map.insert("string".to_owned(), new_native_fn(builtin_string, 1));
     map.insert("load".to_owned(), new_native_fn(builtin_load, 1));
+    #[cfg(feature = "native")]
     map.insert(
         "load_native".to_owned(),
         new_native_fn(builtin_load_function, 2),
     );
+    #[cfg(feature = "native")]
+    map.insert(
+        "native_import".to_owned(),
+        new_native_fn(plugin::builtin_native_import, 1),
+    );
 
     map.insert("scopy".to_owned(), new_native_fn(builtin_scopy, 1));
     map.insert("sfind".to_owned(), new_native_fn(builtin_sfind, 2));
     map.insert("sget".to_owned(), new_native_fn(builtin_sget, 2));
     map.insert("schars".to_owned(), new_native_fn(builtin_schars, 1));
+    map.insert("char".to_owned(), new_native_fn(builtin_char, 1));
+    map.insert("ord".to_owned(), new_native_fn(builtin_ord, 1));
     map.insert(
         "str_from_chars".to_owned(),
         new_native_fn(builtin_str_from_chars, 1),
@@ -306,7 +527,39 @@ pub fn builtins_init() -> HashMap<String, Value> {
         "instanceof".to_owned(),
         new_native_fn(builtin_instanceof, 2),
     );
+    map.insert("new_error".to_owned(), new_native_fn(builtin_new_error, -1));
+    map.insert(
+        "check_type".to_owned(),
+        new_native_fn(builtin_check_type, 4),
+    );
+    map.insert("dispose".to_owned(), new_native_fn(builtin_dispose, 1));
+    map.insert("assert".to_owned(), new_native_fn(builtin_assert, -1));
+    map.insert("assert_eq".to_owned(), new_native_fn(builtin_assert_eq, -1));
+    map.insert("assert_ne".to_owned(), new_native_fn(builtin_assert_ne, -1));
 
+    #[cfg(feature = "fs")]
     io::file_builtins(&mut map);
+    #[cfg(feature = "fs")]
+    archive::archive_builtins(&mut map);
+    num::num_builtins(&mut map);
+    bytes::bytes_builtins(&mut map);
+    encoding::encoding_builtins(&mut map);
+    builder::builder_builtins(&mut map);
+    func::func_builtins(&mut map);
+    iter::iter_builtins(&mut map);
+    map::map_builtins(&mut map);
+    promise::promise_builtins(&mut map);
+    channel::channel_builtins(&mut map);
+    shared::shared_builtins(&mut map);
+    fiber::fiber_builtins(&mut map);
+    os::os_builtins(&mut map);
+    #[cfg(feature = "process")]
+    shell::shell_builtins(&mut map);
+    debug::debug_builtins(&mut map);
+    symbol::symbol_builtins(&mut map);
+    uuid::uuid_builtins(&mut map);
+    term::term_builtins(&mut map);
+    cli::cli_builtins(&mut map);
+    report::report_builtins(&mut map);
     return map;
 }
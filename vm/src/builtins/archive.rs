@@ -0,0 +1,788 @@
+//! Zip and tar archives, gzipped or not, for backup and packaging scripts:
+//!
+//! ```ignore
+//! $archive_create("backup.tar.gz", $array("src", "README.md"))
+//! foreach entry in $archive_list("backup.tar.gz") { $print(entry.name, "\n") }
+//! $archive_extract("backup.tar.gz", "restore")
+//! ```
+//!
+//! Archives are read from a path or from Bytes, and their format is told from their first
+//! bytes. Symbolic links and other special tar entries are skipped.
+
+use crate::builtins::bytes::{with_bytes, Bytes};
+use crate::deflate;
+use crate::*;
+use value::*;
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn truncated() -> String {
+    "unexpected end of archive".to_owned()
+}
+
+#[derive(Copy, Clone)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    /// Format named by the extension of `path`.
+    fn from_path(path: &str) -> Option<Format> {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(Format::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+struct Entry {
+    /// Path in the archive, `/` separated, without a trailing `/` for directories.
+    name: String,
+    dir: bool,
+    data: Vec<u8>,
+    /// Unix time of the last modification, in seconds.
+    mtime: u64,
+}
+
+/// `name` split on `/` without empty and `.` components, `None` if it goes up with `..` or
+/// names a Windows drive. Leading `/`s are dropped, so absolute names become relative.
+fn normalize_name(name: &str) -> Option<String> {
+    let mut parts = vec![];
+    for part in name.split(|c| c == '/' || c == '\\') {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part if part.contains(':') => return None,
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Days since 1970-01-01 of a date in the Gregorian calendar.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Date in the Gregorian calendar of a number of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// MS-DOS time and date fields of zip entries, which only cover 1980 to 2107.
+fn dos_time(unix: u64) -> (u16, u16) {
+    let (y, m, d) = civil_from_days((unix / 86400) as i64);
+    if y < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let y = (y - 1980).min(127);
+    let secs = unix % 86400;
+    let time = (secs / 3600) << 11 | (secs % 3600 / 60) << 5 | secs % 60 / 2;
+    (time as u16, (y << 9 | m << 5 | d) as u16)
+}
+
+fn unix_time(time: u16, date: u16) -> u64 {
+    let (y, m, d) = (
+        1980 + (date >> 9) as i64,
+        (date >> 5 & 15) as i64,
+        (date & 31) as i64,
+    );
+    let days = days_from_civil(y, m.max(1), d.max(1)) as u64;
+    let secs = (time >> 11) as u64 * 3600 + (time >> 5 & 63) as u64 * 60 + (time & 31) as u64 * 2;
+    days * 86400 + secs
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, String> {
+    let bytes = data.get(pos..pos + 2).ok_or_else(truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, String> {
+    let bytes = data.get(pos..pos + 4).ok_or_else(truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_zip(data: &[u8]) -> Result<Vec<Entry>, String> {
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .find(|pos| data[*pos..].starts_with(b"PK\x05\x06"))
+        .ok_or("zip end of central directory not found")?;
+    let count = u16_at(data, end + 10)?;
+    let mut pos = u32_at(data, end + 16)? as usize;
+    let mut entries = vec![];
+    for _ in 0..count {
+        if u32_at(data, pos)? != 0x0201_4b50 {
+            return Err("corrupt zip central directory".to_owned());
+        }
+        let flags = u16_at(data, pos + 8)?;
+        let method = u16_at(data, pos + 10)?;
+        let time = u16_at(data, pos + 12)?;
+        let date = u16_at(data, pos + 14)?;
+        let crc = u32_at(data, pos + 16)?;
+        let size = u32_at(data, pos + 20)? as usize;
+        let unpacked = u32_at(data, pos + 24)? as usize;
+        let name_len = u16_at(data, pos + 28)? as usize;
+        let extra_len = u16_at(data, pos + 30)? as usize;
+        let comment_len = u16_at(data, pos + 32)? as usize;
+        let offset = u32_at(data, pos + 42)? as usize;
+        let name = data
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        pos += 46 + name_len + extra_len + comment_len;
+        if flags & 1 != 0 {
+            return Err(format!("'{}' is encrypted", name));
+        }
+        if size == 0xffff_ffff || offset == 0xffff_ffff {
+            return Err(format!("'{}' needs zip64, which isn't supported", name));
+        }
+        if u32_at(data, offset)? != 0x0403_4b50 {
+            return Err(format!("corrupt zip header of '{}'", name));
+        }
+        let start = offset + 30 + u16_at(data, offset + 26)? as usize;
+        let start = start + u16_at(data, offset + 28)? as usize;
+        let raw = data.get(start..start + size).ok_or_else(truncated)?;
+        let contents = match method {
+            0 => raw.to_vec(),
+            8 => deflate::inflate(raw, unpacked.min(deflate::MAX_INFLATED))?.0,
+            method => {
+                return Err(format!(
+                    "'{}' uses unsupported compression method {}",
+                    name, method
+                ))
+            }
+        };
+        if deflate::crc32(&contents) != crc {
+            return Err(format!("checksum mismatch in '{}'", name));
+        }
+        entries.push(Entry {
+            dir: name.ends_with('/'),
+            name: name.trim_end_matches('/').to_owned(),
+            data: contents,
+            mtime: unix_time(time, date),
+        });
+    }
+    Ok(entries)
+}
+
+/// Text of a zero padded tar header field.
+fn tar_str(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn tar_octal(field: &[u8]) -> Result<u64, String> {
+    let text = tar_str(field);
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| "corrupt tar header".to_owned())
+}
+
+/// `path` of a pax extended header, made of `<length> <key>=<value>\n` records.
+fn pax_path(mut records: &[u8]) -> Option<String> {
+    while !records.is_empty() {
+        let space = records.iter().position(|b| *b == b' ')?;
+        let len = std::str::from_utf8(&records[..space]).ok()?.parse().ok()?;
+        let record = records.get(space + 1..len)?;
+        if record.starts_with(b"path=") && record.ends_with(b"\n") {
+            let path = &record[5..record.len() - 1];
+            return Some(String::from_utf8_lossy(path).into_owned());
+        }
+        records = &records[len..];
+    }
+    None
+}
+
+/// Sum of the bytes of a tar header, with the checksum field counted as spaces.
+fn tar_checksum(header: &[u8]) -> u64 {
+    let sum: u64 = header.iter().map(|b| *b as u64).sum();
+    let field: u64 = header[148..156].iter().map(|b| *b as u64).sum();
+    sum - field + 8 * b' ' as u64
+}
+
+fn read_tar(data: &[u8]) -> Result<Vec<Entry>, String> {
+    let mut entries = vec![];
+    let mut long_name = None;
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 512).ok_or_else(truncated)?;
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        if tar_octal(&header[148..156])? != tar_checksum(header) {
+            return Err("corrupt tar header".to_owned());
+        }
+        let size = tar_octal(&header[124..136])? as usize;
+        let body = data
+            .get(pos + 512..pos + 512 + size)
+            .ok_or_else(truncated)?;
+        pos += 512 + (size + 511) / 512 * 512;
+        let kind = header[156];
+        match kind {
+            // GNU long name and pax extended header, both for the next entry.
+            b'L' => long_name = Some(tar_str(body)),
+            b'x' => long_name = pax_path(body).or(long_name),
+            0 | b'0' | b'7' | b'5' => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = tar_str(&header[..100]);
+                    let prefix = if &header[257..262] == b"ustar" {
+                        tar_str(&header[345..500])
+                    } else {
+                        String::new()
+                    };
+                    if prefix.is_empty() {
+                        name
+                    } else {
+                        format!("{}/{}", prefix, name)
+                    }
+                });
+                entries.push(Entry {
+                    dir: kind == b'5' || name.ends_with('/'),
+                    name: name.trim_end_matches('/').to_owned(),
+                    data: body.to_vec(),
+                    mtime: tar_octal(&header[136..148])?,
+                });
+            }
+            _ => long_name = None,
+        }
+    }
+    Ok(entries)
+}
+
+fn read_archive(data: &[u8]) -> Result<Vec<Entry>, String> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        read_zip(data)
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        read_tar(&deflate::gunzip(data, deflate::MAX_INFLATED)?)
+    } else {
+        read_tar(data)
+    }
+}
+
+fn put16(out: &mut Vec<u8>, x: u16) {
+    out.extend(&x.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, x: u32) {
+    out.extend(&x.to_le_bytes());
+}
+
+fn write_zip(entries: &[Entry]) -> Result<Vec<u8>, String> {
+    if entries.len() > 0xffff {
+        return Err("too many entries for zip".to_owned());
+    }
+    let mut out = vec![];
+    let mut central = vec![];
+    for entry in entries {
+        let name = if entry.dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        let compressed = deflate::deflate(&entry.data);
+        let (method, body) = if compressed.len() < entry.data.len() {
+            (8, &compressed)
+        } else {
+            (0, &entry.data)
+        };
+        if out.len() + body.len() >= 0xffff_ffff || entry.data.len() >= 0xffff_ffff {
+            return Err(format!("'{}' is too large for zip", entry.name));
+        }
+        let offset = out.len() as u32;
+        let crc = deflate::crc32(&entry.data);
+        let (time, date) = dos_time(entry.mtime);
+        // Version 2.0 and UTF-8 names in both headers.
+        let mut common = vec![];
+        put16(&mut common, 20);
+        put16(&mut common, 1 << 11);
+        put16(&mut common, method);
+        put16(&mut common, time);
+        put16(&mut common, date);
+        put32(&mut common, crc);
+        put32(&mut common, body.len() as u32);
+        put32(&mut common, entry.data.len() as u32);
+        put16(&mut common, name.len() as u16);
+        put16(&mut common, 0);
+
+        put32(&mut out, 0x0403_4b50);
+        out.extend(&common);
+        out.extend(name.as_bytes());
+        out.extend(body);
+
+        put32(&mut central, 0x0201_4b50);
+        // Made by Unix, for the permissions in the external attributes.
+        put16(&mut central, 3 << 8 | 20);
+        central.extend(&common);
+        put16(&mut central, 0);
+        put16(&mut central, 0);
+        put16(&mut central, 0);
+        let (mode, dos) = if entry.dir {
+            (0o40755, 0x10)
+        } else {
+            (0o100644, 0)
+        };
+        put32(&mut central, mode << 16 | dos);
+        put32(&mut central, offset);
+        central.extend(name.as_bytes());
+    }
+    let central_offset = out.len() as u32;
+    out.extend(&central);
+    put32(&mut out, 0x0605_4b50);
+    put16(&mut out, 0);
+    put16(&mut out, 0);
+    put16(&mut out, entries.len() as u16);
+    put16(&mut out, entries.len() as u16);
+    put32(&mut out, central.len() as u32);
+    put32(&mut out, central_offset);
+    put16(&mut out, 0);
+    Ok(out)
+}
+
+fn put_octal(field: &mut [u8], value: u64, name: &str) -> Result<(), String> {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    if digits.len() >= field.len() {
+        return Err(format!("'{}' is too large for tar", name));
+    }
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    Ok(())
+}
+
+/// Prefix and name fields of a ustar header, which hold 155 and 100 bytes. `None` if `name`
+/// can't be split to fit.
+fn split_tar_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && !name.is_empty() && name.len() <= 100)
+}
+
+fn put_tar_entry(
+    out: &mut Vec<u8>,
+    name: &[u8],
+    prefix: &[u8],
+    kind: u8,
+    data: &[u8],
+    mtime: u64,
+) -> Result<(), String> {
+    let display = String::from_utf8_lossy(name);
+    let mut header = [0u8; 512];
+    let name = &name[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    let mode = if kind == b'5' { 0o755 } else { 0o644 };
+    put_octal(&mut header[100..108], mode, &display)?;
+    put_octal(&mut header[108..116], 0, &display)?;
+    put_octal(&mut header[116..124], 0, &display)?;
+    put_octal(&mut header[124..136], data.len() as u64, &display)?;
+    put_octal(&mut header[136..148], mtime, &display)?;
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix);
+    let sum = tar_checksum(&header);
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    out.extend(&header[..]);
+    out.extend(data);
+    out.resize((out.len() + 511) / 512 * 512, 0);
+    Ok(())
+}
+
+fn write_tar(entries: &[Entry]) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    for entry in entries {
+        let name = if entry.dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        let kind = if entry.dir { b'5' } else { b'0' };
+        match split_tar_name(&name) {
+            Some((prefix, short)) => put_tar_entry(
+                &mut out,
+                short.as_bytes(),
+                prefix.as_bytes(),
+                kind,
+                &entry.data,
+                entry.mtime,
+            )?,
+            // A GNU long name entry holds the name for the entry after it.
+            None => {
+                let mut long_name = name.clone().into_bytes();
+                long_name.push(0);
+                put_tar_entry(&mut out, b"././@LongLink", b"", b'L', &long_name, 0)?;
+                let (name, data) = (name.as_bytes(), &entry.data);
+                put_tar_entry(&mut out, name, b"", kind, data, entry.mtime)?;
+            }
+        }
+    }
+    out.resize(out.len() + 1024, 0);
+    Ok(out)
+}
+
+/// Adds the file or directory at `path` as `name`, directories with everything in them.
+fn add_path(entries: &mut Vec<Entry>, name: &str, path: &Path) -> Result<(), String> {
+    let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    let metadata = fs::metadata(path).map_err(error)?;
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    if !metadata.is_dir() {
+        entries.push(Entry {
+            name: name.to_owned(),
+            dir: false,
+            data: fs::read(path).map_err(error)?,
+            mtime,
+        });
+        return Ok(());
+    }
+    // An empty name is the root of the archive, which has no entry of its own.
+    if !name.is_empty() {
+        entries.push(Entry {
+            name: name.to_owned(),
+            dir: true,
+            data: vec![],
+            mtime,
+        });
+    }
+    let mut children = fs::read_dir(path)
+        .and_then(|dir| dir.collect::<Result<Vec<_>, _>>())
+        .map_err(error)?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let child_name = child.file_name().to_string_lossy().into_owned();
+        let child_name = if name.is_empty() {
+            child_name
+        } else {
+            format!("{}/{}", name, child_name)
+        };
+        add_path(entries, &child_name, &child.path())?;
+    }
+    Ok(())
+}
+
+fn field(object: &Ref<Object>, key: &str) -> Option<Value> {
+    object.borrow().get(Value::String(Ref(key.to_owned())))
+}
+
+/// Entries to create an archive from: paths of files and directories, `{name, path}` to
+/// store a file or directory under another name and `{name, bytes}` for contents in memory,
+/// as Bytes or a String.
+fn new_entries(value: &Value) -> Result<Vec<Entry>, Value> {
+    let name = "archive_create";
+    let items = match value {
+        Value::Array(items) => items.borrow().to_vec(),
+        _ => return Err(error(name, "Array of entries expected")),
+    };
+    let mut entries = vec![];
+    for item in items {
+        let (entry_name, path, bytes) = match &item {
+            Value::String(path) => {
+                let path = path.borrow().clone();
+                (path.clone(), Some(path), None)
+            }
+            Value::Object(object) => {
+                let entry_name = match field(object, "name") {
+                    Some(Value::String(s)) => s.borrow().clone(),
+                    _ => return Err(error(name, "entry name must be a String")),
+                };
+                let path = field(object, "path").map(|path| path.to_string());
+                (entry_name, path, field(object, "bytes"))
+            }
+            _ => return Err(error(name, "entries must be paths or objects")),
+        };
+        let normal = match normalize_name(&entry_name) {
+            Some(normal) => normal,
+            None => return Err(error(name, &format!("invalid name '{}'", entry_name))),
+        };
+        if let Some(path) = path {
+            add_path(&mut entries, &normal, Path::new(&path)).map_err(|e| error(name, &e))?;
+            continue;
+        }
+        let data = match bytes {
+            Some(Value::String(s)) => s.borrow().as_bytes().to_vec(),
+            Some(bytes) => with_bytes(&bytes, name, |buf| Ok(buf.clone()))?,
+            None => return Err(error(name, "entry needs a path or bytes")),
+        };
+        if normal.is_empty() {
+            return Err(error(name, &format!("invalid name '{}'", entry_name)));
+        }
+        entries.push(Entry {
+            name: normal,
+            dir: false,
+            data,
+            mtime: now(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Entries of the archive at a path or in Bytes.
+fn open(value: &Value, name: &str) -> Result<Vec<Entry>, Value> {
+    let data = match value {
+        Value::String(path) => {
+            let path = path.borrow();
+            fs::read(&*path).map_err(|e| error(name, &format!("{}: {}", path, e)))?
+        }
+        _ => with_bytes(value, name, |buf| Ok(buf.clone()))?,
+    };
+    read_archive(&data).map_err(|e| error(name, &e))
+}
+
+/// `$archive_list(archive)`: `{name, size, dir, mtime}` of every entry, in order.
+pub fn archive_list(args: &[Value]) -> Result<Value, Value> {
+    let entries = open(&args[0], "archive_list")?;
+    let list = entries.into_iter().map(|entry| {
+        let mut object = Object {
+            prototype: None,
+            table: Default::default(),
+            modcount: 0,
+            attributes: Default::default(),
+            sealed: false,
+        };
+        let key = |key: &str| Value::String(Ref(key.to_owned()));
+        object.set(key("name"), Value::String(Ref(entry.name)));
+        object.set(key("size"), Value::Int(entry.data.len() as i64));
+        object.set(key("dir"), Value::Bool(entry.dir));
+        object.set(key("mtime"), Value::Int(entry.mtime as i64));
+        Value::Object(Ref(object))
+    });
+    Ok(Value::Array(Ref(list.collect())))
+}
+
+/// `$archive_read(archive, name)`: contents of the file `name` as Bytes.
+pub fn archive_read(args: &[Value]) -> Result<Value, Value> {
+    let wanted = args[1].to_string();
+    let normal = normalize_name(&wanted);
+    let entries = open(&args[0], "archive_read")?;
+    match entries
+        .into_iter()
+        .find(|entry| !entry.dir && Some(&entry.name) == normal.as_ref())
+    {
+        Some(entry) => Ok(Value::User(Ref(Bytes(entry.data)))),
+        None => Err(error("archive_read", &format!("no file '{}'", wanted))),
+    }
+}
+
+/// `$archive_extract(archive, dir)`: writes the entries under `dir`, creating it if needed,
+/// and returns the paths written. Throws before writing anything if an entry would end up
+/// outside of `dir`.
+pub fn archive_extract(args: &[Value]) -> Result<Value, Value> {
+    let name = "archive_extract";
+    let dir = args[1].to_string();
+    let entries = open(&args[0], name)?;
+    let mut targets = vec![];
+    for entry in &entries {
+        match normalize_name(&entry.name) {
+            Some(normal) if normal.is_empty() => targets.push(None),
+            Some(normal) => targets.push(Some(Path::new(&dir).join(normal))),
+            None => return Err(error(name, &format!("unsafe entry name '{}'", entry.name))),
+        }
+    }
+    let mut written = vec![];
+    fs::create_dir_all(&dir).map_err(|e| error(name, &format!("{}: {}", dir, e)))?;
+    for (entry, target) in entries.iter().zip(targets) {
+        let target = match target {
+            Some(target) => target,
+            None => continue,
+        };
+        let io_error = |e: std::io::Error| error(name, &format!("{}: {}", target.display(), e));
+        if entry.dir {
+            fs::create_dir_all(&target).map_err(io_error)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(io_error)?;
+            }
+            fs::write(&target, &entry.data).map_err(io_error)?;
+        }
+        let path = target.to_string_lossy().into_owned();
+        written.push(Value::String(Ref(path)));
+    }
+    Ok(Value::Array(Ref(written.into_iter().collect())))
+}
+
+/// `$archive_create(path, entries)`: writes an archive of the format its extension names,
+/// `.zip`, `.tar` or `.tar.gz`. See `new_entries` for what `entries` holds.
+pub fn archive_create(args: &[Value]) -> Result<Value, Value> {
+    let name = "archive_create";
+    let path = args[0].to_string();
+    let format = match Format::from_path(&path) {
+        Some(format) => format,
+        None => return Err(error(name, "expected a .zip, .tar or .tar.gz path")),
+    };
+    let entries = new_entries(&args[1])?;
+    let data = match format {
+        Format::Zip => write_zip(&entries),
+        Format::Tar => write_tar(&entries),
+        Format::TarGz => write_tar(&entries).map(|tar| deflate::gzip(&tar)),
+    };
+    let data = data.map_err(|e| error(name, &e))?;
+    fs::write(&path, data).map_err(|e| error(name, &format!("{}: {}", path, e)))?;
+    Ok(Value::Null)
+}
+
+use super::*;
+
+pub fn archive_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("archive_list".to_owned(), new_native_fn(archive_list, 1));
+    map.insert("archive_read".to_owned(), new_native_fn(archive_read, 2));
+    map.insert(
+        "archive_extract".to_owned(),
+        new_native_fn(archive_extract, 2),
+    );
+    map.insert(
+        "archive_create".to_owned(),
+        new_native_fn(archive_create, 2),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, data: &[u8]) -> Entry {
+        Entry {
+            name: name.to_owned(),
+            dir: false,
+            data: data.to_vec(),
+            mtime: 1_600_000_000,
+        }
+    }
+
+    fn sample() -> Vec<Entry> {
+        vec![
+            Entry {
+                name: "docs".to_owned(),
+                dir: true,
+                data: vec![],
+                mtime: 1_600_000_000,
+            },
+            file("docs/readme.txt", b"hello, archive\n"),
+            file("data.bin", &b"abcabcabc".repeat(1000)),
+        ]
+    }
+
+    fn assert_same(read: &[Entry], written: &[Entry]) {
+        assert_eq!(read.len(), written.len());
+        for (read, written) in read.iter().zip(written) {
+            assert_eq!(read.name, written.name);
+            assert_eq!(read.dir, written.dir);
+            assert_eq!(read.data, written.data);
+            assert_eq!(read.mtime, written.mtime);
+        }
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let entries = sample();
+        assert_same(
+            &read_archive(&write_zip(&entries).unwrap()).unwrap(),
+            &entries,
+        );
+    }
+
+    #[test]
+    fn tar_round_trip_with_long_names() {
+        let mut entries = sample();
+        // Split between the prefix and name fields of the header.
+        entries.push(file(
+            &format!("{}/{}", "d".repeat(120), "f".repeat(90)),
+            b"split",
+        ));
+        // Too long for both, stored in a GNU long name entry.
+        entries.push(file(&"n".repeat(300), b"long"));
+        let tar = write_tar(&entries).unwrap();
+        assert_same(&read_archive(&tar).unwrap(), &entries);
+        assert_same(&read_archive(&deflate::gzip(&tar)).unwrap(), &entries);
+    }
+
+    #[test]
+    fn truncated_archives_are_errors() {
+        let zip = write_zip(&sample()).unwrap();
+        for len in 0..zip.len() {
+            assert!(read_zip(&zip[..len]).is_err(), "zip cut at {}", len);
+        }
+        // Cuts in the padding after a body lose nothing, cuts in a header or a body do.
+        let tar = write_tar(&sample()).unwrap();
+        let mut pos = 0;
+        while tar[pos..pos + 512].iter().any(|&b| b != 0) {
+            let size = tar_octal(&tar[pos + 124..pos + 136]).unwrap() as usize;
+            for len in pos + 1..pos + 512 + size {
+                assert!(read_tar(&tar[..len]).is_err(), "tar cut at {}", len);
+            }
+            pos += 512 + (size + 511) / 512 * 512;
+        }
+    }
+
+    #[test]
+    fn corrupt_archives_are_errors() {
+        let tar = write_tar(&sample()).unwrap();
+        // Readers stop at the first of the two zero blocks at the end, flips after it go unseen.
+        let mut pos = 0;
+        while pos < tar.len() - 512 {
+            let size = tar_octal(&tar[pos + 124..pos + 136]).unwrap_or(0) as usize;
+            // The checksum counts its own field as spaces, so its terminator isn't covered.
+            for i in (pos..pos + 512).filter(|i| i - pos != 155) {
+                let mut corrupt = tar.clone();
+                corrupt[i] ^= 0xff;
+                assert!(read_tar(&corrupt).is_err(), "tar byte {} flipped", i);
+            }
+            pos += 512 + (size + 511) / 512 * 512;
+        }
+        // Not every byte of a zip is checked, but none may panic.
+        let zip = write_zip(&sample()).unwrap();
+        for i in 0..zip.len() {
+            let mut corrupt = zip.clone();
+            corrupt[i] ^= 0xff;
+            let _ = read_zip(&corrupt);
+        }
+    }
+
+    #[test]
+    fn zip_entries_inflate_to_their_stated_size() {
+        let mut zip = write_zip(&[file("zeros", &vec![0; 1 << 20])]).unwrap();
+        let central = u32_at(&zip, zip.len() - 6).unwrap() as usize;
+        zip[central + 24..central + 28].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(read_zip(&zip).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::*;
+use value::*;
+
+use std::fmt;
+
+/// Growable string, appending to it doesn't copy what was appended before.
+pub struct StringBuilder(pub String);
+
+impl fmt::Debug for StringBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl fmt::Display for StringBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl UserKind for StringBuilder {
+    fn get_kind(&self) -> &'static str {
+        "string_builder"
+    }
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn with_builder<R>(
+    value: &Value,
+    name: &str,
+    f: impl FnOnce(&mut String) -> R,
+) -> Result<R, Value> {
+    match value {
+        Value::User(user) => match user.borrow_mut().downcast_mut::<StringBuilder>() {
+            Some(builder) => Ok(f(&mut builder.0)),
+            None => Err(error(name, "StringBuilder expected")),
+        },
+        _ => Err(error(name, "StringBuilder expected")),
+    }
+}
+
+/// `$string_builder(capacity?)`
+pub fn builder_new(args: &[Value]) -> Result<Value, Value> {
+    let capacity = match args.get(0) {
+        Some(Value::Null) | None => 0,
+        Some(x) => match x.to_int() {
+            Some(x) if x >= 0 => x as usize,
+            _ => return Err(error("string_builder", "capacity must be a positive Int")),
+        },
+    };
+    Ok(Value::User(Ref(StringBuilder(String::with_capacity(
+        capacity,
+    )))))
+}
+
+/// `$builder_append(builder, value)` appends `value` as `+` would, returns the builder.
+pub fn builder_append(args: &[Value]) -> Result<Value, Value> {
+    let value = display(&args[1])?;
+    with_builder(&args[0], "builder_append", |s| s.push_str(&value))?;
+    Ok(args[0].clone())
+}
+
+pub fn builder_to_string(args: &[Value]) -> Result<Value, Value> {
+    with_builder(&args[0], "builder_to_string", |s| {
+        Value::String(Ref(s.clone()))
+    })
+}
+
+pub fn builder_len(args: &[Value]) -> Result<Value, Value> {
+    with_builder(&args[0], "builder_len", |s| Value::Int(s.len() as _))
+}
+
+/// `$sconcat(values...)`: all values concatenated as by `+`, with a single allocation
+/// for the result. The optimizer uses it for chains of `+` starting with a string, so values
+/// are converted through `display` like `+` does.
+pub fn builder_concat(args: &[Value]) -> Result<Value, Value> {
+    let mut s = String::new();
+    for arg in args.iter() {
+        s.push_str(&display(arg)?);
+    }
+    Ok(Value::String(Ref(s)))
+}
+
+use super::*;
+
+pub fn builder_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("string_builder".to_owned(), new_native_fn(builder_new, -1));
+    map.insert(
+        "builder_append".to_owned(),
+        new_native_fn(builder_append, 2),
+    );
+    map.insert(
+        "builder_to_string".to_owned(),
+        new_native_fn(builder_to_string, 1),
+    );
+    map.insert("builder_len".to_owned(), new_native_fn(builder_len, 1));
+    map.insert("sconcat".to_owned(), new_native_fn(builder_concat, -1));
+}
@@ -0,0 +1,342 @@
+use crate::*;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use std::fmt;
+use std::ops::Range;
+
+/// Mutable byte buffer.
+pub struct Bytes(pub Vec<u8>);
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<bytes {}>", self.0.len())
+    }
+}
+
+impl UserKind for Bytes {
+    fn get_kind(&self) -> &'static str {
+        "bytes"
+    }
+}
+
+/// Largest buffer `$bytes` makes or cursor writes grow, so a stray offset fails instead of
+/// exhausting memory.
+const MAX_BYTES: usize = 1 << 30;
+
+/// Read/write position over a `Bytes` buffer. Writes past the end grow the buffer.
+pub struct BytesCursor {
+    bytes: Ref<dyn UserKind>,
+    pos: usize,
+}
+
+impl fmt::Debug for BytesCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BytesCursor({})", self.pos)
+    }
+}
+
+impl fmt::Display for BytesCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<cursor at {}>", self.pos)
+    }
+}
+
+impl UserKind for BytesCursor {
+    fn get_kind(&self) -> &'static str {
+        "cursor"
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    U8,
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+    I64Le,
+    I64Be,
+    F64Le,
+    F64Be,
+}
+
+impl Scalar {
+    fn size(self) -> usize {
+        match self {
+            Scalar::U8 => 1,
+            Scalar::U16Le | Scalar::U16Be => 2,
+            Scalar::U32Le | Scalar::U32Be => 4,
+            _ => 8,
+        }
+    }
+
+    fn read(self, buf: &[u8]) -> Value {
+        match self {
+            Scalar::U8 => Value::Int(buf[0] as _),
+            Scalar::U16Le => Value::Int(LittleEndian::read_u16(buf) as _),
+            Scalar::U16Be => Value::Int(BigEndian::read_u16(buf) as _),
+            Scalar::U32Le => Value::Int(LittleEndian::read_u32(buf) as _),
+            Scalar::U32Be => Value::Int(BigEndian::read_u32(buf) as _),
+            Scalar::I64Le => Value::Int(LittleEndian::read_i64(buf)),
+            Scalar::I64Be => Value::Int(BigEndian::read_i64(buf)),
+            Scalar::F64Le => Value::Float(LittleEndian::read_f64(buf)),
+            Scalar::F64Be => Value::Float(BigEndian::read_f64(buf)),
+        }
+    }
+
+    fn write(self, buf: &mut [u8], value: &Value) -> Result<(), String> {
+        match self {
+            Scalar::F64Le | Scalar::F64Be => {
+                let x = value.to_float().ok_or("Number expected")?;
+                match self {
+                    Scalar::F64Le => LittleEndian::write_f64(buf, x),
+                    _ => BigEndian::write_f64(buf, x),
+                }
+            }
+            _ => {
+                let x = value.to_int().ok_or("Integer expected")?;
+                match self {
+                    Scalar::U8 => buf[0] = x as u8,
+                    Scalar::U16Le => LittleEndian::write_u16(buf, x as u16),
+                    Scalar::U16Be => BigEndian::write_u16(buf, x as u16),
+                    Scalar::U32Le => LittleEndian::write_u32(buf, x as u32),
+                    Scalar::U32Be => BigEndian::write_u32(buf, x as u32),
+                    Scalar::I64Le => LittleEndian::write_i64(buf, x),
+                    _ => BigEndian::write_i64(buf, x),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+pub(crate) fn with_bytes<R>(
+    value: &Value,
+    name: &str,
+    f: impl FnOnce(&mut Vec<u8>) -> Result<R, Value>,
+) -> Result<R, Value> {
+    match value {
+        Value::User(user) => match user.borrow_mut().downcast_mut::<Bytes>() {
+            Some(bytes) => f(&mut bytes.0),
+            None => Err(error(name, "Bytes expected")),
+        },
+        _ => Err(error(name, "Bytes expected")),
+    }
+}
+
+fn with_cursor<R>(
+    value: &Value,
+    name: &str,
+    f: impl FnOnce(&mut Vec<u8>, &mut usize) -> Result<R, Value>,
+) -> Result<R, Value> {
+    match value {
+        Value::User(user) => match user.borrow_mut().downcast_mut::<BytesCursor>() {
+            Some(cursor) => {
+                let pos = &mut cursor.pos;
+                match cursor.bytes.borrow_mut().downcast_mut::<Bytes>() {
+                    Some(bytes) => f(&mut bytes.0, pos),
+                    None => Err(error(name, "Bytes expected")),
+                }
+            }
+            None => Err(error(name, "Cursor expected")),
+        },
+        _ => Err(error(name, "Cursor expected")),
+    }
+}
+
+fn offset(value: &Value, name: &str) -> Result<usize, Value> {
+    match value.to_int() {
+        Some(x) if x >= 0 => Ok(x as usize),
+        _ => Err(error(name, "non-negative offset expected")),
+    }
+}
+
+/// `off..off + len` if it ends within `limit`, offsets can be anything a script passes.
+fn span(off: usize, len: usize, limit: usize) -> Option<Range<usize>> {
+    match off.checked_add(len) {
+        Some(end) if end <= limit => Some(off..end),
+        _ => None,
+    }
+}
+
+fn bytes_read_scalar(args: &[Value], scalar: Scalar, name: &str) -> Result<Value, Value> {
+    let off = offset(&args[1], name)?;
+    with_bytes(&args[0], name, |buf| {
+        match span(off, scalar.size(), buf.len()) {
+            Some(range) => Ok(scalar.read(&buf[range])),
+            None => Err(error(name, "read out of bounds")),
+        }
+    })
+}
+
+fn bytes_write_scalar(args: &[Value], scalar: Scalar, name: &str) -> Result<Value, Value> {
+    let off = offset(&args[1], name)?;
+    with_bytes(&args[0], name, |buf| {
+        let range = match span(off, scalar.size(), buf.len()) {
+            Some(range) => range,
+            None => return Err(error(name, "write out of bounds")),
+        };
+        scalar
+            .write(&mut buf[range], &args[2])
+            .map_err(|e| error(name, &e))?;
+        Ok(Value::Null)
+    })
+}
+
+fn cursor_read_scalar(args: &[Value], scalar: Scalar, name: &str) -> Result<Value, Value> {
+    with_cursor(&args[0], name, |buf, pos| {
+        let range = match span(*pos, scalar.size(), buf.len()) {
+            Some(range) => range,
+            None => return Err(error(name, "read past end of buffer")),
+        };
+        *pos = range.end;
+        Ok(scalar.read(&buf[range]))
+    })
+}
+
+fn cursor_write_scalar(args: &[Value], scalar: Scalar, name: &str) -> Result<Value, Value> {
+    with_cursor(&args[0], name, |buf, pos| {
+        let range = match span(*pos, scalar.size(), MAX_BYTES) {
+            Some(range) => range,
+            None => return Err(error(name, "write past the largest buffer size")),
+        };
+        if range.end > buf.len() {
+            buf.resize(range.end, 0);
+        }
+        scalar
+            .write(&mut buf[range.clone()], &args[1])
+            .map_err(|e| error(name, &e))?;
+        *pos = range.end;
+        Ok(Value::Null)
+    })
+}
+
+macro_rules! scalar_builtins {
+    ($($scalar: ident => $read: ident, $write: ident, $cread: ident, $cwrite: ident;)*) => {
+        $(
+            pub fn $read(args: &[Value]) -> Result<Value, Value> {
+                bytes_read_scalar(args, Scalar::$scalar, stringify!($read))
+            }
+            pub fn $write(args: &[Value]) -> Result<Value, Value> {
+                bytes_write_scalar(args, Scalar::$scalar, stringify!($write))
+            }
+            pub fn $cread(args: &[Value]) -> Result<Value, Value> {
+                cursor_read_scalar(args, Scalar::$scalar, stringify!($cread))
+            }
+            pub fn $cwrite(args: &[Value]) -> Result<Value, Value> {
+                cursor_write_scalar(args, Scalar::$scalar, stringify!($cwrite))
+            }
+        )*
+
+        fn scalar_builtins(map: &mut std::collections::HashMap<String, Value>) {
+            $(
+                map.insert(stringify!($read).to_owned(), new_native_fn($read, 2));
+                map.insert(stringify!($write).to_owned(), new_native_fn($write, 3));
+                map.insert(stringify!($cread).to_owned(), new_native_fn($cread, 1));
+                map.insert(stringify!($cwrite).to_owned(), new_native_fn($cwrite, 2));
+            )*
+        }
+    };
+}
+
+scalar_builtins! {
+    U8 => bytes_read_u8, bytes_write_u8, cursor_read_u8, cursor_write_u8;
+    U16Le => bytes_read_u16_le, bytes_write_u16_le, cursor_read_u16_le, cursor_write_u16_le;
+    U16Be => bytes_read_u16_be, bytes_write_u16_be, cursor_read_u16_be, cursor_write_u16_be;
+    U32Le => bytes_read_u32_le, bytes_write_u32_le, cursor_read_u32_le, cursor_write_u32_le;
+    U32Be => bytes_read_u32_be, bytes_write_u32_be, cursor_read_u32_be, cursor_write_u32_be;
+    I64Le => bytes_read_i64_le, bytes_write_i64_le, cursor_read_i64_le, cursor_write_i64_le;
+    I64Be => bytes_read_i64_be, bytes_write_i64_be, cursor_read_i64_be, cursor_write_i64_be;
+    F64Le => bytes_read_f64_le, bytes_write_f64_le, cursor_read_f64_le, cursor_write_f64_le;
+    F64Be => bytes_read_f64_be, bytes_write_f64_be, cursor_read_f64_be, cursor_write_f64_be;
+}
+
+pub fn bytes_new(args: &[Value]) -> Result<Value, Value> {
+    let bytes = match &args[0] {
+        Value::Int(len) if *len >= 0 && *len as u64 <= MAX_BYTES as u64 => vec![0; *len as usize],
+        Value::String(s) => s.borrow().as_bytes().to_vec(),
+        Value::Array(array) => {
+            let mut bytes = vec![];
+            for x in array.borrow().iter() {
+                match x.to_int() {
+                    Some(x) => bytes.push(x as u8),
+                    None => return Err(error("bytes", "Array of integers expected")),
+                }
+            }
+            bytes
+        }
+        Value::Int(_) => return Err(error("bytes", "length out of range")),
+        _ => return Err(error("bytes", "length, String or Array expected")),
+    };
+    Ok(Value::User(Ref(Bytes(bytes))))
+}
+
+pub fn bytes_len(args: &[Value]) -> Result<Value, Value> {
+    with_bytes(&args[0], "bytes_len", |buf| Ok(Value::Int(buf.len() as _)))
+}
+
+pub fn bytes_to_array(args: &[Value]) -> Result<Value, Value> {
+    with_bytes(&args[0], "bytes_to_array", |buf| {
+        Ok(Value::Array(Ref(buf
+            .iter()
+            .map(|x| Value::Int(*x as _))
+            .collect())))
+    })
+}
+
+pub fn bytes_cursor(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::User(user) if user.borrow().is::<Bytes>() => Ok(Value::User(Ref(BytesCursor {
+            bytes: user.clone(),
+            pos: 0,
+        }))),
+        _ => Err(error("cursor", "Bytes expected")),
+    }
+}
+
+pub fn cursor_pos(args: &[Value]) -> Result<Value, Value> {
+    with_cursor(&args[0], "cursor_pos", |_, pos| Ok(Value::Int(*pos as _)))
+}
+
+pub fn cursor_seek(args: &[Value]) -> Result<Value, Value> {
+    let to = offset(&args[1], "cursor_seek")?;
+    with_cursor(&args[0], "cursor_seek", |_, pos| {
+        *pos = to;
+        Ok(Value::Null)
+    })
+}
+
+pub fn cursor_remaining(args: &[Value]) -> Result<Value, Value> {
+    with_cursor(&args[0], "cursor_remaining", |buf, pos| {
+        Ok(Value::Int(buf.len().saturating_sub(*pos) as _))
+    })
+}
+
+use super::*;
+
+pub fn bytes_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("bytes".to_owned(), new_native_fn(bytes_new, 1));
+    map.insert("bytes_len".to_owned(), new_native_fn(bytes_len, 1));
+    map.insert(
+        "bytes_to_array".to_owned(),
+        new_native_fn(bytes_to_array, 1),
+    );
+    map.insert("cursor".to_owned(), new_native_fn(bytes_cursor, 1));
+    map.insert("cursor_pos".to_owned(), new_native_fn(cursor_pos, 1));
+    map.insert("cursor_seek".to_owned(), new_native_fn(cursor_seek, 2));
+    map.insert(
+        "cursor_remaining".to_owned(),
+        new_native_fn(cursor_remaining, 1),
+    );
+    scalar_builtins(map);
+}
@@ -0,0 +1,169 @@
+use crate::builtins::iter::entry;
+use crate::builtins::promise::{is_pending, new_promise, resolve_after, settle};
+use crate::*;
+use value::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+thread_local! {
+    /// Methods available on channels, e.g. `ch.send(value)`.
+    pub static CHANNEL_METHODS: HashMap<String, Value> = channel_methods_init();
+}
+
+pub fn get_channel_method(name: &str) -> Option<Value> {
+    CHANNEL_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Unbounded queue of values. Receivers waiting for a value get it through a promise, in
+/// the order they started waiting.
+pub struct Channel {
+    queue: VecDeque<Value>,
+    /// Promises of `recv`s, and of `$channel_select`s with the index of the channel in
+    /// their array. Selects are left here once another channel or the timeout settled them.
+    waiters: VecDeque<(Value, Option<usize>)>,
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Channel({})", self.queue.len())
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<channel>")
+    }
+}
+
+impl UserKind for Channel {
+    fn get_kind(&self) -> &'static str {
+        "channel"
+    }
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn with_channel<T>(
+    value: &Value,
+    name: &str,
+    f: impl FnOnce(&mut Channel) -> T,
+) -> Result<T, Value> {
+    if let Value::User(user) = value {
+        if let Some(channel) = user.borrow_mut().downcast_mut::<Channel>() {
+            return Ok(f(channel));
+        }
+    }
+    Err(error(name, "Channel expected"))
+}
+
+/// `$channel()`: new empty channel.
+pub fn channel_new(_: &[Value]) -> Result<Value, Value> {
+    Ok(Value::User(Ref(Channel {
+        queue: VecDeque::new(),
+        waiters: VecDeque::new(),
+    })))
+}
+
+/// `ch.send(value)`: gives `value` to the first receiver still waiting, queues it if there
+/// is none.
+pub fn channel_send(args: &[Value]) -> Result<Value, Value> {
+    let value = args[1].clone();
+    let waiter = with_channel(&args[0], "send", |channel| {
+        while let Some((promise, index)) = channel.waiters.pop_front() {
+            if is_pending(&promise) {
+                return Some((promise, index));
+            }
+        }
+        channel.queue.push_back(value.clone());
+        None
+    })?;
+    match waiter {
+        Some((promise, Some(index))) => settle(&promise, Ok(entry(index, value))),
+        Some((promise, None)) => settle(&promise, Ok(value)),
+        None => (),
+    }
+    Ok(Value::Null)
+}
+
+/// `ch.recv()`: promise of the next value.
+pub fn channel_recv(args: &[Value]) -> Result<Value, Value> {
+    let promise = new_promise();
+    let value = with_channel(&args[0], "recv", |channel| {
+        let value = channel.queue.pop_front();
+        if value.is_none() {
+            channel.waiters.push_back((promise.clone(), None));
+        }
+        value
+    })?;
+    if let Some(value) = value {
+        settle(&promise, Ok(value));
+    }
+    Ok(promise)
+}
+
+/// `ch.try_recv()`: `$array(value)` with the next value if there is one, `null` otherwise.
+pub fn channel_try_recv(args: &[Value]) -> Result<Value, Value> {
+    let value = with_channel(&args[0], "try_recv", |channel| channel.queue.pop_front())?;
+    Ok(match value {
+        Some(value) => Value::Array(Ref(vec![value].into())),
+        None => Value::Null,
+    })
+}
+
+/// `$channel_select(channels, timeout_ms)`: promise of an `{index, value}` object with the
+/// first value sent to one of the channels and its index, or of `null` once `timeout_ms`
+/// passed. Waits indefinitely if `timeout_ms` is `null`.
+pub fn channel_select(args: &[Value]) -> Result<Value, Value> {
+    let channels = match &args[0] {
+        Value::Array(channels) => channels.borrow().to_vec(),
+        _ => return Err(error("channel_select", "Array expected")),
+    };
+    let timeout = match &args[1] {
+        Value::Null => None,
+        timeout => match timeout.to_int() {
+            Some(ms) if ms >= 0 => Some(Duration::from_millis(ms as u64)),
+            _ => return Err(error("channel_select", "Non-negative Int expected")),
+        },
+    };
+    let promise = new_promise();
+    for (index, channel) in channels.iter().enumerate() {
+        let value = with_channel(channel, "channel_select", |channel| {
+            channel.queue.pop_front()
+        })?;
+        if let Some(value) = value {
+            settle(&promise, Ok(entry(index, value)));
+            return Ok(promise);
+        }
+    }
+    for (index, channel) in channels.iter().enumerate() {
+        with_channel(channel, "channel_select", |channel| {
+            channel.waiters.push_back((promise.clone(), Some(index)))
+        })?;
+    }
+    if let Some(timeout) = timeout {
+        resolve_after(&promise, timeout);
+    }
+    Ok(promise)
+}
+
+use super::*;
+
+pub fn channel_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("channel".to_owned(), new_native_fn(channel_new, 0));
+    map.insert(
+        "channel_select".to_owned(),
+        new_native_fn(channel_select, 2),
+    );
+}
+
+fn channel_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("send".to_owned(), new_native_fn(channel_send, 1));
+    map.insert("recv".to_owned(), new_native_fn(channel_recv, 0));
+    map.insert("try_recv".to_owned(), new_native_fn(channel_try_recv, 0));
+    map
+}
@@ -0,0 +1,554 @@
+//! Command line parsing from a declarative spec, so scripts don't pick `$args()` apart by
+//! hand:
+//!
+//! ```ignore
+//! var spec = $new(null)
+//! spec.name = "backup"
+//! spec.flags = $array("v,verbose")
+//! spec.options = $array("o,output")
+//! spec.positionals = $array("source")
+//! var cli = $cli_parse(spec)
+//! if cli.verbose { $print("backing up ", cli.source, " to ", cli.output, "\n") }
+//! ```
+//!
+//! A spec has a `name` and `about` text for the help, and arrays of `flags`, `options` and
+//! `positionals`, whose entries are names (`"x,name"` also gives a short form) or objects:
+//!
+//! - flags: `{name, short, help}`, `true` when given.
+//! - options: `{name, short, help, type, default, required, multiple}`. The type is
+//!   `"string"` (the default), `"int"`, `"float"` or `"bool"`, multiple options collect
+//!   every value given in an array.
+//! - positionals: `{name, help, required, rest}`, required unless told otherwise. A rest
+//!   positional, which must be the last, collects every argument left in an array.
+//!
+//! `commands` maps subcommand names to specs of their own, the subcommand picked and its
+//! parsed arguments are put in the `command` field and the field named after it.
+
+use crate::builtins::os::shutdown;
+use crate::*;
+use value::*;
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Flag,
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Flag => "flag",
+            Kind::String => "string",
+            Kind::Int => "int",
+            Kind::Float => "float",
+            Kind::Bool => "bool",
+        }
+    }
+}
+
+struct Opt {
+    name: String,
+    short: Option<char>,
+    help: String,
+    kind: Kind,
+    default: Value,
+    required: bool,
+    multiple: bool,
+}
+
+struct Positional {
+    name: String,
+    help: String,
+    required: bool,
+    rest: bool,
+}
+
+struct Spec {
+    name: String,
+    about: String,
+    options: Vec<Opt>,
+    positionals: Vec<Positional>,
+    commands: Vec<(String, Spec)>,
+}
+
+fn key(name: &str) -> Value {
+    Value::String(Ref(name.to_owned()))
+}
+
+/// Field `name` of a spec object, `None` if it's missing or `null`.
+fn field(object: &Ref<Object>, name: &str) -> Option<Value> {
+    match object.borrow().get(key(name)) {
+        Some(Value::Null) | None => None,
+        value => value,
+    }
+}
+
+fn text_field(object: &Ref<Object>, name: &str) -> String {
+    field(object, name)
+        .map(|value| value.to_string())
+        .unwrap_or_default()
+}
+
+fn bool_field(object: &Ref<Object>, name: &str, default: bool) -> bool {
+    field(object, name).map_or(default, |value| value.to_bool())
+}
+
+/// Name and short form of `"name"` or `"x,name"`.
+fn split_name(spec: &str) -> (String, Option<char>) {
+    let mut parts = spec.splitn(2, ',');
+    let first = parts.next().unwrap_or("").trim();
+    match parts.next() {
+        Some(name) if first.chars().count() == 1 => (name.trim().to_owned(), first.chars().next()),
+        _ => (spec.trim().to_owned(), None),
+    }
+}
+
+fn entries(object: &Ref<Object>, name: &str) -> Result<Vec<Value>, String> {
+    match field(object, name) {
+        None => Ok(vec![]),
+        Some(Value::Array(array)) => Ok(array.borrow().to_vec()),
+        Some(_) => Err(format!("'{}' must be an Array", name)),
+    }
+}
+
+fn read_opt(entry: &Value, flag: bool) -> Result<Opt, String> {
+    let object = match entry {
+        Value::String(s) => {
+            let (name, short) = split_name(&s.borrow());
+            return Ok(Opt {
+                name,
+                short,
+                help: String::new(),
+                kind: if flag { Kind::Flag } else { Kind::String },
+                default: Value::Null,
+                required: false,
+                multiple: false,
+            });
+        }
+        Value::Object(object) => object,
+        _ => return Err("flags and options must be names or objects".to_owned()),
+    };
+    let (name, short) = split_name(&text_field(object, "name"));
+    let short = match field(object, "short") {
+        Some(short) => short.to_string().chars().next(),
+        None => short,
+    };
+    let kind = if flag {
+        Kind::Flag
+    } else {
+        match text_field(object, "type").as_str() {
+            "" | "string" => Kind::String,
+            "int" => Kind::Int,
+            "float" => Kind::Float,
+            "bool" => Kind::Bool,
+            other => return Err(format!("unknown type '{}' of option --{}", other, name)),
+        }
+    };
+    Ok(Opt {
+        name,
+        short,
+        help: text_field(object, "help"),
+        kind,
+        default: field(object, "default").unwrap_or(Value::Null),
+        required: bool_field(object, "required", false),
+        multiple: bool_field(object, "multiple", false),
+    })
+}
+
+fn read_positional(entry: &Value) -> Result<Positional, String> {
+    match entry {
+        Value::String(s) => Ok(Positional {
+            name: s.borrow().clone(),
+            help: String::new(),
+            required: true,
+            rest: false,
+        }),
+        Value::Object(object) => Ok(Positional {
+            name: text_field(object, "name"),
+            help: text_field(object, "help"),
+            required: bool_field(object, "required", true),
+            rest: bool_field(object, "rest", false),
+        }),
+        _ => Err("positionals must be names or objects".to_owned()),
+    }
+}
+
+/// `name` is what the usage of a subcommand calls it, the top-level spec names itself.
+fn read_spec(value: &Value, name: Option<String>) -> Result<Spec, String> {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return Err("spec must be an Object".to_owned()),
+    };
+    let name = name.unwrap_or_else(|| match field(object, "name") {
+        Some(name) => name.to_string(),
+        None => "script".to_owned(),
+    });
+    let mut options = vec![];
+    for entry in entries(object, "flags")? {
+        options.push(read_opt(&entry, true)?);
+    }
+    for entry in entries(object, "options")? {
+        options.push(read_opt(&entry, false)?);
+    }
+    if let Some(opt) = options.iter().find(|opt| opt.name.is_empty()) {
+        return Err(format!("option -{} has no name", opt.short.unwrap_or('?')));
+    }
+    let positionals = entries(object, "positionals")?
+        .iter()
+        .map(read_positional)
+        .collect::<Result<Vec<_>, _>>()?;
+    if positionals
+        .iter()
+        .rev()
+        .skip(1)
+        .any(|positional| positional.rest)
+    {
+        return Err("only the last positional can be a rest positional".to_owned());
+    }
+    let mut commands = vec![];
+    match field(object, "commands") {
+        None => (),
+        Some(Value::Object(table)) => {
+            let table = table.borrow();
+            for (command, spec) in table.fields() {
+                let command = command.to_string();
+                let spec = read_spec(spec, Some(format!("{} {}", name, command)))?;
+                commands.push((command, spec));
+            }
+        }
+        Some(_) => return Err("'commands' must be an Object".to_owned()),
+    }
+    Ok(Spec {
+        name,
+        about: text_field(object, "about"),
+        options,
+        positionals,
+        commands,
+    })
+}
+
+fn usage(spec: &Spec) -> String {
+    let mut usage = format!("Usage: {} [options]", spec.name);
+    for positional in &spec.positionals {
+        usage.push_str(&match (positional.required, positional.rest) {
+            (true, false) => format!(" <{}>", positional.name),
+            (false, false) => format!(" [{}]", positional.name),
+            (true, true) => format!(" <{}>...", positional.name),
+            (false, true) => format!(" [{}]...", positional.name),
+        });
+    }
+    if !spec.commands.is_empty() {
+        usage.push_str(" <command>");
+    }
+    usage
+}
+
+/// Lines of `(left, right)` with the right sides lined up.
+fn table(rows: &[(String, String)]) -> String {
+    let width = rows.iter().map(|(left, _)| left.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (left, right) in rows {
+        let line = format!("  {:width$}  {}", left, right, width = width);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn help(spec: &Spec) -> String {
+    let mut out = usage(spec);
+    out.push('\n');
+    if !spec.about.is_empty() {
+        out.push('\n');
+        out.push_str(&spec.about);
+        out.push('\n');
+    }
+    if !spec.positionals.is_empty() {
+        out.push_str("\nArguments:\n");
+        let rows = spec
+            .positionals
+            .iter()
+            .map(|positional| (format!("<{}>", positional.name), positional.help.clone()))
+            .collect::<Vec<_>>();
+        out.push_str(&table(&rows));
+    }
+    out.push_str("\nOptions:\n");
+    let mut rows = vec![];
+    for opt in &spec.options {
+        let short = match opt.short {
+            Some(short) => format!("-{}, ", short),
+            None => "    ".to_owned(),
+        };
+        let value = match opt.kind {
+            Kind::Flag => String::new(),
+            kind => format!(" <{}>", kind.name()),
+        };
+        let mut text = opt.help.clone();
+        if opt.default != Value::Null {
+            text.push_str(&format!(" (default: {})", opt.default));
+        }
+        if opt.required {
+            text.push_str(" (required)");
+        }
+        rows.push((
+            format!("{}--{}{}", short, opt.name, value),
+            text.trim().to_owned(),
+        ));
+    }
+    rows.push(("-h, --help".to_owned(), "Show this help".to_owned()));
+    out.push_str(&table(&rows));
+    if !spec.commands.is_empty() {
+        out.push_str("\nCommands:\n");
+        let rows = spec
+            .commands
+            .iter()
+            .map(|(name, command)| (name.clone(), command.about.clone()))
+            .collect::<Vec<_>>();
+        out.push_str(&table(&rows));
+    }
+    out
+}
+
+enum Failure {
+    /// `--help` was given, with the help of the command it was given to.
+    Help(String),
+    Usage(String, String),
+}
+
+fn convert(opt: &Opt, text: &str) -> Result<Value, String> {
+    let invalid = || {
+        format!(
+            "option --{} expects {}, got '{}'",
+            opt.name,
+            opt.kind.name(),
+            text
+        )
+    };
+    Ok(match opt.kind {
+        Kind::Flag => Value::Bool(true),
+        Kind::String => Value::String(Ref(text.to_owned())),
+        Kind::Int => Value::Int(text.parse().map_err(|_| invalid())?),
+        Kind::Float => Value::Float(text.parse().map_err(|_| invalid())?),
+        Kind::Bool => match text {
+            "true" | "yes" | "on" | "1" => Value::Bool(true),
+            "false" | "no" | "off" | "0" => Value::Bool(false),
+            _ => return Err(invalid()),
+        },
+    })
+}
+
+/// Field of an option or positional in the result, `dry-run` is `dry_run`.
+fn result_key(name: &str) -> Value {
+    key(&name.replace('-', "_"))
+}
+
+fn parse(spec: &Spec, args: &[String]) -> Result<Object, Failure> {
+    let fail = |msg: String| Failure::Usage(msg, usage(spec));
+    let mut result = Object {
+        prototype: None,
+        table: Default::default(),
+        modcount: 0,
+        attributes: Default::default(),
+        sealed: false,
+    };
+    for opt in &spec.options {
+        let value = match opt.kind {
+            Kind::Flag => Value::Bool(false),
+            _ if opt.multiple => Value::Array(Ref(Elements::new())),
+            _ => opt.default.clone(),
+        };
+        result.set(result_key(&opt.name), value);
+    }
+    for positional in &spec.positionals {
+        let value = if positional.rest {
+            Value::Array(Ref(Elements::new()))
+        } else {
+            Value::Null
+        };
+        result.set(result_key(&positional.name), value);
+    }
+    if !spec.commands.is_empty() {
+        result.set(key("command"), Value::Null);
+    }
+    let mut seen = vec![false; spec.options.len()];
+    let mut store = |result: &mut Object, index: usize, text: &str| -> Result<(), Failure> {
+        let opt = &spec.options[index];
+        let value = convert(opt, text).map_err(fail)?;
+        seen[index] = true;
+        match result.get(result_key(&opt.name)) {
+            Some(Value::Array(values)) if opt.multiple => values.borrow_mut().push(value),
+            _ => result.set(result_key(&opt.name), value),
+        }
+        Ok(())
+    };
+    let mut positional = 0;
+    let mut options_done = false;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+        if !options_done && arg == "--" {
+            options_done = true;
+            continue;
+        }
+        if !options_done && (arg == "--help" || arg == "-h") {
+            return Err(Failure::Help(help(spec)));
+        }
+        if !options_done && arg.starts_with("--") {
+            let (name, inline) = match arg.find('=') {
+                Some(eq) => (&arg[2..eq], Some(&arg[eq + 1..])),
+                None => (&arg[2..], None),
+            };
+            let index = match spec.options.iter().position(|opt| opt.name == name) {
+                Some(index) => index,
+                None => return Err(fail(format!("unknown option '--{}'", name))),
+            };
+            let text = match (spec.options[index].kind, inline) {
+                (Kind::Flag, Some(_)) => {
+                    return Err(fail(format!("option --{} takes no value", name)))
+                }
+                (Kind::Flag, None) => "",
+                (_, Some(text)) => text,
+                (_, None) if i < args.len() => {
+                    i += 1;
+                    &args[i - 1]
+                }
+                (_, None) => return Err(fail(format!("option --{} needs a value", name))),
+            };
+            store(&mut result, index, text)?;
+            continue;
+        }
+        // A lone `-` conventionally means standard input, and negative numbers are values.
+        let is_number = arg.parse::<f64>().is_ok();
+        if !options_done && arg.starts_with('-') && arg.len() > 1 && !is_number {
+            let cluster = &arg[1..];
+            for (at, short) in cluster.char_indices() {
+                let index = match spec.options.iter().position(|opt| opt.short == Some(short)) {
+                    Some(index) => index,
+                    None => return Err(fail(format!("unknown option '-{}'", short))),
+                };
+                if spec.options[index].kind == Kind::Flag {
+                    store(&mut result, index, "")?;
+                    continue;
+                }
+                // The rest of the cluster is the value, or else the next argument.
+                let attached = &cluster[at + short.len_utf8()..];
+                let text = if !attached.is_empty() {
+                    attached
+                } else if i < args.len() {
+                    i += 1;
+                    &args[i - 1]
+                } else {
+                    return Err(fail(format!("option -{} needs a value", short)));
+                };
+                store(&mut result, index, text)?;
+                break;
+            }
+            continue;
+        }
+        if positional == spec.positionals.len() && !spec.commands.is_empty() {
+            let command = spec.commands.iter().find(|(name, _)| name == arg);
+            let (name, command) = match command {
+                Some(command) => command,
+                None => return Err(fail(format!("unknown command '{}'", arg))),
+            };
+            let parsed = parse(command, &args[i..])?;
+            result.set(key("command"), Value::String(Ref(name.clone())));
+            result.set(key(name), Value::Object(Ref(parsed)));
+            return finish(spec, result, &seen, positional, true);
+        }
+        let value = Value::String(Ref(arg.clone()));
+        match spec.positionals.get(positional) {
+            Some(rest) if rest.rest => match result.get(result_key(&rest.name)) {
+                Some(Value::Array(values)) => values.borrow_mut().push(value),
+                _ => unreachable!(),
+            },
+            Some(next) => {
+                result.set(result_key(&next.name), value);
+                positional += 1;
+            }
+            None => return Err(fail(format!("unexpected argument '{}'", arg))),
+        }
+    }
+    finish(spec, result, &seen, positional, false)
+}
+
+/// Checks that required options, positionals and the subcommand were given.
+fn finish(
+    spec: &Spec,
+    result: Object,
+    seen: &[bool],
+    positional: usize,
+    command: bool,
+) -> Result<Object, Failure> {
+    let fail = |msg: String| Err(Failure::Usage(msg, usage(spec)));
+    for (opt, seen) in spec.options.iter().zip(seen) {
+        if opt.required && !seen {
+            return fail(format!("missing option --{}", opt.name));
+        }
+    }
+    let missing = spec.positionals[positional.min(spec.positionals.len())..]
+        .iter()
+        .find(|positional| positional.required);
+    if let Some(missing) = missing {
+        let filled = match result.get(result_key(&missing.name)) {
+            Some(Value::Array(values)) => !values.borrow().is_empty(),
+            _ => false,
+        };
+        if !filled {
+            return fail(format!("missing argument <{}>", missing.name));
+        }
+    }
+    if !spec.commands.is_empty() && !command {
+        return fail("missing command".to_owned());
+    }
+    Ok(result)
+}
+
+/// Arguments to parse, `$args()` unless given.
+fn arguments(args: &[Value]) -> Result<Vec<String>, Value> {
+    match args.get(1) {
+        None | Some(Value::Null) => Ok(get_vm!().args.clone()),
+        Some(Value::Array(array)) => Ok(array.borrow().iter().map(|x| x.to_string()).collect()),
+        Some(_) => Err(error("cli_parse", "Array of arguments expected")),
+    }
+}
+
+/// `$cli_parse(spec, args = $args())`: an object with a field for every flag, option and
+/// positional of `spec`. `--help` prints the help and exits, mistakes throw a message
+/// followed by the usage line.
+pub fn cli_parse(args: &[Value]) -> Result<Value, Value> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(error("cli_parse", "Expected 1 or 2 arguments"));
+    }
+    let spec = read_spec(&args[0], None).map_err(|e| error("cli_parse", &e))?;
+    let arguments = arguments(args)?;
+    match parse(&spec, &arguments) {
+        Ok(result) => Ok(Value::Object(Ref(result))),
+        Err(Failure::Help(help)) => {
+            print!("{}", help);
+            shutdown(0)
+        }
+        Err(Failure::Usage(msg, usage)) => Err(Value::String(Ref(format!("{}\n{}", msg, usage)))),
+    }
+}
+
+/// `$cli_help(spec)`: the text `--help` prints for `spec`.
+pub fn cli_help(args: &[Value]) -> Result<Value, Value> {
+    let spec = read_spec(&args[0], None).map_err(|e| error("cli_help", &e))?;
+    Ok(Value::String(Ref(help(&spec))))
+}
+
+use super::*;
+
+pub fn cli_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("cli_parse".to_owned(), new_native_fn(cli_parse, -1));
+    map.insert("cli_help".to_owned(), new_native_fn(cli_help, 1));
+}
@@ -0,0 +1,156 @@
+use crate::*;
+
+use std::collections::HashMap;
+
+thread_local! {
+    /// Id of the next watchpoint.
+    static NEXT_WATCHPOINT: RefCell<usize> = RefCell::new(0);
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+/// `$watch(object, key, f)`: calls `f(key, old, new)` with `object` as `this` after each
+/// assignment to `object[key]`, `old` is `null` if the field didn't exist. Returns the id
+/// to pass to `$unwatch`. Assignments made by `f` don't call watchpoints.
+pub fn debug_watch(args: &[Value]) -> Result<Value, Value> {
+    let object = match &args[0] {
+        Value::Object(object) => object.clone(),
+        _ => return Err(error("watch", "Object expected")),
+    };
+    match &args[2] {
+        Value::Function(_) => (),
+        _ => return Err(error("watch", "Function expected")),
+    }
+    let id = NEXT_WATCHPOINT.with(|next| {
+        let id = *next.borrow();
+        *next.borrow_mut() += 1;
+        id
+    });
+    let vm = get_vm!();
+    vm.watchpoints
+        .push((id, object, args[1].clone(), args[2].clone()));
+    Ok(Value::Int(id as i64))
+}
+
+/// `$unwatch(id)`: removes the watchpoint `$watch` returned `id` for, `false` if there is
+/// none.
+pub fn debug_unwatch(args: &[Value]) -> Result<Value, Value> {
+    let id = match args[0].to_int() {
+        Some(id) => id as usize,
+        None => return Err(error("unwatch", "Int expected")),
+    };
+    let vm = get_vm!();
+    let len = vm.watchpoints.len();
+    vm.watchpoints.retain(|watchpoint| watchpoint.0 != id);
+    Ok(Value::Bool(vm.watchpoints.len() != len))
+}
+
+fn plural(n: usize) -> &'static str {
+    match n {
+        1 => "item",
+        _ => "items",
+    }
+}
+
+/// Appends `value` to `out` as `$show` lays it out, `indent` levels deep. Arrays and objects
+/// more than `depth` levels down are summarized, only the first `max_items` entries of the
+/// others are listed.
+fn show(
+    value: &Value,
+    depth: usize,
+    max_items: usize,
+    indent: usize,
+    out: &mut String,
+) -> Result<(), Value> {
+    let entries = match value {
+        Value::Array(array) => {
+            let array = array.borrow();
+            let shown = array.iter().take(max_items).map(|value| (None, value));
+            (('[', ']'), shown.collect::<Vec<_>>(), array.len())
+        }
+        Value::Object(object) if to_string_method(value).is_none() => {
+            let object = object.borrow();
+            let shown = object
+                .fields()
+                .take(max_items)
+                .map(|(key, value)| (Some(key.clone()), value.clone()));
+            (
+                ('{', '}'),
+                shown.collect::<Vec<_>>(),
+                object.fields().count(),
+            )
+        }
+        _ => {
+            out.push_str(&display(value)?);
+            return Ok(());
+        }
+    };
+    let ((open, close), shown, len) = entries;
+    if len == 0 {
+        out.push(open);
+        out.push(close);
+        return Ok(());
+    }
+    if depth == 0 {
+        out.push_str(&format!("{}... {} {}{}", open, len, plural(len), close));
+        return Ok(());
+    }
+    let pad = "  ".repeat(indent + 1);
+    out.push(open);
+    out.push('\n');
+    for (i, (key, value)) in shown.iter().enumerate() {
+        out.push_str(&pad);
+        if let Some(key) = key {
+            out.push_str(&display(key)?);
+            out.push_str(" => ");
+        }
+        show(value, depth - 1, max_items, indent + 1, out)?;
+        if i + 1 < len {
+            out.push(',');
+        }
+        out.push('\n');
+    }
+    if len > shown.len() {
+        let more = len - shown.len();
+        out.push_str(&format!("{}... {} more {}\n", pad, more, plural(more)));
+    }
+    out.push_str(&"  ".repeat(indent));
+    out.push(close);
+    Ok(())
+}
+
+/// `$show(value, depth, max_items)`: prints `value` with an element or field per line.
+/// `depth` (2 by default) is how many levels of nested arrays and objects are expanded,
+/// deeper ones only show their size, and `max_items` (100 by default) how many elements or
+/// fields of each are listed. Both are optional.
+pub fn debug_show(args: &[Value]) -> Result<Value, Value> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(error("show", "Expected 1 to 3 arguments"));
+    }
+    let option = |index: usize, name: &str, default: usize| match args.get(index) {
+        None | Some(Value::Null) => Ok(default),
+        Some(value) => match value.to_int() {
+            Some(n) if n >= 0 => Ok(n as usize),
+            _ => Err(error(
+                "show",
+                &format!("{} must be a non-negative Int", name),
+            )),
+        },
+    };
+    let depth = option(1, "depth", 2)?;
+    let max_items = option(2, "max_items", 100)?;
+    let mut out = String::new();
+    show(&args[0], depth, max_items, 0, &mut out)?;
+    println!("{}", out);
+    Ok(Value::Null)
+}
+
+use super::*;
+
+pub fn debug_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("watch".to_owned(), new_native_fn(debug_watch, 3));
+    map.insert("unwatch".to_owned(), new_native_fn(debug_unwatch, 1));
+    map.insert("show".to_owned(), new_native_fn(debug_show, -1));
+}
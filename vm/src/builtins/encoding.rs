@@ -0,0 +1,172 @@
+use crate::builtins::bytes::{with_bytes, Bytes};
+use crate::*;
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+/// The optional `strict` argument after the first one.
+fn strict_flag(args: &[Value], name: &str) -> Result<bool, Value> {
+    match args.len() {
+        1 => Ok(false),
+        2 => Ok(args[1].to_bool()),
+        _ => Err(error(name, "Expected 1 or 2 arguments")),
+    }
+}
+
+fn string_arg(args: &[Value], name: &str) -> Result<String, Value> {
+    match &args[0] {
+        Value::String(s) => Ok(s.borrow().clone()),
+        _ => Err(error(name, "String expected")),
+    }
+}
+
+fn new_bytes(bytes: Vec<u8>) -> Value {
+    Value::User(Ref(Bytes(bytes)))
+}
+
+/// `$utf8_encode(s)`: bytes of `s` in UTF-8.
+pub fn encoding_utf8_encode(args: &[Value]) -> Result<Value, Value> {
+    Ok(new_bytes(string_arg(args, "utf8_encode")?.into_bytes()))
+}
+
+/// `$utf8_decode(bytes, strict)`: text of UTF-8 `bytes`. Invalid sequences become U+FFFD, or
+/// throw if `strict` is set.
+pub fn encoding_utf8_decode(args: &[Value]) -> Result<Value, Value> {
+    let strict = strict_flag(args, "utf8_decode")?;
+    with_bytes(&args[0], "utf8_decode", |buf| {
+        let s = match std::str::from_utf8(buf) {
+            Ok(s) => s.to_owned(),
+            Err(e) if strict => {
+                let msg = format!("invalid UTF-8 at byte {}", e.valid_up_to());
+                return Err(error("utf8_decode", &msg));
+            }
+            Err(_) => String::from_utf8_lossy(buf).into_owned(),
+        };
+        Ok(Value::String(Ref(s)))
+    })
+}
+
+fn utf16_encode(args: &[Value], name: &str, to_bytes: fn(u16) -> [u8; 2]) -> Result<Value, Value> {
+    let s = string_arg(args, name)?;
+    let bytes = s.encode_utf16().flat_map(|unit| to_bytes(unit).to_vec());
+    Ok(new_bytes(bytes.collect()))
+}
+
+/// Text of UTF-16 `bytes`. Unpaired surrogates and a trailing odd byte become U+FFFD, or
+/// throw if `strict` is set.
+fn utf16_decode(
+    args: &[Value],
+    name: &str,
+    from_bytes: fn([u8; 2]) -> u16,
+) -> Result<Value, Value> {
+    let strict = strict_flag(args, name)?;
+    with_bytes(&args[0], name, |buf| {
+        if strict && buf.len() % 2 != 0 {
+            return Err(error(name, "odd number of bytes"));
+        }
+        let units = buf
+            .chunks_exact(2)
+            .map(|pair| from_bytes([pair[0], pair[1]]))
+            .collect::<Vec<_>>();
+        let mut s = String::with_capacity(units.len());
+        for ch in std::char::decode_utf16(units) {
+            match ch {
+                Ok(ch) => s.push(ch),
+                Err(e) if strict => {
+                    let msg = format!("unpaired surrogate {:#x}", e.unpaired_surrogate());
+                    return Err(error(name, &msg));
+                }
+                Err(_) => s.push(std::char::REPLACEMENT_CHARACTER),
+            }
+        }
+        if buf.len() % 2 != 0 {
+            s.push(std::char::REPLACEMENT_CHARACTER);
+        }
+        Ok(Value::String(Ref(s)))
+    })
+}
+
+/// `$utf16le_encode(s)`: bytes of `s` in UTF-16, little endian.
+pub fn encoding_utf16le_encode(args: &[Value]) -> Result<Value, Value> {
+    utf16_encode(args, "utf16le_encode", u16::to_le_bytes)
+}
+
+/// `$utf16be_encode(s)`: bytes of `s` in UTF-16, big endian.
+pub fn encoding_utf16be_encode(args: &[Value]) -> Result<Value, Value> {
+    utf16_encode(args, "utf16be_encode", u16::to_be_bytes)
+}
+
+/// `$utf16le_decode(bytes, strict)`: text of little endian UTF-16 `bytes`.
+pub fn encoding_utf16le_decode(args: &[Value]) -> Result<Value, Value> {
+    utf16_decode(args, "utf16le_decode", u16::from_le_bytes)
+}
+
+/// `$utf16be_decode(bytes, strict)`: text of big endian UTF-16 `bytes`.
+pub fn encoding_utf16be_decode(args: &[Value]) -> Result<Value, Value> {
+    utf16_decode(args, "utf16be_decode", u16::from_be_bytes)
+}
+
+/// `$latin1_encode(s, strict)`: bytes of `s` in Latin-1. Characters past U+00FF become `?`,
+/// or throw if `strict` is set.
+pub fn encoding_latin1_encode(args: &[Value]) -> Result<Value, Value> {
+    let strict = strict_flag(args, "latin1_encode")?;
+    let s = string_arg(args, "latin1_encode")?;
+    let mut bytes = Vec::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch as u32 {
+            x if x <= 0xff => bytes.push(x as u8),
+            _ if strict => {
+                let msg = format!("'{}' can't be encoded in Latin-1", ch);
+                return Err(error("latin1_encode", &msg));
+            }
+            _ => bytes.push(b'?'),
+        }
+    }
+    Ok(new_bytes(bytes))
+}
+
+/// `$latin1_decode(bytes)`: text of Latin-1 `bytes`, every byte is a character.
+pub fn encoding_latin1_decode(args: &[Value]) -> Result<Value, Value> {
+    with_bytes(&args[0], "latin1_decode", |buf| {
+        let s = buf.iter().map(|x| *x as char).collect();
+        Ok(Value::String(Ref(s)))
+    })
+}
+
+use super::*;
+
+pub fn encoding_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert(
+        "utf8_encode".to_owned(),
+        new_native_fn(encoding_utf8_encode, 1),
+    );
+    map.insert(
+        "utf8_decode".to_owned(),
+        new_native_fn(encoding_utf8_decode, -1),
+    );
+    map.insert(
+        "utf16le_encode".to_owned(),
+        new_native_fn(encoding_utf16le_encode, 1),
+    );
+    map.insert(
+        "utf16be_encode".to_owned(),
+        new_native_fn(encoding_utf16be_encode, 1),
+    );
+    map.insert(
+        "utf16le_decode".to_owned(),
+        new_native_fn(encoding_utf16le_decode, -1),
+    );
+    map.insert(
+        "utf16be_decode".to_owned(),
+        new_native_fn(encoding_utf16be_decode, -1),
+    );
+    map.insert(
+        "latin1_encode".to_owned(),
+        new_native_fn(encoding_latin1_encode, -1),
+    );
+    map.insert(
+        "latin1_decode".to_owned(),
+        new_native_fn(encoding_latin1_decode, 1),
+    );
+}
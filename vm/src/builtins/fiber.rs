@@ -0,0 +1,67 @@
+use crate::builtins::promise::{new_promise, resolve, settle};
+use crate::interp::Frame;
+use crate::*;
+
+use std::collections::{HashMap, VecDeque};
+
+thread_local! {
+    /// Fibers ready to run in turn, with the promise of their result.
+    static FIBERS: RefCell<VecDeque<(Frame, Value)>> = RefCell::new(VecDeque::new());
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+/// `$fiber_spawn(f)`: runs `f` as a fiber from the event loop, taking turns with the other
+/// fibers at each `$fiber_yield()`. Returns the promise of its result.
+pub fn fiber_spawn(args: &[Value]) -> Result<Value, Value> {
+    let frame = match &args[0] {
+        Value::Function(function) if !function.borrow().native => {
+            let function = function.borrow();
+            if function.argc > 0 {
+                return Err(error("fiber_spawn", "Function without parameters expected"));
+            }
+            Frame::new(&function, Value::Null)
+        }
+        _ => return Err(error("fiber_spawn", "Function expected")),
+    };
+    let promise = new_promise();
+    FIBERS.with(|fibers| fibers.borrow_mut().push_back((frame, promise.clone())));
+    Ok(promise)
+}
+
+/// `$fiber_yield()`: lets the other fibers run before the calling one continues. Only
+/// script functions can be between the fiber's function and the call.
+pub fn fiber_yield(_: &[Value]) -> Result<Value, Value> {
+    let vm = get_vm!();
+    if !vm.can_suspend_fiber() {
+        return Err(error("fiber_yield", "Not called from a fiber"));
+    }
+    vm.suspend_fiber = true;
+    Ok(Value::Null)
+}
+
+/// Runs the next fiber until it yields or finishes, `false` if there is none.
+pub fn run_fiber() -> bool {
+    let (frame, promise) = match FIBERS.with(|fibers| fibers.borrow_mut().pop_front()) {
+        Some(fiber) => fiber,
+        None => return false,
+    };
+    let vm = get_vm!();
+    match vm.protect(|vm| vm.resume(frame, true)) {
+        Ok((_, Some(frame))) => {
+            FIBERS.with(|fibers| fibers.borrow_mut().push_back((frame, promise)))
+        }
+        Ok((value, None)) => resolve(&promise, value),
+        Err(e) => settle(&promise, Err(e)),
+    }
+    true
+}
+
+use super::*;
+
+pub fn fiber_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("fiber_spawn".to_owned(), new_native_fn(fiber_spawn, 1));
+    map.insert("fiber_yield".to_owned(), new_native_fn(fiber_yield, 0));
+}
@@ -0,0 +1,233 @@
+use crate::interp::{val_call, val_callex};
+use crate::*;
+
+use std::collections::HashMap;
+
+thread_local! {
+    /// Methods available on function values, e.g. `f.bind(this)`.
+    pub static FUNCTION_METHODS: HashMap<String, Value> = function_methods_init();
+}
+
+pub fn get_function_method(name: &str) -> Option<Value> {
+    FUNCTION_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Creates a native function whose env is passed as its first argument.
+pub fn new_native_closure(
+    x: fn(&[Value]) -> Result<Value, Value>,
+    env: Vec<Value>,
+    argc: i32,
+) -> Value {
+    Value::Function(Ref(Function {
+        native: true,
+        address: x as usize,
+        env: Value::Array(Ref(env.into())),
+        module: None,
+        argc,
+        params: Rc::from(vec![]),
+        source: None,
+        doc: None,
+        max_stack: 0,
+        hotness: 0,
+    }))
+}
+
+fn expect_function(value: &Value, name: &str) -> Result<(), Value> {
+    match value {
+        Value::Function(_) => Ok(()),
+        _ => Err(Value::String(Ref(format!("{}: Function expected", name)))),
+    }
+}
+
+fn script_function(value: &Value, name: &str) -> Result<Ref<Function>, Value> {
+    match value {
+        Value::Function(f) if !f.borrow().native => Ok(f.clone()),
+        Value::Function(_) => Err(Value::String(Ref(format!(
+            "{}: Script function expected",
+            name
+        )))),
+        _ => Err(Value::String(Ref(format!("{}: Function expected", name)))),
+    }
+}
+
+/// `f.disassemble()`: the instructions of `f`, one per line.
+pub fn fn_disassemble(args: &[Value]) -> Result<Value, Value> {
+    let f = script_function(&args[0], "disassemble")?;
+    let f = f.borrow();
+    let m = f.module.as_ref().unwrap().borrow();
+    let lines = m
+        .function_code(f.address)
+        .map(|pc| format!("{:04}: {:?}", pc, m.code[pc].current()))
+        .collect::<Vec<_>>();
+    Ok(Value::String(Ref(lines.join("\n"))))
+}
+
+/// `f.source()`: the source text of `f`, `null` for functions the compiler generated.
+pub fn fn_source(args: &[Value]) -> Result<Value, Value> {
+    let f = script_function(&args[0], "source")?;
+    let source = f.borrow().source.clone();
+    Ok(match source {
+        Some(source) => Value::String(Ref(source.to_string())),
+        None => Value::Null,
+    })
+}
+
+/// `f.doc()`: the text of the `///` comments before `f`, `null` if there are none.
+pub fn fn_doc(args: &[Value]) -> Result<Value, Value> {
+    expect_function(&args[0], "doc")?;
+    let doc = match &args[0] {
+        Value::Function(f) => f.borrow().doc.clone(),
+        _ => unreachable!(),
+    };
+    Ok(match doc {
+        Some(doc) => Value::String(Ref(doc.to_string())),
+        None => Value::Null,
+    })
+}
+
+/// `$help(f)`: the parameters of `f` followed by its doc comment.
+pub fn func_help(args: &[Value]) -> Result<Value, Value> {
+    expect_function(&args[0], "help")?;
+    let f = match &args[0] {
+        Value::Function(f) => f.borrow(),
+        _ => unreachable!(),
+    };
+    let header = if f.native {
+        "native function".to_owned()
+    } else {
+        format!("fun({})", f.params.join(", "))
+    };
+    Ok(Value::String(Ref(match &f.doc {
+        Some(doc) => format!("{}\n\n{}", header, doc),
+        None => header,
+    })))
+}
+
+/// env: [function, this, preset args...]
+fn bound_trampoline(args: &[Value]) -> Result<Value, Value> {
+    let env = match &args[0] {
+        Value::Array(env) => env.borrow().to_vec(),
+        _ => unreachable!(),
+    };
+    let mut call_args = env[2..].to_vec();
+    call_args.extend_from_slice(&args[1..]);
+    val_callex(env[0].clone(), env[1].clone(), &call_args)
+}
+
+pub fn fn_bind(args: &[Value]) -> Result<Value, Value> {
+    expect_function(&args[0], "bind")?;
+    let this = args.get(1).cloned().unwrap_or(Value::Null);
+    let mut env = vec![args[0].clone(), this];
+    if args.len() > 2 {
+        env.extend_from_slice(&args[2..]);
+    }
+    Ok(new_native_closure(bound_trampoline, env, -1))
+}
+
+pub fn fn_call(args: &[Value]) -> Result<Value, Value> {
+    expect_function(&args[0], "call")?;
+    let this = args.get(1).cloned().unwrap_or(Value::Null);
+    let rest = if args.len() > 2 { &args[2..] } else { &[] };
+    val_callex(args[0].clone(), this, rest)
+}
+
+pub fn fn_apply(args: &[Value]) -> Result<Value, Value> {
+    expect_function(&args[0], "apply")?;
+    match &args[2] {
+        Value::Array(array) => {
+            let array = array.borrow().to_vec();
+            val_callex(args[0].clone(), args[1].clone(), &array)
+        }
+        Value::Null => val_callex(args[0].clone(), args[1].clone(), &[]),
+        _ => Err(Value::String(Ref(
+            "apply: Array of arguments expected".to_owned()
+        ))),
+    }
+}
+
+/// env: [function, preset args...]
+fn partial_trampoline(args: &[Value]) -> Result<Value, Value> {
+    let env = match &args[0] {
+        Value::Array(env) => env.borrow().to_vec(),
+        _ => unreachable!(),
+    };
+    let mut call_args = env[1..].to_vec();
+    call_args.extend_from_slice(&args[1..]);
+    val_call(env[0].clone(), &call_args)
+}
+
+/// env: [f, g]
+fn compose_trampoline(args: &[Value]) -> Result<Value, Value> {
+    let env = match &args[0] {
+        Value::Array(env) => env.borrow().to_vec(),
+        _ => unreachable!(),
+    };
+    let inner = val_call(env[1].clone(), &args[1..])?;
+    val_call(env[0].clone(), &[inner])
+}
+
+/// env: [function, collected args...]
+fn curry_trampoline(args: &[Value]) -> Result<Value, Value> {
+    let mut env = match &args[0] {
+        Value::Array(env) => env.borrow().to_vec(),
+        _ => unreachable!(),
+    };
+    let argc = match &env[0] {
+        Value::Function(f) => f.borrow().argc as usize,
+        _ => unreachable!(),
+    };
+    env.extend_from_slice(&args[1..]);
+    if env.len() - 1 >= argc {
+        val_call(env[0].clone(), &env[1..])
+    } else {
+        Ok(new_native_closure(curry_trampoline, env, -1))
+    }
+}
+
+pub fn func_partial(args: &[Value]) -> Result<Value, Value> {
+    if args.is_empty() {
+        return Err(Value::String(Ref("partial: Function expected".to_owned())));
+    }
+    expect_function(&args[0], "partial")?;
+    Ok(new_native_closure(partial_trampoline, args.to_vec(), -1))
+}
+
+pub fn func_compose(args: &[Value]) -> Result<Value, Value> {
+    expect_function(&args[0], "compose")?;
+    expect_function(&args[1], "compose")?;
+    Ok(new_native_closure(compose_trampoline, args.to_vec(), -1))
+}
+
+pub fn func_curry(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Function(f) if f.borrow().argc >= 0 => Ok(new_native_closure(
+            curry_trampoline,
+            vec![args[0].clone()],
+            -1,
+        )),
+        Value::Function(_) => Err(Value::String(Ref(
+            "curry: Function with fixed arity expected".to_owned(),
+        ))),
+        _ => Err(Value::String(Ref("curry: Function expected".to_owned()))),
+    }
+}
+
+use super::*;
+
+pub fn func_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("partial".to_owned(), new_native_fn(func_partial, -1));
+    map.insert("compose".to_owned(), new_native_fn(func_compose, 2));
+    map.insert("curry".to_owned(), new_native_fn(func_curry, 1));
+    map.insert("help".to_owned(), new_native_fn(func_help, 1));
+}
+
+fn function_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("bind".to_owned(), new_native_fn(fn_bind, -1));
+    map.insert("call".to_owned(), new_native_fn(fn_call, -1));
+    map.insert("apply".to_owned(), new_native_fn(fn_apply, 2));
+    map.insert("disassemble".to_owned(), new_native_fn(fn_disassemble, 0));
+    map.insert("source".to_owned(), new_native_fn(fn_source, 0));
+    map.insert("doc".to_owned(), new_native_fn(fn_doc, 0));
+    map
+}
@@ -1,6 +1,8 @@
 use crate::*;
 use value::*;
 
+use crate::builtins::promise::spawn_task;
+use crate::builtins::shared::Shared;
 use std::fs::File;
 use std::io::{Read, Write};
 
@@ -130,8 +132,8 @@ pub fn file_write(args: &[Value]) -> Result<Value, Value> {
                         let mut bytes = vec![];
                         for x in array.borrow().iter() {
                             match x {
-                                Value::Int(x) => bytes.push(*x as u8),
-                                Value::Char(x) => bytes.extend((*x as u32).to_le_bytes().iter()),
+                                Value::Int(x) => bytes.push(x as u8),
+                                Value::Char(x) => bytes.extend((x as u32).to_le_bytes().iter()),
                                 _ => {
                                     return Err(Value::String(Ref(
                                         "Unexpected value to write".to_owned()
@@ -189,6 +191,29 @@ pub fn file_write_byte(args: &[Value]) -> Result<Value, Value> {
         }
     }
 }
+/// `$file_read_async(path)`: promise of the contents of the file at `path`, read on another
+/// thread.
+pub fn file_read_async(args: &[Value]) -> Result<Value, Value> {
+    let path = args[0].to_string();
+    Ok(spawn_task("file_read_async", move || {
+        std::fs::read_to_string(&path)
+            .map(Shared::String)
+            .map_err(|e| e.to_string())
+    }))
+}
+
+/// `$file_write_async(path, contents)`: writes `contents` to the file at `path` on another
+/// thread, the promise is fulfilled with `null` once it is written.
+pub fn file_write_async(args: &[Value]) -> Result<Value, Value> {
+    let path = args[0].to_string();
+    let contents = args[1].to_string();
+    Ok(spawn_task("file_write_async", move || {
+        std::fs::write(&path, contents)
+            .map(|_| Shared::Null)
+            .map_err(|e| e.to_string())
+    }))
+}
+
 use super::*;
 
 pub fn file_builtins(map: &mut std::collections::HashMap<String, Value>) {
@@ -205,4 +230,12 @@ pub fn file_builtins(map: &mut std::collections::HashMap<String, Value>) {
         new_native_fn(file_write_byte, 2),
     );
     map.insert("file_bytes".to_owned(), new_native_fn(file_bytes, 1));
+    map.insert(
+        "file_read_async".to_owned(),
+        new_native_fn(file_read_async, 1),
+    );
+    map.insert(
+        "file_write_async".to_owned(),
+        new_native_fn(file_write_async, 2),
+    );
 }
@@ -0,0 +1,445 @@
+use crate::builtins::symbol::{find_hook, WellKnown};
+use crate::interp::{val_call, val_callex, Frame};
+use crate::*;
+use value::*;
+
+use std::collections::HashMap;
+use std::fmt;
+
+thread_local! {
+    /// Methods available on array values, e.g. `a.entries()`.
+    pub static ARRAY_METHODS: HashMap<String, Value> = array_methods_init();
+    /// Methods available on iterators and generators, e.g. `it.map(f)`.
+    pub static ITER_METHODS: HashMap<String, Value> = iter_methods_init();
+}
+
+pub fn get_array_method(name: &str) -> Option<Value> {
+    ARRAY_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+pub fn get_iter_method(name: &str) -> Option<Value> {
+    ITER_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Arrays and objects remember their modification count, iterating them throws once
+/// elements or fields are added or removed.
+enum Source {
+    Array(Ref<Elements>, usize),
+    /// Byte offset of the next character.
+    Chars(Ref<String>),
+    /// `{index, value}` objects of an array.
+    Entries(Ref<Elements>, usize),
+    /// Field names of an object, without its prototype's. They're taken when the iterator is
+    /// created, which the modification count keeps valid, so each step is a lookup.
+    Keys(Ref<Object>, usize, Vec<Value>),
+    /// Function returning `$array(value)` or `null` once exhausted.
+    Next(Value),
+    Generator(Ref<dyn UserKind>),
+    /// `f` of each value of another iterator. Adapters step it only when asked for a value.
+    Map(Value, Value),
+    /// Values `f` holds for.
+    Filter(Value, Value),
+    /// At most `n` values, `pos` counts those taken.
+    Take(Value, usize),
+    /// Values after the first `n`, `pos` counts those skipped.
+    Skip(Value, usize),
+    /// `$array(x, y)` pairs of values of two iterators, until either is exhausted.
+    Zip(Value, Value),
+}
+
+/// Iterator used by `foreach`. Collections are read through a cursor instead of being
+/// copied.
+pub struct ValueIter {
+    source: Source,
+    pos: usize,
+}
+
+impl fmt::Debug for ValueIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ValueIter({})", self.pos)
+    }
+}
+
+impl fmt::Display for ValueIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<iterator>")
+    }
+}
+
+impl UserKind for ValueIter {
+    fn get_kind(&self) -> &'static str {
+        "iterator"
+    }
+}
+
+/// A call of a generator function, iterators over it share its state.
+pub enum Generator {
+    Suspended(Frame),
+    Running,
+    /// The body returned or threw.
+    Done,
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Generator::Suspended(_) => "suspended",
+            Generator::Running => "running",
+            Generator::Done => "done",
+        };
+        write!(f, "Generator({})", state)
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<generator>")
+    }
+}
+
+impl UserKind for Generator {
+    fn get_kind(&self) -> &'static str {
+        "generator"
+    }
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn modified() -> Value {
+    Value::String(Ref("collection modified during iteration".to_owned()))
+}
+
+fn new_iter(source: Source) -> Value {
+    Value::User(Ref(ValueIter { source, pos: 0 }))
+}
+
+/// `{index, value}` object.
+pub fn entry(index: usize, value: Value) -> Value {
+    let mut object = Object {
+        prototype: None,
+        table: Default::default(),
+        modcount: 0,
+        attributes: Default::default(),
+        sealed: false,
+    };
+    object.set(
+        Value::String(Ref("index".to_owned())),
+        Value::Int(index as _),
+    );
+    object.set(Value::String(Ref("value".to_owned())), value);
+    Value::Object(Ref(object))
+}
+
+/// `$iter(value)`: iterator over an array, the characters of a string, a function
+/// returning `$array(value)`/`null`, a generator, the result of the `@@iterator` method of
+/// an object, which can also be an object with such a `next` method, or the field names of
+/// any other object.
+pub fn iter_new(args: &[Value]) -> Result<Value, Value> {
+    Ok(match &args[0] {
+        Value::Array(array) => new_iter(Source::Array(array.clone(), array.borrow().modcount)),
+        Value::String(s) => new_iter(Source::Chars(s.clone())),
+        Value::Function(_) => new_iter(Source::Next(args[0].clone())),
+        Value::User(user) if user.borrow().is::<ValueIter>() => args[0].clone(),
+        Value::User(user) if user.borrow().is::<Generator>() => {
+            new_iter(Source::Generator(user.clone()))
+        }
+        Value::Object(object) => match find_hook(&args[0], WellKnown::Iterator) {
+            Some(method) => match val_callex(method, args[0].clone(), &[])? {
+                Value::Object(it) => {
+                    let next = it.borrow().get(Value::String(Ref("next".to_owned())));
+                    match next {
+                        Some(next @ Value::Function(_)) => {
+                            new_iter(Source::Next(func::fn_bind(&[next, Value::Object(it)])?))
+                        }
+                        _ => return Err(error("iter", "@@iterator result has no next method")),
+                    }
+                }
+                it => iter_new(&[it])?,
+            },
+            None => {
+                let keys = object
+                    .borrow()
+                    .fields()
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                new_iter(Source::Keys(object.clone(), object.borrow().modcount, keys))
+            }
+        },
+        _ => {
+            return Err(error(
+                "iter",
+                "Array, String, Object, Function or iterator expected",
+            ))
+        }
+    })
+}
+
+/// `$iter_next(it)`: `$array(value)` with the next value, or `null` once exhausted. `it`
+/// can also be a generator.
+pub fn iter_next(args: &[Value]) -> Result<Value, Value> {
+    let user = match &args[0] {
+        Value::User(user) if user.borrow().is::<Generator>() => return generator_next(user),
+        Value::User(user) => user.clone(),
+        _ => return Err(error("iter_next", "Iterator expected")),
+    };
+    let mut user = user.borrow_mut();
+    let it = match user.downcast_mut::<ValueIter>() {
+        Some(it) => it,
+        None => return Err(error("iter_next", "Iterator expected")),
+    };
+    let value = match &it.source {
+        Source::Array(array, modcount) => {
+            if array.borrow().modcount != *modcount {
+                return Err(modified());
+            }
+            let value = array.borrow().get(it.pos);
+            it.pos += 1;
+            value
+        }
+        Source::Chars(s) => {
+            let c = s.borrow()[it.pos..].chars().next();
+            if let Some(c) = c {
+                it.pos += c.len_utf8();
+            }
+            c.map(Value::Char)
+        }
+        Source::Entries(array, modcount) => {
+            if array.borrow().modcount != *modcount {
+                return Err(modified());
+            }
+            let value = array.borrow().get(it.pos);
+            let value = value.map(|value| entry(it.pos, value));
+            it.pos += 1;
+            value
+        }
+        Source::Keys(object, modcount, keys) => {
+            if object.borrow().modcount != *modcount {
+                return Err(modified());
+            }
+            let key = keys.get(it.pos).cloned();
+            it.pos += 1;
+            key
+        }
+        Source::Next(next) => {
+            let next = next.clone();
+            drop(user);
+            return val_call(next, &[]);
+        }
+        Source::Generator(generator) => {
+            let generator = generator.clone();
+            drop(user);
+            return generator_next(&generator);
+        }
+        // Adapters call back into scripts, which may step this iterator again, so it isn't
+        // borrowed while they run.
+        Source::Map(src, f) => {
+            let (src, f) = (src.clone(), f.clone());
+            drop(user);
+            match step(&src)? {
+                Some(value) => Some(val_call(f, &[value])?),
+                None => None,
+            }
+        }
+        Source::Filter(src, f) => {
+            let (src, f) = (src.clone(), f.clone());
+            drop(user);
+            loop {
+                match step(&src)? {
+                    Some(value) => {
+                        if val_call(f.clone(), &[value.clone()])?.to_bool() {
+                            break Some(value);
+                        }
+                    }
+                    None => break None,
+                }
+            }
+        }
+        Source::Take(src, n) => {
+            if it.pos >= *n {
+                return Ok(Value::Null);
+            }
+            it.pos += 1;
+            let src = src.clone();
+            drop(user);
+            step(&src)?
+        }
+        Source::Skip(src, n) => {
+            let (src, skip) = (src.clone(), n - it.pos);
+            it.pos = *n;
+            drop(user);
+            for _ in 0..skip {
+                if step(&src)?.is_none() {
+                    return Ok(Value::Null);
+                }
+            }
+            step(&src)?
+        }
+        Source::Zip(src, other) => {
+            let (src, other) = (src.clone(), other.clone());
+            drop(user);
+            match (step(&src)?, step(&other)?) {
+                (Some(x), Some(y)) => Some(Value::Array(Ref(vec![x, y].into()))),
+                _ => None,
+            }
+        }
+    };
+    Ok(match value {
+        Some(value) => Value::Array(Ref(vec![value].into())),
+        None => Value::Null,
+    })
+}
+
+/// Next value of the iterator `it`, `None` once exhausted.
+fn step(it: &Value) -> Result<Option<Value>, Value> {
+    match iter_next(&[it.clone()])? {
+        Value::Array(array) => Ok(array.borrow().get(0)),
+        Value::Null => Ok(None),
+        value => Err(Value::String(Ref(format!(
+            "iter_next: next returned {}, Array or null expected",
+            value
+        )))),
+    }
+}
+
+/// Count argument of `take` and `skip`.
+fn count(name: &str, value: &Value) -> Result<usize, Value> {
+    match value {
+        Value::Int(n) if *n >= 0 => Ok(*n as usize),
+        _ => Err(error(name, "non-negative Int expected")),
+    }
+}
+
+/// `it.map(f)`: iterator over `f(x)` for each value `x` of `it`.
+pub fn iter_map(args: &[Value]) -> Result<Value, Value> {
+    Ok(new_iter(Source::Map(
+        iter_new(&args[..1])?,
+        args[1].clone(),
+    )))
+}
+
+/// `it.filter(f)`: iterator over the values `x` of `it` for which `f(x)` is true.
+pub fn iter_filter(args: &[Value]) -> Result<Value, Value> {
+    Ok(new_iter(Source::Filter(
+        iter_new(&args[..1])?,
+        args[1].clone(),
+    )))
+}
+
+/// `it.take(n)`: iterator over the first `n` values of `it`.
+pub fn iter_take(args: &[Value]) -> Result<Value, Value> {
+    let n = count("take", &args[1])?;
+    Ok(new_iter(Source::Take(iter_new(&args[..1])?, n)))
+}
+
+/// `it.skip(n)`: iterator over the values of `it` after its first `n`.
+pub fn iter_skip(args: &[Value]) -> Result<Value, Value> {
+    let n = count("skip", &args[1])?;
+    Ok(new_iter(Source::Skip(iter_new(&args[..1])?, n)))
+}
+
+/// `it.zip(other)`: iterator over `$array(x, y)` pairs of the values of `it` and of
+/// `$iter(other)`, as long as both have values.
+pub fn iter_zip(args: &[Value]) -> Result<Value, Value> {
+    Ok(new_iter(Source::Zip(
+        iter_new(&args[..1])?,
+        iter_new(&args[1..])?,
+    )))
+}
+
+/// `it.collect()`: Array of the values left in `it`.
+pub fn iter_collect(args: &[Value]) -> Result<Value, Value> {
+    let mut values = vec![];
+    while let Some(value) = step(&args[0])? {
+        values.push(value);
+    }
+    Ok(Value::Array(Ref(values.into())))
+}
+
+/// `it.sum()`: the values left in `it` added up with `+`, `0` if there are none.
+pub fn iter_sum(args: &[Value]) -> Result<Value, Value> {
+    let mut total = Value::Int(0);
+    while let Some(value) = step(&args[0])? {
+        total = get_vm!().plus(total, value)?;
+    }
+    Ok(total)
+}
+
+/// `array.entries()`: iterator over `{index, value}` objects.
+pub fn array_entries(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Array(array) => Ok(new_iter(Source::Entries(
+            array.clone(),
+            array.borrow().modcount,
+        ))),
+        _ => Err(error("entries", "Array expected")),
+    }
+}
+
+/// Runs a generator up to its next `yield`, `$array(value)` with the yielded value or `null`
+/// once it is done. The body is left running while resumed, so it can't resume itself.
+fn generator_next(generator: &Ref<dyn UserKind>) -> Result<Value, Value> {
+    let state = match generator.borrow_mut().downcast_mut::<Generator>() {
+        Some(state) => std::mem::replace(state, Generator::Running),
+        None => return Err(error("iter_next", "Generator expected")),
+    };
+    let frame = match state {
+        Generator::Suspended(frame) => frame,
+        Generator::Running => return Err(error("iter_next", "Generator is already running")),
+        Generator::Done => {
+            *generator.borrow_mut().downcast_mut::<Generator>().unwrap() = Generator::Done;
+            return Ok(Value::Null);
+        }
+    };
+    let (state, value) = match get_vm!().resume(frame, false) {
+        Ok((value, Some(frame))) => (
+            Generator::Suspended(frame),
+            Ok(Value::Array(Ref(vec![value].into()))),
+        ),
+        Ok((_, None)) => (Generator::Done, Ok(Value::Null)),
+        Err(e) => (Generator::Done, Err(e)),
+    };
+    *generator.borrow_mut().downcast_mut::<Generator>().unwrap() = state;
+    value
+}
+
+/// `$generator(body, this)`: generator running `body` with `this` up to each `yield`. Calls
+/// of functions containing `yield` return one.
+pub fn generator_new(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Function(function) if !function.borrow().native => {
+            let frame = Frame::new(&function.borrow(), args[1].clone());
+            Ok(Value::User(Ref(Generator::Suspended(frame))))
+        }
+        _ => Err(error("generator", "Function expected")),
+    }
+}
+
+use super::*;
+
+pub fn iter_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("iter".to_owned(), new_native_fn(iter_new, 1));
+    map.insert("iter_next".to_owned(), new_native_fn(iter_next, 1));
+    map.insert("generator".to_owned(), new_native_fn(generator_new, 2));
+}
+
+fn array_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("entries".to_owned(), new_native_fn(array_entries, 0));
+    map.insert("push".to_owned(), new_native_fn(builtin_apush, 1));
+    map.insert("pop".to_owned(), new_native_fn(builtin_apop, 0));
+    map
+}
+
+fn iter_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("map".to_owned(), new_native_fn(iter_map, 1));
+    map.insert("filter".to_owned(), new_native_fn(iter_filter, 1));
+    map.insert("take".to_owned(), new_native_fn(iter_take, 1));
+    map.insert("skip".to_owned(), new_native_fn(iter_skip, 1));
+    map.insert("zip".to_owned(), new_native_fn(iter_zip, 1));
+    map.insert("collect".to_owned(), new_native_fn(iter_collect, 0));
+    map.insert("sum".to_owned(), new_native_fn(iter_sum, 0));
+    map
+}
@@ -0,0 +1,155 @@
+use crate::*;
+use value::*;
+
+use hashlink::LinkedHashMap;
+use std::fmt;
+
+/// Key of a `Map`. Arrays, objects, functions and user values are keyed by allocation in
+/// identity maps, everything else by value.
+#[derive(PartialEq, Eq, Hash)]
+enum MapKey {
+    Value(Value),
+    Identity(usize),
+}
+
+/// Insertion-ordered map with arbitrary keys, see `$map`.
+pub struct ValueMap {
+    identity: bool,
+    /// The original key is kept with the value, which also keeps identity keys alive.
+    table: LinkedHashMap<MapKey, (Value, Value), ValueHashBuilder>,
+}
+
+impl ValueMap {
+    fn key(&self, key: &Value) -> MapKey {
+        if !self.identity {
+            return MapKey::Value(key.clone());
+        }
+        match key {
+            Value::Array(x) => MapKey::Identity(x.as_ptr() as *const u8 as usize),
+            Value::Object(x) => MapKey::Identity(x.as_ptr() as *const u8 as usize),
+            Value::Function(x) => MapKey::Identity(x.as_ptr() as *const u8 as usize),
+            Value::User(x) => MapKey::Identity(x.as_ptr() as *const u8 as usize),
+            _ => MapKey::Value(key.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for ValueMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ValueMap({})", self.table.len())
+    }
+}
+
+impl fmt::Display for ValueMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "map {{")?;
+        for (i, (key, value)) in self.table.values().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, " {} => {}", key, value)?;
+        }
+        write!(f, " }}")
+    }
+}
+
+impl UserKind for ValueMap {
+    fn get_kind(&self) -> &'static str {
+        "map"
+    }
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn with_map<R>(value: &Value, name: &str, f: impl FnOnce(&mut ValueMap) -> R) -> Result<R, Value> {
+    match value {
+        Value::User(user) => match user.borrow_mut().downcast_mut::<ValueMap>() {
+            Some(map) => Ok(f(map)),
+            None => Err(error(name, "Map expected")),
+        },
+        _ => Err(error(name, "Map expected")),
+    }
+}
+
+/// `$map(identity?)`: empty map. Identity maps key arrays, objects and functions by
+/// reference instead of by contents.
+pub fn map_new(args: &[Value]) -> Result<Value, Value> {
+    let identity = match args.get(0) {
+        Some(Value::Bool(identity)) => *identity,
+        Some(Value::Null) | None => false,
+        Some(_) => return Err(error("map", "Bool expected")),
+    };
+    Ok(Value::User(Ref(ValueMap {
+        identity,
+        table: Default::default(),
+    })))
+}
+
+/// `$map_get(map, key)`: the value of `key`, or `null`.
+pub fn map_get(args: &[Value]) -> Result<Value, Value> {
+    with_map(&args[0], "map_get", |map| {
+        let key = map.key(&args[1]);
+        map.table
+            .get(&key)
+            .map(|(_, value)| value.clone())
+            .unwrap_or(Value::Null)
+    })
+}
+
+/// `$map_set(map, key, value)`, an existing key keeps its position.
+pub fn map_set(args: &[Value]) -> Result<Value, Value> {
+    with_map(&args[0], "map_set", |map| {
+        let key = map.key(&args[1]);
+        match map.table.get_mut(&key) {
+            Some(entry) => entry.1 = args[2].clone(),
+            None => {
+                map.table.insert(key, (args[1].clone(), args[2].clone()));
+            }
+        }
+        Value::Null
+    })
+}
+
+pub fn map_has(args: &[Value]) -> Result<Value, Value> {
+    with_map(&args[0], "map_has", |map| {
+        let key = map.key(&args[1]);
+        Value::Bool(map.table.contains_key(&key))
+    })
+}
+
+/// `$map_remove(map, key)`: the removed value, or `null`.
+pub fn map_remove(args: &[Value]) -> Result<Value, Value> {
+    with_map(&args[0], "map_remove", |map| {
+        let key = map.key(&args[1]);
+        map.table
+            .remove(&key)
+            .map(|(_, value)| value)
+            .unwrap_or(Value::Null)
+    })
+}
+
+pub fn map_size(args: &[Value]) -> Result<Value, Value> {
+    with_map(&args[0], "map_size", |map| Value::Int(map.table.len() as _))
+}
+
+/// `$map_keys(map)`: array of the keys in insertion order.
+pub fn map_keys(args: &[Value]) -> Result<Value, Value> {
+    with_map(&args[0], "map_keys", |map| {
+        let keys = map.table.values().map(|(key, _)| key.clone()).collect();
+        Value::Array(Ref(keys))
+    })
+}
+
+use super::*;
+
+pub fn map_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("map".to_owned(), new_native_fn(map_new, -1));
+    map.insert("map_get".to_owned(), new_native_fn(map_get, 2));
+    map.insert("map_set".to_owned(), new_native_fn(map_set, 3));
+    map.insert("map_has".to_owned(), new_native_fn(map_has, 2));
+    map.insert("map_remove".to_owned(), new_native_fn(map_remove, 2));
+    map.insert("map_size".to_owned(), new_native_fn(map_size, 1));
+    map.insert("map_keys".to_owned(), new_native_fn(map_keys, 1));
+}
@@ -0,0 +1,376 @@
+use crate::*;
+
+use std::collections::HashMap;
+
+thread_local! {
+    /// Methods available on Int, Float and BigInt values, e.g. `x.to_fixed(2)`.
+    pub static NUMBER_METHODS: HashMap<String, Value> = number_methods_init();
+}
+
+pub fn get_number_method(name: &str) -> Option<Value> {
+    NUMBER_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+fn strict_flag(args: &[Value], idx: usize) -> bool {
+    args.get(idx).map(|x| x.to_bool()).unwrap_or(false)
+}
+
+pub fn num_parse_int(args: &[Value]) -> Result<Value, Value> {
+    let s = match args.get(0) {
+        Some(Value::String(s)) => s.borrow().clone(),
+        _ => return Err(Value::String(Ref("parse_int: String expected".to_owned()))),
+    };
+    let radix = match args.get(1) {
+        Some(Value::Null) | None => 10,
+        Some(x) => match x.to_int() {
+            Some(radix) if radix >= 2 && radix <= 36 => radix as u32,
+            _ => {
+                return Err(Value::String(Ref(
+                    "parse_int: radix must be between 2 and 36".to_owned(),
+                )))
+            }
+        },
+    };
+    match i64::from_str_radix(s.trim(), radix) {
+        Ok(x) => Ok(Value::Int(x)),
+        Err(_) if strict_flag(args, 2) => Err(Value::String(Ref(format!(
+            "parse_int: invalid integer '{}'",
+            s
+        )))),
+        Err(_) => Ok(Value::Null),
+    }
+}
+
+pub fn num_parse_float(args: &[Value]) -> Result<Value, Value> {
+    let s = match args.get(0) {
+        Some(Value::String(s)) => s.borrow().clone(),
+        _ => {
+            return Err(Value::String(
+                Ref("parse_float: String expected".to_owned()),
+            ))
+        }
+    };
+    match s.trim().parse::<f64>() {
+        Ok(x) => Ok(Value::Float(x)),
+        Err(_) if strict_flag(args, 1) => Err(Value::String(Ref(format!(
+            "parse_float: invalid number '{}'",
+            s
+        )))),
+        Err(_) => Ok(Value::Null),
+    }
+}
+
+/// `x.to_fixed(digits)`: `x` with exactly `digits` digits after the point.
+pub fn num_to_fixed(args: &[Value]) -> Result<Value, Value> {
+    let x = match args[0].to_float() {
+        Some(x) => x,
+        None => return Err(Value::String(Ref("to_fixed: Number expected".to_owned()))),
+    };
+    match args[1].to_int() {
+        Some(digits) if digits >= 0 && digits <= 100 => {
+            Ok(Value::String(Ref(format!("{:.*}", digits as usize, x))))
+        }
+        _ => Err(Value::String(Ref(
+            "to_fixed: digits must be between 0 and 100".to_owned(),
+        ))),
+    }
+}
+
+/// `x.to_radix(base)`: the digits of the integer `x` in `base`, from 2 to 36.
+pub fn num_to_radix(args: &[Value]) -> Result<Value, Value> {
+    if let Value::BigInt(x) = &args[0] {
+        return match args[1].to_int() {
+            Some(base) if base >= 2 && base <= 36 => {
+                Ok(Value::String(Ref(x.borrow().to_str_radix(base as u32))))
+            }
+            _ => Err(Value::String(Ref(
+                "to_radix: base must be between 2 and 36".to_owned(),
+            ))),
+        };
+    }
+    let x = match &args[0] {
+        Value::Int(x) => *x,
+        Value::Float(x) if x.fract() == 0.0 => *x as i64,
+        _ => return Err(Value::String(Ref("to_radix: Integer expected".to_owned()))),
+    };
+    let base = match args[1].to_int() {
+        Some(base) if base >= 2 && base <= 36 => base as u64,
+        _ => {
+            return Err(Value::String(Ref(
+                "to_radix: base must be between 2 and 36".to_owned(),
+            )))
+        }
+    };
+    let mut n = (x as i128).abs() as u64;
+    let mut digits = vec![];
+    loop {
+        digits.push(std::char::from_digit((n % base) as u32, base as u32).unwrap());
+        n /= base;
+        if n == 0 {
+            break;
+        }
+    }
+    if x < 0 {
+        digits.push('-');
+    }
+    Ok(Value::String(Ref(digits.iter().rev().collect())))
+}
+
+pub fn num_bigint(args: &[Value]) -> Result<Value, Value> {
+    use num_bigint::BigInt;
+    use num_traits::FromPrimitive;
+    match &args[0] {
+        Value::String(s) => match BigInt::parse_bytes(s.borrow().trim().as_bytes(), 10) {
+            Some(x) => Ok(Value::BigInt(Ref(x))),
+            None => Err(Value::String(Ref(format!(
+                "bigint: invalid integer '{}'",
+                s.borrow()
+            )))),
+        },
+        Value::Float(x) => match BigInt::from_f64(x.trunc()) {
+            Some(x) => Ok(Value::BigInt(Ref(x))),
+            None => Err(Value::String(Ref(format!(
+                "bigint: cannot convert {} to BigInt",
+                x
+            )))),
+        },
+        value => match value.to_bigint() {
+            Some(x) => Ok(Value::BigInt(Ref(x))),
+            None => Err(Value::String(Ref(
+                "bigint: String or Number expected".to_owned()
+            ))),
+        },
+    }
+}
+
+pub fn num_bigint_to_int(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::BigInt(_) | Value::Int(_) => {
+            Ok(args[0].to_int().map(Value::Int).unwrap_or(Value::Null))
+        }
+        _ => Err(Value::String(Ref(
+            "bigint_to_int: BigInt expected".to_owned()
+        ))),
+    }
+}
+
+pub fn num_bigint_to_float(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::BigInt(_) | Value::Int(_) => Ok(Value::Float(args[0].to_float().unwrap())),
+        _ => Err(Value::String(Ref(
+            "bigint_to_float: BigInt expected".to_owned()
+        ))),
+    }
+}
+
+/// Int operands of the `$checked_*` and `$wrapping_*` functions.
+fn int_operands(args: &[Value], name: &str) -> Result<(i64, i64), Value> {
+    match (&args[0], &args[1]) {
+        (Value::Int(x), Value::Int(y)) => Ok((*x, *y)),
+        _ => Err(Value::String(Ref(format!("{}: Int expected", name)))),
+    }
+}
+
+/// `$checked_add(x, y)`: `x + y`, `null` if it overflows.
+pub fn num_checked_add(args: &[Value]) -> Result<Value, Value> {
+    let (x, y) = int_operands(args, "checked_add")?;
+    Ok(x.checked_add(y).map(Value::Int).unwrap_or(Value::Null))
+}
+
+/// `$checked_sub(x, y)`: `x - y`, `null` if it overflows.
+pub fn num_checked_sub(args: &[Value]) -> Result<Value, Value> {
+    let (x, y) = int_operands(args, "checked_sub")?;
+    Ok(x.checked_sub(y).map(Value::Int).unwrap_or(Value::Null))
+}
+
+/// `$checked_mul(x, y)`: `x * y`, `null` if it overflows.
+pub fn num_checked_mul(args: &[Value]) -> Result<Value, Value> {
+    let (x, y) = int_operands(args, "checked_mul")?;
+    Ok(x.checked_mul(y).map(Value::Int).unwrap_or(Value::Null))
+}
+
+/// `$wrapping_add(x, y)`: `x + y`, wrapped around on overflow.
+pub fn num_wrapping_add(args: &[Value]) -> Result<Value, Value> {
+    let (x, y) = int_operands(args, "wrapping_add")?;
+    Ok(Value::Int(x.wrapping_add(y)))
+}
+
+/// `$wrapping_sub(x, y)`: `x - y`, wrapped around on overflow.
+pub fn num_wrapping_sub(args: &[Value]) -> Result<Value, Value> {
+    let (x, y) = int_operands(args, "wrapping_sub")?;
+    Ok(Value::Int(x.wrapping_sub(y)))
+}
+
+/// `$wrapping_mul(x, y)`: `x * y`, wrapped around on overflow.
+pub fn num_wrapping_mul(args: &[Value]) -> Result<Value, Value> {
+    let (x, y) = int_operands(args, "wrapping_mul")?;
+    Ok(Value::Int(x.wrapping_mul(y)))
+}
+
+/// `$decimal(x)`: a decimal from a string like `"10.35"`, an integer or a finite float.
+/// Floats convert through their shortest text, so `$decimal(0.1)` is `0.1`.
+pub fn num_decimal(args: &[Value]) -> Result<Value, Value> {
+    let parsed = match &args[0] {
+        Value::String(s) => match Decimal::parse(s.borrow().trim()) {
+            Some(x) => x,
+            None => {
+                return Err(Value::String(Ref(format!(
+                    "decimal: invalid decimal '{}'",
+                    s.borrow()
+                ))))
+            }
+        },
+        Value::Float(x) if x.is_finite() => Decimal::parse(&x.to_string()).unwrap(),
+        Value::Float(x) => {
+            return Err(Value::String(Ref(format!(
+                "decimal: cannot convert {} to decimal",
+                x
+            ))))
+        }
+        value => match value.to_decimal() {
+            Some(x) => x,
+            None => {
+                return Err(Value::String(Ref(
+                    "decimal: String or Number expected".to_owned()
+                )))
+            }
+        },
+    };
+    Ok(Value::Decimal(Ref(parsed)))
+}
+
+fn decimal_arg(value: &Value, name: &str) -> Result<Decimal, Value> {
+    value
+        .to_decimal()
+        .ok_or_else(|| Value::String(Ref(format!("{}: Decimal expected", name))))
+}
+
+/// Decimal places and rounding mode arguments starting at `idx`. The mode is one of
+/// `"half_even"` (the default), `"half_up"`, `"down"`, `"up"`, `"floor"` and `"ceiling"`.
+fn rounding_args(
+    args: &[Value],
+    idx: usize,
+    name: &str,
+) -> Result<(u32, crate::decimal::Rounding), Value> {
+    use crate::decimal::Rounding;
+    if args.len() != idx + 1 && args.len() != idx + 2 {
+        return Err(Value::String(Ref(format!(
+            "{}: Expected {} or {} arguments",
+            name,
+            idx + 1,
+            idx + 2
+        ))));
+    }
+    let places = match &args[idx] {
+        Value::Int(x) if *x >= 0 && *x <= u16::max_value() as i64 => *x as u32,
+        _ => {
+            return Err(Value::String(Ref(format!(
+                "{}: decimal places must be a non-negative Int",
+                name
+            ))))
+        }
+    };
+    let rounding = match args.get(idx + 1) {
+        None | Some(Value::Null) => Rounding::HalfEven,
+        Some(Value::String(s)) => match Rounding::from_name(&s.borrow()) {
+            Some(rounding) => rounding,
+            None => {
+                return Err(Value::String(Ref(format!(
+                    "{}: unknown rounding mode '{}'",
+                    name,
+                    s.borrow()
+                ))))
+            }
+        },
+        Some(_) => {
+            return Err(Value::String(Ref(format!(
+                "{}: rounding mode must be a String",
+                name
+            ))))
+        }
+    };
+    Ok((places, rounding))
+}
+
+/// `$decimal_div(x, y, places, rounding)`: `x / y` rounded to `places` decimal places.
+pub fn num_decimal_div(args: &[Value]) -> Result<Value, Value> {
+    if args.len() < 3 {
+        return Err(Value::String(Ref(
+            "decimal_div: Expected 3 or 4 arguments".to_owned()
+        )));
+    }
+    let x = decimal_arg(&args[0], "decimal_div")?;
+    let y = decimal_arg(&args[1], "decimal_div")?;
+    let (places, rounding) = rounding_args(args, 2, "decimal_div")?;
+    match x.div(&y, places, rounding) {
+        Some(x) => Ok(Value::Decimal(Ref(x))),
+        None => Err(Value::String(Ref(
+            "decimal_div: division by zero".to_owned()
+        ))),
+    }
+}
+
+/// `$decimal_round(x, places, rounding)`: `x` with exactly `places` decimal places.
+pub fn num_decimal_round(args: &[Value]) -> Result<Value, Value> {
+    if args.len() < 2 {
+        return Err(Value::String(Ref(
+            "decimal_round: Expected 2 or 3 arguments".to_owned(),
+        )));
+    }
+    let x = decimal_arg(&args[0], "decimal_round")?;
+    let (places, rounding) = rounding_args(args, 1, "decimal_round")?;
+    Ok(Value::Decimal(Ref(x.round(places, rounding))))
+}
+
+pub fn num_decimal_to_float(args: &[Value]) -> Result<Value, Value> {
+    let x = decimal_arg(&args[0], "decimal_to_float")?;
+    Ok(x.to_f64().map(Value::Float).unwrap_or(Value::Null))
+}
+
+use super::*;
+
+pub fn num_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("parse_int".to_owned(), new_native_fn(num_parse_int, -1));
+    map.insert("parse_float".to_owned(), new_native_fn(num_parse_float, -1));
+    map.insert("bigint".to_owned(), new_native_fn(num_bigint, 1));
+    map.insert(
+        "bigint_to_int".to_owned(),
+        new_native_fn(num_bigint_to_int, 1),
+    );
+    map.insert(
+        "bigint_to_float".to_owned(),
+        new_native_fn(num_bigint_to_float, 1),
+    );
+    map.insert("checked_add".to_owned(), new_native_fn(num_checked_add, 2));
+    map.insert("checked_sub".to_owned(), new_native_fn(num_checked_sub, 2));
+    map.insert("checked_mul".to_owned(), new_native_fn(num_checked_mul, 2));
+    map.insert(
+        "wrapping_add".to_owned(),
+        new_native_fn(num_wrapping_add, 2),
+    );
+    map.insert(
+        "wrapping_sub".to_owned(),
+        new_native_fn(num_wrapping_sub, 2),
+    );
+    map.insert(
+        "wrapping_mul".to_owned(),
+        new_native_fn(num_wrapping_mul, 2),
+    );
+    map.insert("decimal".to_owned(), new_native_fn(num_decimal, 1));
+    map.insert("decimal_div".to_owned(), new_native_fn(num_decimal_div, -1));
+    map.insert(
+        "decimal_round".to_owned(),
+        new_native_fn(num_decimal_round, -1),
+    );
+    map.insert(
+        "decimal_to_float".to_owned(),
+        new_native_fn(num_decimal_to_float, 1),
+    );
+}
+
+fn number_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("to_fixed".to_owned(), new_native_fn(num_to_fixed, 1));
+    map.insert("to_radix".to_owned(), new_native_fn(num_to_radix, 1));
+    map
+}
@@ -0,0 +1,116 @@
+use crate::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+thread_local! {
+    /// Methods available on objects that have no field of that name, e.g. `o.sort_keys()`.
+    pub static OBJECT_METHODS: HashMap<String, Value> = object_methods_init();
+}
+
+pub fn get_object_method(name: &str) -> Option<Value> {
+    OBJECT_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Numbers in ascending order, then everything else by its string form.
+fn key_order(a: &Value, b: &Value) -> Ordering {
+    match (a.to_float(), b.to_float()) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+/// `object.sort_keys()`: reorders the object's own fields by key and returns the object.
+pub fn object_sort_keys(args: &[Value]) -> Result<Value, Value> {
+    let object = match &args[0] {
+        Value::Object(object) => object,
+        _ => return Err(Value::String(Ref("sort_keys: Object expected".to_owned()))),
+    };
+    let mut object = object.borrow_mut();
+    let mut fields: Vec<_> = std::mem::take(&mut object.table).into_iter().collect();
+    fields.sort_by(|(a, _), (b, _)| key_order(a, b));
+    object.table.extend(fields);
+    object.modcount += 1;
+    Ok(args[0].clone())
+}
+
+fn object<'a>(name: &str, value: &'a Value) -> Result<&'a Ref<Object>, Value> {
+    match value {
+        Value::Object(object) => Ok(object),
+        _ => Err(Value::String(Ref(format!("{}: Object expected", name)))),
+    }
+}
+
+/// `object.keys()`: the object's own enumerable keys in order.
+pub fn object_keys(args: &[Value]) -> Result<Value, Value> {
+    let object = object("keys", &args[0])?.borrow();
+    let keys = object.fields().map(|(key, _)| key.clone()).collect();
+    Ok(Value::Array(Ref(keys)))
+}
+
+/// `object.define(key, value, enumerable, writable)`: sets a field with the given attributes
+/// and returns the object. Fields that aren't enumerable are left out of `foreach`, `keys`,
+/// printing and JSON, fields that aren't writable can't be assigned.
+pub fn object_define(args: &[Value]) -> Result<Value, Value> {
+    let mut object = object("define", &args[0])?.borrow_mut();
+    let attributes = Attributes {
+        enumerable: args[3].to_bool(),
+        writable: args[4].to_bool(),
+    };
+    object.set(args[1].clone(), args[2].clone());
+    object.set_attributes(args[1].clone(), attributes);
+    Ok(args[0].clone())
+}
+
+/// `object.remove(key)`: removes the object's own field `key` like `delete object[key]`,
+/// returns whether there was one.
+pub fn object_remove(args: &[Value]) -> Result<Value, Value> {
+    let mut object = object("remove", &args[0])?.borrow_mut();
+    match object.delete(&args[1]) {
+        Ok(deleted) => Ok(Value::Bool(deleted)),
+        Err(e) => Err(Value::String(Ref(format!("remove: {}", e)))),
+    }
+}
+
+/// `object.has_own(key)`: whether the object itself has the field `key`, its prototypes
+/// aren't looked at.
+pub fn object_has_own(args: &[Value]) -> Result<Value, Value> {
+    let object = object("has_own", &args[0])?.borrow();
+    Ok(Value::Bool(object.table.contains_key(&args[1])))
+}
+
+/// `object.seal()`: no fields can be added to the object anymore. Returns the object.
+pub fn object_seal(args: &[Value]) -> Result<Value, Value> {
+    object("seal", &args[0])?.borrow_mut().sealed = true;
+    Ok(args[0].clone())
+}
+
+/// `object.freeze()`: seals the object and makes its fields read-only. Returns the object.
+pub fn object_freeze(args: &[Value]) -> Result<Value, Value> {
+    let mut object = object("freeze", &args[0])?.borrow_mut();
+    object.sealed = true;
+    let keys = object.table.keys().cloned().collect::<Vec<_>>();
+    for key in keys {
+        let attributes = Attributes {
+            writable: false,
+            ..object.attributes(&key)
+        };
+        object.set_attributes(key, attributes);
+    }
+    Ok(args[0].clone())
+}
+
+use super::*;
+
+fn object_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("sort_keys".to_owned(), new_native_fn(object_sort_keys, 0));
+    map.insert("keys".to_owned(), new_native_fn(object_keys, 0));
+    map.insert("define".to_owned(), new_native_fn(object_define, 4));
+    map.insert("seal".to_owned(), new_native_fn(object_seal, 0));
+    map.insert("freeze".to_owned(), new_native_fn(object_freeze, 0));
+    map.insert("remove".to_owned(), new_native_fn(object_remove, 1));
+    map.insert("has_own".to_owned(), new_native_fn(object_has_own, 1));
+    map
+}
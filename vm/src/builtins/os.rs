@@ -0,0 +1,92 @@
+use crate::interp::{error_message, val_call, Stop};
+use crate::*;
+
+use std::collections::HashMap;
+
+thread_local! {
+    /// Functions registered with `$at_exit`, run last to first.
+    static EXIT_HOOKS: RefCell<Vec<Value>> = RefCell::new(vec![]);
+}
+
+/// Exit code when no bytecode file is given.
+pub const EXIT_USAGE: i32 = 64;
+/// Exit code when the bytecode file can't be read.
+pub const EXIT_NO_INPUT: i32 = 66;
+/// Exit code after an uncaught exception.
+pub const EXIT_UNCAUGHT: i32 = 70;
+/// Exit code when the trace or profile file to write to can't be created.
+pub const EXIT_CANT_CREATE: i32 = 73;
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+/// Runs the exit hooks, finishes the trace being recorded and the profile, and exits the
+/// process with `code`. Hooks that throw are reported and the remaining ones still run. Under
+/// a harness setting `Vm::fuel`, stops the run instead, as does the end of the hooks without
+/// the `process` feature.
+pub fn shutdown(code: i32) -> ! {
+    if get_vm!().fuel.is_some() {
+        std::panic::panic_any(Stop::Exit(code));
+    }
+    while let Some(hook) = EXIT_HOOKS.with(|hooks| hooks.borrow_mut().pop()) {
+        let result = get_vm!().protect(|_| val_call(hook, &[]));
+        if let Err(e) = result {
+            eprintln!("Error in exit hook: {}", error_message(&e));
+        }
+    }
+    if let Some(recorder) = get_vm!().recorder.take() {
+        recorder.finish();
+    }
+    if let Some(profiler) = get_vm!().profiler.take() {
+        profiler.finish();
+    }
+    exit_process(code)
+}
+
+#[cfg(feature = "process")]
+fn exit_process(code: i32) -> ! {
+    std::process::exit(code)
+}
+
+/// Without a process to exit, unwinds to the embedder, which catches `Stop::Exit`.
+#[cfg(not(feature = "process"))]
+fn exit_process(code: i32) -> ! {
+    std::panic::panic_any(Stop::Exit(code))
+}
+
+/// `$at_exit(f)`: calls `f` when the script finishes or calls `$exit`, after the hooks
+/// registered later.
+pub fn os_at_exit(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Function(_) => {
+            EXIT_HOOKS.with(|hooks| hooks.borrow_mut().push(args[0].clone()));
+            Ok(Value::Null)
+        }
+        _ => Err(error("at_exit", "Function expected")),
+    }
+}
+
+/// `$exit(code)`: runs the exit hooks and exits with `code`.
+pub fn os_exit(args: &[Value]) -> Result<Value, Value> {
+    match args[0].to_int() {
+        Some(code) => shutdown(code as i32),
+        None => Err(error("exit", "Int expected")),
+    }
+}
+
+/// `$args()`: the arguments given after the bytecode file, as strings.
+pub fn os_args(_: &[Value]) -> Result<Value, Value> {
+    let args = get_vm!().args.iter();
+    Ok(Value::Array(Ref(args
+        .map(|arg| Value::String(Ref(arg.clone())))
+        .collect())))
+}
+
+use super::*;
+
+pub fn os_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("at_exit".to_owned(), new_native_fn(os_at_exit, 1));
+    map.insert("exit".to_owned(), new_native_fn(os_exit, 1));
+    map.insert("args".to_owned(), new_native_fn(os_args, 0));
+}
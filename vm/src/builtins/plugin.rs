@@ -0,0 +1,249 @@
+//! Native extension modules: shared libraries `$native_import` loads, which register
+//! functions, values and methods of their own user types. A plugin is a `cdylib` depending
+//! on `jazzlight` with `default-features = false`, built with the same compiler and
+//! `jazzlight` version as the interpreter, and declares its entry point with `jazz_module!`,
+//! which also makes it allocate through the interpreter. Its functions work on their
+//! arguments only, the interpreter's state isn't shared with the plugin's copy of the crate.
+//!
+//! ```ignore
+//! use jazzlight::builtins::plugin::Registrar;
+//! use jazzlight::value::Value;
+//!
+//! fn twice(args: &[Value]) -> Result<Value, Value> {
+//!     Ok(Value::Int(args[1].to_int().unwrap_or(0) * 2))
+//! }
+//!
+//! fn init(registrar: &mut Registrar) {
+//!     registrar.function("twice", twice, 1);
+//! }
+//!
+//! jazzlight::jazz_module!(init);
+//! ```
+
+use super::new_native_fn;
+use crate::*;
+use value::*;
+
+#[cfg(feature = "native")]
+use libloading::{Library, Symbol};
+use std::alloc::{GlobalAlloc, Layout};
+#[cfg(feature = "native")]
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// Version of the plugin interface, a library built against another one isn't loaded.
+pub const ABI_VERSION: u32 = 7;
+
+/// The interpreter's allocator, which plugins use so values can move between the two.
+pub struct AllocFns {
+    pub alloc: unsafe fn(Layout) -> *mut u8,
+    pub dealloc: unsafe fn(*mut u8, Layout),
+    pub realloc: unsafe fn(*mut u8, Layout, usize) -> *mut u8,
+}
+
+#[cfg(feature = "native")]
+static HOST_ALLOC: AllocFns = AllocFns {
+    alloc: std::alloc::alloc,
+    dealloc: std::alloc::dealloc,
+    realloc: std::alloc::realloc,
+};
+
+/// Global allocator of a plugin, forwarding to the interpreter's once `jazz_module_init`
+/// installed it and to the system allocator before.
+pub struct HostAllocator(AtomicPtr<AllocFns>);
+
+impl HostAllocator {
+    pub const fn new() -> HostAllocator {
+        HostAllocator(AtomicPtr::new(std::ptr::null_mut()))
+    }
+
+    pub fn install(&self, fns: &'static AllocFns) {
+        self.0
+            .store(fns as *const AllocFns as *mut AllocFns, Ordering::SeqCst);
+    }
+
+    fn host(&self) -> Option<&'static AllocFns> {
+        unsafe { self.0.load(Ordering::SeqCst).as_ref() }
+    }
+}
+
+unsafe impl GlobalAlloc for HostAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.host() {
+            Some(host) => (host.alloc)(layout),
+            None => std::alloc::System.alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.host() {
+            Some(host) => (host.dealloc)(ptr, layout),
+            None => std::alloc::System.dealloc(ptr, layout),
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match self.host() {
+            Some(host) => (host.realloc)(ptr, layout, new_size),
+            None => std::alloc::System.realloc(ptr, layout, new_size),
+        }
+    }
+}
+
+/// Entry point a plugin exports as `jazz_module_init`.
+pub type InitFn = fn(&mut Registrar);
+
+#[cfg(feature = "native")]
+thread_local! {
+    /// Loaded libraries by path, kept open for the functions they registered, with their
+    /// exports.
+    static LIBRARIES: RefCell<HashMap<String, (Library, Value)>> = RefCell::new(HashMap::new());
+    /// Methods plugins registered for their user types, by `UserKind::get_kind`.
+    static TYPE_METHODS: RefCell<HashMap<&'static str, HashMap<String, Value>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Defines the symbols `$native_import` looks for, with `init` as `jazz_module_init`, and
+/// the plugin's global allocator.
+#[macro_export]
+macro_rules! jazz_module {
+    ($init:path) => {
+        #[global_allocator]
+        static JAZZ_ALLOCATOR: $crate::builtins::plugin::HostAllocator =
+            $crate::builtins::plugin::HostAllocator::new();
+
+        #[no_mangle]
+        pub static jazz_abi_version: u32 = $crate::builtins::plugin::ABI_VERSION;
+
+        #[no_mangle]
+        pub fn jazz_module_init(registrar: &mut $crate::builtins::plugin::Registrar) {
+            JAZZ_ALLOCATOR.install(registrar.allocator());
+            let init: $crate::builtins::plugin::InitFn = $init;
+            init(registrar)
+        }
+    };
+}
+
+/// What a plugin's `jazz_module_init` registers into.
+pub struct Registrar {
+    allocator: &'static AllocFns,
+    exports: Vec<(String, Value)>,
+    methods: Vec<(&'static str, String, Value)>,
+}
+
+impl Registrar {
+    pub fn allocator(&self) -> &'static AllocFns {
+        self.allocator
+    }
+
+    /// Exports `f` as `name`, taking `argc` arguments or any number when -1. It gets the
+    /// exports of the module as its first argument, followed by them.
+    pub fn function(&mut self, name: &str, f: fn(&[Value]) -> Result<Value, Value>, argc: i32) {
+        self.value(name, new_native_fn(f, argc));
+    }
+
+    /// Exports `value` as `name`.
+    pub fn value(&mut self, name: &str, value: Value) {
+        self.exports.push((name.to_owned(), value));
+    }
+
+    /// Makes `f` the method `name` of user values whose `get_kind` is `kind`. It gets the
+    /// value as its first argument, followed by `argc` others.
+    pub fn method(
+        &mut self,
+        kind: &'static str,
+        name: &str,
+        f: fn(&[Value]) -> Result<Value, Value>,
+        argc: i32,
+    ) {
+        self.methods
+            .push((kind, name.to_owned(), new_native_fn(f, argc)));
+    }
+}
+
+#[cfg(feature = "native")]
+pub fn get_plugin_method(kind: &str, name: &str) -> Option<Value> {
+    TYPE_METHODS.with(|methods| {
+        methods
+            .borrow()
+            .get(kind)
+            .and_then(|methods| methods.get(name).cloned())
+    })
+}
+
+#[cfg(feature = "native")]
+fn error(msg: String) -> Value {
+    Value::String(Ref(format!("native_import: {}", msg)))
+}
+
+#[cfg(feature = "native")]
+/// Opens the library at `path` and runs its `jazz_module_init`.
+fn open(path: &str) -> Result<(Library, Value), Value> {
+    let lib = Library::new(path).map_err(|e| error(e.to_string()))?;
+    let mut registrar = Registrar {
+        allocator: &HOST_ALLOC,
+        exports: vec![],
+        methods: vec![],
+    };
+    unsafe {
+        let version: Symbol<*const u32> = lib
+            .get(b"jazz_abi_version\0")
+            .map_err(|_| error(format!("'{}' is not a jazz module", path)))?;
+        if **version != ABI_VERSION {
+            return Err(error(format!(
+                "'{}' is built for plugin ABI {}, not {}",
+                path, **version, ABI_VERSION
+            )));
+        }
+        let init: Symbol<InitFn> = lib
+            .get(b"jazz_module_init\0")
+            .map_err(|e| error(e.to_string()))?;
+        init(&mut registrar);
+    }
+    let Registrar {
+        exports, methods, ..
+    } = registrar;
+    TYPE_METHODS.with(|registered| {
+        let mut registered = registered.borrow_mut();
+        for (kind, name, f) in methods {
+            registered.entry(kind).or_default().insert(name, f);
+        }
+    });
+    let mut object = Object {
+        prototype: None,
+        table: Default::default(),
+        modcount: 0,
+        attributes: Default::default(),
+        sealed: false,
+    };
+    for (name, value) in exports {
+        object.set(Value::String(Ref(name)), value);
+    }
+    let object = Value::Object(Ref(object));
+    if let Value::Object(exports) = &object {
+        for value in exports.borrow().table.values() {
+            match value {
+                Value::Function(f) if f.borrow().native => f.borrow_mut().env = object.clone(),
+                _ => (),
+            }
+        }
+    }
+    Ok((lib, object))
+}
+
+#[cfg(feature = "native")]
+/// `$native_import(path)`: exports of the plugin at `path`. A library is loaded once, later
+/// imports return the same exports.
+pub fn builtin_native_import(args: &[Value]) -> Result<Value, Value> {
+    let path = match &args[0] {
+        Value::String(path) => path.borrow().clone(),
+        _ => return Err(error("String expected".to_owned())),
+    };
+    if let Some(exports) = LIBRARIES.with(|libs| libs.borrow().get(&path).map(|lib| lib.1.clone()))
+    {
+        return Ok(exports);
+    }
+    let (lib, exports) = open(&path)?;
+    LIBRARIES.with(|libs| libs.borrow_mut().insert(path, (lib, exports.clone())));
+    Ok(exports)
+}
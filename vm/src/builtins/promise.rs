@@ -0,0 +1,423 @@
+use crate::builtins::fiber;
+use crate::builtins::func::new_native_closure;
+use crate::builtins::shared::{from_shared, Shared};
+use crate::interp::val_call;
+use crate::*;
+use value::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+thread_local! {
+    /// Methods available on promises, e.g. `p.then(f)`.
+    pub static PROMISE_METHODS: HashMap<String, Value> = promise_methods_init();
+    /// Handlers of settled promises waiting to run, with their argument and the promise
+    /// they settle.
+    static JOBS: RefCell<VecDeque<(Value, Result<Value, Value>, Value)>> = RefCell::new(VecDeque::new());
+    /// Promises of `$timeout` with the time they resolve at, see `clock::now`.
+    static TIMERS: RefCell<Vec<(Duration, Value)>> = RefCell::new(vec![]);
+    /// Promises of the tasks running on other threads by id, and the id of the next one.
+    static TASKS: RefCell<(HashMap<usize, Value>, usize)> = RefCell::new((HashMap::new(), 0));
+    /// Outcomes of finished tasks by id.
+    static COMPLETIONS: (Sender<(usize, Result<Shared, String>)>, Receiver<(usize, Result<Shared, String>)>) = channel();
+}
+
+pub fn get_promise_method(name: &str) -> Option<Value> {
+    PROMISE_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+enum State {
+    /// Fulfillment and rejection handlers with the promise they settle, `null` handlers
+    /// pass the outcome on.
+    Pending(Vec<(Value, Value, Value)>),
+    Fulfilled(Value),
+    Rejected(Value),
+}
+
+/// Eventual outcome of an asynchronous operation. Handlers run from the event loop, never
+/// while the promise is being settled.
+pub struct Promise {
+    state: State,
+}
+
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state {
+            State::Pending(_) => "pending",
+            State::Fulfilled(_) => "fulfilled",
+            State::Rejected(_) => "rejected",
+        };
+        write!(f, "Promise({})", state)
+    }
+}
+
+impl fmt::Display for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<promise>")
+    }
+}
+
+impl UserKind for Promise {
+    fn get_kind(&self) -> &'static str {
+        "promise"
+    }
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+pub fn is_pending(promise: &Value) -> bool {
+    with_state(promise, |state| match state {
+        State::Pending(_) => true,
+        _ => false,
+    })
+}
+
+fn is_promise(value: &Value) -> bool {
+    match value {
+        Value::User(user) => user.borrow().is::<Promise>(),
+        _ => false,
+    }
+}
+
+pub fn new_promise() -> Value {
+    Value::User(Ref(Promise {
+        state: State::Pending(vec![]),
+    }))
+}
+
+fn with_state<T>(promise: &Value, f: impl FnOnce(&mut State) -> T) -> T {
+    match promise {
+        Value::User(user) => f(&mut user.borrow_mut().downcast_mut::<Promise>().unwrap().state),
+        _ => unreachable!(),
+    }
+}
+
+fn enqueue(handler: Value, outcome: Result<Value, Value>, result: Value) {
+    JOBS.with(|jobs| jobs.borrow_mut().push_back((handler, outcome, result)));
+}
+
+/// Runs `on_fulfilled` or `on_rejected` once `promise` settles and settles `result` with
+/// what it returns or throws. `result` can be `null`.
+fn subscribe(promise: &Value, on_fulfilled: Value, on_rejected: Value, result: Value) {
+    with_state(promise, |state| match state {
+        State::Pending(reactions) => reactions.push((on_fulfilled, on_rejected, result)),
+        State::Fulfilled(value) => enqueue(on_fulfilled, Ok(value.clone()), result),
+        State::Rejected(e) => enqueue(on_rejected, Err(e.clone()), result),
+    })
+}
+
+/// Settles `promise` unless it already is.
+pub fn settle(promise: &Value, outcome: Result<Value, Value>) {
+    let reactions = with_state(promise, |state| match state {
+        State::Pending(_) => {
+            let settled = match &outcome {
+                Ok(value) => State::Fulfilled(value.clone()),
+                Err(e) => State::Rejected(e.clone()),
+            };
+            match std::mem::replace(state, settled) {
+                State::Pending(reactions) => reactions,
+                _ => unreachable!(),
+            }
+        }
+        _ => vec![],
+    });
+    for (on_fulfilled, on_rejected, result) in reactions {
+        match &outcome {
+            Ok(_) => enqueue(on_fulfilled, outcome.clone(), result),
+            Err(_) => enqueue(on_rejected, outcome.clone(), result),
+        }
+    }
+}
+
+/// Fulfills `promise` with `value`, or once `value` settles if it is a promise itself.
+pub fn resolve(promise: &Value, value: Value) {
+    if is_promise(&value) {
+        subscribe(&value, Value::Null, Value::Null, promise.clone());
+    } else {
+        settle(promise, Ok(value));
+    }
+}
+
+/// Calls `f`, giving back what it throws instead of unwinding past the caller.
+fn call_protected(f: Value, args: &[Value]) -> Result<Value, Value> {
+    get_vm!().protect(|_| val_call(f, args))
+}
+
+/// Fulfills `promise` with `null` after `duration`, unless it is settled by then.
+pub fn resolve_after(promise: &Value, duration: Duration) {
+    let at = clock::now() + duration;
+    TIMERS.with(|timers| timers.borrow_mut().push((at, promise.clone())));
+}
+
+/// Runs `task` on a new thread, or right away without the `threads` feature. The returned
+/// promise is settled with its outcome, rejected with `name: error` if it fails.
+pub fn spawn_task(
+    name: &'static str,
+    task: impl FnOnce() -> Result<Shared, String> + Send + 'static,
+) -> Value {
+    let promise = new_promise();
+    let id = TASKS.with(|tasks| {
+        let (tasks, next) = &mut *tasks.borrow_mut();
+        tasks.insert(*next, promise.clone());
+        *next += 1;
+        *next - 1
+    });
+    let sender = COMPLETIONS.with(|(sender, _)| sender.clone());
+    let run = move || {
+        let outcome = task().map_err(|e| format!("{}: {}", name, e));
+        // The receiver only goes away with the VM's thread.
+        let _ = sender.send((id, outcome));
+    };
+    if cfg!(feature = "threads") {
+        std::thread::spawn(run);
+    } else {
+        run();
+    }
+    promise
+}
+
+/// Waits for a task to finish until `deadline`, if any is running, and settles its promise.
+fn complete_task(deadline: Option<Duration>) -> bool {
+    if TASKS.with(|tasks| tasks.borrow().0.is_empty()) {
+        return false;
+    }
+    let completion = COMPLETIONS.with(|(_, receiver)| match deadline {
+        Some(deadline) => {
+            let timeout = deadline.checked_sub(clock::now()).unwrap_or_default();
+            receiver.recv_timeout(timeout).ok()
+        }
+        None => receiver.recv().ok(),
+    });
+    let (id, outcome) = match completion {
+        Some(completion) => completion,
+        None => return false,
+    };
+    let promise = TASKS.with(|tasks| tasks.borrow_mut().0.remove(&id).unwrap());
+    match outcome {
+        Ok(value) => settle(&promise, Ok(from_shared(&value))),
+        Err(e) => settle(&promise, Err(Value::String(Ref(e)))),
+    }
+    true
+}
+
+/// Runs queued handlers, fibers, tasks and timers until nothing is left to do. Fibers take
+/// turns with the handlers their yields let run.
+pub fn run_event_loop() {
+    loop {
+        while let Some((handler, outcome, result)) = JOBS.with(|jobs| jobs.borrow_mut().pop_front())
+        {
+            let outcome = match (handler, outcome) {
+                (Value::Null, outcome) => outcome,
+                (handler, Ok(value)) | (handler, Err(value)) => call_protected(handler, &[value]),
+            };
+            match (result, outcome) {
+                (Value::Null, _) => (),
+                (result, Ok(value)) => resolve(&result, value),
+                (result, Err(e)) => settle(&result, Err(e)),
+            }
+        }
+        if fiber::run_fiber() {
+            continue;
+        }
+        let deadline = TIMERS.with(|timers| timers.borrow().iter().map(|(at, _)| *at).min());
+        if complete_task(deadline) {
+            continue;
+        }
+        let timer = TIMERS.with(|timers| {
+            let mut timers = timers.borrow_mut();
+            let next = (0..timers.len()).min_by_key(|&i| timers[i].0)?;
+            Some(timers.remove(next))
+        });
+        match timer {
+            Some((_, promise)) if !is_pending(&promise) => (),
+            Some((at, promise)) => {
+                let now = clock::now();
+                if at > now {
+                    clock::sleep(at - now);
+                }
+                resolve(&promise, Value::Null);
+            }
+            None => break,
+        }
+    }
+}
+
+fn resolve_fn(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Array(env) => resolve(&env.borrow().get(0).unwrap(), args[1].clone()),
+        _ => unreachable!(),
+    }
+    Ok(Value::Null)
+}
+
+fn reject_fn(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Array(env) => settle(&env.borrow().get(0).unwrap(), Err(args[1].clone())),
+        _ => unreachable!(),
+    }
+    Ok(Value::Null)
+}
+
+/// `$promise(executor)`: promise settled by the `resolve` and `reject` functions passed to
+/// `executor`, rejected if it throws.
+pub fn promise_new(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Function(_) => (),
+        _ => return Err(error("promise", "Function expected")),
+    }
+    let promise = new_promise();
+    let resolve = new_native_closure(resolve_fn, vec![promise.clone()], 1);
+    let reject = new_native_closure(reject_fn, vec![promise.clone()], 1);
+    if let Err(e) = call_protected(args[0].clone(), &[resolve, reject]) {
+        settle(&promise, Err(e));
+    }
+    Ok(promise)
+}
+
+/// Promise fulfilled with `value`, `value` itself if it is one.
+fn to_promise(value: Value) -> Value {
+    if is_promise(&value) {
+        return value;
+    }
+    let promise = new_promise();
+    settle(&promise, Ok(value));
+    promise
+}
+
+/// `$promise_resolve(value)`: promise fulfilled with `value`, `value` itself if it is one.
+pub fn promise_resolve(args: &[Value]) -> Result<Value, Value> {
+    Ok(to_promise(args[0].clone()))
+}
+
+/// `$promise_reject(e)`: promise rejected with `e`.
+pub fn promise_reject(args: &[Value]) -> Result<Value, Value> {
+    let promise = new_promise();
+    settle(&promise, Err(args[0].clone()));
+    Ok(promise)
+}
+
+/// Promises of an array passed to `$promise_all` or `$promise_race`, other values count as
+/// fulfilled ones.
+fn promises(args: &[Value], name: &str) -> Result<Vec<Value>, Value> {
+    match &args[0] {
+        Value::Array(array) => Ok(array.borrow().iter().map(to_promise).collect()),
+        _ => Err(error(name, "Array expected")),
+    }
+}
+
+/// Env: the promise of `$promise_all`, the results, the index and the remaining count.
+fn all_fulfilled(args: &[Value]) -> Result<Value, Value> {
+    let env = match &args[0] {
+        Value::Array(env) => env.borrow().to_vec(),
+        _ => unreachable!(),
+    };
+    let (results, index, remaining) = match (&env[1], &env[2], &env[3]) {
+        (Value::Array(results), Value::Int(index), Value::Array(remaining)) => {
+            (results, *index as usize, remaining)
+        }
+        _ => unreachable!(),
+    };
+    results.borrow_mut().set(index, args[1].clone());
+    let left = remaining.borrow().get(0).and_then(|x| x.to_int()).unwrap() - 1;
+    remaining.borrow_mut().set(0, Value::Int(left));
+    if left == 0 {
+        let values = results.borrow().clone();
+        settle(&env[0], Ok(Value::Array(Ref(values))));
+    }
+    Ok(Value::Null)
+}
+
+/// `$promise_all(promises)`: promise of the array of their values, rejected as soon as one
+/// of them is.
+pub fn promise_all(args: &[Value]) -> Result<Value, Value> {
+    let promises = promises(args, "promise_all")?;
+    let promise = new_promise();
+    if promises.is_empty() {
+        settle(&promise, Ok(Value::Array(Ref(Elements::new()))));
+        return Ok(promise);
+    }
+    let results = Value::Array(Ref(vec![Value::Null; promises.len()].into()));
+    let remaining = Value::Array(Ref(vec![Value::Int(promises.len() as _)].into()));
+    for (i, p) in promises.iter().enumerate() {
+        let env = vec![
+            promise.clone(),
+            results.clone(),
+            Value::Int(i as _),
+            remaining.clone(),
+        ];
+        let on_fulfilled = new_native_closure(all_fulfilled, env, 1);
+        let on_rejected = new_native_closure(reject_fn, vec![promise.clone()], 1);
+        subscribe(p, on_fulfilled, on_rejected, Value::Null);
+    }
+    Ok(promise)
+}
+
+/// `$promise_race(promises)`: promise settled like the first of them to settle.
+pub fn promise_race(args: &[Value]) -> Result<Value, Value> {
+    let promises = promises(args, "promise_race")?;
+    let promise = new_promise();
+    for p in promises.iter() {
+        subscribe(p, Value::Null, Value::Null, promise.clone());
+    }
+    Ok(promise)
+}
+
+/// `$timeout(ms)`: promise fulfilled with `null` after `ms` milliseconds.
+pub fn promise_timeout(args: &[Value]) -> Result<Value, Value> {
+    let ms = match args[0].to_int() {
+        Some(ms) if ms >= 0 => ms as u64,
+        _ => return Err(error("timeout", "Non-negative Int expected")),
+    };
+    let promise = new_promise();
+    resolve_after(&promise, Duration::from_millis(ms));
+    Ok(promise)
+}
+
+/// `p.then(on_fulfilled, on_rejected)`: promise settled with what the handler called for
+/// the outcome of `p` returns or throws. Either handler can be omitted or `null`.
+pub fn promise_then(args: &[Value]) -> Result<Value, Value> {
+    if !is_promise(&args[0]) {
+        return Err(error("then", "Promise expected"));
+    }
+    let handler = |i: usize| match args.get(i) {
+        Some(f @ Value::Function(_)) => Ok(f.clone()),
+        Some(Value::Null) | None => Ok(Value::Null),
+        Some(_) => Err(error("then", "Function expected")),
+    };
+    let result = new_promise();
+    subscribe(&args[0], handler(1)?, handler(2)?, result.clone());
+    Ok(result)
+}
+
+/// `p.catch(on_rejected)`: `p.then(null, on_rejected)`.
+pub fn promise_catch(args: &[Value]) -> Result<Value, Value> {
+    promise_then(&[args[0].clone(), Value::Null, args[1].clone()])
+}
+
+use super::*;
+
+pub fn promise_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("promise".to_owned(), new_native_fn(promise_new, 1));
+    map.insert(
+        "promise_resolve".to_owned(),
+        new_native_fn(promise_resolve, 1),
+    );
+    map.insert(
+        "promise_reject".to_owned(),
+        new_native_fn(promise_reject, 1),
+    );
+    map.insert("promise_all".to_owned(), new_native_fn(promise_all, 1));
+    map.insert("promise_race".to_owned(), new_native_fn(promise_race, 1));
+    map.insert("timeout".to_owned(), new_native_fn(promise_timeout, 1));
+}
+
+fn promise_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("then".to_owned(), new_native_fn(promise_then, -1));
+    map.insert("catch".to_owned(), new_native_fn(promise_catch, 1));
+    map
+}
@@ -0,0 +1,334 @@
+//! Text tables and unified diffs for reports and assertion failures:
+//!
+//! ```ignore
+//! $print($table_render($array($array("a.txt", 12), $array("b.txt", 3400)), $array("name", "size")))
+//! $print($diff_text($file_contents($file_open("old.txt")), $file_contents($file_open("new.txt"))))
+//! ```
+
+use crate::builtins::term::strip;
+use crate::*;
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn key(name: &str) -> Value {
+    Value::String(Ref(name.to_owned()))
+}
+
+/// Numbers are aligned to the right of their column, everything else to the left.
+struct Cell {
+    lines: Vec<String>,
+    numeric: bool,
+}
+
+impl Cell {
+    fn new(value: &Value) -> Cell {
+        let text = match value {
+            Value::Null => String::new(),
+            value => value.to_string(),
+        };
+        Cell {
+            lines: text.lines().map(str::to_owned).collect(),
+            numeric: match value {
+                Value::Int(_) | Value::Float(_) | Value::BigInt(_) | Value::Decimal(_) => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Width of `text` on a terminal, without color escapes.
+fn width(text: &str) -> usize {
+    strip(text).chars().count()
+}
+
+fn pad(text: &str, width: usize, right: bool) -> String {
+    let fill = " ".repeat(width.saturating_sub(self::width(text)));
+    if right {
+        fill + text
+    } else {
+        format!("{}{}", text, fill)
+    }
+}
+
+/// Cells of `rows`, which are Arrays of cells or Objects with a field for every header. The
+/// headers of Objects default to their fields in the order they're first seen.
+fn read_rows(rows: &Value, headers: &Value) -> Result<(Vec<String>, Vec<Vec<Value>>), String> {
+    let rows = match rows {
+        Value::Array(rows) => rows.borrow().to_vec(),
+        _ => return Err("Array of rows expected".to_owned()),
+    };
+    let mut headers = match headers {
+        Value::Null => vec![],
+        Value::Array(headers) => headers.borrow().iter().map(|h| h.to_string()).collect(),
+        _ => return Err("headers must be an Array".to_owned()),
+    };
+    if headers.is_empty() {
+        for row in &rows {
+            if let Value::Object(object) = row {
+                for field in object.borrow().table.keys() {
+                    let field = field.to_string();
+                    if !headers.contains(&field) {
+                        headers.push(field);
+                    }
+                }
+            }
+        }
+    }
+    let mut cells = vec![];
+    for row in rows {
+        cells.push(match row {
+            Value::Array(row) => row.borrow().to_vec(),
+            Value::Object(object) => {
+                let object = object.borrow();
+                headers
+                    .iter()
+                    .map(|header| object.get(key(header)).unwrap_or(Value::Null))
+                    .collect()
+            }
+            row => return Err(format!("row {} is neither an Array nor an Object", row)),
+        });
+    }
+    Ok((headers, cells))
+}
+
+/// Lays out `rows` in columns separated by `|`, with a rule under the headers if there are
+/// any. Cells spanning several lines make their row as tall as needed.
+pub fn render_table(headers: &[String], rows: &[Vec<Value>]) -> String {
+    let header_cells: Vec<Cell> = headers.iter().map(|h| Cell::new(&key(h))).collect();
+    let rows: Vec<Vec<Cell>> = rows
+        .iter()
+        .map(|row| row.iter().map(Cell::new).collect())
+        .collect();
+    let columns = rows
+        .iter()
+        .map(Vec::len)
+        .chain(Some(headers.len()))
+        .max()
+        .unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in rows.iter().chain(Some(&header_cells)) {
+        for (column, cell) in row.iter().enumerate() {
+            for line in &cell.lines {
+                widths[column] = widths[column].max(width(line));
+            }
+        }
+    }
+    let mut out = String::new();
+    let render_row = |row: &[Cell]| {
+        let mut out = String::new();
+        let height = row.iter().map(|cell| cell.lines.len()).max().unwrap_or(0);
+        for i in 0..height.max(1) {
+            let line = (0..columns)
+                .map(|column| match row.get(column) {
+                    Some(cell) => {
+                        let text = cell.lines.get(i).map_or("", String::as_str);
+                        pad(text, widths[column], cell.numeric)
+                    }
+                    None => " ".repeat(widths[column]),
+                })
+                .collect::<Vec<_>>()
+                .join(" | ");
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    };
+    if !headers.is_empty() {
+        out.push_str(&render_row(&header_cells));
+        let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+        out.push_str(&rule.join("-+-"));
+        out.push('\n');
+    }
+    for row in &rows {
+        out.push_str(&render_row(row));
+    }
+    out
+}
+
+/// `$table_render(rows, headers?)`: `rows` as an aligned text table.
+pub fn table_render(args: &[Value]) -> Result<Value, Value> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(error("table_render", "Expected 1 or 2 arguments"));
+    }
+    let headers = args.get(1).cloned().unwrap_or(Value::Null);
+    let (headers, rows) = read_rows(&args[0], &headers).map_err(|e| error("table_render", &e))?;
+    Ok(Value::String(Ref(render_table(&headers, &rows))))
+}
+
+#[derive(Copy, Clone)]
+enum Edit {
+    Keep(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Lines of `text` with their terminators, so a missing newline at the end is a difference.
+fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (i, b) in text.bytes().enumerate() {
+        if b == b'\n' {
+            lines.push(&text[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
+/// Shortest edit script turning `a` into `b`, by Myers' algorithm.
+fn shortest_edit(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max as usize + 1;
+    let mut v = vec![0isize; 2 * offset + 1];
+    let mut trace = vec![];
+    'search: for d in 0..=max {
+        // Step `d` only reads diagonals `-d - 1..=d + 1`, so that's all backtracking needs.
+        trace.push(v[offset - d as usize - 1..=offset + d as usize + 1].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset as isize) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let i = (k + d + 1) as usize;
+        let prev_k = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + d + 1) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Keep(x as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// `start,len` of a hunk header, 1-based unless the range is empty.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Unified diff of the lines of `a` and `b` with `context` unchanged lines around changes,
+/// empty if they're equal.
+pub fn unified_diff(a: &str, b: &str, context: usize, from: &str, to: &str) -> String {
+    let (a, b) = (split_lines(a), split_lines(b));
+    let edits = shortest_edit(&a, &b);
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|i| !matches!(edits[*i], Edit::Keep(..)))
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("--- {}\n+++ {}\n", from, to);
+    let mut i = 0;
+    while i < changes.len() {
+        // Changes with at most twice the context between them share a hunk.
+        let mut j = i;
+        while j + 1 < changes.len() && changes[j + 1] - changes[j] <= 2 * context + 1 {
+            j += 1;
+        }
+        let start = changes[i].saturating_sub(context);
+        let end = (changes[j] + context + 1).min(edits.len());
+        let hunk = &edits[start..end];
+        let (a_start, b_start) = edits[..start]
+            .iter()
+            .fold((0, 0), |(x, y), edit| match edit {
+                Edit::Keep(..) => (x + 1, y + 1),
+                Edit::Delete(_) => (x + 1, y),
+                Edit::Insert(_) => (x, y + 1),
+            });
+        let a_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Insert(_)))
+            .count();
+        let b_len = hunk
+            .iter()
+            .filter(|e| !matches!(e, Edit::Delete(_)))
+            .count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(a_start, a_len),
+            range(b_start, b_len)
+        ));
+        for edit in hunk {
+            match *edit {
+                Edit::Keep(x) => push_line(&mut out, ' ', a[x]),
+                Edit::Delete(x) => push_line(&mut out, '-', a[x]),
+                Edit::Insert(y) => push_line(&mut out, '+', b[y]),
+            }
+        }
+        i = j + 1;
+    }
+    out
+}
+
+/// `$diff_text(a, b, context?)`: unified diff of two strings, line by line, with 3 lines of
+/// context unless told otherwise. Empty when there's no difference.
+pub fn diff_text(args: &[Value]) -> Result<Value, Value> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(error("diff_text", "Expected 2 or 3 arguments"));
+    }
+    let context = match args.get(2) {
+        None | Some(Value::Null) => 3,
+        Some(Value::Int(n)) if *n >= 0 => *n as usize,
+        Some(_) => return Err(error("diff_text", "context must be a non-negative Int")),
+    };
+    let (a, b) = (args[0].to_string(), args[1].to_string());
+    Ok(Value::String(Ref(unified_diff(&a, &b, context, "a", "b"))))
+}
+
+use super::*;
+
+pub fn report_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("table_render".to_owned(), new_native_fn(table_render, -1));
+    map.insert("diff_text".to_owned(), new_native_fn(diff_text, -1));
+}
@@ -0,0 +1,267 @@
+use crate::interp::val_call;
+use crate::*;
+use value::*;
+
+use num_bigint::BigInt;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    /// Methods available on shared cells, e.g. `cell.update(f)`.
+    pub static SHARED_CELL_METHODS: HashMap<String, Value> = shared_cell_methods_init();
+    /// Cells whose `update` function is running on this thread.
+    static UPDATING: RefCell<Vec<usize>> = RefCell::new(vec![]);
+}
+
+pub fn get_shared_cell_method(name: &str) -> Option<Value> {
+    SHARED_CELL_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Copy of a value that doesn't share anything with the heap it came from, so it can be
+/// sent to other threads. Arrays and objects reached more than once, including through a
+/// cycle, are copied the first time and referred to by `Ref` afterwards.
+#[derive(Clone)]
+pub enum Shared {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    BigInt(BigInt),
+    Decimal(Decimal),
+    String(String),
+    Array(Vec<Shared>),
+    /// Prototype, fields with their attributes and whether the object is sealed.
+    Object(Option<Box<Shared>>, Vec<(Shared, Shared, Attributes)>, bool),
+    /// The N-th array or object copied, counting in the order they were first reached.
+    Ref(usize),
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+/// Deep copy of `value`, keeping its cycles and the arrays and objects it refers to more
+/// than once. Functions and user values can't be copied.
+pub fn to_shared(value: &Value) -> Result<Shared, Value> {
+    fn copy(value: &Value, seen: &mut HashMap<usize, usize>) -> Result<Shared, Value> {
+        let ptr = match value {
+            Value::Array(x) => x.as_ptr() as *const u8 as usize,
+            Value::Object(x) => x.as_ptr() as *const u8 as usize,
+            _ => 0,
+        };
+        if ptr != 0 {
+            if let Some(&id) = seen.get(&ptr) {
+                return Ok(Shared::Ref(id));
+            }
+            let id = seen.len();
+            seen.insert(ptr, id);
+        }
+        Ok(match value {
+            Value::Null => Shared::Null,
+            Value::Bool(x) => Shared::Bool(*x),
+            Value::Int(x) => Shared::Int(*x),
+            Value::Float(x) => Shared::Float(*x),
+            Value::Char(x) => Shared::Char(*x),
+            Value::BigInt(x) => Shared::BigInt(x.borrow().clone()),
+            Value::Decimal(x) => Shared::Decimal(x.borrow().clone()),
+            Value::String(x) => Shared::String(x.borrow().clone()),
+            Value::Array(x) => {
+                let values = x.borrow().to_vec();
+                Shared::Array(
+                    values
+                        .iter()
+                        .map(|value| copy(value, seen))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Value::Object(x) => {
+                let (prototype, fields, sealed) = {
+                    let object = x.borrow();
+                    let fields = object
+                        .table
+                        .iter()
+                        .map(|(key, value)| (key.clone(), value.clone(), object.attributes(key)))
+                        .collect::<Vec<_>>();
+                    (object.prototype.clone(), fields, object.sealed)
+                };
+                let prototype = match prototype {
+                    Some(prototype) => Some(Box::new(copy(&Value::Object(prototype), seen)?)),
+                    None => None,
+                };
+                let mut copies = vec![];
+                for (key, value, attributes) in fields.iter() {
+                    copies.push((copy(key, seen)?, copy(value, seen)?, *attributes));
+                }
+                Shared::Object(prototype, copies, sealed)
+            }
+            Value::Function(_) | Value::User(_) => {
+                let msg = format!("{} can't be shared", value.type_name());
+                return Err(error("shared", &msg));
+            }
+        })
+    }
+    copy(value, &mut HashMap::new())
+}
+
+/// Value of the current heap with the contents of `shared`.
+pub fn from_shared(shared: &Shared) -> Value {
+    fn build(shared: &Shared, made: &mut Vec<Value>) -> Value {
+        match shared {
+            Shared::Null => Value::Null,
+            Shared::Bool(x) => Value::Bool(*x),
+            Shared::Int(x) => Value::Int(*x),
+            Shared::Float(x) => Value::Float(*x),
+            Shared::Char(x) => Value::Char(*x),
+            Shared::BigInt(x) => Value::BigInt(Ref(x.clone())),
+            Shared::Decimal(x) => Value::Decimal(Ref(x.clone())),
+            Shared::String(x) => Value::String(Ref(x.clone())),
+            Shared::Array(x) => {
+                let array = Ref(Elements::with_capacity(x.len()));
+                made.push(Value::Array(array.clone()));
+                for value in x.iter() {
+                    let value = build(value, made);
+                    array.borrow_mut().push(value);
+                }
+                Value::Array(array)
+            }
+            Shared::Object(prototype, fields, sealed) => {
+                let object = Ref(Object {
+                    prototype: None,
+                    table: Default::default(),
+                    modcount: 0,
+                    attributes: Default::default(),
+                    sealed: false,
+                });
+                made.push(Value::Object(object.clone()));
+                if let Some(Value::Object(prototype)) =
+                    prototype.as_ref().map(|prototype| build(prototype, made))
+                {
+                    object.borrow_mut().prototype = Some(prototype);
+                }
+                for (key, value, attributes) in fields.iter() {
+                    let key = build(key, made);
+                    let value = build(value, made);
+                    let mut object = object.borrow_mut();
+                    object.set(key.clone(), value);
+                    object.set_attributes(key, *attributes);
+                }
+                object.borrow_mut().sealed = *sealed;
+                Value::Object(object)
+            }
+            Shared::Ref(id) => made[*id].clone(),
+        }
+    }
+    build(shared, &mut vec![])
+}
+
+/// `$structured_clone(value)`: deep copy of `value`, the one shared cells and tasks make,
+/// with the same cycles and shared arrays and objects.
+pub fn structured_clone(args: &[Value]) -> Result<Value, Value> {
+    Ok(from_shared(&to_shared(&args[0])?))
+}
+
+/// Value guarded by a mutex, holding a copy of what was stored so any thread can read it.
+pub struct SharedCell {
+    value: Arc<Mutex<Shared>>,
+}
+
+impl fmt::Debug for SharedCell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SharedCell")
+    }
+}
+
+impl fmt::Display for SharedCell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<shared cell>")
+    }
+}
+
+impl UserKind for SharedCell {
+    fn get_kind(&self) -> &'static str {
+        "shared_cell"
+    }
+}
+
+/// The mutex of the cell `value`, unless its `update` function is running on this thread,
+/// where locking it again would never return.
+fn cell(value: &Value, name: &str) -> Result<Arc<Mutex<Shared>>, Value> {
+    let cell = match value {
+        Value::User(user) => match user.borrow().downcast_ref::<SharedCell>() {
+            Some(cell) => cell.value.clone(),
+            None => return Err(error(name, "Shared cell expected")),
+        },
+        _ => return Err(error(name, "Shared cell expected")),
+    };
+    let ptr = Arc::as_ptr(&cell) as usize;
+    if UPDATING.with(|updating| updating.borrow().contains(&ptr)) {
+        return Err(error(name, "Shared cell is being updated"));
+    }
+    Ok(cell)
+}
+
+/// `$shared_cell(initial)`: cell holding a copy of `initial`.
+pub fn shared_cell_new(args: &[Value]) -> Result<Value, Value> {
+    let value = to_shared(&args[0])?;
+    Ok(Value::User(Ref(SharedCell {
+        value: Arc::new(Mutex::new(value)),
+    })))
+}
+
+/// `cell.get()`: copy of the value.
+pub fn shared_cell_get(args: &[Value]) -> Result<Value, Value> {
+    let cell = cell(&args[0], "get")?;
+    let value = cell.lock().unwrap();
+    Ok(from_shared(&value))
+}
+
+/// `cell.set(value)`: stores a copy of `value`.
+pub fn shared_cell_set(args: &[Value]) -> Result<Value, Value> {
+    let cell = cell(&args[0], "set")?;
+    let value = to_shared(&args[1])?;
+    *cell.lock().unwrap() = value;
+    Ok(Value::Null)
+}
+
+/// `cell.swap(value)`: stores a copy of `value` and returns the previous one.
+pub fn shared_cell_swap(args: &[Value]) -> Result<Value, Value> {
+    let cell = cell(&args[0], "swap")?;
+    let value = to_shared(&args[1])?;
+    let old = std::mem::replace(&mut *cell.lock().unwrap(), value);
+    Ok(from_shared(&old))
+}
+
+/// `cell.update(f)`: stores `f(value)` and returns it. The cell stays locked while `f` runs,
+/// and is left unchanged if it throws.
+pub fn shared_cell_update(args: &[Value]) -> Result<Value, Value> {
+    let cell = cell(&args[0], "update")?;
+    let ptr = Arc::as_ptr(&cell) as usize;
+    let mut value = cell.lock().unwrap();
+    UPDATING.with(|updating| updating.borrow_mut().push(ptr));
+    let result = val_call(args[1].clone(), &[from_shared(&value)]);
+    UPDATING.with(|updating| updating.borrow_mut().retain(|cell| *cell != ptr));
+    let result = result?;
+    *value = to_shared(&result)?;
+    Ok(result)
+}
+
+use super::*;
+
+pub fn shared_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("shared_cell".to_owned(), new_native_fn(shared_cell_new, 1));
+    map.insert(
+        "structured_clone".to_owned(),
+        new_native_fn(structured_clone, 1),
+    );
+}
+
+fn shared_cell_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert("get".to_owned(), new_native_fn(shared_cell_get, 0));
+    map.insert("set".to_owned(), new_native_fn(shared_cell_set, 1));
+    map.insert("swap".to_owned(), new_native_fn(shared_cell_swap, 1));
+    map.insert("update".to_owned(), new_native_fn(shared_cell_update, 1));
+    map
+}
@@ -0,0 +1,187 @@
+//! Running external commands from scripts, for using them where a shell script would be:
+//!
+//! ```ignore
+//! if $sh("make") != 0 { $exit(1) }
+//! var out = $sh_capture("git rev-parse HEAD")
+//! var n = $pipeline($array($array("ls"), $array("wc", "-l")), null).stdout
+//! ```
+
+use crate::*;
+use value::*;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+fn shell(cmd: &str) -> Command {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command.arg(cmd);
+    command
+}
+
+/// Exit code of a finished command, -1 when a signal ended it.
+fn status_code(status: ExitStatus) -> Value {
+    Value::Int(status.code().unwrap_or(-1) as i64)
+}
+
+/// `{status, stdout, stderr}` object.
+fn outcome(status: ExitStatus, stdout: &[u8], stderr: &[u8]) -> Value {
+    let mut object = Object {
+        prototype: None,
+        table: Default::default(),
+        modcount: 0,
+        attributes: Default::default(),
+        sealed: false,
+    };
+    let string = |bytes: &[u8]| Value::String(Ref(String::from_utf8_lossy(bytes).into_owned()));
+    object.set(Value::String(Ref("status".to_owned())), status_code(status));
+    object.set(Value::String(Ref("stdout".to_owned())), string(stdout));
+    object.set(Value::String(Ref("stderr".to_owned())), string(stderr));
+    Value::Object(Ref(object))
+}
+
+/// Optional standard input of a command, `null` for none.
+fn input(name: &str, value: Option<&Value>) -> Result<Option<String>, Value> {
+    match value {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.borrow().clone())),
+        Some(_) => Err(error(name, "String expected as input")),
+    }
+}
+
+/// Writes `input` to the standard input of `child` and closes it, on another thread so a
+/// command writing a lot before reading all of it doesn't block.
+fn feed(child: &mut Child, input: Option<String>) {
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().unwrap();
+        // A command that exits without reading its input closes the pipe, which isn't an
+        // error of the script.
+        std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    }
+}
+
+fn stdin_for(input: &Option<String>) -> Stdio {
+    if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    }
+}
+
+/// `$sh(cmd)`: runs `cmd` with the system shell, sharing the script's standard streams, and
+/// returns its exit code.
+pub fn shell_sh(args: &[Value]) -> Result<Value, Value> {
+    let cmd = match &args[0] {
+        Value::String(cmd) => cmd.borrow().clone(),
+        _ => return Err(error("sh", "String expected")),
+    };
+    match shell(&cmd).status() {
+        Ok(status) => Ok(status_code(status)),
+        Err(e) => Err(error("sh", &e.to_string())),
+    }
+}
+
+/// `$sh_capture(cmd, input = null)`: runs `cmd` with the system shell, with `input` as its
+/// standard input, and returns `{status, stdout, stderr}`.
+pub fn shell_sh_capture(args: &[Value]) -> Result<Value, Value> {
+    let cmd = match args.get(0) {
+        Some(Value::String(cmd)) => cmd.borrow().clone(),
+        _ => return Err(error("sh_capture", "String expected")),
+    };
+    let input = input("sh_capture", args.get(1))?;
+    let mut child = shell(&cmd)
+        .stdin(stdin_for(&input))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| error("sh_capture", &e.to_string()))?;
+    feed(&mut child, input);
+    match child.wait_with_output() {
+        Ok(output) => Ok(outcome(output.status, &output.stdout, &output.stderr)),
+        Err(e) => Err(error("sh_capture", &e.to_string())),
+    }
+}
+
+/// Program and arguments of one command of a pipeline.
+fn argv(value: &Value) -> Result<Vec<String>, Value> {
+    let argv = match value {
+        Value::Array(argv) => argv.borrow().iter().map(|arg| arg.to_string()).collect(),
+        _ => vec![],
+    };
+    if argv.is_empty() {
+        return Err(error("pipeline", "Array of program and arguments expected"));
+    }
+    Ok(argv)
+}
+
+/// `$pipeline(commands, input)`: runs the commands, arrays of a program and its arguments,
+/// each reading what the previous one writes, without a shell. Returns the exit code and
+/// output of the last one and what all of them wrote to standard error.
+pub fn shell_pipeline(args: &[Value]) -> Result<Value, Value> {
+    let commands = match &args[0] {
+        Value::Array(commands) if !commands.borrow().is_empty() => commands.borrow().to_vec(),
+        _ => return Err(error("pipeline", "Array of commands expected")),
+    };
+    let input = input("pipeline", args.get(1))?;
+    let mut children: Vec<Child> = vec![];
+    for (i, command) in commands.iter().enumerate() {
+        let argv = argv(command)?;
+        let stdin = match children.last_mut() {
+            Some(previous) => Stdio::from(previous.stdout.take().unwrap()),
+            None => stdin_for(&input),
+        };
+        let mut child = Command::new(&argv[0])
+            .args(&argv[1..])
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| error("pipeline", &format!("{}: {}", argv[0], e)))?;
+        if i == 0 {
+            feed(&mut child, input.clone());
+        }
+        children.push(child);
+    }
+    // The earlier commands are waited for on their own threads, so none of them blocks on a
+    // full standard error pipe while the last one runs.
+    let last = children.pop().unwrap();
+    let earlier = children
+        .into_iter()
+        .map(|child| std::thread::spawn(move || child.wait_with_output()))
+        .collect::<Vec<_>>();
+    let output = last
+        .wait_with_output()
+        .map_err(|e| error("pipeline", &e.to_string()))?;
+    let mut stderr = vec![];
+    for child in earlier {
+        if let Ok(Ok(output)) = child.join() {
+            stderr.extend_from_slice(&output.stderr);
+        }
+    }
+    stderr.extend_from_slice(&output.stderr);
+    Ok(outcome(output.status, &output.stdout, &stderr))
+}
+
+pub fn shell_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("sh".to_owned(), super::new_native_fn(shell_sh, 1));
+    map.insert(
+        "sh_capture".to_owned(),
+        super::new_native_fn(shell_sh_capture, -1),
+    );
+    map.insert(
+        "pipeline".to_owned(),
+        super::new_native_fn(shell_pipeline, 2),
+    );
+}
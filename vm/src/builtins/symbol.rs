@@ -0,0 +1,119 @@
+use crate::builtins::new_native_fn;
+use crate::*;
+use value::*;
+
+use std::collections::HashMap;
+use std::fmt;
+
+thread_local! {
+    /// Methods available on symbols, e.g. `s.description()`.
+    pub static SYMBOL_METHODS: HashMap<String, Value> = symbol_methods_init();
+    /// `@@iterator`, `@@to_string` and `@@dispose`.
+    static WELL_KNOWN: [Value; 3] = [
+        new_symbol("@@iterator"),
+        new_symbol("@@to_string"),
+        new_symbol("@@dispose"),
+    ];
+}
+
+pub fn get_symbol_method(name: &str) -> Option<Value> {
+    SYMBOL_METHODS.with(|methods| methods.get(name).cloned())
+}
+
+/// Key only equal to itself, so fields keyed by symbols never collide with string keys or
+/// with each other.
+pub struct Symbol {
+    description: String,
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({:?})", self.description)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({})", self.description)
+    }
+}
+
+impl UserKind for Symbol {
+    fn get_kind(&self) -> &'static str {
+        "symbol"
+    }
+}
+
+/// Symbols the VM looks up on objects instead of field names.
+#[derive(Copy, Clone)]
+pub enum WellKnown {
+    /// Method returning what `foreach` and `$iter` iterate over.
+    Iterator,
+    /// Method returning the text `$string`, `$print` and string concatenation use.
+    ToString,
+    /// Method `using` calls when leaving its body.
+    Dispose,
+}
+
+pub fn well_known(symbol: WellKnown) -> Value {
+    WELL_KNOWN.with(|symbols| symbols[symbol as usize].clone())
+}
+
+/// Method `symbol` of `value` if it is an object and has one.
+pub fn find_hook(value: &Value, symbol: WellKnown) -> Option<Value> {
+    match value {
+        Value::Object(object) => match object.borrow().get(well_known(symbol)) {
+            Some(method @ Value::Function(_)) => Some(method),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn new_symbol(description: &str) -> Value {
+    Value::User(Ref(Symbol {
+        description: description.to_owned(),
+    }))
+}
+
+/// `$symbol(description)`: new symbol, different from every other one.
+pub fn symbol_new(args: &[Value]) -> Result<Value, Value> {
+    match &args[0] {
+        Value::Null => Ok(new_symbol("")),
+        description => Ok(new_symbol(&description.to_string())),
+    }
+}
+
+/// `s.description()`: text given to `$symbol`.
+pub fn symbol_description(args: &[Value]) -> Result<Value, Value> {
+    if let Value::User(user) = &args[0] {
+        if let Some(symbol) = user.borrow().downcast_ref::<Symbol>() {
+            return Ok(Value::String(Ref(symbol.description.clone())));
+        }
+    }
+    Err(Value::String(
+        Ref("description: Symbol expected".to_owned()),
+    ))
+}
+
+pub fn symbol_builtins(map: &mut HashMap<String, Value>) {
+    map.insert("symbol".to_owned(), new_native_fn(symbol_new, 1));
+    for (name, symbol) in [
+        ("@@iterator", WellKnown::Iterator),
+        ("@@to_string", WellKnown::ToString),
+        ("@@dispose", WellKnown::Dispose),
+    ]
+    .iter()
+    {
+        map.insert((*name).to_owned(), well_known(*symbol));
+    }
+}
+
+fn symbol_methods_init() -> HashMap<String, Value> {
+    let mut map = HashMap::new();
+    map.insert(
+        "description".to_owned(),
+        new_native_fn(symbol_description, 0),
+    );
+    map
+}
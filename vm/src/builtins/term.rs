@@ -0,0 +1,366 @@
+//! Terminal output and prompts for command line tools:
+//!
+//! ```ignore
+//! $print($term_color("error:", "red"), " file not found\n")
+//! if $confirm("Overwrite out.txt?", false) { ... }
+//! var token = $prompt_secret("Token: ")
+//! ```
+//!
+//! Colors and styles are left out when standard output isn't a terminal or `NO_COLOR` is
+//! set, unless `$term_set_colors(true)` says otherwise.
+
+use crate::*;
+
+use std::cell::Cell;
+use std::io::{BufRead, Write};
+
+thread_local! {
+    /// Colors as `$term_set_colors` set them, `None` until then.
+    static COLORS: Cell<Option<bool>> = Cell::new(None);
+}
+
+fn error(name: &str, msg: &str) -> Value {
+    Value::String(Ref(format!("{}: {}", name, msg)))
+}
+
+#[cfg(unix)]
+fn is_terminal(fd: i32) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+#[cfg(not(unix))]
+fn is_terminal(_: i32) -> bool {
+    true
+}
+
+fn colors_enabled() -> bool {
+    COLORS
+        .with(|colors| colors.get())
+        .unwrap_or_else(|| std::env::var_os("NO_COLOR").is_none() && is_terminal(1))
+}
+
+/// Columns and rows of the terminal standard output is on.
+#[cfg(unix)]
+fn window_size() -> Option<(i64, i64)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+        return None;
+    }
+    Some((size.ws_col as i64, size.ws_row as i64))
+}
+
+#[cfg(not(unix))]
+fn window_size() -> Option<(i64, i64)> {
+    None
+}
+
+/// `$COLUMNS` or `$LINES`, for terminals that don't tell their size.
+fn size_from_env(var: &str, default: i64) -> i64 {
+    std::env::var(var)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// SGR parameters of a color, `base` is 30 for the foreground and 40 for the background.
+fn color_code(color: &Value, base: u32) -> Option<String> {
+    const NAMES: [&str; 8] = [
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+    match color {
+        Value::Int(x) if (0..256).contains(x) => Some(format!("{};5;{}", base + 8, x)),
+        Value::String(name) => {
+            let name = name.borrow();
+            let (bright, name) = if name.starts_with("bright_") {
+                (true, &name[7..])
+            } else {
+                (false, &name[..])
+            };
+            let index = NAMES.iter().position(|known| *known == name)? as u32;
+            Some(if bright {
+                (base + 60 + index).to_string()
+            } else {
+                (base + index).to_string()
+            })
+        }
+        _ => None,
+    }
+}
+
+fn style_code(style: &str) -> Option<&'static str> {
+    Some(match style {
+        "bold" => "1",
+        "dim" => "2",
+        "italic" => "3",
+        "underline" => "4",
+        "inverse" => "7",
+        "strike" => "9",
+        _ => return None,
+    })
+}
+
+/// `text` between `ESC[<code>m` and a reset, or just `text` without colors.
+fn sgr(text: &Value, code: &str) -> Value {
+    if colors_enabled() {
+        Value::String(Ref(format!("\x1b[{}m{}\x1b[0m", code, text)))
+    } else {
+        Value::String(Ref(text.to_string()))
+    }
+}
+
+/// `$term_color(text, color)`: `text` in `color`, a name like `"red"` or `"bright_red"` or
+/// an Int from the 256 color palette.
+pub fn term_color(args: &[Value]) -> Result<Value, Value> {
+    match color_code(&args[1], 30) {
+        Some(code) => Ok(sgr(&args[0], &code)),
+        None => Err(error("term_color", &format!("unknown color {}", args[1]))),
+    }
+}
+
+/// `$term_bg(text, color)`: `text` on a background of `color`, see `$term_color`.
+pub fn term_bg(args: &[Value]) -> Result<Value, Value> {
+    match color_code(&args[1], 40) {
+        Some(code) => Ok(sgr(&args[0], &code)),
+        None => Err(error("term_bg", &format!("unknown color {}", args[1]))),
+    }
+}
+
+/// `$term_style(text, style)`: `text` in `"bold"`, `"dim"`, `"italic"`, `"underline"`,
+/// `"inverse"` or `"strike"`.
+pub fn term_style(args: &[Value]) -> Result<Value, Value> {
+    match style_code(&args[1].to_string()) {
+        Some(code) => Ok(sgr(&args[0], code)),
+        None => Err(error("term_style", &format!("unknown style {}", args[1]))),
+    }
+}
+
+/// `text` without escape sequences.
+pub fn strip(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            out.push(ch);
+            continue;
+        }
+        // CSI sequences end at a byte in `@`..`~`, other escapes are two characters long.
+        if chars.next() == Some('[') {
+            for ch in chars.by_ref() {
+                if ('@'..='~').contains(&ch) {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// `$term_strip(text)`: `text` without escape sequences, to measure or log it.
+pub fn term_strip(args: &[Value]) -> Result<Value, Value> {
+    Ok(Value::String(Ref(strip(&args[0].to_string()))))
+}
+
+pub fn term_colors(_: &[Value]) -> Result<Value, Value> {
+    Ok(Value::Bool(colors_enabled()))
+}
+
+/// `$term_set_colors(on)`: turns colors on or off whatever the terminal, `null` goes back to
+/// deciding from it.
+pub fn term_set_colors(args: &[Value]) -> Result<Value, Value> {
+    let colors = match &args[0] {
+        Value::Null => None,
+        value => Some(value.to_bool()),
+    };
+    COLORS.with(|current| current.set(colors));
+    Ok(Value::Null)
+}
+
+pub fn term_width(_: &[Value]) -> Result<Value, Value> {
+    let width = match window_size() {
+        Some((columns, _)) => columns,
+        None => size_from_env("COLUMNS", 80),
+    };
+    Ok(Value::Int(width))
+}
+
+pub fn term_height(_: &[Value]) -> Result<Value, Value> {
+    let height = match window_size() {
+        Some((_, rows)) => rows,
+        None => size_from_env("LINES", 24),
+    };
+    Ok(Value::Int(height))
+}
+
+/// Writes an escape sequence to standard output right away.
+fn escape(name: &str, sequence: &str) -> Result<Value, Value> {
+    let mut stdout = std::io::stdout();
+    stdout
+        .write_all(sequence.as_bytes())
+        .and_then(|_| stdout.flush())
+        .map_err(|e| error(name, &e.to_string()))?;
+    Ok(Value::Null)
+}
+
+fn count(value: &Value, name: &str) -> Result<i64, Value> {
+    match value {
+        Value::Int(x) if *x >= 0 => Ok(*x),
+        _ => Err(error(name, "non-negative Int expected")),
+    }
+}
+
+/// `$term_move(row, column)`: moves the cursor, counting from 1 at the top left.
+pub fn term_move(args: &[Value]) -> Result<Value, Value> {
+    let row = count(&args[0], "term_move")?;
+    let column = count(&args[1], "term_move")?;
+    escape("term_move", &format!("\x1b[{};{}H", row, column))
+}
+
+pub fn term_up(args: &[Value]) -> Result<Value, Value> {
+    let n = count(&args[0], "term_up")?;
+    escape("term_up", &format!("\x1b[{}A", n))
+}
+
+pub fn term_down(args: &[Value]) -> Result<Value, Value> {
+    let n = count(&args[0], "term_down")?;
+    escape("term_down", &format!("\x1b[{}B", n))
+}
+
+/// `$term_clear()`: clears the screen and moves the cursor to the top left.
+pub fn term_clear(_: &[Value]) -> Result<Value, Value> {
+    escape("term_clear", "\x1b[2J\x1b[H")
+}
+
+/// `$term_clear_line()`: clears the line of the cursor and moves it to the start, to redraw
+/// progress output.
+pub fn term_clear_line(_: &[Value]) -> Result<Value, Value> {
+    escape("term_clear_line", "\x1b[2K\r")
+}
+
+pub fn term_hide_cursor(_: &[Value]) -> Result<Value, Value> {
+    escape("term_hide_cursor", "\x1b[?25l")
+}
+
+pub fn term_show_cursor(_: &[Value]) -> Result<Value, Value> {
+    escape("term_show_cursor", "\x1b[?25h")
+}
+
+/// Prints `message` and reads a line of standard input without its line break, `None` at
+/// the end of input.
+fn read_answer(message: &Value, name: &str) -> Result<Option<String>, Value> {
+    let io_error = |e: std::io::Error| error(name, &e.to_string());
+    let mut stdout = std::io::stdout();
+    write!(stdout, "{}", message).map_err(io_error)?;
+    stdout.flush().map_err(io_error)?;
+    let mut line = String::new();
+    let stdin = std::io::stdin();
+    if stdin.lock().read_line(&mut line).map_err(io_error)? == 0 {
+        return Ok(None);
+    }
+    let len = line.trim_end_matches(|c| c == '\n' || c == '\r').len();
+    line.truncate(len);
+    Ok(Some(line))
+}
+
+/// `$prompt(message)`: prints `message` and returns the line typed, `null` at the end of
+/// input.
+pub fn term_prompt(args: &[Value]) -> Result<Value, Value> {
+    let line = read_answer(&args[0], "prompt")?;
+    Ok(line
+        .map(|line| Value::String(Ref(line)))
+        .unwrap_or(Value::Null))
+}
+
+/// Runs `f` with the terminal on standard input not echoing what's typed.
+#[cfg(unix)]
+fn without_echo<R>(f: impl FnOnce() -> R) -> R {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if !is_terminal(0) || unsafe { libc::tcgetattr(0, &mut termios) } != 0 {
+        return f();
+    }
+    let mut silent = termios;
+    silent.c_lflag &= !libc::ECHO;
+    unsafe { libc::tcsetattr(0, libc::TCSANOW, &silent) };
+    let result = f();
+    unsafe { libc::tcsetattr(0, libc::TCSANOW, &termios) };
+    result
+}
+
+#[cfg(not(unix))]
+fn without_echo<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+/// `$prompt_secret(message)`: `$prompt` without showing what's typed, for passwords and
+/// tokens.
+pub fn term_prompt_secret(args: &[Value]) -> Result<Value, Value> {
+    let line = without_echo(|| read_answer(&args[0], "prompt_secret"))?;
+    // The line break typed wasn't echoed either.
+    if is_terminal(0) {
+        println!();
+    }
+    Ok(line
+        .map(|line| Value::String(Ref(line)))
+        .unwrap_or(Value::Null))
+}
+
+/// `$confirm(message, default = false)`: asks a yes or no question until it gets an answer.
+/// An empty line or the end of input picks `default`.
+pub fn term_confirm(args: &[Value]) -> Result<Value, Value> {
+    let default = match args.len() {
+        1 => false,
+        2 => args[1].to_bool(),
+        _ => return Err(error("confirm", "Expected 1 or 2 arguments")),
+    };
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    let question = Value::String(Ref(format!("{} {} ", args[0], hint)));
+    loop {
+        let answer = match read_answer(&question, "confirm")? {
+            Some(answer) => answer.trim().to_lowercase(),
+            None => return Ok(Value::Bool(default)),
+        };
+        match answer.as_str() {
+            "" => return Ok(Value::Bool(default)),
+            "y" | "yes" => return Ok(Value::Bool(true)),
+            "n" | "no" => return Ok(Value::Bool(false)),
+            _ => continue,
+        }
+    }
+}
+
+use super::*;
+
+pub fn term_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("term_color".to_owned(), new_native_fn(term_color, 2));
+    map.insert("term_bg".to_owned(), new_native_fn(term_bg, 2));
+    map.insert("term_style".to_owned(), new_native_fn(term_style, 2));
+    map.insert("term_strip".to_owned(), new_native_fn(term_strip, 1));
+    map.insert("term_colors".to_owned(), new_native_fn(term_colors, 0));
+    map.insert(
+        "term_set_colors".to_owned(),
+        new_native_fn(term_set_colors, 1),
+    );
+    map.insert("term_width".to_owned(), new_native_fn(term_width, 0));
+    map.insert("term_height".to_owned(), new_native_fn(term_height, 0));
+    map.insert("term_move".to_owned(), new_native_fn(term_move, 2));
+    map.insert("term_up".to_owned(), new_native_fn(term_up, 1));
+    map.insert("term_down".to_owned(), new_native_fn(term_down, 1));
+    map.insert("term_clear".to_owned(), new_native_fn(term_clear, 0));
+    map.insert(
+        "term_clear_line".to_owned(),
+        new_native_fn(term_clear_line, 0),
+    );
+    map.insert(
+        "term_hide_cursor".to_owned(),
+        new_native_fn(term_hide_cursor, 0),
+    );
+    map.insert(
+        "term_show_cursor".to_owned(),
+        new_native_fn(term_show_cursor, 0),
+    );
+    map.insert("prompt".to_owned(), new_native_fn(term_prompt, 1));
+    map.insert(
+        "prompt_secret".to_owned(),
+        new_native_fn(term_prompt_secret, 1),
+    );
+    map.insert("confirm".to_owned(), new_native_fn(term_confirm, -1));
+}
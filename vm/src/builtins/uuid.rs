@@ -0,0 +1,193 @@
+use crate::*;
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Digits of ULIDs, Crockford's base 32.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+thread_local! {
+    static RANDOM: RandomState = RandomState::new();
+    static COUNTER: Cell<u64> = Cell::new(0);
+}
+
+/// Random bits from SipHash with the keys `RandomState` draws from the OS. Unique enough for
+/// identifiers, not meant for secrets.
+fn random_u64() -> u64 {
+    let count = COUNTER.with(|counter| {
+        counter.set(counter.get().wrapping_add(1));
+        counter.get()
+    });
+    let mut hasher = RANDOM.with(|random| random.build_hasher());
+    hasher.write_u64(count);
+    hasher.finish()
+}
+
+fn random_u128() -> u128 {
+    (random_u64() as u128) << 64 | random_u64() as u128
+}
+
+/// Milliseconds since the Unix epoch, 0 on wasm32 which has no system time.
+fn unix_millis() -> u64 {
+    if cfg!(target_arch = "wasm32") {
+        return 0;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// `x` in the 8-4-4-4-12 form, lowercase.
+fn format_uuid(x: u128) -> String {
+    let hex = format!("{:032x}", x);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Sets the version nibble and the RFC 4122 variant bits of `x`.
+fn with_version(x: u128, version: u128) -> u128 {
+    let x = x & !(0xf << 76) | version << 76;
+    x & !(0x3 << 62) | 0x2 << 62
+}
+
+/// Parses a UUID with or without hyphens, braces or a `urn:uuid:` prefix, in either case.
+fn parse_uuid(s: &str) -> Option<u128> {
+    let s = s.trim();
+    let urn = s
+        .get(..9)
+        .is_some_and(|p| p.eq_ignore_ascii_case("urn:uuid:"));
+    let s = if urn {
+        &s[9..]
+    } else if s.starts_with('{') && s.ends_with('}') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    };
+    let hex = match s.len() {
+        32 => s.to_owned(),
+        36 => {
+            let bytes = s.as_bytes();
+            if [8, 13, 18, 23].iter().any(|i| bytes[*i] != b'-') {
+                return None;
+            }
+            s.replace('-', "")
+        }
+        _ => return None,
+    };
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u128::from_str_radix(&hex, 16).ok()
+}
+
+fn format_ulid(x: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[(x >> (i * 5)) as usize & 31] as char)
+        .collect()
+}
+
+/// Parses a ULID in either case. The first digit holds only 3 bits, so it's at most `7`.
+fn parse_ulid(s: &str) -> Option<u128> {
+    if s.len() != 26 || s.as_bytes()[0] > b'7' {
+        return None;
+    }
+    let mut x = 0u128;
+    for b in s.bytes() {
+        let digit = CROCKFORD
+            .iter()
+            .position(|d| *d == b.to_ascii_uppercase())?;
+        x = x << 5 | digit as u128;
+    }
+    Some(x)
+}
+
+fn string_arg(args: &[Value], name: &str) -> Result<String, Value> {
+    match &args[0] {
+        Value::String(s) => Ok(s.borrow().clone()),
+        _ => Err(Value::String(Ref(format!("{}: String expected", name)))),
+    }
+}
+
+/// `$uuid_v4()`: a random UUID.
+pub fn uuid_v4(_: &[Value]) -> Result<Value, Value> {
+    let uuid = with_version(random_u128(), 4);
+    Ok(Value::String(Ref(format_uuid(uuid))))
+}
+
+/// `$uuid_v7()`: a UUID starting with the current Unix time in milliseconds, so later ones sort
+/// after earlier ones.
+pub fn uuid_v7(_: &[Value]) -> Result<Value, Value> {
+    let time = (unix_millis() as u128 & 0xffff_ffff_ffff) << 80;
+    let uuid = with_version(time | random_u128() >> 48, 7);
+    Ok(Value::String(Ref(format_uuid(uuid))))
+}
+
+/// `$uuid_parse(s)`: `s` in the lowercase hyphenated form, throws if it isn't a UUID.
+pub fn uuid_parse(args: &[Value]) -> Result<Value, Value> {
+    let s = string_arg(args, "uuid_parse")?;
+    match parse_uuid(&s) {
+        Some(uuid) => Ok(Value::String(Ref(format_uuid(uuid)))),
+        None => Err(Value::String(Ref(format!(
+            "uuid_parse: invalid UUID '{}'",
+            s
+        )))),
+    }
+}
+
+/// `$uuid_valid(s)`: whether `$uuid_parse(s)` would succeed.
+pub fn uuid_valid(args: &[Value]) -> Result<Value, Value> {
+    Ok(Value::Bool(match &args[0] {
+        Value::String(s) => parse_uuid(&s.borrow()).is_some(),
+        _ => false,
+    }))
+}
+
+/// `$ulid()`: the current Unix time in milliseconds and 80 random bits, in 26 digits of
+/// Crockford's base 32.
+pub fn uuid_ulid(_: &[Value]) -> Result<Value, Value> {
+    let time = (unix_millis() as u128 & 0xffff_ffff_ffff) << 80;
+    let ulid = time | random_u128() >> 48;
+    Ok(Value::String(Ref(format_ulid(ulid))))
+}
+
+/// `$ulid_valid(s)`: whether `s` is a ULID.
+pub fn uuid_ulid_valid(args: &[Value]) -> Result<Value, Value> {
+    Ok(Value::Bool(match &args[0] {
+        Value::String(s) => parse_ulid(&s.borrow()).is_some(),
+        _ => false,
+    }))
+}
+
+/// `$ulid_time(s)`: Unix time in milliseconds a ULID was made at, throws if `s` isn't one.
+pub fn uuid_ulid_time(args: &[Value]) -> Result<Value, Value> {
+    let s = string_arg(args, "ulid_time")?;
+    match parse_ulid(&s) {
+        Some(ulid) => Ok(Value::Int((ulid >> 80) as i64)),
+        None => Err(Value::String(Ref(format!(
+            "ulid_time: invalid ULID '{}'",
+            s
+        )))),
+    }
+}
+
+use super::*;
+
+pub fn uuid_builtins(map: &mut std::collections::HashMap<String, Value>) {
+    map.insert("uuid_v4".to_owned(), new_native_fn(uuid_v4, 0));
+    map.insert("uuid_v7".to_owned(), new_native_fn(uuid_v7, 0));
+    map.insert("uuid_parse".to_owned(), new_native_fn(uuid_parse, 1));
+    map.insert("uuid_valid".to_owned(), new_native_fn(uuid_valid, 1));
+    map.insert("ulid".to_owned(), new_native_fn(uuid_ulid, 0));
+    map.insert("ulid_valid".to_owned(), new_native_fn(uuid_ulid_valid, 1));
+    map.insert("ulid_time".to_owned(), new_native_fn(uuid_ulid_time, 1));
+}
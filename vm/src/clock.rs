@@ -0,0 +1,70 @@
+//! Time as timers and timeouts see it. Hosts without `std::time::Instant`, like browsers
+//! running wasm32-unknown-unknown, provide their own clock with `set_clock`.
+
+use crate::*;
+
+use std::time::{Duration, Instant};
+
+pub trait Clock {
+    /// Time elapsed since a fixed point, the same for every call.
+    fn now(&self) -> Duration;
+    /// Blocks for `duration`. Hosts that can't block return right away, timers then fire
+    /// early.
+    fn sleep(&self, duration: Duration);
+}
+
+/// Clock of the operating system.
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock(Instant::now())
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// Clock that never moves, the default on wasm32 until the host sets one.
+pub struct StoppedClock;
+
+impl Clock for StoppedClock {
+    fn now(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn sleep(&self, _: Duration) {}
+}
+
+fn default_clock() -> Rc<dyn Clock> {
+    if cfg!(target_arch = "wasm32") {
+        Rc::new(StoppedClock)
+    } else {
+        Rc::new(SystemClock::new())
+    }
+}
+
+thread_local! {
+    static CLOCK: RefCell<Rc<dyn Clock>> = RefCell::new(default_clock());
+}
+
+/// Makes the VM on this thread use `clock`.
+pub fn set_clock(clock: impl Clock + 'static) {
+    CLOCK.with(|current| *current.borrow_mut() = Rc::new(clock));
+}
+
+pub fn now() -> Duration {
+    CLOCK.with(|clock| clock.borrow().now())
+}
+
+pub fn sleep(duration: Duration) {
+    let clock = CLOCK.with(|clock| clock.borrow().clone());
+    clock.sleep(duration)
+}
@@ -0,0 +1,367 @@
+//! Decimal numbers, for amounts of money and anything else a Float can't hold exactly.
+//!
+//! A `Decimal` is an integer of any size scaled down by a power of ten, `12.50` is 1250 with
+//! a scale of 2. Addition, subtraction, multiplication and remainder are exact, division
+//! rounds the quotient to a number of decimal places.
+
+use num_bigint::BigInt;
+use num_traits::{Pow, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Decimal places `/` keeps before trailing zeros are dropped.
+pub const DIVISION_SCALE: u32 = 16;
+
+#[derive(Clone, Debug)]
+pub struct Decimal {
+    pub digits: BigInt,
+    /// Number of digits after the decimal point.
+    pub scale: u32,
+}
+
+/// How a result is rounded to fewer decimal places.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rounding {
+    /// To the nearest, ties to the even neighbor.
+    HalfEven,
+    /// To the nearest, ties away from zero.
+    HalfUp,
+    /// Toward zero.
+    Down,
+    /// Away from zero.
+    Up,
+    /// Toward negative infinity.
+    Floor,
+    /// Toward positive infinity.
+    Ceiling,
+}
+
+impl Rounding {
+    pub fn from_name(name: &str) -> Option<Rounding> {
+        Some(match name {
+            "half_even" => Rounding::HalfEven,
+            "half_up" => Rounding::HalfUp,
+            "down" => Rounding::Down,
+            "up" => Rounding::Up,
+            "floor" => Rounding::Floor,
+            "ceiling" => Rounding::Ceiling,
+            _ => return None,
+        })
+    }
+}
+
+fn pow10(n: u32) -> BigInt {
+    BigInt::from(10).pow(n)
+}
+
+/// `num / den` rounded to an integer as `rounding` says. `den` must not be zero.
+fn divide(num: &BigInt, den: &BigInt, rounding: Rounding) -> BigInt {
+    let quotient = num / den;
+    let remainder = num % den;
+    if remainder.is_zero() {
+        return quotient;
+    }
+    let negative = num.is_negative() != den.is_negative();
+    let away = if negative {
+        &quotient - BigInt::from(1)
+    } else {
+        &quotient + BigInt::from(1)
+    };
+    let half = (remainder.abs() * BigInt::from(2)).cmp(&den.abs());
+    let round_away = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => true,
+        Rounding::Floor => negative,
+        Rounding::Ceiling => !negative,
+        Rounding::HalfUp => half != Ordering::Less,
+        Rounding::HalfEven => match half {
+            Ordering::Equal => !(&quotient % BigInt::from(2)).is_zero(),
+            half => half == Ordering::Greater,
+        },
+    };
+    if round_away {
+        away
+    } else {
+        quotient
+    }
+}
+
+impl Decimal {
+    /// Parses `[+-]digits[.digits]`.
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let (negative, unsigned) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (int, frac) = match unsigned.find('.') {
+            Some(dot) => (&unsigned[..dot], &unsigned[dot + 1..]),
+            None => (unsigned, ""),
+        };
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if int.is_empty() || !all_digits(int) || !all_digits(frac) {
+            return None;
+        }
+        let digits = BigInt::parse_bytes(format!("{}{}", int, frac).as_bytes(), 10)?;
+        Some(Decimal {
+            digits: if negative { -digits } else { digits },
+            scale: frac.len() as u32,
+        })
+    }
+
+    pub fn from_bigint(x: BigInt) -> Decimal {
+        Decimal {
+            digits: x,
+            scale: 0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.digits.is_zero()
+    }
+
+    /// Digits of `self` with `scale` decimal places, which must be at least `self.scale`.
+    fn digits_at(&self, scale: u32) -> BigInt {
+        &self.digits * pow10(scale - self.scale)
+    }
+
+    /// Digits of both operands at the larger of their scales, and that scale.
+    fn align(&self, other: &Decimal) -> (BigInt, BigInt, u32) {
+        let scale = self.scale.max(other.scale);
+        (self.digits_at(scale), other.digits_at(scale), scale)
+    }
+
+    pub fn add(&self, other: &Decimal) -> Decimal {
+        let (x, y, scale) = self.align(other);
+        Decimal {
+            digits: x + y,
+            scale,
+        }
+    }
+
+    pub fn sub(&self, other: &Decimal) -> Decimal {
+        let (x, y, scale) = self.align(other);
+        Decimal {
+            digits: x - y,
+            scale,
+        }
+    }
+
+    pub fn mul(&self, other: &Decimal) -> Decimal {
+        Decimal {
+            digits: &self.digits * &other.digits,
+            scale: self.scale + other.scale,
+        }
+    }
+
+    /// Remainder of truncating division, with the sign of `self`. `None` if `other` is zero.
+    pub fn rem(&self, other: &Decimal) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        let (x, y, scale) = self.align(other);
+        Some(Decimal {
+            digits: x % y,
+            scale,
+        })
+    }
+
+    /// `self / other` with `scale` decimal places. `None` if `other` is zero.
+    pub fn div(&self, other: &Decimal, scale: u32, rounding: Rounding) -> Option<Decimal> {
+        if other.is_zero() {
+            return None;
+        }
+        // self / other * 10^scale = digits * 10^(other.scale + scale) / (other.digits * 10^self.scale)
+        let num = &self.digits * pow10(other.scale + scale);
+        let den = &other.digits * pow10(self.scale);
+        Some(Decimal {
+            digits: divide(&num, &den, rounding),
+            scale,
+        })
+    }
+
+    /// `self` with `scale` decimal places, padded with zeros or rounded.
+    pub fn round(&self, scale: u32, rounding: Rounding) -> Decimal {
+        if scale >= self.scale {
+            return Decimal {
+                digits: self.digits_at(scale),
+                scale,
+            };
+        }
+        Decimal {
+            digits: divide(&self.digits, &pow10(self.scale - scale), rounding),
+            scale,
+        }
+    }
+
+    /// `self` without trailing zeros after the decimal point.
+    pub fn normalize(&self) -> Decimal {
+        let ten = BigInt::from(10);
+        let mut digits = self.digits.clone();
+        let mut scale = self.scale;
+        while scale > 0 && (&digits % &ten).is_zero() {
+            digits /= &ten;
+            scale -= 1;
+        }
+        Decimal { digits, scale }
+    }
+
+    pub fn neg(&self) -> Decimal {
+        Decimal {
+            digits: -&self.digits,
+            scale: self.scale,
+        }
+    }
+
+    pub fn to_f64(&self) -> Option<f64> {
+        self.to_string().parse().ok()
+    }
+
+    /// Integer part, truncated toward zero.
+    pub fn to_i64(&self) -> Option<i64> {
+        (&self.digits / pow10(self.scale)).to_i64()
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        let (x, y, _) = self.align(other);
+        x.cmp(&y)
+    }
+}
+
+impl Hash for Decimal {
+    /// Equal decimals with different scales hash the same.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let normal = self.normalize();
+        normal.digits.hash(state);
+        normal.scale.hash(state);
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.digits.abs().to_string();
+        let scale = self.scale as usize;
+        let digits = if digits.len() <= scale {
+            format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+        } else {
+            digits
+        };
+        if self.digits.is_negative() {
+            write!(f, "-")?;
+        }
+        let (int, frac) = digits.split_at(digits.len() - scale);
+        if frac.is_empty() {
+            write!(f, "{}", int)
+        } else {
+            write!(f, "{}.{}", int, frac)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        Decimal::parse(s).unwrap()
+    }
+
+    #[test]
+    fn add_sub_and_mul_are_exact() {
+        assert_eq!(dec("0.1").add(&dec("0.2")).to_string(), "0.3");
+        assert_eq!(dec("1.10").add(&dec("2.205")).to_string(), "3.305");
+        assert_eq!(
+            dec("12345678901234567890.12345")
+                .add(&dec("0.00001"))
+                .to_string(),
+            "12345678901234567890.12346"
+        );
+        assert_eq!(dec("0.3").sub(&dec("0.1")).to_string(), "0.2");
+        assert_eq!(dec("1").sub(&dec("0.001")).to_string(), "0.999");
+        assert_eq!(dec("-5.5").sub(&dec("4.5")).to_string(), "-10.0");
+        assert_eq!(dec("1.1").mul(&dec("1.1")).to_string(), "1.21");
+        assert_eq!(dec("-0.05").mul(&dec("20")).to_string(), "-1.00");
+        assert_eq!(
+            dec("99999999999999999999")
+                .mul(&dec("0.99999999999999999999"))
+                .to_string(),
+            "99999999999999999998.00000000000000000001"
+        );
+        // Ten additions of a tenth make exactly one, unlike with Floats.
+        let mut sum = dec("0");
+        for _ in 0..10 {
+            sum = sum.add(&dec("0.1"));
+        }
+        assert_eq!(sum, dec("1"));
+    }
+
+    #[test]
+    fn division_rounds_as_told() {
+        let modes = [
+            Rounding::HalfEven,
+            Rounding::HalfUp,
+            Rounding::Down,
+            Rounding::Up,
+            Rounding::Floor,
+            Rounding::Ceiling,
+        ];
+        // A dividend and its quotient by 1 with no decimal places in each mode above.
+        let table: &[(&str, [i64; 6])] = &[
+            ("2.5", [2, 3, 2, 3, 2, 3]),
+            ("3.5", [4, 4, 3, 4, 3, 4]),
+            ("2.6", [3, 3, 2, 3, 2, 3]),
+            ("2.4", [2, 2, 2, 3, 2, 3]),
+            ("-2.5", [-2, -3, -2, -3, -3, -2]),
+            ("-3.5", [-4, -4, -3, -4, -4, -3]),
+            ("-2.6", [-3, -3, -2, -3, -3, -2]),
+            ("-2.4", [-2, -2, -2, -3, -3, -2]),
+            ("7", [7, 7, 7, 7, 7, 7]),
+        ];
+        for (dividend, expected) in table {
+            for (rounding, expected) in modes.iter().zip(expected) {
+                let quotient = dec(dividend).div(&dec("1"), 0, *rounding).unwrap();
+                assert_eq!(
+                    quotient.to_i64(),
+                    Some(*expected),
+                    "{} rounded {:?}",
+                    dividend,
+                    rounding
+                );
+            }
+        }
+
+        let third = |x: &str, rounding| dec(x).div(&dec("3"), 4, rounding).unwrap().to_string();
+        assert_eq!(third("1", Rounding::HalfEven), "0.3333");
+        assert_eq!(third("1", Rounding::Up), "0.3334");
+        assert_eq!(third("-2", Rounding::HalfUp), "-0.6667");
+        assert_eq!(third("-2", Rounding::Down), "-0.6666");
+        assert_eq!(third("-2", Rounding::Floor), "-0.6667");
+        assert_eq!(third("-2", Rounding::Ceiling), "-0.6666");
+        // The divisor's scale counts as much as the dividend's.
+        assert_eq!(
+            dec("1")
+                .div(&dec("0.08"), 2, Rounding::Down)
+                .unwrap()
+                .to_string(),
+            "12.50"
+        );
+        assert!(dec("1").div(&dec("0.00"), 2, Rounding::HalfEven).is_none());
+    }
+}
@@ -0,0 +1,499 @@
+//! DEFLATE compression (RFC 1951) and the gzip container around it (RFC 1952), for archives.
+//!
+//! Decompression handles every block type. Compression finds repeats with a single-entry
+//! hash table and codes them with the fixed Huffman codes, which keeps it short at the cost
+//! of a somewhat larger output than zlib's.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Most bytes decompressing one stream may produce unless the caller knows a smaller bound,
+/// so a few crafted bytes can't make it exhaust memory.
+pub const MAX_INFLATED: usize = 1 << 30;
+/// Order code length code lengths are stored in by dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+lazy_static::lazy_static! {
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        table
+    };
+}
+
+/// CRC-32 of `data`, as zip and gzip use it.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc = CRC_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn need(&mut self, n: u32) -> Result<(), String> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or("unexpected end of data")?;
+            self.bits |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        if n == 0 {
+            return Ok(0);
+        }
+        self.need(n)?;
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left of the current byte.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code, as the number of codes of each length and the symbols in code
+/// order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<u16, String> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for len in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_owned())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let nlen = input.bits(5)? as usize + 257;
+    let ndist = input.bits(5)? as usize + 1;
+    let ncode = input.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for i in 0..ncode {
+        lengths[CODE_LENGTH_ORDER[i]] = input.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths);
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < nlen + ndist {
+        let (len, repeat) = match code_lengths.decode(input)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 if i == 0 => return Err("repeat with no previous length".to_owned()),
+            16 => (lengths[i - 1], 3 + input.bits(2)?),
+            17 => (0, 3 + input.bits(3)?),
+            _ => (0, 11 + input.bits(7)?),
+        };
+        if i + repeat as usize > nlen + ndist {
+            return Err("too many code lengths".to_owned());
+        }
+        for _ in 0..repeat {
+            lengths[i] = len;
+            i += 1;
+        }
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+fn too_large(limit: usize) -> String {
+    format!("data inflates to more than {} bytes", limit)
+}
+
+fn inflate_block(
+    input: &mut BitReader,
+    out: &mut Vec<u8>,
+    lengths: &Huffman,
+    dists: &Huffman,
+    limit: usize,
+) -> Result<(), String> {
+    loop {
+        let symbol = lengths.decode(input)? as usize;
+        if symbol < 256 {
+            if out.len() == limit {
+                return Err(too_large(limit));
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err("invalid length code".to_owned());
+        }
+        let len = LENGTH_BASE[symbol] as usize + input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+        let symbol = dists.decode(input)? as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err("invalid distance code".to_owned());
+        }
+        let dist = DIST_BASE[symbol] as usize + input.bits(DIST_EXTRA[symbol] as u32)? as usize;
+        if dist > out.len() {
+            return Err("distance too far back".to_owned());
+        }
+        if len > limit - out.len() {
+            return Err(too_large(limit));
+        }
+        let start = out.len() - dist;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+/// Decompresses the DEFLATE stream at the start of `data`, returning it and the number of
+/// bytes it took. Fails once the output would grow past `limit` bytes.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut input = BitReader {
+        data,
+        pos: 0,
+        bits: 0,
+        count: 0,
+    };
+    let mut out = vec![];
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let header = data
+                    .get(input.pos..input.pos + 4)
+                    .ok_or("unexpected end of data")?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                if len != !u16::from_le_bytes([header[2], header[3]]) as usize {
+                    return Err("corrupt stored block length".to_owned());
+                }
+                let start = input.pos + 4;
+                let block = data
+                    .get(start..start + len)
+                    .ok_or("unexpected end of data")?;
+                if len > limit - out.len() {
+                    return Err(too_large(limit));
+                }
+                out.extend_from_slice(block);
+                input.pos = start + len;
+            }
+            1 => {
+                let (lengths, dists) = fixed_codes();
+                inflate_block(&mut input, &mut out, &lengths, &dists, limit)?;
+            }
+            2 => {
+                let (lengths, dists) = dynamic_codes(&mut input)?;
+                inflate_block(&mut input, &mut out, &lengths, &dists, limit)?;
+            }
+            _ => return Err("invalid block type".to_owned()),
+        }
+        if last {
+            return Ok((out, input.pos));
+        }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes go most significant bit first.
+    fn put_code(&mut self, code: u32, n: u32) {
+        self.put(code.reverse_bits() >> (32 - n), n)
+    }
+
+    fn literal(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, len: usize, dist: usize) {
+        let code = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base as usize <= len)
+            .unwrap();
+        self.literal(257 + code as u32);
+        self.put(
+            (len - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        );
+        let code = DIST_BASE
+            .iter()
+            .rposition(|base| *base as usize <= dist)
+            .unwrap();
+        self.put_code(code as u32, 5);
+        self.put(
+            (dist - DIST_BASE[code] as usize) as u32,
+            DIST_EXTRA[code] as u32,
+        );
+    }
+}
+
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+fn hash(data: &[u8]) -> usize {
+    let x = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (x.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses `data` into a single DEFLATE block.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        bits: 0,
+        count: 0,
+    };
+    // Final block with fixed codes.
+    out.put(1, 1);
+    out.put(1, 2);
+    let mut last_seen = vec![usize::max_value(); 1 << HASH_BITS];
+    let mut i = 0;
+    while i < data.len() {
+        if i + 3 <= data.len() {
+            let slot = &mut last_seen[hash(&data[i..])];
+            let candidate = std::mem::replace(slot, i);
+            if candidate != usize::max_value() && i - candidate <= WINDOW {
+                let max = MAX_MATCH.min(data.len() - i);
+                let len = (0..max)
+                    .take_while(|k| data[candidate + k] == data[i + k])
+                    .count();
+                if len >= 3 {
+                    out.copy(len, i - candidate);
+                    i += len;
+                    continue;
+                }
+            }
+        }
+        out.literal(data[i] as u32);
+        i += 1;
+    }
+    out.literal(256);
+    if out.count > 0 {
+        out.out.push(out.bits as u8);
+    }
+    out.out
+}
+
+/// `data` compressed in a gzip member.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, deflate, no flags, no time, no extra flags, unknown OS.
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+    out.extend(deflate(data));
+    out.extend(&crc32(data).to_le_bytes());
+    out.extend(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Contents of the gzip members in `data`, one after the other, at most `limit` bytes of
+/// them.
+pub fn gunzip(mut data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    while !data.is_empty() {
+        if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+            return Err("not gzip data".to_owned());
+        }
+        let flags = data[3];
+        let mut pos = 10;
+        let truncated = || "unexpected end of data".to_owned();
+        if flags & 4 != 0 {
+            let extra = data.get(pos..pos + 2).ok_or_else(truncated)?;
+            pos += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        // File name and comment, both zero terminated.
+        for flag in &[8, 16] {
+            if flags & flag != 0 {
+                let end = data[pos.min(data.len())..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .ok_or_else(truncated)?;
+                pos += end + 1;
+            }
+        }
+        if flags & 2 != 0 {
+            pos += 2;
+        }
+        let rest = data.get(pos..).ok_or_else(truncated)?;
+        // Members share the limit, which is the one to report.
+        let left = limit - out.len();
+        let (member, used) = inflate(rest, left).map_err(|e| match e {
+            _ if e == too_large(left) => too_large(limit),
+            _ => e,
+        })?;
+        let trailer = data.get(pos + used..pos + used + 8).ok_or_else(truncated)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&member) || size != member.len() as u32 {
+            return Err("gzip checksum mismatch".to_owned());
+        }
+        out.extend(member);
+        data = &data[pos + used + 8..];
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Vec<u8>> {
+        vec![
+            vec![],
+            b"a".to_vec(),
+            b"hello hello hello hello".to_vec(),
+            (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect(),
+        ]
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        for data in samples() {
+            assert_eq!(gunzip(&gzip(&data), MAX_INFLATED).unwrap(), data);
+        }
+        let members = [gzip(b"one "), gzip(b"two")].concat();
+        assert_eq!(gunzip(&members, MAX_INFLATED).unwrap(), b"one two");
+    }
+
+    #[test]
+    fn truncated_gzip_is_an_error() {
+        for data in samples() {
+            let gz = gzip(&data);
+            for len in 1..gz.len() {
+                assert!(gunzip(&gz[..len], MAX_INFLATED).is_err(), "cut at {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_gzip_is_an_error() {
+        let gz = gzip(b"hello hello hello hello");
+        // Only the time, the extra flags and the OS in the header may change.
+        for i in 10..gz.len() {
+            let mut corrupt = gz.clone();
+            corrupt[i] ^= 0xff;
+            assert!(
+                gunzip(&corrupt, MAX_INFLATED).is_err(),
+                "byte {} flipped",
+                i
+            );
+        }
+        assert!(gunzip(b"not gzip at all, really", MAX_INFLATED).is_err());
+        assert!(inflate(&[0x07], MAX_INFLATED).is_err());
+    }
+
+    #[test]
+    fn inflating_past_the_limit_is_an_error() {
+        let zeros = vec![0; 1 << 20];
+        let packed = deflate(&zeros);
+        assert!(packed.len() < 1 << 14);
+        assert_eq!(inflate(&packed, 1 << 20).unwrap().0, zeros);
+        assert_eq!(inflate(&packed, 1000), Err(too_large(1000)));
+
+        // Members of a gzip share the limit.
+        let gz = [gzip(&zeros), gzip(&zeros)].concat();
+        let limit = 3 << 19;
+        assert_eq!(gunzip(&gz, limit), Err(too_large(limit)));
+
+        // Stored blocks count too.
+        let stored = [&[1, 5, 0, 0xfa, 0xff][..], b"hello"].concat();
+        assert_eq!(inflate(&stored, 5).unwrap().0, b"hello");
+        assert_eq!(inflate(&stored, 4), Err(too_large(4)));
+    }
+}
@@ -0,0 +1,257 @@
+//! Element storage of arrays.
+//!
+//! An array holding only integers or only floats keeps them unboxed, half the size of a
+//! `Value` each. The first element of another kind moves the array to a vector of values for
+//! good, except that an empty array picks its storage again on the next insert. Scripts can't
+//! tell the representations apart: elements are read back as the values stored.
+
+use crate::value::Value;
+
+#[derive(Clone)]
+pub struct Elements {
+    storage: Storage,
+    /// Incremented when an element is added or removed, so iterators can detect the change.
+    pub modcount: usize,
+}
+
+#[derive(Clone)]
+enum Storage {
+    Ints(Vec<i64>),
+    Floats(Vec<f64>),
+    Values(Vec<Value>),
+}
+
+impl Default for Elements {
+    fn default() -> Elements {
+        Elements::from_storage(Storage::Ints(vec![]))
+    }
+}
+
+impl Elements {
+    pub fn new() -> Elements {
+        Elements::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Elements {
+        Elements::from_storage(Storage::Ints(Vec::with_capacity(capacity)))
+    }
+
+    fn from_storage(storage: Storage) -> Elements {
+        Elements {
+            storage,
+            modcount: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.storage {
+            Storage::Ints(ints) => ints.len(),
+            Storage::Floats(floats) => floats.len(),
+            Storage::Values(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn capacity(&self) -> usize {
+        match &self.storage {
+            Storage::Ints(ints) => ints.capacity(),
+            Storage::Floats(floats) => floats.capacity(),
+            Storage::Values(values) => values.capacity(),
+        }
+    }
+
+    /// Name of the storage in use, for tracing.
+    pub fn kind(&self) -> &'static str {
+        match &self.storage {
+            Storage::Ints(_) => "ints",
+            Storage::Floats(_) => "floats",
+            Storage::Values(_) => "values",
+        }
+    }
+
+    /// Position of `index`, counted from the end if it's negative. `None` if it's out of
+    /// bounds either way.
+    pub fn position(&self, index: i64) -> Option<usize> {
+        let len = self.len() as i64;
+        let index = if index < 0 { index + len } else { index };
+        if (0..len).contains(&index) {
+            Some(index as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value> {
+        match &self.storage {
+            Storage::Ints(ints) => ints.get(index).map(|x| Value::Int(*x)),
+            Storage::Floats(floats) => floats.get(index).map(|x| Value::Float(*x)),
+            Storage::Values(values) => values.get(index).cloned(),
+        }
+    }
+
+    /// Makes room for `value`, moving to a vector of values if the storage can't hold it.
+    fn accept(&mut self, value: &Value) {
+        let fits = match (&self.storage, value) {
+            (Storage::Ints(_), Value::Int(_)) => true,
+            (Storage::Floats(_), Value::Float(_)) => true,
+            (Storage::Values(_), _) => true,
+            _ => false,
+        };
+        if fits {
+            return;
+        }
+        if self.is_empty() {
+            let capacity = self.capacity();
+            self.storage = match value {
+                Value::Int(_) => Storage::Ints(Vec::with_capacity(capacity)),
+                Value::Float(_) => Storage::Floats(Vec::with_capacity(capacity)),
+                _ => Storage::Values(Vec::with_capacity(capacity)),
+            };
+            return;
+        }
+        self.storage = Storage::Values(self.to_vec());
+    }
+
+    /// Replaces the element at `index`, which must be in bounds.
+    pub fn set(&mut self, index: usize, value: Value) {
+        self.accept(&value);
+        match (&mut self.storage, value) {
+            (Storage::Ints(ints), Value::Int(x)) => ints[index] = x,
+            (Storage::Floats(floats), Value::Float(x)) => floats[index] = x,
+            (Storage::Values(values), value) => values[index] = value,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.accept(&value);
+        self.modcount += 1;
+        match (&mut self.storage, value) {
+            (Storage::Ints(ints), Value::Int(x)) => ints.push(x),
+            (Storage::Floats(floats), Value::Float(x)) => floats.push(x),
+            (Storage::Values(values), value) => values.push(value),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Inserts `value` before `index`, which must be at most the length.
+    pub fn insert(&mut self, index: usize, value: Value) {
+        self.accept(&value);
+        self.modcount += 1;
+        match (&mut self.storage, value) {
+            (Storage::Ints(ints), Value::Int(x)) => ints.insert(index, x),
+            (Storage::Floats(floats), Value::Float(x)) => floats.insert(index, x),
+            (Storage::Values(values), value) => values.insert(index, value),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        self.modcount += 1;
+        match &mut self.storage {
+            Storage::Ints(ints) => ints.pop().map(Value::Int),
+            Storage::Floats(floats) => floats.pop().map(Value::Float),
+            Storage::Values(values) => values.pop(),
+        }
+    }
+
+    /// Removes the element at `index`, which must be in bounds.
+    pub fn remove(&mut self, index: usize) -> Value {
+        self.modcount += 1;
+        match &mut self.storage {
+            Storage::Ints(ints) => Value::Int(ints.remove(index)),
+            Storage::Floats(floats) => Value::Float(floats.remove(index)),
+            Storage::Values(values) => values.remove(index),
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.modcount += 1;
+        match &mut self.storage {
+            Storage::Ints(ints) => ints.truncate(len),
+            Storage::Floats(floats) => floats.truncate(len),
+            Storage::Values(values) => values.truncate(len),
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            elements: self,
+            front: 0,
+            back: self.len(),
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<Value> {
+        self.iter().collect()
+    }
+}
+
+impl From<Vec<Value>> for Elements {
+    /// Unboxes the values if they are all integers or all floats.
+    fn from(values: Vec<Value>) -> Elements {
+        Elements::from_storage(
+            if values.iter().all(|value| matches!(value, Value::Int(_))) {
+                Storage::Ints(values.iter().filter_map(Value::to_int).collect())
+            } else if values.iter().all(|value| matches!(value, Value::Float(_))) {
+                Storage::Floats(values.iter().filter_map(Value::to_float).collect())
+            } else {
+                Storage::Values(values)
+            },
+        )
+    }
+}
+
+impl std::iter::FromIterator<Value> for Elements {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Elements {
+        Elements::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl PartialEq for Elements {
+    fn eq(&self, other: &Elements) -> bool {
+        match (&self.storage, &other.storage) {
+            (Storage::Ints(x), Storage::Ints(y)) => x == y,
+            _ => self.len() == other.len() && self.iter().eq(other.iter()),
+        }
+    }
+}
+
+/// Elements of an array by value, see `Elements::iter`.
+pub struct Iter<'a> {
+    elements: &'a Elements,
+    front: usize,
+    back: usize,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        if self.front == self.back {
+            return None;
+        }
+        self.front += 1;
+        self.elements.get(self.front - 1)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.back - self.front;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Value> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        self.elements.get(self.back)
+    }
+}
@@ -0,0 +1,147 @@
+//! Type feedback and quickening.
+//!
+//! The first time an arithmetic, comparison or `Load` instruction runs, the interpreter
+//! records the types of its operands and, when they fit, rewrites the instruction in place
+//! to a variant that handles only those types. A quickened instruction whose guess turns out
+//! wrong turns back into the generic one, which then stays generic and keeps recording. What
+//! each site saw is kept in `Module::feedback` for a compiler specializing the code.
+
+use crate::opcode::Op;
+use crate::value::Value;
+
+/// Set of operand types, one bit per kind of value.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct TypeSet(u16);
+
+impl TypeSet {
+    pub const NULL: TypeSet = TypeSet(1);
+    pub const BOOL: TypeSet = TypeSet(1 << 1);
+    pub const INT: TypeSet = TypeSet(1 << 2);
+    pub const FLOAT: TypeSet = TypeSet(1 << 3);
+    pub const STRING: TypeSet = TypeSet(1 << 4);
+    pub const ARRAY: TypeSet = TypeSet(1 << 5);
+    pub const OBJECT: TypeSet = TypeSet(1 << 6);
+    pub const FUNCTION: TypeSet = TypeSet(1 << 7);
+    pub const CHAR: TypeSet = TypeSet(1 << 8);
+    pub const BIGINT: TypeSet = TypeSet(1 << 9);
+    pub const USER: TypeSet = TypeSet(1 << 10);
+    pub const DECIMAL: TypeSet = TypeSet(1 << 11);
+
+    pub fn of(value: &Value) -> TypeSet {
+        match value {
+            Value::Null => TypeSet::NULL,
+            Value::Bool(_) => TypeSet::BOOL,
+            Value::Int(_) => TypeSet::INT,
+            Value::Float(_) => TypeSet::FLOAT,
+            Value::String(_) => TypeSet::STRING,
+            Value::Array(_) => TypeSet::ARRAY,
+            Value::Object(_) => TypeSet::OBJECT,
+            Value::Function(_) => TypeSet::FUNCTION,
+            Value::Char(_) => TypeSet::CHAR,
+            Value::BigInt(_) => TypeSet::BIGINT,
+            Value::Decimal(_) => TypeSet::DECIMAL,
+            Value::User(_) => TypeSet::USER,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Every type in `self` is in `other`.
+    pub fn within(self, other: TypeSet) -> bool {
+        self.0 & !other.0 == 0
+    }
+}
+
+impl std::ops::BitOr for TypeSet {
+    type Output = TypeSet;
+    fn bitor(self, other: TypeSet) -> TypeSet {
+        TypeSet(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for TypeSet {
+    fn bitor_assign(&mut self, other: TypeSet) {
+        self.0 |= other.0;
+    }
+}
+
+/// What one instruction has seen.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Feedback {
+    /// Types of the operand popped first: the left operand, or the receiver of a `Load`.
+    pub lhs: TypeSet,
+    /// Types of the operand popped second: the right operand, or the key of a `Load`.
+    pub rhs: TypeSet,
+    /// A quickened variant failed its guard, the instruction isn't quickened again.
+    pub generic: bool,
+}
+
+impl Feedback {
+    pub fn record(&mut self, lhs: &Value, rhs: &Value) {
+        self.lhs |= TypeSet::of(lhs);
+        self.rhs |= TypeSet::of(rhs);
+    }
+
+    /// The operands were only ever numbers, so the instruction can work on them directly.
+    pub fn numeric(&self) -> bool {
+        let numbers = TypeSet::INT | TypeSet::FLOAT;
+        !self.lhs.is_empty() && self.lhs.within(numbers) && self.rhs.within(numbers)
+    }
+}
+
+/// Specialized variant of `op` for what `feedback` recorded, if there is one.
+pub fn quicken(op: &Op, feedback: &Feedback) -> Option<Quick> {
+    if feedback.generic {
+        return None;
+    }
+    let numeric = feedback.numeric();
+    Some(match op {
+        Op::Add if numeric => Quick::AddNum,
+        Op::Sub if numeric => Quick::SubNum,
+        Op::Mul if numeric => Quick::MulNum,
+        Op::Gt if numeric => Quick::GtNum,
+        Op::Gte if numeric => Quick::GteNum,
+        Op::Lt if numeric => Quick::LtNum,
+        Op::Lte if numeric => Quick::LteNum,
+        Op::Load if feedback.lhs == TypeSet::OBJECT && feedback.rhs == TypeSet::STRING => {
+            Quick::LoadField
+        }
+        Op::Load if feedback.lhs == TypeSet::ARRAY && feedback.rhs == TypeSet::INT => {
+            Quick::LoadIndex
+        }
+        _ => return None,
+    })
+}
+
+/// Specialized variant an instruction is quickened to. It has no operands, so it fits in a
+/// `Cell` next to the instruction and the interpreter reads it without borrowing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Quick {
+    AddNum,
+    SubNum,
+    MulNum,
+    GtNum,
+    GteNum,
+    LtNum,
+    LteNum,
+    LoadField,
+    LoadIndex,
+}
+
+impl Quick {
+    pub fn op(self) -> Op {
+        match self {
+            Quick::AddNum => Op::AddNum,
+            Quick::SubNum => Op::SubNum,
+            Quick::MulNum => Op::MulNum,
+            Quick::GtNum => Op::GtNum,
+            Quick::GteNum => Op::GteNum,
+            Quick::LtNum => Op::LtNum,
+            Quick::LteNum => Op::LteNum,
+            Quick::LoadField => Op::LoadField,
+            Quick::LoadIndex => Op::LoadIndex,
+        }
+    }
+}
@@ -283,7 +283,7 @@ macro_rules! trace_for_simple {
 
 trace_for_simple!(u8, u16, u32, u64, bool, i8, i16, i32, i64, i128, u128, f32, f64, String);
 
-impl<K: Trace, V: Trace> Trace for std::collections::HashMap<K, V> {
+impl<K: Trace, V: Trace, S> Trace for std::collections::HashMap<K, V, S> {
     fn trace(&self, tracer: &mut Tracer) {
         for (key, val) in self.iter() {
             key.trace(tracer);
@@ -292,7 +292,7 @@ impl<K: Trace, V: Trace> Trace for std::collections::HashMap<K, V> {
     }
 }
 
-impl<K: Trace, V: Trace> Trace for hashlink::LinkedHashMap<K, V> {
+impl<K: Trace, V: Trace, S> Trace for hashlink::LinkedHashMap<K, V, S> {
     fn trace(&self, tracer: &mut Tracer) {
         for (key, val) in self.iter() {
             key.trace(tracer);
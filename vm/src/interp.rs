@@ -10,19 +10,119 @@ pub enum Infos {
         Value,
         Value,
         Ref<HashMap<u16, Value>>,
+        /// Operand stack depth to restore when the frame is left.
+        usize,
     ),
 }
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 pub struct Vm {
     pub pc: usize,
     pub stack: Ref<Vec<Value>>,
-    pub exception_stack: Vec<(usize, Infos)>,
+    /// Handler address, state to restore (including the operand stack height) and the call
+    /// depth at the time of `CatchPush`.
+    pub exception_stack: Vec<(usize, Infos, usize)>,
     pub info_stack: Vec<Infos>,
     pub env: Value,
     pub locals: Ref<HashMap<u16, Value>>,
     pub this: Value,
+    /// Stack height and call depth of every generator body or fiber being resumed, and
+    /// whether it is a fiber, innermost last.
+    pub resumed: Vec<(usize, usize, bool)>,
+    /// State of the generator body or fiber suspended last, taken by `resume`.
+    pub suspended: Option<Frame>,
+    /// Set by natives to suspend the fiber calling them once they return.
+    pub suspend_fiber: bool,
+    /// Exception leaving a nested `interp` call for a catch clause of its caller, rethrown
+    /// by the native that started the call.
+    pub pending: Option<Value>,
+    /// Id, object, key and function of every `$watch`. `Store` only looks further when
+    /// there is one.
+    pub watchpoints: Vec<(usize, Ref<Object>, Value, Value)>,
+    /// Whether a watchpoint function is running, its own writes don't trigger watchpoints.
+    pub watching: bool,
+    /// Set by `jazzlight --record` to write every instruction to a trace file.
+    pub recorder: Option<crate::trace::Recorder>,
+    /// Set by `jazzlight --profile` to count the instructions run.
+    pub profiler: Option<crate::profile::Profiler>,
+    /// Instructions left before the run stops with a `Stop::OutOfFuel` panic, for harnesses
+    /// running untrusted code. While set, `$exit` panics with `Stop::Exit` instead of
+    /// exiting the process.
+    pub fuel: Option<u64>,
+    /// What `+`, `-` and `*` of two Ints do when the result doesn't fit, set by
+    /// `jazzlight --overflow`.
+    pub overflow: Overflow,
+    /// Arguments given after the bytecode file, for `$args()`.
+    pub args: Vec<String>,
+}
+
+/// Behavior of Int arithmetic on overflow, see `Vm::overflow`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    /// Wrap around in two's complement.
+    Wrap,
+    /// Throw an error.
+    Throw,
+}
+
+/// Panic payload ending a run that has `Vm::fuel` set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    OutOfFuel,
+    Exit(i32),
+}
+
+/// A suspended generator body or fiber.
+pub struct Frame {
+    pub module: Ref<Module>,
+    pub pc: usize,
+    pub env: Value,
+    pub this: Value,
+    pub locals: Ref<HashMap<u16, Value>>,
+    /// Operands of the body.
+    pub stack: Vec<Value>,
+    /// Callers of the running function inside the body, with stack heights relative to it.
+    /// Always empty for generators.
+    pub infos: Vec<Infos>,
+    /// Active catch blocks, with stack height and call depth relative to the body.
+    pub handlers: Vec<(usize, Infos, usize)>,
+}
+
+impl Frame {
+    /// State to start running the body of `function`.
+    pub fn new(function: &Function, this: Value) -> Frame {
+        Frame {
+            module: function.module.clone().unwrap(),
+            pc: function.address,
+            env: function.env.clone(),
+            this,
+            locals: Ref(HashMap::new()),
+            stack: vec![],
+            infos: vec![],
+            handlers: vec![],
+        }
+    }
+}
+
+/// Moves the stack height of a saved state by `sp`.
+fn rebase_info(info: Infos, sp: isize) -> Infos {
+    match info {
+        Infos::Info(module, pc, env, this, locals, s) => {
+            Infos::Info(module, pc, env, this, locals, (s as isize + sp) as usize)
+        }
+        Infos::Exit => Infos::Exit,
+    }
+}
+
+/// Moves the stack height and call depth of a catch block by `sp` and `depth`.
+fn rebase(
+    (addr, info, d): (usize, Infos, usize),
+    sp: isize,
+    depth: isize,
+) -> (usize, Infos, usize) {
+    (addr, rebase_info(info, sp), (d as isize + depth) as usize)
 }
 
 thread_local! {
@@ -46,6 +146,17 @@ impl Vm {
             env: Value::Null,
             locals: Ref(HashMap::new()),
             this: Value::Null,
+            resumed: vec![],
+            suspended: None,
+            suspend_fiber: false,
+            pending: None,
+            watchpoints: vec![],
+            watching: false,
+            recorder: None,
+            profiler: None,
+            fuel: None,
+            overflow: Overflow::Wrap,
+            args: vec![],
         };
 
         vm
@@ -61,12 +172,13 @@ impl Vm {
             self.env.clone(),
             self.this.clone(),
             self.locals.clone(),
+            self.stack.borrow().len(),
         ));
     }
     pub fn pop_state(&mut self, m: Option<&mut Ref<Module>>) -> bool {
         match self.info_stack.pop().unwrap() {
             Infos::Exit => true,
-            Infos::Info(module, pc, env, this, locals) => {
+            Infos::Info(module, pc, env, this, locals, sp) => {
                 match m {
                     Some(m) => match module {
                         Some(module) => *m = module,
@@ -78,16 +190,286 @@ impl Vm {
                 self.pc = pc;
                 self.env = env;
                 self.this = this;
+                self.stack().truncate(sp);
                 false
             }
         }
     }
+    /// Runs a generator body or fiber from where `frame` left it until it is suspended or
+    /// returns. Gives the yielded or returned value, and the new state of the body unless it
+    /// returned.
+    pub fn resume(&mut self, frame: Frame, fiber: bool) -> Result<(Value, Option<Frame>), Value> {
+        self.save_state_exit();
+        let (pc, env, this, locals) = (
+            self.pc,
+            self.env.clone(),
+            self.this.clone(),
+            self.locals.clone(),
+        );
+        let sp = self.stack.borrow().len();
+        let depth = self.info_stack.len();
+        self.stack().extend(frame.stack);
+        for info in frame.infos {
+            self.info_stack.push(rebase_info(info, sp as isize));
+        }
+        for handler in frame.handlers {
+            let handler = rebase(handler, sp as isize, depth as isize);
+            self.exception_stack.push(handler);
+        }
+        self.pc = frame.pc;
+        self.env = frame.env;
+        self.this = frame.this;
+        self.locals = frame.locals;
+        self.resumed.push((sp, depth, fiber));
+        let value = self.interp(frame.module);
+        self.resumed.pop();
+        self.stack().truncate(sp);
+        self.pc = pc;
+        self.env = env;
+        self.this = this;
+        self.locals = locals;
+        match self.pending.take() {
+            Some(e) => Err(e),
+            None => Ok((value, self.suspended.take())),
+        }
+    }
+
+    /// Saves the body resumed at stack height `sp` and call depth `depth` in `suspended` and
+    /// leaves it, `interp` must return right after.
+    fn suspend(&mut self, m: &Ref<Module>, sp: usize, depth: usize) {
+        let stack = self.stack().split_off(sp);
+        let infos = self
+            .info_stack
+            .split_off(depth)
+            .into_iter()
+            .map(|info| rebase_info(info, -(sp as isize)))
+            .collect();
+        let first = self
+            .exception_stack
+            .iter()
+            .position(|(_, _, d)| *d >= depth)
+            .unwrap_or(self.exception_stack.len());
+        let handlers = self
+            .exception_stack
+            .split_off(first)
+            .into_iter()
+            .map(|handler| rebase(handler, -(sp as isize), -(depth as isize)))
+            .collect();
+        self.suspended = Some(Frame {
+            module: m.clone(),
+            pc: self.pc,
+            env: self.env.clone(),
+            this: self.this.clone(),
+            locals: self.locals.clone(),
+            stack,
+            infos,
+            handlers,
+        });
+        // The `Exit` pushed by `resume`.
+        self.info_stack.pop();
+    }
+
+    /// Suspends the fiber after a native requested it with `suspend_fiber`.
+    fn suspend_current_fiber(&mut self, m: &Ref<Module>) {
+        self.suspend_fiber = false;
+        let (sp, depth, _) = *self.resumed.last().unwrap();
+        self.suspend(m, sp, depth);
+    }
+
+    /// Runs `f` with a catch block outside of it, so exceptions it doesn't catch leave the
+    /// `interp` calls it makes as `pending` instead of unwinding further.
+    pub fn protect<T>(&mut self, f: impl FnOnce(&mut Vm) -> T) -> T {
+        let depth = self.info_stack.len();
+        self.exception_stack.push((0, Infos::Exit, depth));
+        let result = f(self);
+        self.exception_stack.pop();
+        result
+    }
+
+    /// Calls the functions watching `key` of `object` after it was set from `old` to `new`.
+    fn notify_watchpoints(
+        &mut self,
+        object: &Ref<Object>,
+        key: &Value,
+        old: Value,
+        new: Value,
+    ) -> Result<(), Value> {
+        let handlers = self
+            .watchpoints
+            .iter()
+            .filter(|(_, o, k, _)| o.as_ptr() == object.as_ptr() && k == key)
+            .map(|(_, _, _, f)| f.clone())
+            .collect::<Vec<_>>();
+        self.watching = true;
+        let mut result = Ok(());
+        for f in handlers {
+            let args = [key.clone(), old.clone(), new.clone()];
+            result = val_callex(f, Value::Object(object.clone()), &args).map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        self.watching = false;
+        result
+    }
+
+    /// Whether a native can suspend the fiber calling it, which needs every function since
+    /// the fiber was resumed to be a script function.
+    pub fn can_suspend_fiber(&self) -> bool {
+        match self.resumed.last() {
+            Some(&(_, depth, true)) => self.info_stack[depth..].iter().all(|info| match info {
+                Infos::Info(..) => true,
+                Infos::Exit => false,
+            }),
+            _ => false,
+        }
+    }
+
+    /// Source location of the instruction at `pc`, if the module has debug info.
+    fn location(m: &Ref<Module>, pc: usize) -> Option<String> {
+        match m.borrow().trace_info.get(&(pc as u32)) {
+            Some((line, file)) if *line > 0 => Some(format!("{}:{}", file, line)),
+            _ => None,
+        }
+    }
+
+    /// Locations of the current instruction and of every pending call, innermost first.
+    pub fn stack_trace(&self, m: &Ref<Module>) -> Vec<String> {
+        let mut trace = vec![];
+        trace.extend(Self::location(m, self.pc.saturating_sub(1)));
+        for info in self.info_stack.iter().rev() {
+            if let Infos::Info(Some(module), pc, ..) = info {
+                trace.extend(Self::location(module, pc.saturating_sub(1)));
+            }
+        }
+        trace
+    }
+
+    /// Stores the stack trace in the `stack` field of a thrown object. Objects that already
+    /// have one are being rethrown and keep the trace of the original throw.
+    fn record_trace(&self, e: &Value, m: &Ref<Module>) {
+        if let Value::Object(object) = e {
+            let key = Value::String(Ref("stack".to_owned()));
+            if object.borrow().table.contains_key(&key) {
+                return;
+            }
+            let trace = self
+                .stack_trace(m)
+                .into_iter()
+                .map(|loc| Value::String(Ref(loc)))
+                .collect();
+            let mut object = object.borrow_mut();
+            object.set(key.clone(), Value::Array(Ref(trace)));
+            object.set_attributes(
+                key,
+                Attributes {
+                    enumerable: false,
+                    writable: true,
+                },
+            );
+        }
+    }
+
+    /// Transfers control to the innermost catch clause, exits if there is none. Returns
+    /// `true` if the clause belongs to the caller of this `interp` call, which then returns
+    /// and leaves the exception in `pending`. Kept out of line so every throwing
+    /// instruction in the dispatch loop stays small.
+    #[cold]
+    #[inline(never)]
+    fn unwind(&mut self, e: Value, m: &mut Ref<Module>) -> bool {
+        self.record_trace(&e, m);
+        let exit = self.info_stack.iter().rposition(|info| match info {
+            Infos::Exit => true,
+            _ => false,
+        });
+        match (self.exception_stack.last(), exit) {
+            (Some((_, _, depth)), Some(exit)) if *depth <= exit => {
+                self.info_stack.truncate(exit);
+                self.pending = Some(e);
+                return true;
+            }
+            _ => (),
+        }
+        match self.exception_stack.pop() {
+            Some((catch, Infos::Info(module, _, env, this, locals, sp), depth)) => {
+                self.pc = catch as _;
+                self.info_stack.truncate(depth);
+                if let Some(module) = module {
+                    *m = module;
+                }
+                self.env = env;
+                self.this = this;
+                self.locals = locals;
+                // Drop operands of the expression that threw, the handler starts with the
+                // stack as it was at `CatchPush`.
+                let mut stack = self.stack();
+                stack.truncate(sp);
+                stack.push(e);
+                false
+            }
+            Some(_) => unreachable!(),
+            None => {
+                self.report_uncaught(&e, m);
+                builtins::os::shutdown(builtins::os::EXIT_UNCAUGHT);
+            }
+        }
+    }
+
+    /// Prints an uncaught exception with its stack trace and the chain of its causes.
+    fn report_uncaught(&self, e: &Value, m: &Ref<Module>) {
+        match (error_field(e, "stack"), Self::location(m, self.pc - 1)) {
+            (None, Some(loc)) => eprintln!("Error in {}: {}", loc, error_message(e)),
+            _ => eprintln!("Error: {}", error_message(e)),
+        }
+        let mut e = e.clone();
+        loop {
+            if let Some(Value::Array(stack)) = error_field(&e, "stack") {
+                for loc in stack.borrow().iter() {
+                    eprintln!("    at {}", loc);
+                }
+            }
+            match error_field(&e, "cause") {
+                Some(Value::Null) | None => break,
+                Some(cause) => {
+                    eprintln!("Caused by: {}", error_message(&cause));
+                    e = cause;
+                }
+            }
+        }
+    }
+
+    /// Records the operands of the instruction that just started and quickens it when they
+    /// fit one of its variants.
+    fn observe(&self, m: &Ref<Module>, op: &opcode::Op, lhs: &Value, rhs: &Value) {
+        let pc = self.pc - 1;
+        let mut m = m.borrow_mut();
+        let feedback = m.feedback_at(pc);
+        feedback.record(lhs, rhs);
+        if let Some(quick) = feedback::quicken(op, feedback) {
+            m.code[pc].quick.set(Some(quick));
+        }
+    }
+
+    /// Turns the quickened instruction that just started back into the generic one for good
+    /// and sets up to run that instead, with the operands it popped, `lhs` first.
+    fn deopt(&mut self, m: &Ref<Module>, lhs: Value, rhs: Value) {
+        self.pc -= 1;
+        let mut m = m.borrow_mut();
+        m.feedback_at(self.pc).generic = true;
+        m.code[self.pc].quick.set(None);
+        let mut stack = self.stack();
+        stack.push(rhs);
+        stack.push(lhs);
+    }
+
     pub fn stack(&self) -> std::cell::RefMut<'_, Vec<Value>> {
         self.stack.borrow_mut()
     }
 
     pub fn interp(&mut self, mut m: Ref<Module>) -> Value {
         use opcode::Op;
+        // Code of the running function, replaced whenever `m` changes on call, return or throw.
+        let mut code = m.borrow().code.clone();
         macro_rules! throw {
             ($val: expr) => {
                 catch!(Err($val));
@@ -98,39 +480,36 @@ impl Vm {
                 match $e {
                     Ok(val) => val,
                     Err(e) => {
-                        if self.exception_stack.is_empty() {
-                            let info = m.borrow().trace_info.get(&(self.pc as u32)).cloned();
-                            if let Some((line, file)) = info {
-                                eprintln!("Error in {}:{}: {}", file, line, e);
-                            } else {
-                                eprintln!("Error: {}", e);
-                            }
-                            std::process::exit(1);
-                        } else {
-                            if let Some((catch, Infos::Info(module, _, env, this, locals))) =
-                                self.exception_stack.pop()
-                            {
-                                self.pc = catch as _;
-                                match module {
-                                    Some(module) => m = module,
-                                    _ => (),
-                                }
-                                self.env = env;
-                                self.this = this;
-                                self.locals = locals;
-                                self.stack().push(e);
-                                continue;
-                            } else {
-                                unreachable!()
-                            }
+                        if self.unwind(e, &mut m) {
+                            return Value::Null;
                         }
+                        code = m.borrow().code.clone();
+                        continue;
                     }
                 }
             };
         }
 
-        'inner: while self.pc < m.borrow().code.len() {
-            let op = m.borrow().code[self.pc].clone();
+        'inner: loop {
+            let op = match code.get(self.pc) {
+                Some(instr) => instr.current(),
+                None => break,
+            };
+            if let Some(fuel) = &mut self.fuel {
+                if *fuel == 0 {
+                    std::panic::panic_any(Stop::OutOfFuel);
+                }
+                *fuel -= 1;
+            }
+            if let Some(profiler) = &mut self.profiler {
+                profiler.count(&m, self.pc);
+            }
+            if let Some(recorder) = &mut self.recorder {
+                let depth = self.info_stack.len();
+                if !recorder.record(&m, self.pc, &op, depth, &self.stack.borrow()) {
+                    self.recorder = None;
+                }
+            }
             self.pc += 1;
             match op {
                 Op::LoadBuiltin(name) => {
@@ -155,6 +534,15 @@ impl Vm {
                     self.stack()
                         .push(m.borrow().globals.get(idx).cloned().unwrap_or(Value::Null));
                 }
+                Op::StoreGlobal(idx) => {
+                    let value = self.stack().pop().unwrap();
+                    let mut m = m.borrow_mut();
+                    let idx = idx as usize;
+                    if idx >= m.globals.len() {
+                        m.globals.resize(idx + 1, Value::Null);
+                    }
+                    m.globals[idx] = value;
+                }
                 Op::LoadLocal(idx) => {
                     self.stack().push(
                         self.locals
@@ -171,7 +559,7 @@ impl Vm {
                             if idx >= array.borrow().len() {
                                 panic!("JZVM RUNTIME ERROR: Reading outside env");
                             }
-                            self.stack().push(array.borrow()[idx].clone());
+                            self.stack().push(array.borrow().get(idx).unwrap());
                         }
                         _ => unreachable!(),
                     }
@@ -192,7 +580,7 @@ impl Vm {
                     match value {
                         Some(value) => match &self.env {
                             Value::Array(array) => {
-                                array.borrow_mut()[idx] = value;
+                                array.borrow_mut().set(idx, value);
                             }
                             _ => unreachable!(),
                         },
@@ -211,12 +599,32 @@ impl Vm {
                 Op::Ret => {
                     let value = self.stack().pop().unwrap_or(Value::Null);
                     let exit = self.pop_state(Some(&mut m));
+                    // Catch blocks of the returning function are no longer active.
+                    let depth = self.info_stack.len();
+                    while let Some((_, _, catch_depth)) = self.exception_stack.last() {
+                        if *catch_depth <= depth {
+                            break;
+                        }
+                        self.exception_stack.pop();
+                    }
                     if exit {
                         return value;
                     } else {
+                        code = m.borrow().code.clone();
                         self.stack().push(value);
                     }
                 }
+                Op::Yield => {
+                    let value = self.stack().pop().unwrap_or(Value::Null);
+                    let (sp, depth) = match self.resumed.last() {
+                        Some(&(sp, depth, false)) if depth == self.info_stack.len() => (sp, depth),
+                        _ => throw!(Value::String(
+                            Ref("yield outside of a generator".to_owned())
+                        )),
+                    };
+                    self.suspend(&m, sp, depth);
+                    return value;
+                }
                 Op::CatchPush(addr) => {
                     let info = Infos::Info(
                         Some(m.clone()),
@@ -224,14 +632,19 @@ impl Vm {
                         self.env.clone(),
                         self.this.clone(),
                         self.locals.clone(),
+                        self.stack.borrow().len(),
                     );
-                    self.exception_stack.push((addr as usize, info));
+                    let depth = self.info_stack.len();
+                    self.exception_stack.push((addr as usize, info, depth));
+                }
+                Op::CatchPop => {
+                    self.exception_stack.pop();
                 }
                 Op::Throw => {
                     let value = self.stack().pop().unwrap();
                     catch!(Err(value));
                 }
-                Op::TailCall(argc) | Op::Call(argc) => {
+                Op::TailCall(argc) | Op::Call(argc) | Op::CallNamed(argc, _) => {
                     let function = self.stack().pop().unwrap();
                     let args = (0..argc)
                         .into_iter()
@@ -240,6 +653,12 @@ impl Vm {
                     match function {
                         Value::Function(function) => {
                             let function = function.borrow();
+                            let args = match op {
+                                Op::CallNamed(_, names) | Op::InvokeNamed(_, _, names) => {
+                                    catch!(bind_named(&m, names, &function, args))
+                                }
+                                _ => args,
+                            };
                             if function.argc != -1 {
                                 if args.len() < function.argc as usize
                                     || args.len() > function.argc as usize
@@ -259,6 +678,7 @@ impl Vm {
                                 self.env = function.env.clone();
                                 self.locals = Ref(HashMap::new());
                                 m = function.module.as_ref().unwrap().clone();
+                                code = m.borrow().code.clone();
                                 let mut locals = self.locals.borrow_mut();
 
                                 for (i, arg) in args.iter().enumerate() {
@@ -266,12 +686,14 @@ impl Vm {
                                 }
                                 self.this = Value::Null;
                                 self.pc = function.address;
+                                self.stack().reserve(function.max_stack as usize);
                             } else {
-                                let fun: fn(&[Value]) -> Result<Value, Value> =
-                                    unsafe { std::mem::transmute(function.address) };
-
-                                let result = catch!(fun(&args));
+                                let result = catch!(call_native(&function, None, &args));
                                 self.stack().push(result);
+                                if self.suspend_fiber {
+                                    self.suspend_current_fiber(&m);
+                                    return Value::Null;
+                                }
                                 /*match fun(&args) {
                                     Ok(val) => self.stack().push(val),
                                     Err(e) => throw!(Err(e)),
@@ -284,9 +706,27 @@ impl Vm {
                         )))),
                     }
                 }
-                Op::ObjCall(argc) => {
-                    let function = self.stack().pop().unwrap();
-                    let this = self.stack().pop().unwrap();
+                Op::ObjCall(_) | Op::InvokeMethod(..) | Op::InvokeNamed(..) => {
+                    let (function, this, argc) = match op {
+                        Op::InvokeMethod(name, argc) | Op::InvokeNamed(name, argc, _) => {
+                            let this = self.stack().pop().unwrap();
+                            let name = m.borrow().globals[name as usize].clone();
+                            match find_property(&this, &name) {
+                                Some(function) => (function, this, argc),
+                                None => throw!(Value::String(Ref(format!(
+                                    "Undefined property '{}' of {}",
+                                    name,
+                                    this.type_name()
+                                )))),
+                            }
+                        }
+                        Op::ObjCall(argc) => {
+                            let function = self.stack().pop().unwrap();
+                            let this = self.stack().pop().unwrap();
+                            (function, this, argc)
+                        }
+                        _ => unreachable!(),
+                    };
                     /*let args = (0..argc)
                     .into_iter()
                     .map(|_| self.stack().pop().unwrap_or(Value::Null))
@@ -299,8 +739,12 @@ impl Vm {
                     match function {
                         Value::Function(function) => {
                             let function = function.borrow();
-                            self.save_state(Some(m.clone()));
-                            self.env = function.env.clone();
+                            let args = match op {
+                                Op::CallNamed(_, names) | Op::InvokeNamed(_, _, names) => {
+                                    catch!(bind_named(&m, names, &function, args))
+                                }
+                                _ => args,
+                            };
                             if function.argc != -1 {
                                 if args.len() < function.argc as usize
                                     || args.len() > function.argc as usize
@@ -313,9 +757,12 @@ impl Vm {
                                 }
                             }
                             if !function.native {
+                                self.save_state(Some(m.clone()));
+                                self.env = function.env.clone();
                                 self.locals = Ref(HashMap::new());
                                 if let Some(module) = &function.module {
                                     m = module.clone();
+                                    code = m.borrow().code.clone();
                                 }
                                 let mut locals = self.locals.borrow_mut();
                                 for (i, arg) in args.iter().enumerate() {
@@ -323,214 +770,56 @@ impl Vm {
                                 }
                                 self.this = this;
                                 self.pc = function.address;
+                                self.stack().reserve(function.max_stack as usize);
                             } else {
-                                let fun: fn(&[Value]) -> Result<Value, Value> =
-                                    unsafe { std::mem::transmute(function.address) };
-                                let mut new_args = vec![this];
-                                for i in args.iter() {
-                                    new_args.push(i.clone());
-                                }
-                                let result = catch!(fun(&new_args));
+                                let result = catch!(call_native(&function, Some(this), &args));
                                 self.stack().push(result);
+                                if self.suspend_fiber {
+                                    self.suspend_current_fiber(&m);
+                                    return Value::Null;
+                                }
                                 /*match fun(&args) {
                                     Ok(val) => self.stack().push(val),
                                     Err(e) => throw!(Err(e)),
                                 }*/
                             }
                         }
-                        _ => throw!(Value::String(Ref("ObjCall: Function expected".to_owned()))),
+                        _ => throw!(Value::String(Ref(format!(
+                            "Call at {:x}: Function expected",
+                            self.pc - 1
+                        )))),
                     }
                 }
                 Op::Nop => {}
-                Op::MakeEnv(count) => {
-                    let function = self.stack().pop().unwrap();
-                    assert_eq!(function.tag(), ValTag::Func);
-                    let values = (0..count)
-                        .into_iter()
-                        .map(|_| self.stack().pop().unwrap_or(Value::Null))
-                        .collect::<Vec<Value>>();
-                    match &function {
-                        Value::Function(func) => match &func.borrow().env {
-                            Value::Array(array) => array.borrow_mut().extend(values),
-                            _ => unreachable!(),
-                        },
-                        _ => unreachable!(),
-                    }
-                    self.stack().push(function);
+                Op::Pop(count) => {
+                    let mut stack = self.stack();
+                    let len = stack.len() - count as usize;
+                    stack.truncate(len);
                 }
+                Op::MakeEnv(count) => catch!(self.make_env(count)),
 
-                Op::Load => {
-                    let object = self.stack().pop().unwrap();
-                    let key = self.stack().pop().unwrap();
-                    match object {
-                        Value::Array(array) => match key {
-                            Value::Int(x) => self.stack().push(
-                                array
-                                    .borrow()
-                                    .get(x as usize)
-                                    .cloned()
-                                    .unwrap_or(Value::Null),
-                            ),
-                            Value::Float(x) => self.stack().push(
-                                array
-                                    .borrow()
-                                    .get(x as usize)
-                                    .cloned()
-                                    .unwrap_or(Value::Null),
-                            ),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Object(object) => {
-                            self.stack()
-                                .push(object.borrow().get(key).unwrap_or(Value::Null));
-                        }
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Store => {
-                    let object = self.stack().pop().unwrap();
-                    let key = self.stack().pop().unwrap();
-                    let value = self.stack().pop().unwrap();
-                    match object {
-                        Value::Array(array) => match key {
-                            Value::Int(x) => {
-                                if x as usize >= array.borrow().len() {
-                                    throw!(Value::String(Ref(
-                                        "Array index out of bounds".to_owned()
-                                    )));
-                                }
-                                array.borrow_mut()[x as usize] = value;
-                            }
-                            Value::Float(x) => {
-                                if x as usize >= array.borrow().len() {
-                                    throw!(Value::String(Ref(
-                                        "Array index out of bounds".to_owned()
-                                    )));
-                                }
-                                array.borrow_mut()[x as usize] = value;
-                            }
-                            _ => (),
-                        },
-                        Value::Object(object) => {
-                            object.borrow_mut().set(key, value);
-                        }
-                        _ => throw!(Value::String(Ref("Invalid store operation".to_string()))),
-                    }
-                }
+                Op::Load => catch!(self.load(&m, &op)),
+                Op::LoadElementStrict => catch!(self.load_element_strict()),
+                Op::LoadStrict => catch!(self.load_strict()),
+                Op::Store => catch!(self.store()),
+                Op::Delete => catch!(self.delete()),
                 Op::MakeArray(count) => {
-                    let values = (0..count)
-                        .into_iter()
-                        .map(|_| self.stack().pop().unwrap())
-                        .collect::<Vec<Value>>();
-
-                    self.stack().push(Value::Array(Ref(values)));
-                }
-                Op::Add => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::String(x) => {
-                            self.stack()
-                                .push(Value::String(Ref(format!("{}{}", *x.borrow(), rhs))))
-                        }
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x + y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 + y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 + y as u32)
-                            })),
-                            Value::Int(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 + y as u32)
-                            })),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x + y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x + y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Sub => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x - y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 - y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 - y as u32)
-                            })),
-                            Value::Int(y) => self.stack().push(Value::Char(unsafe {
-                                std::char::from_u32_unchecked(x as u32 - y as u32)
-                            })),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x - y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x - y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
+                    // The elements are the top `count` values, in the order they were pushed.
+                    let mut stack = self.stack();
+                    let start = stack.len() - count as usize;
+                    let values = stack.split_off(start);
+                    stack.push(Value::Array(Ref(values.into())));
                 }
-                Op::Div => {
+                Op::Add => catch!(self.add(&m, &op)),
+                Op::Sub => catch!(self.sub(&m, &op)),
+                Op::Div => catch!(self.div(&op)),
+                Op::Mul => catch!(self.mul(&m, &op)),
+                Op::Mod => catch!(self.rem(&op)),
+                Op::Pow => {
                     let lhs = self.stack().pop().unwrap();
                     let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x / y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 / y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x / y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x / y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Mul => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x * y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 * y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x * y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x * y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
-                Op::Mod => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Int(x % y)),
-                            Value::Float(y) => self.stack().push(Value::Float(x as f64 % y)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Float(x % y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Float(x % y as f64)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        _ => self.stack().push(Value::Null),
-                    }
+                    let result = catch!(pow(&lhs, &rhs));
+                    self.stack().push(result);
                 }
                 Op::Shr => {
                     let lhs = self.stack().pop().unwrap();
@@ -540,160 +829,96 @@ impl Vm {
                         _ => self.stack().push(Value::Null),
                     }
                 }
-                Op::Shl => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match (lhs, rhs) {
-                        (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x << y)),
-                        (Value::Array(array), any_value) => {
-                            self.stack().push(any_value.clone());
-                            array.borrow_mut().push(any_value);
-                        }
-                        _ => self.stack().push(Value::Null),
-                    }
-                }
+                Op::Shl => catch!(self.shl()),
 
-                Op::Gt => {
+                Op::Gt | Op::Gte | Op::Lt | Op::Lte => catch!(self.compare(&m, &op)),
+                Op::AddNum | Op::SubNum | Op::MulNum => {
                     let lhs = self.stack().pop().unwrap();
                     let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x > y)),
-                            Value::Int(y) => self.stack().push(Value::Bool(x as u32 > y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::String(x) => match rhs {
-                            Value::String(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() > y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x > y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) > y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x > y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x > y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() > y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
+                    let result = match (&lhs, &rhs) {
+                        (Value::Int(x), Value::Int(y)) => {
+                            catch!(int_arith(&op, *x, *y, self.overflow))
+                        }
+                        _ => match float_operands(&lhs, &rhs) {
+                            Some((x, y)) => match op {
+                                Op::AddNum => Value::Float(x + y),
+                                Op::SubNum => Value::Float(x - y),
+                                _ => Value::Float(x * y),
+                            },
+                            None => {
+                                self.deopt(&m, lhs, rhs);
+                                continue;
+                            }
                         },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
+                    };
+                    self.stack().push(result);
                 }
-                Op::Gte => {
+                Op::GtNum | Op::GteNum | Op::LtNum | Op::LteNum => {
                     let lhs = self.stack().pop().unwrap();
                     let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x >= y)),
-                            Value::Int(y) => self.stack().push(Value::Bool(x as u32 >= y as u32)),
-                            _ => self.stack().push(Value::Null),
+                    let result = match (&lhs, &rhs) {
+                        (Value::Int(x), Value::Int(y)) => match op {
+                            Op::GtNum => x > y,
+                            Op::GteNum => x >= y,
+                            Op::LtNum => x < y,
+                            _ => x <= y,
                         },
-                        Value::String(x) => match rhs {
-                            Value::String(y) => {
-                                self.stack().push(Value::Bool(*x.borrow() >= *y.borrow()))
+                        _ => match float_operands(&lhs, &rhs) {
+                            Some((x, y)) => match op {
+                                Op::GtNum => x > y,
+                                Op::GteNum => x >= y,
+                                Op::LtNum => x < y,
+                                _ => x <= y,
+                            },
+                            None => {
+                                self.deopt(&m, lhs, rhs);
+                                continue;
                             }
-                            _ => self.stack().push(Value::Bool(false)),
                         },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) >= y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self.stack().push(Value::Bool(
-                                (x.borrow().len() > y.borrow().len()) || *x.borrow() == *y.borrow(),
-                            )),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
+                    };
+                    self.stack().push(Value::Bool(result));
                 }
-                Op::Lte => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::String(x) => match rhs {
-                            Value::String(y) => {
-                                self.stack().push(Value::Bool(*x.borrow() >= *y.borrow()))
-                            }
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x <= y)),
-                            Value::Int(y) => self.stack().push(Value::Bool((x as u32) <= y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) >= y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x >= y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self.stack().push(Value::Bool(
-                                (x.borrow().len() < y.borrow().len()) || *x.borrow() == *y.borrow(),
-                            )),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
+                Op::LoadField => {
+                    let object = self.stack().pop().unwrap();
+                    let key = self.stack().pop().unwrap();
+                    let value = match (&object, &key) {
+                        (Value::Object(object), Value::String(name)) => {
+                            let value = object.borrow().get(key.clone());
+                            value.or_else(|| builtins::object::get_object_method(&name.borrow()))
+                        }
+                        _ => {
+                            self.deopt(&m, object, key);
+                            continue;
+                        }
+                    };
+                    self.stack().push(value.unwrap_or(Value::Null));
                 }
-                Op::Lt => {
-                    let lhs = self.stack().pop().unwrap();
-                    let rhs = self.stack().pop().unwrap();
-                    match lhs {
-                        Value::String(x) => match rhs {
-                            Value::String(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() < y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Char(x) => match rhs {
-                            Value::Char(y) => self.stack().push(Value::Bool(x < y)),
-                            Value::Int(y) => self.stack().push(Value::Bool((x as u32) < y as u32)),
-                            _ => self.stack().push(Value::Null),
-                        },
-                        Value::Int(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x < y)),
-                            Value::Float(y) => self.stack().push(Value::Bool((x as f64) < y)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Float(x) => match rhs {
-                            Value::Int(y) => self.stack().push(Value::Bool(x < y as f64)),
-                            Value::Float(y) => self.stack().push(Value::Bool(x < y as f64)),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        Value::Array(x) => match rhs {
-                            Value::Array(y) => self
-                                .stack()
-                                .push(Value::Bool(x.borrow().len() < y.borrow().len())),
-                            _ => self.stack().push(Value::Bool(false)),
-                        },
-                        _ => self.stack().push(Value::Bool(false)),
-                    }
+                Op::LoadIndex => {
+                    let array = self.stack().pop().unwrap();
+                    let index = self.stack().pop().unwrap();
+                    let value = match (&array, &index) {
+                        (Value::Array(array), Value::Int(_)) => {
+                            let array = array.borrow();
+                            element_position(&array, &index)
+                                .and_then(|position| array.get(position))
+                        }
+                        _ => {
+                            self.deopt(&m, array, index);
+                            continue;
+                        }
+                    };
+                    self.stack().push(value.unwrap_or(Value::Null));
                 }
                 Op::Eq => {
                     let lhs = self.stack().pop().unwrap();
                     let rhs = self.stack().pop().unwrap();
                     self.stack().push(Value::Bool(lhs == rhs));
                 }
+                Op::StrictEq => {
+                    let lhs = self.stack().pop().unwrap();
+                    let rhs = self.stack().pop().unwrap();
+                    self.stack().push(Value::Bool(lhs.strict_eq(&rhs)));
+                }
                 Op::Neq => {
                     let lhs = self.stack().pop().unwrap();
                     let rhs = self.stack().pop().unwrap();
@@ -710,6 +935,14 @@ impl Vm {
                 Op::Jump(to) => {
                     self.pc = to as _;
                 }
+                Op::Switch(table, cases) => {
+                    let value = self.stack().pop().unwrap();
+                    let case = match m.borrow().globals.get(table as usize) {
+                        Some(Value::Object(table)) => switch_case(&table.borrow(), &value),
+                        _ => None,
+                    };
+                    self.pc += case.unwrap_or(cases as usize);
+                }
                 Op::JumpIf(to) => {
                     let value = self.stack().pop().unwrap().to_bool();
                     if value {
@@ -724,19 +957,10 @@ impl Vm {
                 }
                 Op::Not => {
                     let val = self.stack().pop().unwrap();
-                    match val {
-                        Value::Int(x) => self.stack().push(Value::Int(!x)),
-                        _ => self.stack().push(Value::Bool(!val.to_bool())),
-                    }
-                }
-                Op::Neg => {
-                    let val = self.stack().pop().unwrap();
-                    match val {
-                        Value::Int(x) => self.stack().push(Value::Int(-x)),
-                        Value::Float(x) => self.stack().push(Value::Float(-x)),
-                        _ => self.stack().push(Value::Null),
-                    }
+                    self.stack().push(Value::Bool(!val.to_bool()));
                 }
+                Op::Neg => catch!(self.neg(&op)),
+                Op::BitNot => catch!(self.bit_not()),
                 Op::And => {
                     let (x, y) = (self.stack().pop().unwrap(), self.stack().pop().unwrap());
                     match (x.clone(), y.clone()) {
@@ -750,7 +974,7 @@ impl Vm {
                     match (x.clone(), y.clone()) {
                         (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x | y)),
                         (Value::Bool(x), Value::Bool(y)) => self.stack().push(Value::Bool(x | y)),
-                        _ => self.stack().push(Value::Bool(x.to_bool() & y.to_bool())),
+                        _ => self.stack().push(Value::Bool(x.to_bool() | y.to_bool())),
                     }
                 }
                 Op::Xor => {
@@ -760,21 +984,7 @@ impl Vm {
                         _ => self.stack().push(Value::Null),
                     }
                 }
-                Op::New => {
-                    let proto = self.stack().pop().unwrap();
-                    let proto = match proto {
-                        Value::Null => None,
-                        Value::Object(obj) => Some(obj),
-                        _ => throw!(Value::String(Ref(
-                            "Object or null expected as prototype".to_owned()
-                        ))),
-                    };
-                    let object = Object {
-                        prototype: proto,
-                        table: hashlink::LinkedHashMap::new(),
-                    };
-                    self.stack().push(Value::Object(Ref(object)));
-                }
+                Op::New => catch!(self.new_object()),
                 Op::Last => break 'inner,
                 _ => unimplemented!(),
             }
@@ -783,20 +993,852 @@ impl Vm {
     }
 }
 
+/// Handlers of the generic instructions that only work on the operand stack, kept out of the
+/// dispatch loop in `interp` so it stays small. The quickened variants, which hot code runs
+/// instead, stay in the loop. The handlers return what the instruction throws.
+impl Vm {
+    /// `MakeEnv(count)`: a closure of the function on top of the stack over the `count`
+    /// values under it.
+    #[inline(never)]
+    fn make_env(&mut self, count: u16) -> Result<(), Value> {
+        let function = self.stack().pop().unwrap();
+        assert_eq!(function.tag(), ValTag::Func);
+        let values = (0..count)
+            .into_iter()
+            .map(|_| self.stack().pop().unwrap_or(Value::Null))
+            .collect::<Vec<Value>>();
+        // Every closure instance gets its own env, the function constant is only a template.
+        let closure = match &function {
+            Value::Function(func) => {
+                let func = func.borrow();
+                Function {
+                    native: func.native,
+                    address: func.address,
+                    env: Value::Array(Ref(values.into())),
+                    module: func.module.clone(),
+                    argc: func.argc,
+                    params: func.params.clone(),
+                    source: func.source.clone(),
+                    doc: func.doc.clone(),
+                    max_stack: func.max_stack,
+                    hotness: func.hotness,
+                }
+            }
+            _ => unreachable!(),
+        };
+        self.stack().push(Value::Function(Ref(closure)));
+        Ok(())
+    }
+
+    /// `Load`: `object[key]`, recording what it loads from.
+    #[inline(never)]
+    fn load(&mut self, m: &Ref<Module>, op: &opcode::Op) -> Result<(), Value> {
+        let object = self.stack().pop().unwrap();
+        let key = self.stack().pop().unwrap();
+        self.observe(m, op, &object, &key);
+        self.stack().push(load(&object, &key));
+        Ok(())
+    }
+
+    /// `LoadElementStrict`: like `Load`, throwing for array indices out of bounds.
+    #[inline(never)]
+    fn load_element_strict(&mut self) -> Result<(), Value> {
+        let object = self.stack().pop().unwrap();
+        let key = self.stack().pop().unwrap();
+        let value = match &object {
+            Value::Array(array) if is_index(&key) => {
+                let array = array.borrow();
+                element_position(&array, &key)
+                    .and_then(|position| array.get(position))
+                    .ok_or_else(|| out_of_bounds(&key, array.len()))
+            }
+            _ => Ok(load(&object, &key)),
+        };
+        let value = value?;
+        self.stack().push(value);
+        Ok(())
+    }
+
+    /// `LoadStrict`: like `Load`, throwing for missing properties.
+    #[inline(never)]
+    fn load_strict(&mut self) -> Result<(), Value> {
+        let object = self.stack().pop().unwrap();
+        let key = self.stack().pop().unwrap();
+        match find_property(&object, &key) {
+            Some(value) => self.stack().push(value),
+            None => {
+                return Err(Value::String(Ref(format!(
+                    "Undefined property '{}' of {}",
+                    key,
+                    builtins::builtin_typeof(&[object]).unwrap_or(Value::Null)
+                ))))
+            }
+        }
+        Ok(())
+    }
+
+    /// `Store`: `object[key] = value`, notifying watchpoints.
+    #[inline(never)]
+    fn store(&mut self) -> Result<(), Value> {
+        let object = self.stack().pop().unwrap();
+        let key = self.stack().pop().unwrap();
+        let value = self.stack().pop().unwrap();
+        match object {
+            Value::Array(array) if is_index(&key) => {
+                let len = array.borrow().len();
+                let position = element_position(&array.borrow(), &key);
+                match position {
+                    Some(position) => array.borrow_mut().set(position, value),
+                    // Assigning just past the end appends.
+                    None if to_index(&key) == Some(len as i64) => array.borrow_mut().push(value),
+                    None => return Err(out_of_bounds(&key, len)),
+                }
+            }
+            Value::Array(_) => (),
+            Value::Object(object) => {
+                if self.watchpoints.is_empty() || self.watching {
+                    object
+                        .borrow_mut()
+                        .assign(key, value)
+                        .map_err(|e| Value::String(Ref(e)))?;
+                } else {
+                    let old = object.borrow().table.get(&key).cloned();
+                    object
+                        .borrow_mut()
+                        .assign(key.clone(), value.clone())
+                        .map_err(|e| Value::String(Ref(e)))?;
+                    let old = old.unwrap_or(Value::Null);
+                    self.notify_watchpoints(&object, &key, old, value)?;
+                }
+            }
+            _ => return Err(Value::String(Ref("Invalid store operation".to_string()))),
+        }
+        Ok(())
+    }
+
+    /// `Delete`: removes a field of an object.
+    #[inline(never)]
+    fn delete(&mut self) -> Result<(), Value> {
+        let object = self.stack().pop().unwrap();
+        let key = self.stack().pop().unwrap();
+        let object = match object {
+            Value::Object(object) => object,
+            _ => {
+                return Err(Value::String(Ref(format!(
+                    "Cannot delete field of {}",
+                    object.type_name()
+                ))))
+            }
+        };
+        let old = object.borrow().table.get(&key).cloned();
+        let deleted = object
+            .borrow_mut()
+            .delete(&key)
+            .map_err(|e| Value::String(Ref(e)))?;
+        if deleted && !self.watchpoints.is_empty() && !self.watching {
+            let old = old.unwrap_or(Value::Null);
+            self.notify_watchpoints(&object, &key, old, Value::Null)?;
+        }
+        self.stack().push(Value::Bool(deleted));
+        Ok(())
+    }
+
+    /// `Add`: numeric addition, string concatenation and char offsets.
+    #[inline(never)]
+    fn add(&mut self, m: &Ref<Module>, op: &opcode::Op) -> Result<(), Value> {
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        self.observe(m, op, &lhs, &rhs);
+        let result = self.plus(lhs, rhs)?;
+        self.stack().push(result);
+        Ok(())
+    }
+
+    /// `lhs + rhs` the way `Add` computes it.
+    pub(crate) fn plus(&self, lhs: Value, rhs: Value) -> Result<Value, Value> {
+        let op = &opcode::Op::Add;
+        if is_decimal_op(&lhs, &rhs) {
+            return decimal_binop(op, &lhs, &rhs);
+        }
+        if is_bigint_op(&lhs, &rhs) {
+            return bigint_binop(op, &lhs, &rhs);
+        }
+        Ok(match lhs {
+            Value::String(x) => {
+                let rhs = builtins::display(&rhs)?;
+                Value::String(Ref(format!("{}{}", *x.borrow(), rhs)))
+            }
+            Value::Int(x) => match rhs {
+                Value::Int(y) => int_arith(op, x, y, self.overflow)?,
+                Value::Float(y) => Value::Float(x as f64 + y),
+                _ => Value::Null,
+            },
+            Value::Char(x) => match rhs {
+                Value::Int(y) => char_offset(x, y)?,
+                _ => Value::Null,
+            },
+            Value::Float(x) => match rhs {
+                Value::Int(y) => Value::Float(x + y as f64),
+                Value::Float(y) => Value::Float(x + y as f64),
+                _ => Value::Null,
+            },
+            _ => Value::Null,
+        })
+    }
+
+    /// `Sub`: numeric subtraction and char differences and offsets.
+    #[inline(never)]
+    fn sub(&mut self, m: &Ref<Module>, op: &opcode::Op) -> Result<(), Value> {
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        self.observe(m, op, &lhs, &rhs);
+        if is_decimal_op(&lhs, &rhs) {
+            let result = decimal_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        if is_bigint_op(&lhs, &rhs) {
+            let result = bigint_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        match lhs {
+            Value::Int(x) => match rhs {
+                Value::Int(y) => {
+                    let result = int_arith(op, x, y, self.overflow)?;
+                    self.stack().push(result)
+                }
+                Value::Float(y) => self.stack().push(Value::Float(x as f64 - y)),
+                _ => self.stack().push(Value::Null),
+            },
+            Value::Char(x) => match rhs {
+                Value::Char(y) => self.stack().push(Value::Int(x as i64 - y as i64)),
+                Value::Int(y) => {
+                    let result = char_offset(x, y.wrapping_neg())?;
+                    self.stack().push(result)
+                }
+                _ => self.stack().push(Value::Null),
+            },
+            Value::Float(x) => match rhs {
+                Value::Int(y) => self.stack().push(Value::Float(x - y as f64)),
+                Value::Float(y) => self.stack().push(Value::Float(x - y as f64)),
+                _ => self.stack().push(Value::Null),
+            },
+            _ => self.stack().push(Value::Null),
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn div(&mut self, op: &opcode::Op) -> Result<(), Value> {
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        if is_decimal_op(&lhs, &rhs) {
+            let result = decimal_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        if is_bigint_op(&lhs, &rhs) {
+            let result = bigint_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        match lhs {
+            Value::Int(x) => match rhs {
+                Value::Int(y) => {
+                    let result = int_arith(op, x, y, self.overflow)?;
+                    self.stack().push(result);
+                }
+                Value::Float(y) => self.stack().push(Value::Float(x as f64 / y)),
+                _ => self.stack().push(Value::Null),
+            },
+            Value::Float(x) => match rhs {
+                Value::Int(y) => self.stack().push(Value::Float(x / y as f64)),
+                Value::Float(y) => self.stack().push(Value::Float(x / y as f64)),
+                _ => self.stack().push(Value::Null),
+            },
+            _ => self.stack().push(Value::Null),
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn mul(&mut self, m: &Ref<Module>, op: &opcode::Op) -> Result<(), Value> {
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        self.observe(m, op, &lhs, &rhs);
+        if is_decimal_op(&lhs, &rhs) {
+            let result = decimal_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        if is_bigint_op(&lhs, &rhs) {
+            let result = bigint_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        match lhs {
+            Value::Int(x) => match rhs {
+                Value::Int(y) => {
+                    let result = int_arith(op, x, y, self.overflow)?;
+                    self.stack().push(result)
+                }
+                Value::Float(y) => self.stack().push(Value::Float(x as f64 * y)),
+                _ => self.stack().push(Value::Null),
+            },
+            Value::Float(x) => match rhs {
+                Value::Int(y) => self.stack().push(Value::Float(x * y as f64)),
+                Value::Float(y) => self.stack().push(Value::Float(x * y as f64)),
+                _ => self.stack().push(Value::Null),
+            },
+            _ => self.stack().push(Value::Null),
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn rem(&mut self, op: &opcode::Op) -> Result<(), Value> {
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        if is_decimal_op(&lhs, &rhs) {
+            let result = decimal_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        if is_bigint_op(&lhs, &rhs) {
+            let result = bigint_binop(op, &lhs, &rhs)?;
+            self.stack().push(result);
+            return Ok(());
+        }
+        match lhs {
+            Value::Int(x) => match rhs {
+                Value::Int(y) => {
+                    let result = int_arith(op, x, y, self.overflow)?;
+                    self.stack().push(result);
+                }
+                Value::Float(y) => self.stack().push(Value::Float(x as f64 % y)),
+                _ => self.stack().push(Value::Null),
+            },
+            Value::Float(x) => match rhs {
+                Value::Int(y) => self.stack().push(Value::Float(x % y as f64)),
+                Value::Float(y) => self.stack().push(Value::Float(x % y as f64)),
+                _ => self.stack().push(Value::Null),
+            },
+            _ => self.stack().push(Value::Null),
+        }
+        Ok(())
+    }
+
+    /// `Shl`: left shift of Ints, appends to an array.
+    #[inline(never)]
+    fn shl(&mut self) -> Result<(), Value> {
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        match (lhs, rhs) {
+            (Value::Int(x), Value::Int(y)) => self.stack().push(Value::Int(x << y)),
+            (Value::Array(array), any_value) => {
+                self.stack().push(any_value.clone());
+                array.borrow_mut().push(any_value);
+            }
+            _ => self.stack().push(Value::Null),
+        }
+        Ok(())
+    }
+
+    /// `Gt`, `Gte`, `Lt` and `Lte`.
+    #[inline(never)]
+    fn compare(&mut self, m: &Ref<Module>, op: &opcode::Op) -> Result<(), Value> {
+        use opcode::Op;
+        let lhs = self.stack().pop().unwrap();
+        let rhs = self.stack().pop().unwrap();
+        self.observe(m, op, &lhs, &rhs);
+        let ordering = lhs.compare(&rhs)?;
+        let result = match (op, ordering) {
+            (_, None) => false,
+            (Op::Gt, Some(ordering)) => ordering == Ordering::Greater,
+            (Op::Gte, Some(ordering)) => ordering != Ordering::Less,
+            (Op::Lt, Some(ordering)) => ordering == Ordering::Less,
+            (_, Some(ordering)) => ordering != Ordering::Greater,
+        };
+        self.stack().push(Value::Bool(result));
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn neg(&mut self, op: &opcode::Op) -> Result<(), Value> {
+        let val = self.stack().pop().unwrap();
+        match val {
+            Value::Int(x) => {
+                let result = int_arith(op, 0, x, self.overflow)?;
+                self.stack().push(result);
+            }
+            Value::Float(x) => self.stack().push(Value::Float(-x)),
+            Value::BigInt(x) => self.stack().push(Value::BigInt(Ref(-&*x.borrow()))),
+            Value::Decimal(x) => self.stack().push(Value::Decimal(Ref(x.borrow().neg()))),
+            _ => self.stack().push(Value::Null),
+        }
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn bit_not(&mut self) -> Result<(), Value> {
+        let val = self.stack().pop().unwrap();
+        match val {
+            Value::Int(x) => self.stack().push(Value::Int(!x)),
+            Value::BigInt(x) => self.stack().push(Value::BigInt(Ref(!&*x.borrow()))),
+            _ => {
+                return Err(Value::String(Ref(format!(
+                    "~: Int expected, got {}",
+                    val.type_name()
+                ))))
+            }
+        }
+        Ok(())
+    }
+
+    /// `New`: an empty object with the prototype on top of the stack.
+    #[inline(never)]
+    fn new_object(&mut self) -> Result<(), Value> {
+        let proto = self.stack().pop().unwrap();
+        let proto = match proto {
+            Value::Null => None,
+            Value::Object(obj) => Some(obj),
+            _ => {
+                return Err(Value::String(Ref(
+                    "Object or null expected as prototype".to_owned()
+                )))
+            }
+        };
+        let object = Object {
+            prototype: proto,
+            table: Default::default(),
+            modcount: 0,
+            attributes: Default::default(),
+            sealed: false,
+        };
+        self.stack().push(Value::Object(Ref(object)));
+        Ok(())
+    }
+}
+
+/// Both operands as floats when they are numbers and not both Ints, what the quickened
+/// arithmetic and comparison instructions handle besides two Ints.
+fn float_operands(lhs: &Value, rhs: &Value) -> Option<(f64, f64)> {
+    match (lhs, rhs) {
+        (Value::Int(x), Value::Float(y)) => Some((*x as f64, *y)),
+        (Value::Float(x), Value::Int(y)) => Some((*x, *y as f64)),
+        (Value::Float(x), Value::Float(y)) => Some((*x, *y)),
+        _ => None,
+    }
+}
+
+fn is_decimal_op(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::String(_), _) => false,
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => true,
+        _ => false,
+    }
+}
+
+fn is_bigint_op(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::String(_), _) => false,
+        (Value::BigInt(_), _) | (_, Value::BigInt(_)) => true,
+        _ => false,
+    }
+}
+
+/// Binds `args`, whose last entries are the named arguments in the table in global `names`,
+/// to the parameters of `function`.
+fn bind_named(
+    m: &Ref<Module>,
+    names: u32,
+    function: &Function,
+    args: Vec<Value>,
+) -> Result<Vec<Value>, Value> {
+    let names = match m.borrow().globals.get(names as usize) {
+        Some(Value::Object(names)) => names.clone(),
+        _ => unreachable!(),
+    };
+    let named = names
+        .borrow()
+        .table
+        .iter()
+        .map(|(name, index)| {
+            (
+                name.to_string(),
+                args[index.to_int().unwrap() as usize].clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let positional = &args[..args.len() - named.len()];
+    bind_arguments(
+        function,
+        positional,
+        named
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone())),
+    )
+}
+
+/// Arguments for calling `function` with `positional` arguments followed by `named` ones.
+/// Parameters without an argument are null.
+pub fn bind_arguments<'a>(
+    function: &Function,
+    positional: &[Value],
+    named: impl IntoIterator<Item = (&'a str, Value)>,
+) -> Result<Vec<Value>, Value> {
+    let error = |msg: String| Err(Value::String(Ref(msg)));
+    if function.params.is_empty() {
+        return error("Named arguments need a function with declared parameters".to_owned());
+    }
+    let mut bound = vec![None; function.params.len().max(positional.len())];
+    for (i, arg) in positional.iter().enumerate() {
+        bound[i] = Some(arg.clone());
+    }
+    for (name, value) in named {
+        let param = match function.params.iter().position(|param| param == name) {
+            Some(param) => param,
+            None => return error(format!("Unknown argument '{}'", name)),
+        };
+        if bound[param].is_some() {
+            return error(format!("Argument '{}' is given twice", name));
+        }
+        bound[param] = Some(value);
+    }
+    Ok(bound
+        .into_iter()
+        .map(|arg| arg.unwrap_or(Value::Null))
+        .collect())
+}
+
+/// Value of `object[key]` for `Load`, `null` if there is no such field, element or method.
+fn load(object: &Value, key: &Value) -> Value {
+    let value = match object {
+        Value::Array(array) => match key {
+            Value::Int(_) | Value::Float(_) => {
+                let array = array.borrow();
+                element_position(&array, key).and_then(|position| array.get(position))
+            }
+            Value::String(name) => builtins::iter::get_array_method(&name.borrow()),
+            _ => None,
+        },
+        Value::Object(object) => {
+            let value = object.borrow().get(key.clone());
+            value.or_else(|| match key {
+                Value::String(name) => builtins::object::get_object_method(&name.borrow()),
+                _ => None,
+            })
+        }
+        Value::Function(_) => match key {
+            Value::String(name) => builtins::func::get_function_method(&name.borrow()),
+            _ => None,
+        },
+        Value::Int(_) | Value::Float(_) | Value::BigInt(_) => match key {
+            Value::String(name) => builtins::num::get_number_method(&name.borrow()),
+            _ => None,
+        },
+        Value::String(s) => match key {
+            Value::Int(x) if *x >= 0 => s.borrow().chars().nth(*x as usize).map(Value::Char),
+            _ => None,
+        },
+        _ => None,
+    };
+    value.unwrap_or(Value::Null)
+}
+
+/// Array index `key` stands for, floats are truncated. `None` if `key` names a method.
+fn to_index(key: &Value) -> Option<i64> {
+    match key {
+        Value::Int(x) => Some(*x),
+        Value::Float(x) => Some(*x as i64),
+        _ => None,
+    }
+}
+
+/// Whether `key` indexes array elements rather than naming a method.
+fn is_index(key: &Value) -> bool {
+    to_index(key).is_some()
+}
+
+/// Position of the element `key` indexes, see `Elements::position`.
+fn element_position(array: &Elements, key: &Value) -> Option<usize> {
+    to_index(key).and_then(|index| array.position(index))
+}
+
+fn out_of_bounds(key: &Value, len: usize) -> Value {
+    Value::String(Ref(format!(
+        "Array index {} out of bounds for length {}",
+        key, len
+    )))
+}
+
+/// Field or method `key` of `object`, for `LoadStrict` and `InvokeMethod`. Builtin methods
+/// are looked up by borrowing the name, property accesses don't allocate.
+pub(crate) fn find_property(object: &Value, key: &Value) -> Option<Value> {
+    if let Value::Object(object) = object {
+        if let Some(value) = object.borrow().get(key.clone()) {
+            return Some(value);
+        }
+    }
+    let name = match key {
+        Value::String(name) => name.borrow(),
+        _ => return None,
+    };
+    match object {
+        Value::Object(_) => builtins::object::get_object_method(&name),
+        Value::Function(_) => builtins::func::get_function_method(&name),
+        Value::Array(_) => builtins::iter::get_array_method(&name),
+        Value::Int(_) | Value::Float(_) | Value::BigInt(_) => {
+            builtins::num::get_number_method(&name)
+        }
+        Value::User(user) if user.borrow().is::<builtins::promise::Promise>() => {
+            builtins::promise::get_promise_method(&name)
+        }
+        Value::User(user) if user.borrow().is::<builtins::channel::Channel>() => {
+            builtins::channel::get_channel_method(&name)
+        }
+        Value::User(user) if user.borrow().is::<builtins::shared::SharedCell>() => {
+            builtins::shared::get_shared_cell_method(&name)
+        }
+        Value::User(user) if user.borrow().is::<builtins::symbol::Symbol>() => {
+            builtins::symbol::get_symbol_method(&name)
+        }
+        Value::User(user)
+            if user.borrow().is::<builtins::iter::ValueIter>()
+                || user.borrow().is::<builtins::iter::Generator>() =>
+        {
+            builtins::iter::get_iter_method(&name)
+        }
+        #[cfg(feature = "native")]
+        Value::User(user) => builtins::plugin::get_plugin_method(user.borrow().get_kind(), &name),
+        _ => None,
+    }
+}
+
+/// Case of `value` in a switch table, the same one a chain of `==` tests against the keys
+/// would pick.
+fn switch_case(table: &Object, value: &Value) -> Option<usize> {
+    let key = match value {
+        Value::Int(_) | Value::String(_) => value.clone(),
+        Value::Float(x) if x.fract() == 0.0 => Value::Int(*x as i64),
+        Value::BigInt(x) => Value::Int(num_traits::ToPrimitive::to_i64(&*x.borrow())?),
+        Value::Char(ch) => match table.table.get(value) {
+            Some(case) => return case.to_int().map(|case| case as usize),
+            None => Value::Int(*ch as i64),
+        },
+        _ => return None,
+    };
+    table
+        .table
+        .get(&key)
+        .and_then(|case| case.to_int())
+        .map(|case| case as usize)
+}
+
+/// `ch + offset` and `ch - offset`, throws unless the result is a valid char.
+fn char_offset(ch: char, offset: i64) -> Result<Value, Value> {
+    let code = (ch as i64).checked_add(offset);
+    let result = code
+        .filter(|code| *code >= 0 && *code <= u32::MAX as i64)
+        .and_then(|code| std::char::from_u32(code as u32));
+    match result {
+        Some(ch) => Ok(Value::Char(ch)),
+        None => Err(Value::String(Ref(format!(
+            "Invalid char code {}",
+            code.map(|code| code.to_string()).unwrap_or_default()
+        )))),
+    }
+}
+
+/// Largest exact power `**` computes, in bits.
+const MAX_POW_BITS: usize = 1 << 24;
+
+/// `lhs ** rhs`. Integers raised to a non-negative Int stay exact, everything else is a
+/// Float. Throws instead of computing an exact power of more than `MAX_POW_BITS` bits.
+fn pow(lhs: &Value, rhs: &Value) -> Result<Value, Value> {
+    use num_bigint::BigInt;
+    use num_traits::Pow;
+    match (lhs, rhs) {
+        (Value::Int(_), Value::Int(y)) | (Value::BigInt(_), Value::Int(y)) if *y >= 0 => {
+            let bits = lhs.to_bigint().unwrap().bits();
+            // Powers of 0, 1 and -1 only depend on whether the exponent is 0 and its parity.
+            let y = match *y {
+                y if bits <= 1 && y > 2 => 2 + y % 2,
+                y => y,
+            };
+            if y > std::u32::MAX as i64 || bits as u128 * y as u128 > MAX_POW_BITS as u128 {
+                return Err(Value::String(Ref("Exponent too large".to_owned())));
+            }
+            let y = y as u32;
+            Ok(match lhs {
+                Value::Int(x) => match x.checked_pow(y) {
+                    Some(result) => Value::Int(result),
+                    None => Value::BigInt(Ref(BigInt::from(*x).pow(y))),
+                },
+                _ => Value::BigInt(Ref(lhs.to_bigint().unwrap().pow(y))),
+            })
+        }
+        _ => match (lhs.to_float(), rhs.to_float()) {
+            (Some(x), Some(y)) => Ok(Value::Float(x.powf(y))),
+            _ => Err(Value::String(Ref(format!(
+                "Cannot raise {} to {}",
+                lhs.type_name(),
+                rhs.type_name()
+            )))),
+        },
+    }
+}
+
+/// `x + y`, `x - y`, `x * y`, `x / y`, `x % y` or `-y` for `op` and its quickened form,
+/// wrapping around or throwing on overflow as `overflow` says. Division by zero always
+/// throws.
+fn int_arith(op: &opcode::Op, x: i64, y: i64, overflow: Overflow) -> Result<Value, Value> {
+    use opcode::Op;
+    if y == 0 && matches!(op, Op::Div | Op::Mod) {
+        return Err(Value::String(Ref("Division by zero".to_owned())));
+    }
+    let ((result, overflowed), symbol) = match op {
+        Op::Add | Op::AddNum => (x.overflowing_add(y), "+"),
+        Op::Sub | Op::SubNum => (x.overflowing_sub(y), "-"),
+        Op::Div => (x.overflowing_div(y), "/"),
+        // `i64::MIN % -1` is 0, only the quotient overflows.
+        Op::Mod => ((x.overflowing_rem(y).0, false), "%"),
+        Op::Neg => {
+            let (result, overflowed) = y.overflowing_neg();
+            if overflowed && overflow == Overflow::Throw {
+                return Err(Value::String(Ref(format!("Integer overflow in -({})", y))));
+            }
+            return Ok(Value::Int(result));
+        }
+        _ => (x.overflowing_mul(y), "*"),
+    };
+    if overflowed && overflow == Overflow::Throw {
+        return Err(Value::String(Ref(format!(
+            "Integer overflow in {} {} {}",
+            x, symbol, y
+        ))));
+    }
+    Ok(Value::Int(result))
+}
+
+/// Arithmetic with a Decimal operand, `Int` and `BigInt` operands are converted exactly.
+/// Quotients keep `DIVISION_SCALE` decimal places, rounded half to even, without trailing
+/// zeros.
+fn decimal_binop(op: &opcode::Op, lhs: &Value, rhs: &Value) -> Result<Value, Value> {
+    use crate::decimal::{Rounding, DIVISION_SCALE};
+    use opcode::Op;
+    let (x, y) = match (lhs.to_decimal(), rhs.to_decimal()) {
+        (Some(x), Some(y)) => (x, y),
+        _ => {
+            return Err(Value::String(Ref(format!(
+                "Cannot mix decimal and {} in arithmetic",
+                if lhs.tag() == ValTag::Decimal {
+                    rhs.type_name()
+                } else {
+                    lhs.type_name()
+                }
+            ))))
+        }
+    };
+    let result = match op {
+        Op::Add => Some(x.add(&y)),
+        Op::Sub => Some(x.sub(&y)),
+        Op::Mul => Some(x.mul(&y)),
+        Op::Div => x
+            .div(&y, DIVISION_SCALE, Rounding::HalfEven)
+            .map(|x| x.normalize()),
+        Op::Mod => x.rem(&y),
+        _ => unreachable!(),
+    };
+    match result {
+        Some(result) => Ok(Value::Decimal(Ref(result))),
+        None => Err(Value::String(Ref("Decimal division by zero".to_owned()))),
+    }
+}
+
+/// Arithmetic on BigInt operands, `Int` operands are promoted.
+fn bigint_binop(op: &opcode::Op, lhs: &Value, rhs: &Value) -> Result<Value, Value> {
+    use num_bigint::BigInt;
+    use opcode::Op;
+    let (x, y): (BigInt, BigInt) = match (lhs.to_bigint(), rhs.to_bigint()) {
+        (Some(x), Some(y)) => (x, y),
+        _ => {
+            return Err(Value::String(Ref(format!(
+                "Cannot mix BigInt and {:?} in arithmetic",
+                if lhs.tag() == ValTag::BigInt {
+                    rhs.tag()
+                } else {
+                    lhs.tag()
+                }
+            ))))
+        }
+    };
+    let zero = BigInt::from(0);
+    let result = match op {
+        Op::Add => x + y,
+        Op::Sub => x - y,
+        Op::Mul => x * y,
+        Op::Div | Op::Mod if y == zero => {
+            return Err(Value::String(Ref("BigInt division by zero".to_owned())))
+        }
+        Op::Div => x / y,
+        Op::Mod => x % y,
+        _ => unreachable!(),
+    };
+    Ok(Value::BigInt(Ref(result)))
+}
+
+fn error_field(e: &Value, name: &str) -> Option<Value> {
+    match e {
+        Value::Object(object) => object.borrow().get(Value::String(Ref(name.to_owned()))),
+        _ => None,
+    }
+}
+
+/// The `message` of error objects, or the value itself for anything else.
+pub fn error_message(e: &Value) -> String {
+    match error_field(e, "message") {
+        Some(message) => message.to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// Natives with an env are native closures and get the env instead of `this` as first argument.
+pub fn call_native(
+    function: &Function,
+    this: Option<Value>,
+    args: &[Value],
+) -> Result<Value, Value> {
+    let fun: fn(&[Value]) -> Result<Value, Value> =
+        unsafe { std::mem::transmute(function.address) };
+    let mut new_args = vec![];
+    match (&function.env, this) {
+        (Value::Null, Some(this)) => new_args.push(this),
+        (Value::Null, None) => (),
+        (env, _) => new_args.push(env.clone()),
+    }
+    new_args.extend_from_slice(args);
+    fun(&new_args)
+}
+
+/// Calls `f` like `ObjCall` does: a native gets `this` as first argument, whatever it is.
 pub fn val_callex(f: Value, this: Value, args: &[Value]) -> Result<Value, Value> {
+    call_value(f, Some(this), args)
+}
+
+/// Calls `f` like `Call` does, without `this`.
+pub fn val_call(f: Value, args: &[Value]) -> Result<Value, Value> {
+    call_value(f, None, args)
+}
+
+fn call_value(f: Value, this: Option<Value>, args: &[Value]) -> Result<Value, Value> {
     let mut vm = get_vm!();
     match f {
         Value::Function(f) => {
             let function = f.borrow();
             if function.native {
-                let fun: fn(&[Value]) -> Result<Value, Value> =
-                    unsafe { std::mem::transmute(function.address) };
-                let mut new_args = vec![this];
-                for i in args.iter() {
-                    new_args.push(i.clone());
-                }
-
-                return fun(&new_args);
+                return call_native(&function, this, args);
             } else {
                 vm.save_state_exit();
                 let env = vm.env.clone();
@@ -804,7 +1846,7 @@ pub fn val_callex(f: Value, this: Value, args: &[Value]) -> Result<Value, Value>
                 let pc = vm.pc.clone();
                 let this_ = vm.this.clone();
                 vm.pc = function.address;
-                vm.this = this;
+                vm.this = this.unwrap_or(Value::Null);
                 vm.env = function.env.clone();
                 vm.locals = Ref(HashMap::new());
                 if args.len() > function.argc as usize {
@@ -815,14 +1857,47 @@ pub fn val_callex(f: Value, this: Value, args: &[Value]) -> Result<Value, Value>
                 for (i, arg) in args.iter().enumerate() {
                     vm.locals.borrow_mut().insert(i as u16, arg.clone());
                 }
+                let sp = vm.stack.borrow().len();
                 let value = vm.interp(function.module.as_ref().unwrap().clone());
+                vm.stack().truncate(sp);
                 vm.env = env;
                 vm.locals = locals;
                 vm.pc = pc;
                 vm.this = this_;
-                return Ok(value);
+                return match vm.pending.take() {
+                    Some(e) => Err(e),
+                    None => Ok(value),
+                };
             }
         }
         _ => return Err(Value::String(Ref("Function expected".to_owned()))),
     }
 }
+
+/// `val_callex` with named arguments, e.g. `val_callex_kw(f, Value::Null, &[("x", x)])`.
+pub fn val_callex_kw(f: Value, this: Value, args: &[(&str, Value)]) -> Result<Value, Value> {
+    let args = match &f {
+        Value::Function(function) => bind_arguments(
+            &function.borrow(),
+            &[],
+            args.iter().map(|(name, value)| (*name, value.clone())),
+        )?,
+        _ => return Err(Value::String(Ref("Function expected".to_owned()))),
+    };
+    val_callex(f, this, &args)
+}
+
+/// Calls the function `m` exports as `name` with named arguments.
+pub fn call_kw(m: &Ref<Module>, name: &str, args: &[(&str, Value)]) -> Result<Value, Value> {
+    let f = match &m.borrow().exports {
+        Value::Object(exports) => exports.borrow().get(Value::String(Ref(name.to_owned()))),
+        _ => None,
+    };
+    match f {
+        Some(f) => val_callex_kw(f, Value::Null, args),
+        None => Err(Value::String(Ref(format!(
+            "Module has no export '{}'",
+            name
+        )))),
+    }
+}
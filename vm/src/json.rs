@@ -0,0 +1,231 @@
+//! Conversion between values and JSON, for hosts exchanging data with scripts.
+
+use crate::*;
+use value::*;
+
+/// Nesting of arrays and objects `to_json` follows, deeper values are assumed to be cyclic.
+const MAX_DEPTH: usize = 256;
+
+fn quote(s: &str, out: &mut String) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+fn write_value(value: &Value, depth: usize, out: &mut String) -> Result<(), String> {
+    if depth == MAX_DEPTH {
+        return Err("value nested too deeply, or cyclic".to_owned());
+    }
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(x) => out.push_str(if *x { "true" } else { "false" }),
+        Value::Int(x) => out.push_str(&x.to_string()),
+        Value::Float(x) if x.is_finite() => out.push_str(&format!("{:?}", x)),
+        Value::Float(_) => out.push_str("null"),
+        Value::BigInt(x) => out.push_str(&x.borrow().to_string()),
+        Value::Decimal(x) => out.push_str(&x.borrow().to_string()),
+        Value::Char(ch) => quote(&ch.to_string(), out),
+        Value::String(s) => quote(&s.borrow(), out),
+        Value::Array(array) => {
+            out.push('[');
+            for (i, x) in array.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(&x, depth + 1, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(object) => {
+            out.push('{');
+            for (i, (key, x)) in object.borrow().fields().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                quote(&key.to_string(), out);
+                out.push(':');
+                write_value(x, depth + 1, out)?;
+            }
+            out.push('}');
+        }
+        Value::Function(_) | Value::User(_) => {
+            return Err(format!("{} can't be converted to JSON", value.type_name()));
+        }
+    }
+    Ok(())
+}
+
+/// JSON text of `value`. Object keys are converted to strings, non-finite floats to `null`.
+pub fn to_json(value: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(value, 0, &mut out)?;
+    Ok(out)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!("{} at offset {}", msg, self.pos))
+    }
+
+    fn skip_white(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_white();
+        if self.src[self.pos..].starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}' expected", s))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            let ch = match self.peek() {
+                Some(ch) => ch,
+                None => return self.error("unterminated string"),
+            };
+            self.pos += ch.len_utf8();
+            match ch {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escape = self.peek();
+                    self.pos += 1;
+                    s.push(match escape {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hex = self.src.get(self.pos..self.pos + 4).unwrap_or("");
+                            let code = u32::from_str_radix(hex, 16).ok();
+                            self.pos += 4;
+                            match code.and_then(std::char::from_u32) {
+                                Some(ch) => ch,
+                                None => return self.error("invalid \\u escape"),
+                            }
+                        }
+                        _ => return self.error("invalid escape"),
+                    });
+                }
+                ch => s.push(ch),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let len = self.src[start..]
+            .find(|ch: char| !(ch.is_ascii_digit() || "+-.eE".contains(ch)))
+            .unwrap_or(self.src.len() - start);
+        self.pos += len;
+        let text = &self.src[start..self.pos];
+        if let Ok(x) = text.parse::<i64>() {
+            return Ok(Value::Int(x));
+        }
+        match text.parse::<f64>() {
+            Ok(x) => Ok(Value::Float(x)),
+            Err(_) => self.error("invalid number"),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth == MAX_DEPTH {
+            return self.error("value nested too deeply");
+        }
+        self.skip_white();
+        match self.peek() {
+            Some('n') if self.eat("null") => Ok(Value::Null),
+            Some('t') if self.eat("true") => Ok(Value::Bool(true)),
+            Some('f') if self.eat("false") => Ok(Value::Bool(false)),
+            Some('"') => Ok(Value::String(Ref(self.string()?))),
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => self.number(),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                if !self.eat("]") {
+                    loop {
+                        items.push(self.value(depth + 1)?);
+                        if self.eat("]") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Value::Array(Ref(items.into())))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut object = Object {
+                    prototype: None,
+                    table: Default::default(),
+                    modcount: 0,
+                    attributes: Default::default(),
+                    sealed: false,
+                };
+                if !self.eat("}") {
+                    loop {
+                        self.skip_white();
+                        let key = self.string()?;
+                        self.expect(":")?;
+                        let value = self.value(depth + 1)?;
+                        object.set(Value::String(Ref(key)), value);
+                        if self.eat("}") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Ok(Value::Object(Ref(object)))
+            }
+            _ => self.error("value expected"),
+        }
+    }
+}
+
+/// Value of the JSON text `src`. Numbers without fraction or exponent that fit are Ints.
+pub fn from_json(src: &str) -> Result<Value, String> {
+    let mut parser = Parser { src, pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_white();
+    if parser.pos < src.len() {
+        return parser.error("unexpected text after the value");
+    }
+    Ok(value)
+}
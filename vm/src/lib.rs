@@ -8,17 +8,26 @@ extern crate mopa;
 pub mod interp;
 pub mod atomic_ref;
 pub mod builtins;
+pub mod clock;
+pub mod decimal;
+pub mod deflate;
+pub mod elements;
+pub mod feedback;
 pub mod gc;
 
 pub mod jit;
+pub mod json;
+pub mod loader;
 pub mod opcode;
+pub mod profile;
 pub mod reader;
+pub mod trace;
 pub mod value;
 pub mod writer;
 
-use mimalloc::MiMalloc;
+#[cfg(feature = "mimalloc")]
 #[global_allocator]
-pub static GLOBAL: MiMalloc = MiMalloc;
+pub static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 pub use std::cell::RefCell;
 pub use std::rc::{Rc, Weak};
@@ -38,9 +47,122 @@ use value::Value;
 
 pub struct Module {
     pub exports: Value,
-    pub code: Vec<opcode::Op>,
+    /// Shared so the interpreter can hold on to it while running without borrowing the module.
+    pub code: Rc<[Instr]>,
     pub globals: Vec<Value>,
     pub trace_info: HashMap<u32, (usize, String)>,
+    /// What each instruction has seen so far, empty until one of them records some.
+    pub feedback: Vec<feedback::Feedback>,
+}
+
+/// An instruction as codegen wrote it, with the variant running it put in its place if it
+/// was quickened, see `feedback`.
+#[derive(Debug)]
+pub struct Instr {
+    pub op: opcode::Op,
+    pub quick: std::cell::Cell<Option<feedback::Quick>>,
+}
+
+impl Instr {
+    /// The instruction to run.
+    pub fn current(&self) -> opcode::Op {
+        match self.quick.get() {
+            Some(quick) => quick.op(),
+            None => self.op.clone(),
+        }
+    }
+}
+
+/// Sizes of a module's constant pool, see `Module::pool_stats`.
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    pub strings: usize,
+    pub floats: usize,
+    pub bigints: usize,
+    pub functions: usize,
+    /// Variables and other globals starting as `null`.
+    pub others: usize,
+    /// Instructions loading or storing a global.
+    pub uses: usize,
+}
+
+impl std::fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} strings, {} floats, {} bigints, {} functions, {} others, {} uses",
+            self.strings, self.floats, self.bigints, self.functions, self.others, self.uses
+        )
+    }
+}
+
+impl Module {
+    /// Replaces the code, forgetting the feedback about the old one.
+    pub fn set_code(&mut self, code: Vec<opcode::Op>) {
+        self.code = code
+            .into_iter()
+            .map(|op| Instr {
+                op,
+                quick: Default::default(),
+            })
+            .collect();
+        self.feedback.clear();
+    }
+
+    /// Feedback of the instruction at `pc`.
+    pub fn feedback_at(&mut self, pc: usize) -> &mut feedback::Feedback {
+        if self.feedback.len() < self.code.len() {
+            self.feedback.resize(self.code.len(), Default::default());
+        }
+        &mut self.feedback[pc]
+    }
+
+    /// Counts the globals of each kind and the instructions referring to them. Codegen
+    /// interns identical literals, so each appears once however often it is used.
+    pub fn pool_stats(&self) -> PoolStats {
+        let mut stats = PoolStats::default();
+        for value in self.globals.iter() {
+            match value {
+                Value::String(_) => stats.strings += 1,
+                Value::Float(_) => stats.floats += 1,
+                Value::BigInt(_) => stats.bigints += 1,
+                Value::Function(_) => stats.functions += 1,
+                _ => stats.others += 1,
+            }
+        }
+        stats.uses = self
+            .code
+            .iter()
+            .filter(|instr| match instr.op {
+                opcode::Op::LoadGlobal(_)
+                | opcode::Op::StoreGlobal(_)
+                | opcode::Op::Switch(..)
+                | opcode::Op::InvokeMethod(..)
+                | opcode::Op::CallNamed(..)
+                | opcode::Op::InvokeNamed(..) => true,
+                _ => false,
+            })
+            .count();
+        stats
+    }
+
+    /// Instructions of the function at `address`. Codegen lays functions out one after
+    /// the other, followed by the module's own code which the first instruction jumps to.
+    pub fn function_code(&self, address: usize) -> std::ops::Range<usize> {
+        let mut end = match self.code.first().map(|instr| &instr.op) {
+            Some(opcode::Op::Jump(main)) if *main as usize > address => *main as usize,
+            _ => self.code.len(),
+        };
+        for value in self.globals.iter() {
+            if let Value::Function(f) = value {
+                let start = f.borrow().address;
+                if start > address && start < end {
+                    end = start;
+                }
+            }
+        }
+        address..end
+    }
 }
 
 /*
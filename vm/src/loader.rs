@@ -0,0 +1,84 @@
+//! Where `$load` gets modules from. Embedders can serve bytecode from memory, archives or
+//! a database by registering their own `ModuleLoader`, and rename modules with an import
+//! map.
+
+use crate::reader::BytecodeReader;
+use crate::*;
+
+use std::collections::HashMap;
+
+pub trait ModuleLoader {
+    /// Key of the module `name` refers to, passed to `load`. `None` if there is none.
+    fn resolve(&self, name: &str) -> Option<String>;
+    /// Bytecode of the module `resolve` returned `path` for. Loaders serving source can
+    /// compile it with `jazzlightc`.
+    fn load(&self, path: &str) -> Result<Vec<u8>, String>;
+}
+
+/// Loads `.j` files, looking in the directory `JAZZLIGHT_PATH` was set to at build time
+/// first, then in the modules `jazz build` compiled and the packages installed in
+/// `jazz_modules/`.
+pub struct FileLoader;
+
+impl ModuleLoader for FileLoader {
+    fn resolve(&self, name: &str) -> Option<String> {
+        let exists = |path: &str| std::path::Path::new(path).exists();
+        let libs_path: Option<&'static str> = option_env!("JAZZLIGHT_PATH");
+        let path = match libs_path {
+            Some(lpath) if exists(&format!("{}/{}", lpath, name)) => format!("{}/{}", lpath, name),
+            Some(lpath) if exists(&format!("{}/{}.j", lpath, name)) => {
+                format!("{}/{}.j", lpath, name)
+            }
+            _ if exists(&format!("build/{}.j", name)) => format!("build/{}.j", name),
+            _ if exists(&format!("jazz_modules/{}.j", name)) => {
+                format!("jazz_modules/{}.j", name)
+            }
+            _ => name.to_owned(),
+        };
+        if exists(&format!("{}.j", path)) {
+            Some(format!("{}.j", path))
+        } else {
+            Some(path)
+        }
+    }
+
+    fn load(&self, path: &str) -> Result<Vec<u8>, String> {
+        std::fs::read(path).map_err(|e| e.to_string())
+    }
+}
+
+thread_local! {
+    static LOADER: RefCell<Rc<dyn ModuleLoader>> = RefCell::new(Rc::new(FileLoader));
+    /// Names `$load` replaces before resolving them.
+    static IMPORT_MAP: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Makes `$load` get modules from `loader` on this thread.
+pub fn set_module_loader(loader: impl ModuleLoader + 'static) {
+    LOADER.with(|current| *current.borrow_mut() = Rc::new(loader));
+}
+
+/// Makes `$load(alias)` load `target` instead. Targets aren't looked up in the map again.
+pub fn add_import_alias(alias: &str, target: &str) {
+    IMPORT_MAP.with(|map| map.borrow_mut().insert(alias.to_owned(), target.to_owned()));
+}
+
+pub fn clear_import_map() {
+    IMPORT_MAP.with(|map| map.borrow_mut().clear());
+}
+
+/// Reads the module `name` refers to with the current loader, without running it.
+pub fn load_module(name: &str) -> Result<Ref<Module>, String> {
+    let name = IMPORT_MAP
+        .with(|map| map.borrow().get(name).cloned())
+        .unwrap_or_else(|| name.to_owned());
+    let loader = LOADER.with(|loader| loader.borrow().clone());
+    let path = match loader.resolve(&name) {
+        Some(path) => path,
+        None => return Err(format!("no module named '{}'", name)),
+    };
+    match loader.load(&path) {
+        Ok(bytecode) => Ok(BytecodeReader::new(&bytecode).read_module()),
+        Err(e) => Err(format!("failed to load module at '{}': {}", path, e)),
+    }
+}
@@ -1,19 +1,114 @@
 #[macro_use]
 extern crate jazzlight;
 
+use jazzlight::builtins::os::{shutdown, EXIT_CANT_CREATE, EXIT_NO_INPUT, EXIT_USAGE};
 use jazzlight::interp::*;
 
+use jazzlight::profile::{layout, read_profile, Profiler};
 use jazzlight::reader::BytecodeReader;
-use jazzlight::value::Value;
+use jazzlight::trace::{read_trace, replay, Recorder};
+use jazzlight::value::{set_hash_seed, Value};
+use jazzlight::writer::BytecodeWriter;
 use std::io::Cursor;
 
+/// `jazzlight --replay <trace>`: steps through a trace recorded with `--record`.
+fn replay_trace(path: &str) -> ! {
+    let entries = std::fs::read(path).and_then(|contents| read_trace(&contents));
+    match entries {
+        Ok(entries) => {
+            let stdin = std::io::stdin();
+            replay(&entries, stdin.lock());
+            std::process::exit(0)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(EXIT_NO_INPUT)
+        }
+    }
+}
+
+/// `jazzlight --layout <profile> <file> <out>`: writes `<file>` with its functions laid out
+/// by the profile to `<out>`, see `profile::layout`.
+fn layout_module(profile: &str, file: &str, out: &str) -> ! {
+    let result = (|| {
+        let counts = read_profile(&std::fs::read(profile).map_err(|e| (profile, e.to_string()))?)
+            .map_err(|e| (profile, e.to_string()))?;
+        let contents = std::fs::read(file).map_err(|e| (file, e.to_string()))?;
+        let m = BytecodeReader::new(&contents).read_module();
+        layout(&m, &counts).map_err(|e| (file, e))?;
+        let mut writer = BytecodeWriter { bytecode: vec![] };
+        writer.write_module(m);
+        std::fs::write(out, &writer.bytecode).map_err(|e| (out, e.to_string()))
+    })();
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err((path, e)) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(EXIT_NO_INPUT)
+        }
+    }
+}
+
 fn main() {
-    let file = std::env::args().nth(1);
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    if args.len() == 2 && args[0] == "--replay" {
+        replay_trace(&args[1]);
+    }
+    if args.len() == 4 && args[0] == "--layout" {
+        layout_module(&args[1], &args[2], &args[3]);
+    }
+    // `jazzlight --record <trace> <file>` writes every instruction run to `<trace>`,
+    // `--profile <profile>` how many times each instruction of `<file>` ran and
+    // `--hash-seed <seed>` hashes fields with a fixed seed instead of random keys and
+    // `--overflow <wrap|throw>` says what Int arithmetic does on overflow.
+    while args.len() >= 3
+        && (args[0] == "--record"
+            || args[0] == "--profile"
+            || args[0] == "--hash-seed"
+            || args[0] == "--overflow")
+    {
+        if args[0] == "--overflow" {
+            get_vm!().overflow = match args[1].as_str() {
+                "wrap" => Overflow::Wrap,
+                "throw" => Overflow::Throw,
+                _ => {
+                    eprintln!("--overflow: expected wrap or throw, got {}", args[1]);
+                    std::process::exit(EXIT_USAGE);
+                }
+            };
+            args.drain(..2);
+            continue;
+        }
+        if args[0] == "--hash-seed" {
+            match args[1].parse::<u64>() {
+                Ok(seed) => set_hash_seed(seed),
+                Err(_) => {
+                    eprintln!("--hash-seed: {} is not a number", args[1]);
+                    std::process::exit(EXIT_USAGE);
+                }
+            }
+            args.drain(..2);
+            continue;
+        }
+        let created = if args[0] == "--record" {
+            Recorder::create(&args[1]).map(|recorder| get_vm!().recorder = Some(recorder))
+        } else {
+            Profiler::create(&args[1]).map(|profiler| get_vm!().profiler = Some(profiler))
+        };
+        if let Err(e) = created {
+            eprintln!("{}: {}", args[1], e);
+            std::process::exit(EXIT_CANT_CREATE);
+        }
+        args.drain(..2);
+    }
+    let mut args = args.into_iter();
+    let file = args.next();
     if file.is_none() {
         eprintln!("Please select JazzLight bytecode file");
-        std::process::exit(1);
+        std::process::exit(EXIT_USAGE);
     }
     let file = file.unwrap();
+    get_vm!().args = args.collect();
 
     let contents = std::fs::read(&file);
     match contents {
@@ -24,14 +119,17 @@ fn main() {
             let m = reader.read_module();
             let vm = get_vm!();
             vm.save_state_exit();
-            match vm.interp(m) {
-                Value::Int(x) => std::process::exit(x as _),
-                _ => (),
-            }
+            let value = vm.interp(m);
+            jazzlight::builtins::promise::run_event_loop();
+            let code = match value {
+                Value::Int(x) => x as i32,
+                _ => 0,
+            };
+            shutdown(code);
         }
         Err(e) => {
             eprintln!("{}", e);
-            std::process::exit(1);
+            std::process::exit(EXIT_NO_INPUT);
         }
     }
 }
@@ -1,4 +1,9 @@
-#[derive(Clone, Debug)]
+/// Largest number of instructions in a module, jumps are encoded as `i32` offsets.
+pub const MAX_CODE_SIZE: usize = std::i32::MAX as usize;
+
+/// Instructions. Locals, environment slots and argument counts are 16-bit operands, globals
+/// and jump targets 32-bit; codegen reports an error instead of truncating larger values.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Op {
     LoadNull,
     LoadTrue,
@@ -23,9 +28,12 @@ pub enum Op {
     JumpIfNot(u32),
     /// Push catch block address
     CatchPush(u32),
+    /// Drops the innermost catch block when leaving a try body without an exception
+    CatchPop,
     Throw,
     Ret,
     MakeEnv(u16),
+    /// Array of the top N values, the first pushed value is the first element.
     MakeArray(u16),
     IsNull,
     IsNotNull,
@@ -34,6 +42,8 @@ pub enum Op {
     Div,
     Mul,
     Mod,
+    /// `**`, integer results that don't fit an Int become BigInts
+    Pow,
     Shl,
     Shr,
     UShr,
@@ -42,6 +52,8 @@ pub enum Op {
     Xor,
     Eq,
     Neq,
+    /// `===`: same type and value, arrays, objects and functions must be the same reference
+    StrictEq,
     Gt,
     Gte,
     Lt,
@@ -51,6 +63,48 @@ pub enum Op {
     Hash,
     New,
     Nop,
+    /// Like `Load`, but throws if the field doesn't exist. Used in strict mode.
+    LoadStrict,
+    /// Stores into the module's globals, used for module-level variables.
+    StoreGlobal(u32),
+    /// `Switch(table, cases)`: pops a value and continues at the N-th of the `cases` jumps that
+    /// follow if the switch table in global `table` maps it to N, at the jump after them
+    /// otherwise. Used for `match` on constants.
+    Switch(u32, u32),
+    /// `InvokeMethod(name, argc)`: pops the receiver and calls its field or method named by
+    /// global `name` with `argc` arguments and the receiver as `this`. Throws if there is no
+    /// such property.
+    InvokeMethod(u32, u16),
+    /// `CallNamed(argc, names)`: like `Call`, the last arguments are passed by name. Global
+    /// `names` maps their names to their positions.
+    CallNamed(u16, u32),
+    /// `InvokeNamed(name, argc, names)`: `InvokeMethod` with named arguments.
+    InvokeNamed(u32, u16, u32),
+    /// Pops a value and suspends the running generator body, which gives it to the resumer.
+    /// Resuming continues after it.
+    Yield,
+    /// Pops an object and a key and removes the object's own field `key`, pushes whether it
+    /// had one.
+    Delete,
+    /// Like `Load`, but throws if an array index is out of bounds. Used for `a[i]` in strict
+    /// mode.
+    LoadElementStrict,
+    /// `~`: pops an Int or BigInt and pushes its bitwise complement.
+    BitNot,
+
+    /// `Add` of two numbers. This and the following variants only appear in running code,
+    /// where the interpreter puts them in place of the generic instruction, see `feedback`.
+    AddNum,
+    SubNum,
+    MulNum,
+    GtNum,
+    GteNum,
+    LtNum,
+    LteNum,
+    /// `Load` of a field of an object.
+    LoadField,
+    /// `Load` of an element of an array.
+    LoadIndex,
 
     Last,
 }
@@ -0,0 +1,158 @@
+//! Instruction counts of a run and profile-guided layout of a module's code.
+//!
+//! A profile file starts with `MAGIC`, followed by the number of instructions of the
+//! profiled module and how many times each ran, all little-endian. Only the module the run
+//! started with is counted, modules it loads have their own addresses.
+
+use crate::opcode::Op;
+use crate::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use value::*;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Write};
+
+pub const MAGIC: &[u8; 4] = b"JZPF";
+
+pub struct Profiler {
+    path: String,
+    module: Option<Ref<Module>>,
+    counts: Vec<u64>,
+}
+
+impl Profiler {
+    /// Profiler writing to `path` when finished. Creates the file right away so a path that
+    /// can't be written to is reported before the run.
+    pub fn create(path: &str) -> io::Result<Profiler> {
+        File::create(path)?;
+        Ok(Profiler {
+            path: path.to_owned(),
+            module: None,
+            counts: vec![],
+        })
+    }
+
+    /// Counts that the instruction at `pc` of `m` is about to run. The first module seen is
+    /// the one profiled.
+    pub fn count(&mut self, m: &Ref<Module>, pc: usize) {
+        match &self.module {
+            Some(module) if Rc::ptr_eq(module, m) => self.counts[pc] += 1,
+            Some(_) => (),
+            None => {
+                self.counts = vec![0; m.borrow().code.len()];
+                self.counts[pc] = 1;
+                self.module = Some(m.clone());
+            }
+        }
+    }
+
+    pub fn finish(self) {
+        let result = (|| {
+            let mut out = BufWriter::new(File::create(&self.path)?);
+            out.write_all(MAGIC)?;
+            out.write_u32::<LittleEndian>(self.counts.len() as u32)?;
+            for count in self.counts.iter() {
+                out.write_u64::<LittleEndian>(*count)?;
+            }
+            out.flush()
+        })();
+        if let Err(e) = result {
+            eprintln!("profile: {}", e);
+        }
+    }
+}
+
+/// Instruction counts of the profile in `contents`.
+pub fn read_profile(contents: &[u8]) -> io::Result<Vec<u64>> {
+    if !contents.starts_with(MAGIC) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a profile"));
+    }
+    let mut bytes = Cursor::new(contents);
+    bytes.set_position(MAGIC.len() as u64);
+    let len = bytes.read_u32::<LittleEndian>()?;
+    (0..len).map(|_| bytes.read_u64::<LittleEndian>()).collect()
+}
+
+/// Moves the functions of `m` so the hottest come first, right after the jump to the
+/// module's own code, and sets the `hotness` of each to the number of its instructions that
+/// ran. Functions that didn't run keep their order at the end. `counts` must come from a
+/// run of the same code.
+pub fn layout(m: &Ref<Module>, counts: &[u64]) -> Result<(), String> {
+    let mut m = m.borrow_mut();
+    let code = m
+        .code
+        .iter()
+        .map(|instr| instr.op.clone())
+        .collect::<Vec<_>>();
+    if counts.len() != code.len() {
+        return Err(format!(
+            "profile of {} instructions for a module of {}",
+            counts.len(),
+            code.len()
+        ));
+    }
+    let main = match code.first() {
+        Some(Op::Jump(main)) => *main as usize,
+        _ => return Ok(()),
+    };
+    let mut functions = m
+        .globals
+        .iter()
+        .filter_map(|value| match value {
+            Value::Function(f) if !f.borrow().native => Some(f.borrow().address),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    functions.sort_unstable();
+    functions.dedup();
+    // Codegen lays the functions out one after the other between the jump and `main`.
+    if functions.first() != Some(&1) || functions.last().is_some_and(|&f| f >= main) {
+        return Err("functions aren't laid out by the compiler".to_owned());
+    }
+    let mut ranges = functions
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let end = functions.get(i + 1).copied().unwrap_or(main);
+            (start..end, counts[start..end].iter().sum::<u64>())
+        })
+        .collect::<Vec<_>>();
+    ranges.sort_by(|(a, x), (b, y)| y.cmp(x).then(a.start.cmp(&b.start)));
+
+    let mut moved = vec![0; code.len() + 1];
+    let mut order = vec![0];
+    for (range, _) in ranges.iter() {
+        order.extend(range.clone());
+    }
+    order.extend(main..code.len());
+    for (new, &old) in order.iter().enumerate() {
+        moved[old] = new;
+    }
+    moved[code.len()] = code.len();
+    let at = |to: &u32| moved[*to as usize] as u32;
+    let code = order
+        .iter()
+        .map(|&old| match &code[old] {
+            Op::Jump(to) => Op::Jump(at(to)),
+            Op::JumpIf(to) => Op::JumpIf(at(to)),
+            Op::JumpIfNot(to) => Op::JumpIfNot(at(to)),
+            Op::CatchPush(to) => Op::CatchPush(at(to)),
+            op => op.clone(),
+        })
+        .collect::<Vec<_>>();
+    for value in m.globals.iter() {
+        if let Value::Function(f) = value {
+            let mut f = f.borrow_mut();
+            if let Some((_, hotness)) = ranges.iter().find(|(range, _)| range.start == f.address) {
+                f.hotness = *hotness;
+                f.address = moved[f.address];
+            }
+        }
+    }
+    m.trace_info = std::mem::take(&mut m.trace_info)
+        .into_iter()
+        .map(|(pc, info)| (moved[pc as usize] as u32, info))
+        .collect();
+    m.set_code(code);
+    Ok(())
+}
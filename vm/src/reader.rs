@@ -12,6 +12,15 @@ pub const TAG_STRING: u8 = 0;
 pub const TAG_FLOAT: u8 = 1;
 pub const TAG_DBGINFO: u8 = 2;
 pub const TAG_FUN: u8 = 3;
+pub const TAG_BIGINT: u8 = 4;
+/// Slot of a global variable, starts out as null.
+pub const TAG_NULL: u8 = 5;
+pub const TAG_CHAR: u8 = 6;
+/// Object of constant keys mapped to Ints, see `Op::Switch` and `Op::CallNamed`.
+pub const TAG_TABLE: u8 = 7;
+pub const TAG_INT: u8 = 8;
+/// String index of a function without source text or doc comment.
+pub const NO_SOURCE: u32 = std::u32::MAX;
 
 impl<'a> BytecodeReader<'a> {
     pub fn new(bytes: &'a [u8]) -> Self {
@@ -29,6 +38,11 @@ impl<'a> BytecodeReader<'a> {
     pub fn read_u32(&mut self) -> u32 {
         self.bytes.read_u32::<LittleEndian>().unwrap()
     }
+    /// Reads a jump offset, see `BytecodeWriter::write_jump`, and returns the absolute target.
+    pub fn read_jump(&mut self, pc: u32) -> u32 {
+        let offset = self.read_u32() as i32;
+        (pc as i64 + 1 + offset as i64) as u32
+    }
     pub fn read_u64(&mut self) -> u64 {
         self.bytes.read_u64::<LittleEndian>().unwrap()
     }
@@ -53,9 +67,13 @@ impl<'a> BytecodeReader<'a> {
             exports: Value::Object(Ref(Object {
                 prototype: None,
                 table: Default::default(),
+                modcount: 0,
+                attributes: Default::default(),
+                sealed: false,
             })),
             trace_info: HashMap::new(),
-            code: vec![],
+            code: Rc::from(vec![]),
+            feedback: vec![],
             globals: vec![],
         });
         let mut strings = Vec::new();
@@ -93,17 +111,68 @@ impl<'a> BytecodeReader<'a> {
                 TAG_FUN => {
                     let at = self.read_u32();
                     let argc = self.read_u16();
-                    let env = Ref(vec![]);
+                    let params = (0..self.read_u16())
+                        .map(|_| strings[self.read_u32() as usize].clone())
+                        .collect::<Vec<_>>();
+                    let mut text = || match self.read_u32() {
+                        NO_SOURCE => None,
+                        idx => Some(Rc::from(&strings[idx as usize][..])),
+                    };
+                    let source = text();
+                    let doc = text();
+                    let max_stack = self.read_u32();
+                    let hotness = self.read_u64();
+                    let env = Ref(Elements::new());
                     let fun = Function {
                         address: at as _,
                         native: false,
                         env: Value::Array(env),
                         argc: argc as _,
                         module: Some(m.clone()),
+                        params: params.into(),
+                        source,
+                        doc,
+                        max_stack,
+                        hotness,
                     };
                     //gc_add_root(env);
                     m.borrow_mut().globals.push(Value::Function(Ref(fun)));
                 }
+                TAG_BIGINT => {
+                    let len = self.read_u32();
+                    let mut bytes = vec![];
+                    for _ in 0..len {
+                        bytes.push(self.read_u8());
+                    }
+                    let x = num_bigint::BigInt::from_signed_bytes_le(&bytes);
+                    m.borrow_mut().globals.push(Value::BigInt(Ref(x)));
+                }
+                TAG_NULL => m.borrow_mut().globals.push(Value::Null),
+                TAG_CHAR => {
+                    let ch = std::char::from_u32(self.read_u32()).unwrap();
+                    m.borrow_mut().globals.push(Value::Char(ch));
+                }
+                TAG_TABLE => {
+                    let mut table = Object {
+                        prototype: None,
+                        table: Default::default(),
+                        modcount: 0,
+                        attributes: Default::default(),
+                        sealed: false,
+                    };
+                    for _ in 0..self.read_u32() {
+                        let key = match self.read_u8() {
+                            TAG_INT => Value::Int(self.read_u64() as i64),
+                            TAG_CHAR => Value::Char(std::char::from_u32(self.read_u32()).unwrap()),
+                            TAG_STRING => {
+                                Value::String(Ref(strings[self.read_u32() as usize].clone()))
+                            }
+                            _ => unreachable!(),
+                        };
+                        table.set(key, Value::Int(self.read_u32() as i64));
+                    }
+                    m.borrow_mut().globals.push(Value::Object(Ref(table)));
+                }
                 TAG_DBGINFO => {
                     m.borrow_mut().trace_info = self.read_dbginfo(&strings, code_size as _);
                 }
@@ -111,7 +180,8 @@ impl<'a> BytecodeReader<'a> {
             }
         }
         use opcode::Op;
-        for _ in 0..code_size {
+        let mut code = Vec::with_capacity(code_size as usize);
+        for pc in 0..code_size {
             let op = self.read_u8();
             let opcode = match op {
                 0 => Op::LoadNull,
@@ -167,19 +237,19 @@ impl<'a> BytecodeReader<'a> {
                     Op::TailCall(count)
                 }
                 18 => {
-                    let to = self.read_u32();
+                    let to = self.read_jump(pc);
                     Op::Jump(to)
                 }
                 19 => {
-                    let to = self.read_u32();
+                    let to = self.read_jump(pc);
                     Op::JumpIf(to)
                 }
                 20 => {
-                    let to = self.read_u32();
+                    let to = self.read_jump(pc);
                     Op::JumpIfNot(to)
                 }
                 21 => {
-                    let addr = self.read_u32();
+                    let addr = self.read_jump(pc);
                     Op::CatchPush(addr)
                 }
                 22 => Op::Throw,
@@ -217,10 +287,44 @@ impl<'a> BytecodeReader<'a> {
                 48 => Op::New,
                 49 => Op::Nop,
                 50 => Op::Last,
+                51 => Op::LoadStrict,
+                52 => {
+                    let idx = self.read_u32();
+                    Op::StoreGlobal(idx)
+                }
+                53 => Op::CatchPop,
+                54 => Op::StrictEq,
+                55 => Op::Pow,
+                56 => {
+                    let table = self.read_u32();
+                    let cases = self.read_u32();
+                    Op::Switch(table, cases)
+                }
+                57 => {
+                    let name = self.read_u32();
+                    let argc = self.read_u16();
+                    Op::InvokeMethod(name, argc)
+                }
+                58 => {
+                    let argc = self.read_u16();
+                    let names = self.read_u32();
+                    Op::CallNamed(argc, names)
+                }
+                59 => {
+                    let name = self.read_u32();
+                    let argc = self.read_u16();
+                    let names = self.read_u32();
+                    Op::InvokeNamed(name, argc, names)
+                }
+                60 => Op::Yield,
+                61 => Op::Delete,
+                62 => Op::LoadElementStrict,
+                63 => Op::BitNot,
                 _ => unreachable!(),
             };
-            m.borrow_mut().code.push(opcode);
+            code.push(opcode);
         }
+        m.borrow_mut().set_code(code);
 
         m
     }
@@ -0,0 +1,186 @@
+//! Recording of executed instructions, and stepping through a recording afterwards.
+//!
+//! A trace file starts with `MAGIC` and holds one entry per instruction, in the order they
+//! ran: pc, line, call depth, operand stack height, instruction and top of the stack, all
+//! taken just before the instruction runs.
+
+use crate::*;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use value::*;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Cursor, Write};
+
+pub const MAGIC: &[u8; 4] = b"JZTR";
+
+/// Longest description of the top of the stack kept in an entry.
+const MAX_VALUE_LEN: usize = 40;
+
+pub struct Entry {
+    pub pc: u32,
+    pub line: u32,
+    pub depth: u16,
+    pub height: u32,
+    pub op: String,
+    /// Empty when the stack is.
+    pub top: String,
+}
+
+/// Short description of `value`, arrays and objects are summarized by their size and arrays
+/// also by their element storage.
+fn brief(value: &Value) -> String {
+    let s = match value {
+        Value::String(s) => format!("{:?}", s.borrow()),
+        Value::Array(a) => format!("array({}, {})", a.borrow().len(), a.borrow().kind()),
+        Value::Object(o) => format!("object({})", o.borrow().table.len()),
+        value => format!("{}", value),
+    };
+    match s.char_indices().nth(MAX_VALUE_LEN) {
+        Some((end, _)) => format!("{}...", &s[..end]),
+        None => s,
+    }
+}
+
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        Ok(Recorder { out })
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.out.write_u32::<LittleEndian>(s.len() as u32)?;
+        self.out.write_all(s.as_bytes())
+    }
+
+    /// Records that `op` at `pc` of `m` is about to run, at call depth `depth`. Returns
+    /// `false` after reporting a write error, the trace is then cut short.
+    pub fn record(
+        &mut self,
+        m: &Ref<Module>,
+        pc: usize,
+        op: &opcode::Op,
+        depth: usize,
+        stack: &[Value],
+    ) -> bool {
+        let line = match m.borrow().trace_info.get(&(pc as u32)) {
+            Some((line, _)) => *line as u32,
+            None => 0,
+        };
+        let top = stack.last().map(brief).unwrap_or_default();
+        let result = (|| {
+            self.out.write_u32::<LittleEndian>(pc as u32)?;
+            self.out.write_u32::<LittleEndian>(line)?;
+            self.out.write_u16::<LittleEndian>(depth as u16)?;
+            self.out.write_u32::<LittleEndian>(stack.len() as u32)?;
+            self.write_str(&format!("{:?}", op))?;
+            self.write_str(&top)
+        })();
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("record: {}", e);
+                false
+            }
+        }
+    }
+
+    pub fn finish(mut self) {
+        if let Err(e) = self.out.flush() {
+            eprintln!("record: {}", e);
+        }
+    }
+}
+
+fn read_str(bytes: &mut Cursor<&[u8]>) -> io::Result<String> {
+    let len = bytes.read_u32::<LittleEndian>()? as usize;
+    let start = bytes.position() as usize;
+    let s = bytes
+        .get_ref()
+        .get(start..start + len)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated trace"))?;
+    bytes.set_position((start + len) as u64);
+    Ok(String::from_utf8_lossy(s).into_owned())
+}
+
+/// Entries of the trace in `contents`.
+pub fn read_trace(contents: &[u8]) -> io::Result<Vec<Entry>> {
+    if !contents.starts_with(MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a trace file",
+        ));
+    }
+    let mut bytes = Cursor::new(contents);
+    bytes.set_position(MAGIC.len() as u64);
+    let mut entries = vec![];
+    while (bytes.position() as usize) < contents.len() {
+        entries.push(Entry {
+            pc: bytes.read_u32::<LittleEndian>()?,
+            line: bytes.read_u32::<LittleEndian>()?,
+            depth: bytes.read_u16::<LittleEndian>()?,
+            height: bytes.read_u32::<LittleEndian>()?,
+            op: read_str(&mut bytes)?,
+            top: read_str(&mut bytes)?,
+        });
+    }
+    Ok(entries)
+}
+
+fn print_entry(entries: &[Entry], step: usize) {
+    let entry = &entries[step];
+    println!(
+        "{}/{} line {} pc {:04} depth {} {:<24} stack {} {}",
+        step + 1,
+        entries.len(),
+        entry.line,
+        entry.pc,
+        entry.depth,
+        entry.op,
+        entry.height,
+        entry.top
+    );
+}
+
+/// Steps through `entries` with commands read from `input`: `n` (or an empty line) and `p`
+/// move one instruction forward or back, `n N` and `p N` move `N`, `g N` goes to step `N`,
+/// `l` moves to the next instruction on another line and `q` quits.
+pub fn replay(entries: &[Entry], input: impl BufRead) {
+    if entries.is_empty() {
+        println!("empty trace");
+        return;
+    }
+    let mut step = 0;
+    print_entry(entries, step);
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("n");
+        let count = words.next().and_then(|n| n.parse::<usize>().ok());
+        let last = entries.len() - 1;
+        step = match command {
+            "n" => (step + count.unwrap_or(1)).min(last),
+            "p" => step.saturating_sub(count.unwrap_or(1)),
+            "g" => count.unwrap_or(1).max(1).min(entries.len()) - 1,
+            "l" => {
+                let line = entries[step].line;
+                (step..entries.len())
+                    .find(|&i| entries[i].line != line && entries[i].line != 0)
+                    .unwrap_or(last)
+            }
+            "q" => break,
+            _ => {
+                println!("commands: n [N], p [N], g N, l, q");
+                continue;
+            }
+        };
+        print_entry(entries, step);
+    }
+}
@@ -1,6 +1,13 @@
 use crate::*;
+pub use decimal::Decimal;
+pub use elements::Elements;
 use hashlink::LinkedHashMap;
-use std::hash::{Hash, Hasher};
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::cmp::Ordering;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
 
 #[derive(Clone)]
 pub enum Value {
@@ -9,10 +16,12 @@ pub enum Value {
     Int(i64),
     Float(f64),
     String(Ref<String>),
-    Array(Ref<Vec<Value>>),
+    Array(Ref<Elements>),
     Object(Ref<Object>),
     Function(Ref<Function>),
     Char(char),
+    BigInt(Ref<BigInt>),
+    Decimal(Ref<Decimal>),
     User(Ref<dyn UserKind>),
 }
 
@@ -27,32 +36,47 @@ pub enum ValTag {
     Object,
     Func,
     Char,
+    BigInt,
+    Decimal,
     User(&'static str),
 }
 
 impl Value {
+    /// Truthiness used by conditions, `!`, `&&` and `||`: `null`, `false`, zero, NaN and the
+    /// empty string are falsy, everything else including empty arrays and objects is truthy.
     pub fn to_bool(&self) -> bool {
         match self {
             Value::Null => false,
             Value::Bool(x) => *x,
-            Value::Int(x) => {
-                if *x == 0 {
-                    false
-                } else {
-                    true
-                }
-            }
-            Value::Float(x) => {
-                if *x == 0.0 {
-                    false
-                } else {
-                    true
-                }
-            }
+            Value::Int(x) => *x != 0,
+            Value::Float(x) => *x != 0.0 && !x.is_nan(),
+            Value::BigInt(x) => *x.borrow() != BigInt::from(0),
+            Value::Decimal(x) => !x.borrow().is_zero(),
+            Value::String(s) => !s.borrow().is_empty(),
             _ => true,
         }
     }
 
+    /// `===`: no conversion between types, arrays, objects, functions and user values are
+    /// equal only to themselves.
+    pub fn strict_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Bool(x), Value::Bool(y)) => x == y,
+            (Value::Int(x), Value::Int(y)) => x == y,
+            (Value::Float(x), Value::Float(y)) => x == y,
+            (Value::Char(x), Value::Char(y)) => x == y,
+            (Value::BigInt(x), Value::BigInt(y)) => *x.borrow() == *y.borrow(),
+            (Value::Decimal(x), Value::Decimal(y)) => *x.borrow() == *y.borrow(),
+            (Value::String(x), Value::String(y)) => *x.borrow() == *y.borrow(),
+            (Value::Array(x), Value::Array(y)) => Rc::ptr_eq(x, y),
+            (Value::Object(x), Value::Object(y)) => Rc::ptr_eq(x, y),
+            (Value::Function(x), Value::Function(y)) => Rc::ptr_eq(x, y),
+            (Value::User(x), Value::User(y)) => Rc::ptr_eq(x, y),
+            _ => false,
+        }
+    }
+
     pub fn to_object(&self) -> Option<Ref<Object>> {
         match self {
             Value::Object(obj) => return Some(obj.clone()),
@@ -60,7 +84,7 @@ impl Value {
         }
     }
 
-    pub fn to_array(&self) -> Option<Ref<Vec<Value>>> {
+    pub fn to_array(&self) -> Option<Ref<Elements>> {
         match self {
             Value::Array(array) => return Some(array.clone()),
             _ => None,
@@ -70,6 +94,8 @@ impl Value {
         match self {
             Value::Int(x) => Some(*x),
             Value::Float(x) => Some(*x as i64),
+            Value::BigInt(x) => x.borrow().to_i64(),
+            Value::Decimal(x) => x.borrow().to_i64(),
             _ => None,
         }
     }
@@ -78,6 +104,26 @@ impl Value {
         match self {
             Value::Int(x) => Some(*x as f64),
             Value::Float(x) => Some(*x),
+            Value::BigInt(x) => x.borrow().to_f64(),
+            _ => None,
+        }
+    }
+
+    /// Integer values promoted to arbitrary precision, floats are not converted.
+    pub fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(x) => Some(BigInt::from(*x)),
+            Value::BigInt(x) => Some(x.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    /// Integers and decimals as a Decimal, floats are not converted.
+    pub fn to_decimal(&self) -> Option<Decimal> {
+        match self {
+            Value::Int(x) => Some(Decimal::from_bigint(BigInt::from(*x))),
+            Value::BigInt(x) => Some(Decimal::from_bigint(x.borrow().clone())),
+            Value::Decimal(x) => Some(x.borrow().clone()),
             _ => None,
         }
     }
@@ -93,9 +139,131 @@ impl Value {
             Value::Function(_) => ValTag::Func,
             Value::Bool(_) => ValTag::Bool,
             Value::Char(_) => ValTag::Char,
+            Value::BigInt(_) => ValTag::BigInt,
+            Value::Decimal(_) => ValTag::Decimal,
             Value::User(x) => ValTag::User(x.borrow().get_kind()),
         }
     }
+
+    pub fn is_symbol(&self) -> bool {
+        match self {
+            Value::User(user) => user
+                .try_borrow()
+                .map_or(false, |user| user.is::<crate::builtins::symbol::Symbol>()),
+            _ => false,
+        }
+    }
+
+    /// Text of the value, with the elements of arrays and the keys and values of fields
+    /// converted by `convert`.
+    pub fn format_with<E>(
+        &self,
+        convert: &mut dyn FnMut(&Value) -> Result<String, E>,
+    ) -> Result<String, E> {
+        match self {
+            Value::Array(array) => {
+                let values = array.borrow().to_vec();
+                let mut fmt = String::new();
+                fmt.push('[');
+                for (idx, value) in values.iter().enumerate() {
+                    fmt.push_str(&convert(value)?);
+                    if idx < values.len() - 1 {
+                        fmt.push(',');
+                    }
+                }
+                fmt.push(']');
+                Ok(fmt)
+            }
+            Value::Object(object) => {
+                let fields = object
+                    .borrow()
+                    .fields()
+                    .map(|(key, val)| (key.clone(), val.clone()))
+                    .collect::<Vec<_>>();
+                let mut fmt = String::new();
+                fmt.push_str("{\n");
+                for (i, (key, val)) in fields.iter().enumerate() {
+                    let key = convert(key)?;
+                    let value = convert(val)?;
+                    fmt.push_str(&format!("  {} => {}", key, value));
+                    if i < fields.len() - 1 {
+                        fmt.push(',');
+                    }
+                    fmt.push('\n');
+                }
+                fmt.push('}');
+                Ok(fmt)
+            }
+            _ => Ok(self.to_string()),
+        }
+    }
+
+    /// Name of the value's type as returned by `$typeof`.
+    pub fn type_name(&self) -> &'static str {
+        match self.tag() {
+            ValTag::Array => "array",
+            ValTag::Null => "null",
+            ValTag::Float => "float",
+            ValTag::Int => "int",
+            ValTag::Str => "string",
+            ValTag::Bool => "bool",
+            ValTag::Object => "object",
+            ValTag::Char => "char",
+            ValTag::Func => "function",
+            ValTag::BigInt => "bigint",
+            ValTag::Decimal => "decimal",
+            ValTag::User(x) => x,
+        }
+    }
+
+    /// Ordering used by `<`, `<=`, `>`, `>=` and `$cmp`. Numbers of any kind compare by
+    /// value, chars with chars or ints by code point, strings and arrays lexicographically.
+    /// Other combinations throw, `None` means a NaN was involved.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, Value> {
+        Ok(match (self, other) {
+            (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+            (Value::BigInt(_), Value::Int(_) | Value::BigInt(_))
+            | (Value::Int(_), Value::BigInt(_)) => {
+                Some(self.to_bigint().unwrap().cmp(&other.to_bigint().unwrap()))
+            }
+            (Value::Char(x), Value::Char(y)) => Some(x.cmp(y)),
+            (Value::Char(x), Value::Int(y)) => Some((*x as i64).cmp(y)),
+            (Value::Int(x), Value::Char(y)) => Some(x.cmp(&(*y as i64))),
+            (Value::String(x), Value::String(y)) => Some(x.borrow().as_str().cmp(&y.borrow())),
+            (Value::Array(x), Value::Array(y)) => {
+                let (x, y) = (x.borrow(), y.borrow());
+                for (a, b) in x.iter().zip(y.iter()) {
+                    match a.compare(&b)? {
+                        Some(Ordering::Equal) => (),
+                        ordering => return Ok(ordering),
+                    }
+                }
+                Some(x.len().cmp(&y.len()))
+            }
+            (Value::Decimal(_), _) | (_, Value::Decimal(_)) => {
+                match (self.to_decimal(), other.to_decimal()) {
+                    (Some(x), Some(y)) => Some(x.cmp(&y)),
+                    _ => {
+                        return Err(Value::String(Ref(format!(
+                            "Cannot compare {} with {}",
+                            self.type_name(),
+                            other.type_name()
+                        ))))
+                    }
+                }
+            }
+            _ => match (self.to_float(), other.to_float()) {
+                (Some(x), Some(y)) => x.partial_cmp(&y),
+                _ => {
+                    return Err(Value::String(Ref(format!(
+                        "Cannot compare {} with {}",
+                        self.type_name(),
+                        other.type_name()
+                    ))))
+                }
+            },
+        })
+    }
 }
 
 impl Hash for Value {
@@ -118,7 +286,11 @@ impl Hash for Value {
             }
             Value::Array(array) => {
                 4.hash(state);
-                array.borrow().hash(state);
+                let array = array.borrow();
+                array.len().hash(state);
+                for value in array.iter() {
+                    hash_shallow(&value, state);
+                }
             }
             Value::Object(object) => {
                 5.hash(state);
@@ -132,6 +304,22 @@ impl Hash for Value {
                 7.hash(state);
                 x.hash(state);
             }
+            // Equal to the Int of the same value, so it must hash like one.
+            Value::BigInt(x) => match x.borrow().to_i64() {
+                Some(x) => Value::Int(x).hash(state),
+                None => {
+                    8.hash(state);
+                    x.borrow().hash(state);
+                }
+            },
+            Value::Decimal(x) => {
+                10.hash(state);
+                x.borrow().hash(state);
+            }
+            Value::User(x) => {
+                9.hash(state);
+                (x.as_ptr() as *const u8 as usize).hash(state);
+            }
             _ => (),
         }
     }
@@ -143,36 +331,13 @@ impl fmt::Display for Value {
         match self {
             Value::Int(x) => write!(f, "{}", x),
             Value::Float(x) => write!(f, "{}", x),
-            Value::Array(array) => {
-                let mut fmt = String::new();
-                fmt.push('[');
-                for (idx, value) in array.borrow().iter().enumerate() {
-                    fmt.push_str(&value.to_string());
-
-                    if idx < array.borrow().len() - 1 {
-                        fmt.push(',');
-                    }
-                }
-
-                fmt.push(']');
-                write!(f, "{}", fmt)
+            Value::Array(_) | Value::Object(_) => {
+                let text = self.format_with(&mut |value| Ok::<_, fmt::Error>(value.to_string()))?;
+                write!(f, "{}", text)
             }
             Value::Char(x) => write!(f, "{}", x),
-            Value::Object(object) => {
-                let mut fmt = String::new();
-                fmt.push_str("{\n");
-                for (i, (key, val)) in object.borrow().table.iter().enumerate() {
-                    let key = key.to_string();
-                    let value = val.to_string();
-                    fmt.push_str(&format!("  {} => {}", key, value));
-                    if i < object.borrow().table.len() - 1 {
-                        fmt.push(',');
-                    }
-                    fmt.push('\n');
-                }
-                fmt.push('}');
-                write!(f, "{}", fmt)
-            }
+            Value::BigInt(x) => write!(f, "{}", x.borrow()),
+            Value::Decimal(x) => write!(f, "{}", x.borrow()),
             Value::Function(func) => {
                 if func.borrow().native {
                     write!(f, "<function {:x}>", func.borrow().address)
@@ -198,8 +363,21 @@ impl PartialEq for Value {
             Value::Int(x) => match other {
                 Value::Int(y) => x == y,
                 Value::Float(y) => *x == *y as i64,
+                Value::BigInt(y) => BigInt::from(*x) == *y.borrow(),
+                Value::Decimal(_) => other == self,
                 _ => false,
             },
+            Value::BigInt(x) => match other {
+                Value::Decimal(_) => other == self,
+                _ => match other.to_bigint() {
+                    Some(y) => *x.borrow() == y,
+                    None => false,
+                },
+            },
+            Value::Decimal(x) => match other.to_decimal() {
+                Some(y) => *x.borrow() == y,
+                None => false,
+            },
             Value::Float(x) => match other {
                 Value::Int(y) => *x == *y as f64,
                 Value::Float(y) => x == y,
@@ -215,7 +393,7 @@ impl PartialEq for Value {
                 _ => false,
             },
             Value::Array(x) => match other {
-                Value::Array(y) => *x.borrow() == *y.borrow(),
+                Value::Array(y) => Rc::ptr_eq(x, y) || *x.borrow() == *y.borrow(),
                 _ => false,
             },
             Value::Null => match other {
@@ -224,6 +402,12 @@ impl PartialEq for Value {
             },
             Value::Object(x) => match other {
                 Value::Object(y) => {
+                    if Rc::ptr_eq(x, y) {
+                        return true;
+                    }
+                    if x.borrow().table.len() != y.borrow().table.len() {
+                        return false;
+                    }
                     for ((key1, val1), (key2, val2)) in
                         x.borrow().table.iter().zip(y.borrow().table.iter())
                     {
@@ -235,6 +419,10 @@ impl PartialEq for Value {
                 }
                 _ => false,
             },
+            Value::User(x) => match other {
+                Value::User(y) => x.as_ptr() as *const u8 == y.as_ptr() as *const u8,
+                _ => false,
+            },
             _ => false,
         }
     }
@@ -242,9 +430,77 @@ impl PartialEq for Value {
 
 impl Eq for Value {}
 
+/// Hashers of the tables of objects and maps: SipHash with keys picked at random for each VM,
+/// so keys coming from untrusted input can't be chosen to all collide, or with a fixed seed
+/// once `set_hash_seed` is called, for runs that must be reproducible.
+#[derive(Clone)]
+pub enum ValueHashBuilder {
+    Random(RandomState),
+    Seeded(u64),
+}
+
+thread_local! {
+    /// Hasher of the tables created by the VM of this thread.
+    static HASH_BUILDER: RefCell<ValueHashBuilder> =
+        RefCell::new(ValueHashBuilder::Random(RandomState::new()));
+}
+
+/// Makes the tables created afterwards by the VM of this thread hash with `seed` instead of
+/// random keys.
+pub fn set_hash_seed(seed: u64) {
+    HASH_BUILDER.with(|builder| *builder.borrow_mut() = ValueHashBuilder::Seeded(seed));
+}
+
+impl Default for ValueHashBuilder {
+    fn default() -> ValueHashBuilder {
+        HASH_BUILDER.with(|builder| builder.borrow().clone())
+    }
+}
+
+impl BuildHasher for ValueHashBuilder {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        match self {
+            ValueHashBuilder::Random(state) => state.build_hasher(),
+            ValueHashBuilder::Seeded(seed) => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(*seed);
+                hasher
+            }
+        }
+    }
+}
+
+/// Attributes of a field of an object.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Attributes {
+    /// Listed by `foreach`, `o.keys()`, printing and JSON.
+    pub enumerable: bool,
+    /// Can be assigned by scripts, builtins can still change it.
+    pub writable: bool,
+}
+
+impl Default for Attributes {
+    fn default() -> Attributes {
+        Attributes {
+            enumerable: true,
+            writable: true,
+        }
+    }
+}
+
 pub struct Object {
     pub prototype: Option<Ref<Object>>,
-    pub table: LinkedHashMap<Value, Value>,
+    /// Fields in insertion order, which is the order `foreach` and printing see. Updating a
+    /// field keeps its position, `o.sort_keys()` reorders them.
+    pub table: LinkedHashMap<Value, Value, ValueHashBuilder>,
+    /// Incremented when a field is added or removed, so iterators can detect the change.
+    pub modcount: usize,
+    /// Fields whose attributes aren't the default ones.
+    pub attributes: HashMap<Value, Attributes, ValueHashBuilder>,
+    /// Scripts can't add fields, see `o.seal()`.
+    pub sealed: bool,
 }
 
 impl Object {
@@ -258,22 +514,97 @@ impl Object {
         }
     }
 
+    /// Adds or updates a field. Updated fields keep their position.
     pub fn set(&mut self, key: Value, value: Value) {
-        self.table.insert(key, value);
+        match self.table.get_mut(&key) {
+            Some(field) => *field = value,
+            None => {
+                self.table.insert(key, value);
+                self.modcount += 1;
+            }
+        }
+    }
+
+    /// `set` for an assignment in a script, which fails on fields that aren't writable and
+    /// new fields of sealed objects.
+    pub fn assign(&mut self, key: Value, value: Value) -> Result<(), String> {
+        if !self.attributes(&key).writable {
+            return Err(format!("Cannot assign to read-only field '{}'", key));
+        }
+        if self.sealed && !self.table.contains_key(&key) {
+            return Err(format!("Cannot add field '{}' to a sealed object", key));
+        }
+        self.set(key, value);
+        Ok(())
+    }
+
+    /// Removes the field `key` of the object itself, returning its value.
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let value = self.table.remove(key)?;
+        self.attributes.remove(key);
+        self.modcount += 1;
+        Some(value)
+    }
+
+    /// `remove` for a `delete` in a script, which fails on fields that aren't writable and
+    /// on sealed objects. Returns whether there was such a field.
+    pub fn delete(&mut self, key: &Value) -> Result<bool, String> {
+        if !self.table.contains_key(key) {
+            return Ok(false);
+        }
+        if !self.attributes(key).writable {
+            return Err(format!("Cannot delete read-only field '{}'", key));
+        }
+        if self.sealed {
+            return Err(format!("Cannot delete field '{}' of a sealed object", key));
+        }
+        self.remove(key);
+        Ok(true)
+    }
+
+    pub fn attributes(&self, key: &Value) -> Attributes {
+        self.attributes.get(key).copied().unwrap_or_default()
+    }
+
+    pub fn set_attributes(&mut self, key: Value, attributes: Attributes) {
+        if attributes == Attributes::default() {
+            self.attributes.remove(&key);
+        } else {
+            self.attributes.insert(key, attributes);
+        }
+    }
+
+    /// Enumerable fields in order. Fields keyed by symbols are never listed.
+    pub fn fields(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.table
+            .iter()
+            .filter(move |(key, _)| !key.is_symbol() && self.attributes(key).enumerable)
     }
 }
 
 impl Hash for Object {
     fn hash<H: Hasher>(&self, state: &mut H) {
+        self.table.len().hash(state);
         for (key, val) in self.table.iter() {
-            key.hash(state);
-            val.hash(state);
+            hash_shallow(key, state);
+            hash_shallow(val, state);
         }
-        self.table.len().hash(state);
-        match &self.prototype {
-            Some(value) => value.borrow().hash(state),
-            _ => (),
+    }
+}
+
+/// Hashes arrays and objects nested in a key by length only, so hashing a cyclic value
+/// terminates. Use an identity `$map` to key by reference instead.
+fn hash_shallow<H: Hasher>(value: &Value, state: &mut H) {
+    match value {
+        Value::Array(array) => {
+            4.hash(state);
+            array.borrow().len().hash(state);
+        }
+        Value::Object(object) => {
+            5.hash(state);
+            object.borrow().table.len().hash(state);
         }
+        _ => value.hash(state),
     }
 }
 
@@ -284,6 +615,18 @@ pub struct Function {
     pub env: Value,
     pub module: Option<Ref<Module>>,
     pub argc: i32,
+    /// Parameter names for named arguments, empty for native functions.
+    pub params: Rc<[String]>,
+    /// Source text of the function, `None` for native and generated functions.
+    pub source: Option<Rc<str>>,
+    /// Text of the `///` comments before the function.
+    pub doc: Option<Rc<str>>,
+    /// Largest number of values the function's code has on the stack, reserved when it is
+    /// called. 0 for native functions.
+    pub max_stack: u32,
+    /// Instructions of the function that ran in the profile its module was laid out with,
+    /// see `profile::layout`. 0 without one.
+    pub hotness: u64,
 }
 
 pub trait UserKind: mopa::Any + fmt::Debug + fmt::Display {
@@ -3,7 +3,9 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use value::*;
 
 use crate::opcode::Op;
-use crate::reader::{TAG_FLOAT, TAG_FUN, TAG_STRING};
+use crate::reader::{
+    NO_SOURCE, TAG_BIGINT, TAG_CHAR, TAG_FLOAT, TAG_FUN, TAG_INT, TAG_NULL, TAG_STRING, TAG_TABLE,
+};
 use crate::value::{Function, ValTag};
 use hashlink::LinkedHashMap;
 
@@ -25,6 +27,30 @@ impl BytecodeWriter {
         self.bytecode.write_u64::<LittleEndian>(x).unwrap();
     }
 
+    /// Writes the line and file of every instruction, see `Reader::read_dbginfo`.
+    fn write_dbginfo(&mut self, m: &Module, strings: &LinkedHashMap<String, i32>) {
+        let (_, file) = m.trace_info.values().next().unwrap();
+        let file = strings[file];
+        for pc in 0..m.code.len() {
+            match m.trace_info.get(&(pc as u32)) {
+                Some((line, file)) => {
+                    self.write_u32(*line as _);
+                    self.write_u32(strings[file] as _);
+                }
+                None => {
+                    self.write_u32(0);
+                    self.write_u32(file as _);
+                }
+            }
+        }
+    }
+
+    /// Jump targets are encoded as an `i32` offset from the next instruction.
+    fn write_jump(&mut self, pc: usize, to: u32) {
+        let offset = to as i64 - (pc as i64 + 1);
+        self.write_u32(offset as i32 as u32);
+    }
+
     pub fn write_module(&mut self, m: Ref<Module>) {
         let mut strings = LinkedHashMap::new();
         let mut i = 0;
@@ -34,10 +60,50 @@ impl BytecodeWriter {
                 i += 1;
             }
         }
+        for value in m.borrow().globals.iter() {
+            let names = match value {
+                Value::Object(table) => table
+                    .borrow()
+                    .table
+                    .keys()
+                    .filter_map(|key| match key {
+                        Value::String(s) => Some(s.borrow().clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                Value::Function(f) => {
+                    let f = f.borrow();
+                    let mut names = f.params.to_vec();
+                    names.extend(f.source.as_deref().map(str::to_owned));
+                    names.extend(f.doc.as_deref().map(str::to_owned));
+                    names
+                }
+                _ => vec![],
+            };
+            for name in names {
+                if !strings.contains_key(&name) {
+                    strings.insert(name, i);
+                    i += 1;
+                }
+            }
+        }
+        let has_dbginfo = !m.borrow().trace_info.is_empty();
+        for (_, file) in m.borrow().trace_info.values() {
+            if !strings.contains_key(file) {
+                strings.insert(file.clone(), i);
+                i += 1;
+            }
+        }
         let mut globals = vec![];
         for value in m.borrow().globals.iter() {
             match value.tag() {
-                ValTag::Func | ValTag::Str | ValTag::Float => globals.push(value.clone()),
+                ValTag::Func
+                | ValTag::Str
+                | ValTag::Float
+                | ValTag::BigInt
+                | ValTag::Char
+                | ValTag::Object
+                | ValTag::Null => globals.push(value.clone()),
 
                 _ => (), // TODO: Add more values to globals
             }
@@ -46,13 +112,16 @@ impl BytecodeWriter {
         self.write_u32(strings.len() as _);
         self.write_u32(globals.len() as _);
         self.write_u32(m.borrow().code.len() as _);
-        self.write_u8(0);
+        self.write_u8(has_dbginfo as u8);
         for (string, _) in strings.iter() {
             self.write_u32(string.len() as _);
             for byte in string.as_bytes() {
                 self.write_u8(*byte);
             }
         }
+        if has_dbginfo {
+            self.write_dbginfo(&m.borrow(), &strings);
+        }
 
         for i in 0..globals.len() {
             let global = globals[i].clone();
@@ -66,18 +135,66 @@ impl BytecodeWriter {
                     self.write_u8(TAG_FLOAT);
                     self.write_u64(x.to_bits());
                 }
+                Value::BigInt(x) => {
+                    self.write_u8(TAG_BIGINT);
+                    let bytes = x.borrow().to_signed_bytes_le();
+                    self.write_u32(bytes.len() as _);
+                    for byte in bytes.iter() {
+                        self.write_u8(*byte);
+                    }
+                }
                 Value::Function(f) => {
                     let f: &Function = &f.borrow();
                     self.write_u8(TAG_FUN);
                     self.write_u32(f.address as u32);
                     self.write_u16(f.argc as _);
+                    self.write_u16(f.params.len() as _);
+                    for param in f.params.iter() {
+                        self.write_u32(strings[param] as _);
+                    }
+                    for text in [&f.source, &f.doc].iter() {
+                        match text {
+                            Some(text) => self.write_u32(strings[&**text] as _),
+                            None => self.write_u32(NO_SOURCE),
+                        }
+                    }
+                    self.write_u32(f.max_stack);
+                    self.write_u64(f.hotness);
+                }
+                Value::Null => self.write_u8(TAG_NULL),
+                Value::Char(ch) => {
+                    self.write_u8(TAG_CHAR);
+                    self.write_u32(ch as u32);
+                }
+                Value::Object(table) => {
+                    let table = table.borrow();
+                    self.write_u8(TAG_TABLE);
+                    self.write_u32(table.table.len() as _);
+                    for (key, case) in table.table.iter() {
+                        match key {
+                            Value::Int(x) => {
+                                self.write_u8(TAG_INT);
+                                self.write_u64(*x as _);
+                            }
+                            Value::Char(ch) => {
+                                self.write_u8(TAG_CHAR);
+                                self.write_u32(*ch as u32);
+                            }
+                            Value::String(s) => {
+                                self.write_u8(TAG_STRING);
+                                self.write_u32(strings[&*s.borrow()] as _);
+                            }
+                            _ => unreachable!(),
+                        }
+                        self.write_u32(case.to_int().unwrap() as _);
+                    }
                 }
                 _ => (),
             }
         }
 
         for i in 0..m.borrow().code.len() {
-            let op = m.borrow().code[i].clone();
+            let op = m.borrow().code[i].op.clone();
             match op {
                 Op::LoadNull => self.write_u8(0),
                 Op::LoadTrue => self.write_u8(1),
@@ -133,19 +250,19 @@ impl BytecodeWriter {
                 }
                 Op::Jump(to) => {
                     self.write_u8(18);
-                    self.write_u32(to);
+                    self.write_jump(i, to);
                 }
                 Op::JumpIf(to) => {
                     self.write_u8(19);
-                    self.write_u32(to);
+                    self.write_jump(i, to);
                 }
                 Op::JumpIfNot(to) => {
                     self.write_u8(20);
-                    self.write_u32(to);
+                    self.write_jump(i, to);
                 }
                 Op::CatchPush(addr) => {
                     self.write_u8(21);
-                    self.write_u32(addr);
+                    self.write_jump(i, addr);
                 }
                 Op::Throw => self.write_u8(22),
                 Op::Ret => self.write_u8(23),
@@ -182,6 +299,49 @@ impl BytecodeWriter {
                 Op::New => self.write_u8(48),
                 Op::Nop => self.write_u8(49),
                 Op::Last => self.write_u8(50),
+                Op::LoadStrict => self.write_u8(51),
+                Op::StoreGlobal(idx) => {
+                    self.write_u8(52);
+                    self.write_u32(idx);
+                }
+                Op::CatchPop => self.write_u8(53),
+                Op::StrictEq => self.write_u8(54),
+                Op::Pow => self.write_u8(55),
+                Op::Switch(table, cases) => {
+                    self.write_u8(56);
+                    self.write_u32(table);
+                    self.write_u32(cases);
+                }
+                Op::InvokeMethod(name, argc) => {
+                    self.write_u8(57);
+                    self.write_u32(name);
+                    self.write_u16(argc);
+                }
+                Op::CallNamed(argc, names) => {
+                    self.write_u8(58);
+                    self.write_u16(argc);
+                    self.write_u32(names);
+                }
+                Op::InvokeNamed(name, argc, names) => {
+                    self.write_u8(59);
+                    self.write_u32(name);
+                    self.write_u16(argc);
+                    self.write_u32(names);
+                }
+                Op::Yield => self.write_u8(60),
+                Op::Delete => self.write_u8(61),
+                Op::LoadElementStrict => self.write_u8(62),
+                Op::BitNot => self.write_u8(63),
+                // Written as the generic instruction.
+                Op::AddNum
+                | Op::SubNum
+                | Op::MulNum
+                | Op::GtNum
+                | Op::GteNum
+                | Op::LtNum
+                | Op::LteNum
+                | Op::LoadField
+                | Op::LoadIndex => unreachable!(),
             }
         }
     }